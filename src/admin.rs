@@ -0,0 +1,318 @@
+//! Operator actions on saved state: unlocking accounts, force-resolving
+//! disputes, manual adjustments and a view of an account's history. Every
+//! action, refused or not, is appended to an audit log under the name of
+//! the operator who took it.
+//!
+//! Each line of the log carries the SHA-256 of the line before it, and a
+//! head file next to the log keeps the hash of the newest line and the
+//! number of lines. [`verify`] walks the chain: changing or removing any
+//! line breaks it, and cutting lines off the end no longer matches the head.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+
+use crate::{error::Error, sha256, time, Account, ClientId, Engine, Reject, TxId};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Unlock,
+    Resolve(TxId),
+    /// Adds the amount, which may be negative, to the available funds.
+    Adjust(Decimal),
+    History,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Unlock => "unlock",
+            Action::Resolve(_) => "resolve",
+            Action::Adjust(_) => "adjust",
+            Action::History => "history",
+        }
+    }
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AuditEntry {
+    pub time: String,
+    pub operator: String,
+    pub action: &'static str,
+    pub client: ClientId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx: Option<TxId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Decimal>,
+    /// `ok`, or why the action was refused.
+    pub outcome: String,
+}
+
+/// An entry as written, chained to the line before it.
+#[derive(Serialize)]
+struct Chained<'a, T: Serialize> {
+    #[serde(flatten)]
+    entry: &'a T,
+    /// SHA-256 of the previous line, all zeros for the first.
+    prev: &'a str,
+}
+
+/// The newest line of a log and how many there are, kept apart from the
+/// log so lines cut off its end are noticed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Head {
+    pub entries: u64,
+    pub hash: String,
+}
+
+impl Default for Head {
+    fn default() -> Self {
+        Head { entries: 0, hash: "0".repeat(64) }
+    }
+}
+
+impl Head {
+    fn add(&mut self, line: &str) {
+        self.entries += 1;
+        self.hash = sha256::to_hex(&sha256::digest(line.as_bytes()));
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Replaces the head at `path` atomically.
+    fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut tmp = OsString::from(path);
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Where the head of the log at `path` is kept.
+pub fn head_path(path: &Path) -> PathBuf {
+    let mut head = OsString::from(path);
+    head.push(".head");
+    PathBuf::from(head)
+}
+
+/// An append-only, hash-chained JSON-lines audit log.
+pub struct AuditLog {
+    output: Box<dyn Write>,
+    head: Head,
+    /// Where the head is kept, when the log is a file.
+    head_path: Option<PathBuf>,
+}
+
+impl AuditLog {
+    /// A log starting a fresh chain in `output`.
+    pub fn new(output: Box<dyn Write>) -> Self {
+        AuditLog { output, head: Head::default(), head_path: None }
+    }
+
+    /// Opens `path` for appending, creating it if needed, and continues the
+    /// chain from its last line.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut head = Head::default();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    head.add(&line);
+                }
+            }
+        }
+        let output = Box::new(OpenOptions::new().create(true).append(true).open(path)?);
+        Ok(AuditLog { output, head, head_path: Some(head_path(path)) })
+    }
+
+    /// Appends `entry`, an [`AuditEntry`] or any other record kept the same way.
+    pub fn record<T: Serialize>(&mut self, entry: &T) -> Result<(), Error> {
+        let line = serde_json::to_string(&Chained { entry, prev: &self.head.hash }).map_err(io::Error::from)?;
+        writeln!(self.output, "{}", line)?;
+        self.output.flush()?;
+        self.head.add(&line);
+        if let Some(path) = &self.head_path {
+            self.head.write(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that every line of the log in `input` follows the one before it
+/// and, given the log's `head`, that none are missing from the end.
+/// Returns the head the log has.
+pub fn verify(input: impl Read, head: Option<&Head>) -> Result<Head, Error> {
+    let mut found = Head::default();
+    for (line, number) in BufReader::new(input).lines().zip(1..) {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let prev = serde_json::from_str::<serde_json::Value>(&line).ok()
+            .and_then(|entry| entry.get("prev")?.as_str().map(str::to_string));
+        let reason = match prev {
+            None => "not a chained audit entry",
+            Some(prev) if prev != found.hash => "the entry before it was changed or removed",
+            Some(_) => {
+                found.add(&line);
+                continue;
+            }
+        };
+        return Err(Error::AuditTampered { line: number, reason: reason.to_string() });
+    }
+    match head {
+        Some(head) if head.entries > found.entries => Err(Error::AuditTampered {
+            line: found.entries + 1,
+            reason: format!("missing; the head records {} entries", head.entries),
+        }),
+        Some(head) if *head != found => Err(Error::AuditTampered {
+            line: found.entries,
+            reason: "does not match the head".to_string(),
+        }),
+        _ => Ok(found),
+    }
+}
+
+/// Performs `action` on `client`'s main account for `operator` and audits
+/// it. History is written to `out`; the other actions change `engine`,
+/// which the caller saves.
+pub fn perform<W: Write>(
+    engine: &mut Engine,
+    client: ClientId,
+    action: &Action,
+    operator: &str,
+    audit: &mut AuditLog,
+    out: W,
+) -> Result<(), Error> {
+    let result = match action {
+        Action::Unlock => engine.unlock(client).map_err(|reject| refused(engine, reject)),
+        Action::Resolve(tx) => engine.force_resolve(client, *tx).map_err(|reject| refused(engine, reject)),
+        Action::Adjust(amount) => engine.adjust(client, *amount).map_err(|reject| refused(engine, reject)),
+        Action::History => match engine.account(client) {
+            Ok(Some(account)) => write_history(&account, out).map_err(Error::from),
+            Ok(None) => Err(Error::Refused(Reject::UnknownClient)),
+            Err(err) => Err(err),
+        },
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64);
+    audit.record(&AuditEntry {
+        time: time::format_timestamp(now),
+        operator: operator.to_string(),
+        action: action.name(),
+        client,
+        tx: match action { Action::Resolve(tx) => Some(*tx), _ => None },
+        amount: match action { Action::Adjust(amount) => Some(*amount), _ => None },
+        outcome: result.as_ref().map_or_else(|err| err.to_string(), |()| "ok".to_string()),
+    })?;
+    result
+}
+
+/// Why the engine refused an override, or the store failure behind it.
+fn refused(engine: &mut Engine, reject: Reject) -> Error {
+    engine.take_store_error().unwrap_or(Error::Refused(reject))
+}
+
+/// Writes the account's balances and kept history, oldest entry first.
+pub fn write_history<W: Write>(account: &Account, mut out: W) -> io::Result<()> {
+    writeln!(out, "Client {}: available {}, held {}{}", account.client.0, account.available, account.held, if account.locked { ", locked" } else { "" })?;
+    writeln!(out, "{:>10}  {:<10}  {:>14}  {:<18}  {:>6}  Time", "Tx", "Kind", "Amount", "State", "Cycles")?;
+    let mut entries: Vec<_> = account.history.iter().collect();
+    entries.sort_by_key(|(_, deposit)| deposit.seq);
+    for (tx, deposit) in entries {
+        writeln!(
+            out, "{:>10}  {:<10}  {:>14}  {:<18}  {:>6}  {}",
+            tx.0,
+            if deposit.withdrawal { "withdrawal" } else { "deposit" },
+            deposit.amount,
+            deposit.state.to_string(),
+            deposit.cycles,
+            deposit.timestamp.map(time::format_timestamp).unwrap_or_default(),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::{Transaction, TxType};
+
+    #[test]
+    fn test_overrides_are_audited() {
+        let mut engine = Engine::new();
+        for (tx_type, amount) in [(TxType::Deposit, Some(dec!(10))), (TxType::Dispute, None), (TxType::Chargeback, None)] {
+            engine.apply(&Transaction::new(tx_type, ClientId(1), TxId(1), amount)).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("txflow-audit-{}.jsonl", std::process::id()));
+        let mut audit = AuditLog::open(&path).unwrap();
+
+        perform(&mut engine, ClientId(1), &Action::Unlock, "alice", &mut audit, io::sink()).unwrap();
+        perform(&mut engine, ClientId(1), &Action::Adjust(dec!(4)), "alice", &mut audit, io::sink()).unwrap();
+        let refused = perform(&mut engine, ClientId(1), &Action::Adjust(dec!(-5)), "bob", &mut audit, io::sink());
+        assert!(matches!(refused, Err(Error::Refused(Reject::InsufficientFunds))));
+
+        let account = engine.account(ClientId(1)).unwrap().unwrap();
+        assert_eq!((account.available, account.locked), (dec!(4), false));
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let entries: Vec<&str> = log.lines().collect();
+        assert_eq!(entries.len(), 3);
+        assert!(entries[0].contains("\"operator\":\"alice\",\"action\":\"unlock\",\"client\":1,\"outcome\":\"ok\""));
+        assert!(entries[2].contains("\"amount\":\"-5\",\"outcome\":\"refused: insufficient funds\""));
+        std::fs::remove_file(head_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_verify_detects_changed_and_truncated_logs() {
+        let path = std::env::temp_dir().join(format!("txflow-audit-chain-{}.jsonl", std::process::id()));
+        let mut engine = Engine::new();
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(1), Some(dec!(1)))).unwrap();
+        for operator in ["alice", "bob", "carol"] {
+            let mut audit = AuditLog::open(&path).unwrap();
+            perform(&mut engine, ClientId(1), &Action::Adjust(dec!(1)), operator, &mut audit, io::sink()).unwrap();
+        }
+        let log = std::fs::read_to_string(&path).unwrap();
+        let head = Head::read(&head_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(head_path(&path)).unwrap();
+        assert_eq!(verify(log.as_bytes(), Some(&head)).unwrap(), head);
+        assert!(log.lines().next().unwrap().ends_with(&format!("\"prev\":\"{}\"}}", "0".repeat(64))));
+
+        let changed = log.replacen("bob", "eve", 1);
+        assert!(matches!(verify(changed.as_bytes(), Some(&head)), Err(Error::AuditTampered { line: 3, .. })));
+        let truncated: String = log.lines().take(2).map(|line| format!("{}\n", line)).collect();
+        assert!(verify(truncated.as_bytes(), None).is_ok());
+        assert!(matches!(verify(truncated.as_bytes(), Some(&head)), Err(Error::AuditTampered { line: 3, .. })));
+        let last_changed = log.replacen("carol", "mallory", 1);
+        assert!(matches!(verify(last_changed.as_bytes(), Some(&head)), Err(Error::AuditTampered { line: 3, .. })));
+    }
+
+    #[test]
+    fn test_force_resolve_on_a_locked_account() {
+        let mut engine = Engine::new();
+        for (tx_type, tx, amount) in [(TxType::Deposit, 1, Some(dec!(10))), (TxType::Deposit, 2, Some(dec!(3))), (TxType::Dispute, 2, None), (TxType::Dispute, 1, None), (TxType::Chargeback, 1, None)] {
+            engine.apply(&Transaction::new(tx_type, ClientId(1), TxId(tx), amount)).unwrap();
+        }
+        assert_eq!(engine.apply(&Transaction::new(TxType::Resolve, ClientId(1), TxId(2), None)), Err(Reject::AccountLocked));
+        engine.force_resolve(ClientId(1), TxId(2)).unwrap();
+        let account = engine.account(ClientId(1)).unwrap().unwrap();
+        assert_eq!((account.available, account.held, account.locked), (dec!(3), dec!(0), true));
+
+        let mut text = Vec::new();
+        write_history(&account, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("Client 1: available 3, held 0, locked\n"));
+        assert!(text.contains("         2  deposit                  3  won                      0  \n"));
+    }
+}
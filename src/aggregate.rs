@@ -0,0 +1,219 @@
+//! Time-bucketed aggregates: applied rows totalled per day, week or month
+//! of their `timestamp` — deposit and withdrawal volume, dispute counts and
+//! chargeback value — written once the run ends. Rows without a timestamp
+//! belong to no bucket and are only counted.
+
+use std::{collections::BTreeMap, io, str::FromStr};
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+use crate::{error::Error, time, Transaction, TxType};
+
+/// The span each aggregate row covers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Bucket {
+    #[default]
+    Day,
+    /// ISO weeks, labelled by their Monday.
+    Week,
+    Month,
+}
+
+impl FromStr for Bucket {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Bucket::Day),
+            "week" => Ok(Bucket::Week),
+            "month" => Ok(Bucket::Month),
+            other => Err(format!("unknown bucket '{}' (expected day, week or month)", other)),
+        }
+    }
+}
+
+impl Bucket {
+    /// The label of the bucket containing `seconds`: `YYYY-MM-DD` for days
+    /// and weeks, `YYYY-MM` for months. Labels sort chronologically.
+    pub fn label(self, seconds: i64) -> String {
+        let days = seconds.div_euclid(86_400);
+        match self {
+            Bucket::Day => time::format_date(seconds),
+            // 1970-01-01 was a Thursday, three days after a Monday.
+            Bucket::Week => time::format_date((days - (days + 3).rem_euclid(7)) * 86_400),
+            Bucket::Month => time::format_date(seconds)[..7].to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum AggregateFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl FromStr for AggregateFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(AggregateFormat::Csv),
+            "jsonl" => Ok(AggregateFormat::Jsonl),
+            other => Err(format!("unknown aggregate format '{}' (expected csv or jsonl)", other)),
+        }
+    }
+}
+
+/// One bucket's totals.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Totals {
+    pub deposits: u64,
+    pub deposit_volume: Decimal,
+    pub withdrawals: u64,
+    pub withdrawal_volume: Decimal,
+    pub disputes: u64,
+    pub chargebacks: u64,
+    pub chargeback_value: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct AggregateRow<'a> {
+    bucket: &'a str,
+    deposits: u64,
+    deposit_volume: Decimal,
+    withdrawals: u64,
+    withdrawal_volume: Decimal,
+    disputes: u64,
+    chargebacks: u64,
+    chargeback_value: Decimal,
+}
+
+impl<'a> AggregateRow<'a> {
+    fn new(bucket: &'a str, totals: &Totals) -> Self {
+        AggregateRow {
+            bucket,
+            deposits: totals.deposits,
+            deposit_volume: totals.deposit_volume,
+            withdrawals: totals.withdrawals,
+            withdrawal_volume: totals.withdrawal_volume,
+            disputes: totals.disputes,
+            chargebacks: totals.chargebacks,
+            chargeback_value: totals.chargeback_value,
+        }
+    }
+}
+
+/// Totals applied rows per bucket and writes them, oldest bucket first,
+/// when the run ends.
+pub struct AggregateWriter {
+    bucket: Bucket,
+    format: AggregateFormat,
+    output: Box<dyn io::Write>,
+    buckets: BTreeMap<String, Totals>,
+    untimed: u64,
+}
+
+impl AggregateWriter {
+    pub fn new(output: Box<dyn io::Write>, bucket: Bucket, format: AggregateFormat) -> Self {
+        AggregateWriter { bucket, format, output, buckets: BTreeMap::new(), untimed: 0 }
+    }
+
+    /// Feeds one applied row. `charged_back` is the amount of the deposit a
+    /// chargeback reversed, looked up before the engine could forget it.
+    pub fn observe(&mut self, record: &Transaction, charged_back: Option<Decimal>) {
+        let Some(timestamp) = record.timestamp else {
+            self.untimed += 1;
+            return;
+        };
+        let totals = self.buckets.entry(self.bucket.label(timestamp)).or_default();
+        match (record.tx_type, record.amount) {
+            (TxType::Deposit, Some(amount)) => {
+                totals.deposits += 1;
+                totals.deposit_volume += amount;
+            }
+            (TxType::Withdrawal, Some(amount)) => {
+                totals.withdrawals += 1;
+                totals.withdrawal_volume += amount;
+            }
+            (TxType::Dispute, _) => totals.disputes += 1,
+            (TxType::Chargeback, _) => {
+                totals.chargebacks += 1;
+                totals.chargeback_value += charged_back.unwrap_or_default();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn totals(&self, label: &str) -> Option<&Totals> {
+        self.buckets.get(label)
+    }
+
+    pub fn buckets(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Applied rows left out for lack of a timestamp.
+    pub fn untimed(&self) -> u64 {
+        self.untimed
+    }
+
+    /// Writes every bucket in order and flushes.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        let rows = self.buckets.iter().map(|(bucket, totals)| AggregateRow::new(bucket, totals));
+        match self.format {
+            AggregateFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut self.output);
+                for row in rows {
+                    writer.serialize(row)?;
+                }
+                writer.flush()?;
+            }
+            AggregateFormat::Jsonl => {
+                for row in rows {
+                    serde_json::to_writer(&mut self.output, &row).map_err(io::Error::from)?;
+                    self.output.write_all(b"\n")?;
+                }
+            }
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::{ClientId, TxId};
+
+    fn at(tx_type: TxType, amount: Option<Decimal>, timestamp: &str) -> Transaction {
+        Transaction { timestamp: time::parse_timestamp(timestamp), ..Transaction::new(tx_type, ClientId(1), TxId(1), amount) }
+    }
+
+    #[test]
+    fn test_bucket_labels() {
+        let sunday = time::parse_timestamp("2024-01-07T23:00:00Z").unwrap();
+        assert_eq!(Bucket::Day.label(sunday), "2024-01-07");
+        assert_eq!(Bucket::Week.label(sunday), "2024-01-01");
+        assert_eq!(Bucket::Week.label(sunday + 3_600), "2024-01-08");
+        assert_eq!(Bucket::Month.label(sunday), "2024-01");
+    }
+
+    #[test]
+    fn test_totals_per_month() {
+        let mut writer = AggregateWriter::new(Box::new(io::sink()), Bucket::Month, AggregateFormat::Csv);
+        writer.observe(&at(TxType::Deposit, Some(dec!(10)), "2024-01-03"), None);
+        writer.observe(&at(TxType::Deposit, Some(dec!(5)), "2024-01-31"), None);
+        writer.observe(&at(TxType::Withdrawal, Some(dec!(2)), "2024-02-01"), None);
+        writer.observe(&at(TxType::Dispute, None, "2024-02-02"), None);
+        writer.observe(&at(TxType::Chargeback, None, "2024-02-09"), Some(dec!(10)));
+        writer.observe(&Transaction::new(TxType::Deposit, ClientId(1), TxId(9), Some(dec!(1))), None);
+
+        let january = writer.totals("2024-01").unwrap();
+        assert_eq!((january.deposits, january.deposit_volume), (2, dec!(15)));
+        let february = writer.totals("2024-02").unwrap();
+        assert_eq!((february.withdrawal_volume, february.disputes, february.chargeback_value), (dec!(2), 1, dec!(10)));
+        assert_eq!((writer.buckets(), writer.untimed()), (2, 1));
+    }
+}
@@ -0,0 +1,250 @@
+//! Watches chargebacks over a sliding window and raises an alert when their
+//! count or total value crosses a threshold, through a webhook, email or
+//! both. An alert fires once per spike: the monitor re-arms only after the
+//! window drops back under every threshold.
+
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    collections,
+    http::{self, Url},
+    logging::{Level, LogEvent, Logger},
+    observer::EngineObserver,
+    smtp::Mail,
+    time, Account, Transaction,
+};
+
+/// Thresholds and channels, from the policy file's `[alerts]` section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertConfig {
+    /// Width of the sliding window, in seconds.
+    pub window: i64,
+    pub max_chargebacks: Option<u64>,
+    pub max_chargeback_value: Option<Decimal>,
+    pub webhook: Option<Url>,
+    pub mail: Option<Mail>,
+    pub timeout: Duration,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            window: 3_600,
+            max_chargebacks: None,
+            max_chargeback_value: None,
+            webhook: None,
+            mail: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}' for alert option {}", value, key))
+}
+
+impl AlertConfig {
+    /// Sets one option by name. Mail options build up the relay and envelope.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mail = || Mail { server: String::new(), from: String::new(), to: Vec::new() };
+        match key {
+            "window" => self.window = time::parse_duration(value).filter(|window| *window > 0)
+                .ok_or_else(|| format!("invalid alert window '{}'", value))?,
+            "max_chargebacks" => self.max_chargebacks = Some(parse(key, value)?),
+            "max_chargeback_value" => self.max_chargeback_value = Some(parse(key, value)?),
+            "webhook" => self.webhook = Some(value.parse()?),
+            "smtp_server" => self.mail.get_or_insert_with(mail).server = value.to_string(),
+            "smtp_from" => self.mail.get_or_insert_with(mail).from = value.to_string(),
+            "smtp_to" => self.mail.get_or_insert_with(mail).to = value.split(',').map(|to| to.trim().to_string()).collect(),
+            "timeout_ms" => self.timeout = Duration::from_millis(parse(key, value)?),
+            other => return Err(format!("unknown alert option '{}'", other)),
+        }
+        Ok(())
+    }
+
+    /// Checks that the options set make a usable configuration.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_chargebacks.is_none() && self.max_chargeback_value.is_none() {
+            return Err("[alerts] needs max_chargebacks or max_chargeback_value".to_string());
+        }
+        if self.webhook.is_none() && self.mail.is_none() {
+            return Err("[alerts] needs a webhook or smtp_server".to_string());
+        }
+        if let Some(mail) = &self.mail {
+            if mail.server.is_empty() || mail.from.is_empty() || mail.to.is_empty() {
+                return Err("[alerts] email needs smtp_server, smtp_from and smtp_to".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A window that crossed a threshold.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Spike {
+    pub chargebacks: u64,
+    pub value: Decimal,
+    pub window_secs: i64,
+}
+
+/// The sliding-window arithmetic, separate from delivery.
+#[derive(Debug)]
+pub struct ChargebackMonitor {
+    window: i64,
+    max_count: Option<u64>,
+    max_value: Option<Decimal>,
+    recent: VecDeque<(i64, Decimal)>,
+    value: Decimal,
+    firing: bool,
+}
+
+impl ChargebackMonitor {
+    pub fn new(config: &AlertConfig) -> Self {
+        ChargebackMonitor {
+            window: config.window,
+            max_count: config.max_chargebacks,
+            max_value: config.max_chargeback_value,
+            recent: VecDeque::new(),
+            value: Decimal::ZERO,
+            firing: false,
+        }
+    }
+
+    /// Adds a chargeback at Unix time `at`; returns a spike the first time
+    /// the window goes over a threshold.
+    pub fn observe(&mut self, at: i64, amount: Decimal) -> Option<Spike> {
+        self.recent.push_back((at, amount));
+        self.value += amount;
+        while let Some((oldest, amount)) = self.recent.front().copied() {
+            if oldest > at - self.window {
+                break;
+            }
+            self.recent.pop_front();
+            self.value -= amount;
+        }
+
+        let count = self.recent.len() as u64;
+        let over = self.max_count.is_some_and(|max| count > max) || self.max_value.is_some_and(|max| self.value > max);
+        let fire = over && !self.firing;
+        self.firing = over;
+        fire.then_some(Spike { chargebacks: count, value: self.value, window_secs: self.window })
+    }
+}
+
+/// Sends alerts from a background thread; hand [`Alerter::observer`] to the
+/// engine and call [`Alerter::finish`] at the end of the run.
+pub struct Alerter {
+    queue: mpsc::Sender<Option<Spike>>,
+    worker: JoinHandle<u64>,
+    config: AlertConfig,
+}
+
+impl Alerter {
+    pub fn start(config: AlertConfig, logger: Logger) -> Self {
+        let (queue, spikes) = mpsc::channel::<Option<Spike>>();
+        let thresholds = config.clone();
+        let worker = thread::spawn(move || {
+            let mut sent = 0;
+            while let Ok(Some(spike)) = spikes.recv() {
+                let text = format!(
+                    "{} chargebacks totalling {} within {}s",
+                    spike.chargebacks, spike.value, spike.window_secs
+                );
+                logger.log(LogEvent::new(Level::Warn, "alert").reason(&text));
+                if let Some(url) = &config.webhook {
+                    let body = serde_json::json!({ "event": "chargeback-spike", "spike": spike }).to_string();
+                    match http::post(url, "application/json", body.as_bytes(), config.timeout) {
+                        Ok(status) if (200..300).contains(&status) => sent += 1,
+                        Ok(status) => logger.log(LogEvent::new(Level::Warn, "alert").reason(format!("{}: HTTP {}", url, status))),
+                        Err(err) => logger.log(LogEvent::new(Level::Warn, "alert").reason(format!("{}: {}", url, err))),
+                    }
+                }
+                if let Some(mail) = &config.mail {
+                    match mail.send("txflow: chargeback spike", &text, config.timeout) {
+                        Ok(()) => sent += 1,
+                        Err(err) => logger.log(LogEvent::new(Level::Warn, "alert").reason(format!("{}: {}", mail.server, err))),
+                    }
+                }
+            }
+            sent
+        });
+        Alerter { queue, worker, config: thresholds }
+    }
+
+    /// The engine-side half, with its own window.
+    pub fn observer(&self) -> AlertObserver {
+        AlertObserver { queue: self.queue.clone(), monitor: ChargebackMonitor::new(&self.config) }
+    }
+
+    /// Waits for queued alerts to go out; returns how many notifications were delivered.
+    pub fn finish(self) -> u64 {
+        let _ = self.queue.send(None);
+        self.worker.join().unwrap_or_default()
+    }
+}
+
+/// Feeds chargebacks into the monitor; rows without a timestamp count at
+/// wall-clock time, and the deterministic build leaves them out.
+pub struct AlertObserver {
+    queue: mpsc::Sender<Option<Spike>>,
+    monitor: ChargebackMonitor,
+}
+
+impl EngineObserver for AlertObserver {
+    fn on_chargeback(&mut self, transaction: &Transaction, amount: Decimal, _account: &Account) {
+        let Some(at) = transaction.timestamp.or_else(|| collections::now("an alert on rows without a timestamp").ok()) else { return };
+        if let Some(spike) = self.monitor.observe(at, amount) {
+            let _ = self.queue.send(Some(spike));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_monitor_fires_once_per_spike() {
+        let config = AlertConfig { window: 60, max_chargebacks: Some(2), ..Default::default() };
+        let mut monitor = ChargebackMonitor::new(&config);
+        assert_eq!(monitor.observe(0, dec!(1)), None);
+        assert_eq!(monitor.observe(10, dec!(1)), None);
+        assert_eq!(monitor.observe(20, dec!(1)), Some(Spike { chargebacks: 3, value: dec!(3), window_secs: 60 }));
+        assert_eq!(monitor.observe(30, dec!(1)), None);
+        // 0, 10 and 20 have left the window; the monitor re-arms, then fires again.
+        assert_eq!(monitor.observe(85, dec!(1)), None);
+        assert!(monitor.observe(86, dec!(1)).is_some());
+    }
+
+    #[test]
+    fn test_monitor_value_threshold() {
+        let config = AlertConfig { max_chargeback_value: Some(dec!(100)), ..Default::default() };
+        let mut monitor = ChargebackMonitor::new(&config);
+        assert_eq!(monitor.observe(0, dec!(60)), None);
+        assert_eq!(monitor.observe(1, dec!(41)).map(|spike| spike.value), Some(dec!(101)));
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let mut config = AlertConfig::default();
+        config.set("window", "15m").unwrap();
+        assert_eq!(config.window, 900);
+        config.set("max_chargebacks", "5").unwrap();
+        assert!(config.validate().unwrap_err().contains("webhook or smtp_server"));
+        config.set("smtp_server", "localhost:25").unwrap();
+        assert!(config.validate().is_err());
+        config.set("smtp_from", "txflow@ops").unwrap();
+        config.set("smtp_to", "a@ops, b@ops").unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(config.mail.unwrap().to, ["a@ops", "b@ops"]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Anchoring: every so many rows or seconds, and at the end of each input,
+//! a record of the state's fingerprint is published outside txflow, in a
+//! file or to an HTTP endpoint. The record also holds the Merkle root of
+//! the rows applied, when one is kept. Records are chained like the audit
+//! log, each carrying the hash of the one before. A file of them can be
+//! checked with `verify-audit`, and the records give an external timeline
+//! to hold saved state against.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use serde::Serialize;
+
+use crate::{
+    admin::AuditLog,
+    error::Error,
+    http::{self, Url},
+    merkle::MerkleTree,
+    snapshot::SnapshotEvery,
+    store::StateStore,
+    time, webhook, Engine,
+};
+
+/// Where anchors go: `http://host/path`, or a file, optionally as `file:PATH`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnchorTarget {
+    File(PathBuf),
+    Http(Url),
+}
+
+impl FromStr for AnchorTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains("://") {
+            return Ok(AnchorTarget::Http(s.parse()?));
+        }
+        match s.strip_prefix("file:").unwrap_or(s) {
+            "" => Err("anchor target needs a file or an http:// URL".to_string()),
+            path => Ok(AnchorTarget::File(path.into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnchorConfig {
+    pub target: AnchorTarget,
+    /// Also anchor this often during an input, not only at its end.
+    pub every: Option<SnapshotEvery>,
+}
+
+#[derive(Serialize)]
+struct Anchor {
+    /// Left out by the deterministic build.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<String>,
+    rows: u64,
+    accounts: usize,
+    fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merkle_root: Option<String>,
+}
+
+/// Posts each flushed record as one request, retrying like the webhooks do.
+struct Poster {
+    url: Url,
+    pending: Vec<u8>,
+}
+
+impl Write for Poster {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let body = std::mem::take(&mut self.pending);
+        let post = || match http::post(&self.url, "application/json", &body, Duration::from_secs(10)) {
+            Ok(status) if (200..300).contains(&status) => Ok(()),
+            Ok(status) => Err(format!("HTTP {}", status)),
+            Err(err) => Err(err.to_string()),
+        };
+        webhook::deliver(post, 3, Duration::from_millis(500), thread::sleep)
+            .map_err(|err| io::Error::other(format!("anchoring to {}: {}", self.url, err)))
+    }
+}
+
+/// Publishes anchors as they come due.
+pub struct Anchors {
+    log: AuditLog,
+    every: Option<SnapshotEvery>,
+    last: Instant,
+    published: u64,
+}
+
+impl Anchors {
+    /// Opens the target; a file continues the chain it already holds.
+    pub fn open(config: &AnchorConfig) -> Result<Self, Error> {
+        let log = match &config.target {
+            AnchorTarget::File(path) => AuditLog::open(path)?,
+            AnchorTarget::Http(url) => AuditLog::new(Box::new(Poster { url: url.clone(), pending: Vec::new() })),
+        };
+        Ok(Anchors { log, every: config.every, last: Instant::now(), published: 0 })
+    }
+
+    /// Whether an anchor is due after `rows` rows of the current input.
+    pub fn due(&self, rows: u64) -> bool {
+        match self.every {
+            Some(SnapshotEvery::Rows(every)) => rows > 0 && rows.is_multiple_of(every),
+            Some(SnapshotEvery::Interval(every)) => self.last.elapsed() >= every,
+            None => false,
+        }
+    }
+
+    pub fn publish<S: StateStore>(&mut self, engine: &Engine<S>, rows: u64, merkle: Option<&MerkleTree>) -> Result<(), Error> {
+        let time = (!cfg!(feature = "deterministic")).then(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64);
+            time::format_timestamp(now)
+        });
+        self.log.record(&Anchor {
+            time,
+            rows,
+            accounts: engine.len()?,
+            fingerprint: engine.fingerprint()?,
+            merkle_root: merkle.map(MerkleTree::root),
+        })?;
+        self.last = Instant::now();
+        self.published += 1;
+        Ok(())
+    }
+
+    pub fn published(&self) -> u64 {
+        self.published
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        admin,
+        logging::Logger,
+        processor::{self, Options, Sinks},
+    };
+    use std::fs;
+
+    #[test]
+    fn test_anchors_form_a_verifiable_chain() {
+        assert_eq!("file:anchors.jsonl".parse(), Ok(AnchorTarget::File("anchors.jsonl".into())));
+        assert!(matches!("http://127.0.0.1:9/anchors".parse(), Ok(AnchorTarget::Http(_))));
+
+        let path = std::env::temp_dir().join(format!("txflow-anchors-{}.jsonl", std::process::id()));
+        let config = AnchorConfig { target: AnchorTarget::File(path.clone()), every: Some(SnapshotEvery::Rows(2)) };
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\nwithdrawal,1,3,4\n";
+        for _ in 0..2 {
+            let mut engine = Engine::new();
+            let mut sinks = Sinks { anchors: Some(Anchors::open(&config).unwrap()), merkle: Some(MerkleTree::default()), ..Default::default() };
+            processor::process_csv(input.as_bytes(), &mut engine, &mut Vec::new(), &mut sinks, &Options::default(), &Logger::default()).unwrap();
+            assert_eq!(sinks.anchors.unwrap().published(), 2);
+        }
+
+        let log = fs::read_to_string(&path).unwrap();
+        let head = admin::Head::read(&admin::head_path(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(admin::head_path(&path)).unwrap();
+        assert_eq!(admin::verify(log.as_bytes(), Some(&head)).unwrap().entries, 4);
+        let last: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+        assert_eq!((last["rows"].as_u64(), last["accounts"].as_u64()), (Some(3), Some(2)));
+        assert!(last["merkle_root"].is_string() && last["fingerprint"].is_string());
+    }
+}
@@ -0,0 +1,162 @@
+//! Turns a transactions file into one safe to attach to a bug report.
+//! Client and transaction ids are renumbered in order of first appearance,
+//! with every reference following its target, so disputes still land on the
+//! deposits they named. Amounts are scaled by a factor drawn per client
+//! within `1 ± spread`: a client's balance comparisons, and with them which
+//! withdrawals and disputes apply, come out the same up to rounding to four
+//! places. Columns txflow does not read are dropped, since free text is
+//! where personal data hides; timestamps are kept.
+
+use std::{collections::HashMap, hash::BuildHasher, io};
+use rust_decimal::Decimal;
+
+use crate::{
+    collections,
+    error::Error,
+    parse::AmountFormat,
+    source::{CsvSource, TxSource},
+    time, ClientId, TxId,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnonymizeConfig {
+    /// Drives the amount factors; a fresh one each run when unset.
+    pub seed: Option<u64>,
+    /// Largest relative change to an amount, e.g. `0.1` for ±10%.
+    pub spread: Decimal,
+}
+
+impl Default for AnonymizeConfig {
+    fn default() -> Self {
+        AnonymizeConfig { seed: None, spread: Decimal::new(1, 1) }
+    }
+}
+
+/// What an anonymized copy holds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Anonymized {
+    pub rows: u64,
+    /// Rows that did not parse and were left out.
+    pub skipped: u64,
+    pub clients: usize,
+}
+
+/// Hands out 1, 2, 3... to ids in the order they are first seen.
+struct Renumber(HashMap<u32, u32>);
+
+impl Renumber {
+    fn get(&mut self, id: u32) -> u32 {
+        let next = self.0.len() as u32 + 1;
+        *self.0.entry(id).or_insert(next)
+    }
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// `1 ± spread`, in steps of a ten-thousandth of the spread.
+fn factor(seed: u64, client: u32, spread: Decimal) -> Decimal {
+    let step = (mix(seed ^ mix(client as u64)) % 20_001) as i64 - 10_000;
+    Decimal::ONE + spread * Decimal::new(step, 4)
+}
+
+/// Writes an anonymized copy of `input` to `output` as txflow CSV.
+pub fn anonymize(input: impl io::Read, output: impl io::Write, config: &AnonymizeConfig, amounts: &AmountFormat) -> Result<Anonymized, Error> {
+    let seed = config.seed.unwrap_or_else(|| collections::Hasher::default().hash_one(0u8));
+    let mut source = CsvSource::new(input, amounts)?;
+    let times = (source.columns().has_timestamp(), source.columns().has_recorded());
+    let mut writer = csv::Writer::from_writer(output);
+    let mut header = vec!["type", "client", "tx", "amount"];
+    header.extend(times.0.then_some("timestamp"));
+    header.extend(times.1.then_some("recorded_at"));
+    writer.write_record(&header)?;
+
+    let (mut clients, mut txs) = (Renumber(HashMap::new()), Renumber(HashMap::new()));
+    let mut anonymized = Anonymized::default();
+    loop {
+        let row = match source.next_row() {
+            Ok(Some(row)) => row,
+            Ok(None) => break,
+            Err(Error::Parse(_)) => {
+                anonymized.skipped += 1;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        let transaction = row.transaction;
+        let client = ClientId(clients.get(transaction.client.0));
+        let tx = TxId(txs.get(transaction.tx.0));
+        let amount = transaction.amount.map(|amount| {
+            let scaled = (amount * factor(seed, client.0, config.spread)).round_dp(4);
+            if amount > Decimal::ZERO { scaled.max(Decimal::new(1, 4)) } else { scaled }
+        });
+        let mut record = vec![
+            transaction.tx_type.name().to_string(),
+            client.0.to_string(),
+            tx.0.to_string(),
+            amount.map(|amount| amount.to_string()).unwrap_or_default(),
+        ];
+        let format = |time: Option<i64>| time.map(time::format_timestamp).unwrap_or_default();
+        record.extend(times.0.then(|| format(transaction.timestamp)));
+        record.extend(times.1.then(|| format(transaction.recorded)));
+        writer.write_record(&record)?;
+        anonymized.rows += 1;
+    }
+    writer.flush()?;
+    anonymized.clients = clients.0.len();
+    Ok(anonymized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logging::Logger,
+        processor::{self, Options, Sinks},
+        Engine,
+    };
+
+    const INPUT: &str = concat!(
+        "type,client,tx,amount,merchant\n",
+        "deposit,907,5001,100,Acme Ltd\n",
+        "deposit,44,5002,20,\n",
+        "withdrawal,907,5003,100,\n",
+        "dispute,44,5002,,\n",
+        "deposit,907,5004,30,\n",
+        "withdrawal,907,5005,30.0001,\n",
+        "dispute,907,5004,,\n",
+        "chargeback,907,5004,,\n",
+    );
+
+    fn run(input: &[u8]) -> Vec<(bool, bool)> {
+        let mut engine = Engine::new();
+        let options = Options { lenient: true, ..Default::default() };
+        processor::process_csv(input, &mut engine, &mut Vec::new(), &mut Sinks::default(), &options, &Logger::default()).unwrap();
+        let mut outcomes: Vec<_> = engine.reports().unwrap().map(|report| (report.held > Decimal::ZERO, report.locked)).collect();
+        outcomes.sort();
+        outcomes
+    }
+
+    #[test]
+    fn test_renumbers_ids_and_keeps_outcomes() {
+        let config = AnonymizeConfig { seed: Some(7), spread: Decimal::new(2, 1) };
+        let mut output = Vec::new();
+        let anonymized = anonymize(INPUT.as_bytes(), &mut output, &config, &AmountFormat::default()).unwrap();
+        assert_eq!((anonymized.rows, anonymized.clients), (8, 2));
+
+        let text = String::from_utf8(output.clone()).unwrap();
+        let rows: Vec<Vec<&str>> = text.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows[0], ["type", "client", "tx", "amount"]);
+        assert_eq!(rows[1][..3], ["deposit", "1", "1"]);
+        assert_eq!(rows[4][..3], ["dispute", "2", "2"]);
+        assert_eq!(rows[8][..3], ["chargeback", "1", "4"]);
+        let first: Decimal = rows[1][3].parse().unwrap();
+        assert!(first >= Decimal::from(80) && first <= Decimal::from(120), "{}", first);
+        assert!(!text.contains("Acme"));
+
+        assert_eq!(run(&output), run(INPUT.as_bytes()));
+    }
+}
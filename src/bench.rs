@@ -0,0 +1,224 @@
+//! Synthetic load generation for measuring engine throughput on the
+//! hardware at hand.
+
+use std::{fmt, io::{self, Read}, time::{Duration, Instant}};
+use rust_decimal::Decimal;
+
+use crate::{
+    logging::Logger,
+    processor::{self, Options, Sinks},
+    report::ReportWriter,
+    ClientId, Engine, Error, Transaction, TxId, TxType,
+};
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub rows: u64,
+    pub clients: u32,
+    pub seed: u64,
+    /// Render rows as CSV and push them through the parser instead of
+    /// handing transactions straight to the engine.
+    pub parse: bool,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig { rows: 1_000_000, clients: 10_000, seed: 0, parse: false }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub rows: u64,
+    pub applied: u64,
+    pub accounts: usize,
+    pub elapsed: Duration,
+    /// Peak resident set size of the process, where the platform exposes it.
+    pub peak_rss_bytes: Option<u64>,
+}
+
+impl BenchReport {
+    pub fn rows_per_sec(&self) -> f64 {
+        self.rows as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rows:        {}", self.rows)?;
+        writeln!(f, "applied:     {}", self.applied)?;
+        writeln!(f, "accounts:    {}", self.accounts)?;
+        writeln!(f, "elapsed:     {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "throughput:  {:.0} rows/s", self.rows_per_sec())?;
+        match self.peak_rss_bytes {
+            Some(bytes) => write!(f, "peak rss:    {:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+            None => write!(f, "peak rss:    unavailable"),
+        }
+    }
+}
+
+/// Deterministic transaction stream: mostly deposits and withdrawals with a
+/// sprinkling of disputes, resolves and chargebacks against earlier rows.
+pub struct Generator {
+    state: u64,
+    seed: u64,
+    clients: u32,
+    next_tx: u32,
+    remaining: u64,
+}
+
+impl Generator {
+    pub fn new(config: &BenchConfig) -> Self {
+        Generator { state: config.seed, seed: config.seed, clients: config.clients.max(1), next_tx: 1, remaining: config.rows }
+    }
+
+    /// splitmix64: tiny, fast and good enough to spread load across clients.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    /// The client owning a transaction id, so later rows can refer back to it.
+    fn client_of(&self, tx: u32) -> ClientId {
+        ClientId((mix(self.seed ^ tx as u64) % self.clients as u64) as u32 + 1)
+    }
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Iterator for Generator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let roll = self.next_u64() % 100;
+        let amount = Some(Decimal::new((self.next_u64() % 1_000_000) as i64, 4));
+
+        let tx = self.next_tx;
+        self.next_tx = self.next_tx.wrapping_add(1);
+        let (tx_type, target) = match roll {
+            0..=59 => (TxType::Deposit, tx),
+            60..=89 => (TxType::Withdrawal, tx),
+            _ if tx == 1 => (TxType::Deposit, tx),
+            90..=94 => (TxType::Dispute, (self.next_u64() % (tx as u64 - 1)) as u32 + 1),
+            95..=97 => (TxType::Resolve, (self.next_u64() % (tx as u64 - 1)) as u32 + 1),
+            _ => (TxType::Chargeback, (self.next_u64() % (tx as u64 - 1)) as u32 + 1),
+        };
+        let amount = if matches!(tx_type, TxType::Deposit | TxType::Withdrawal) { amount } else { None };
+        Some(Transaction::new(tx_type, self.client_of(target), TxId(target), amount))
+    }
+}
+
+/// Renders a [`Generator`] as CSV on the fly so the parser can be measured
+/// without materializing the whole input.
+pub struct GeneratedCsv {
+    rows: Generator,
+    buffer: Vec<u8>,
+    offset: usize,
+}
+
+impl GeneratedCsv {
+    pub fn new(rows: Generator) -> Self {
+        GeneratedCsv { rows, buffer: b"type,client,tx,amount\n".to_vec(), offset: 0 }
+    }
+}
+
+impl Read for GeneratedCsv {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.buffer.len() {
+            self.buffer.clear();
+            self.offset = 0;
+            for row in self.rows.by_ref().take(1024) {
+                let tx_type = format!("{:?}", row.tx_type).to_lowercase();
+                let amount = row.amount.map(|amount| amount.to_string()).unwrap_or_default();
+                self.buffer.extend_from_slice(format!("{},{},{},{}\n", tx_type, row.client.0, row.tx.0, amount).as_bytes());
+            }
+        }
+        let n = (self.buffer.len() - self.offset).min(buf.len());
+        buf[..n].copy_from_slice(&self.buffer[self.offset..self.offset + n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+pub fn run(config: &BenchConfig, logger: &Logger) -> Result<BenchReport, Error> {
+    let mut engine = Engine::new();
+    let start = Instant::now();
+
+    let applied = if config.parse {
+        let input = GeneratedCsv::new(Generator::new(config));
+        let mut report = ReportWriter::new(io::sink());
+        processor::process_csv(input, &mut engine, &mut report, &mut Sinks::default(), &Options::default(), logger)?
+            .applied
+    } else {
+        Generator::new(config).filter(|row| engine.apply(row).is_ok()).count() as u64
+    };
+
+    Ok(BenchReport {
+        rows: config.rows,
+        applied,
+        accounts: engine.len()?,
+        elapsed: start.elapsed(),
+        peak_rss_bytes: peak_rss_bytes(),
+    })
+}
+
+/// Reads the high-water mark of resident memory from `/proc` on Linux.
+fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Parses counts like `50M`, `1.5k` or `2000`.
+pub fn parse_count(raw: &str) -> Result<u64, String> {
+    let (number, scale) = match raw.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&raw[..raw.len() - 1], 1e3),
+        Some('M') => (&raw[..raw.len() - 1], 1e6),
+        Some('G') => (&raw[..raw.len() - 1], 1e9),
+        _ => (raw, 1.0),
+    };
+    let number: f64 = number.replace('_', "").parse().map_err(|_| format!("invalid count '{}'", raw))?;
+    if number < 0.0 {
+        return Err(format!("invalid count '{}'", raw));
+    }
+    Ok((number * scale).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_count_suffixes() {
+        assert_eq!(parse_count("50M"), Ok(50_000_000));
+        assert_eq!(parse_count("1.5k"), Ok(1_500));
+        assert_eq!(parse_count("2_000"), Ok(2_000));
+        assert!(parse_count("lots").is_err());
+    }
+
+    #[test]
+    fn test_generator_is_deterministic() {
+        let config = BenchConfig { rows: 100, clients: 5, ..Default::default() };
+        let first: Vec<_> = Generator::new(&config).map(|row| (row.tx_type, row.client, row.tx)).collect();
+        let second: Vec<_> = Generator::new(&config).map(|row| (row.tx_type, row.client, row.tx)).collect();
+        assert_eq!(first.len(), 100);
+        assert_eq!(first, second);
+        assert!(first.iter().all(|(_, client, _)| (1..=5).contains(&client.0)));
+    }
+
+    #[test]
+    fn test_parse_and_direct_modes_agree() {
+        let direct = run(&BenchConfig { rows: 500, clients: 7, ..Default::default() }, &Logger::default()).unwrap();
+        let parsed = run(&BenchConfig { rows: 500, clients: 7, parse: true, ..Default::default() }, &Logger::default()).unwrap();
+        assert_eq!((direct.applied, direct.accounts), (parsed.applied, parsed.accounts));
+    }
+}
@@ -0,0 +1,86 @@
+//! A Bloom filter over transaction ids: a compact set that may answer
+//! "maybe" for an id never inserted, but never "no" for one that was. It
+//! lets dispute-flow rows for ids no deposit ever used be refused without
+//! loading the account they name.
+
+use crate::TxId;
+
+/// Bits per expected id and probes per lookup for about a 1% false-positive rate.
+const BITS_PER_ID: usize = 10;
+const PROBES: u64 = 7;
+
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    /// Ids inserted, to tell when the filter is past its capacity.
+    len: usize,
+    capacity: usize,
+}
+
+impl BloomFilter {
+    /// A filter sized for `capacity` ids at about a 1% false-positive rate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(1024);
+        BloomFilter { bits: vec![0; (capacity * BITS_PER_ID).div_ceil(64)], len: 0, capacity }
+    }
+
+    pub fn insert(&mut self, tx: TxId) {
+        let slots = self.bits.len() as u64 * 64;
+        for probe in probes(tx, slots) {
+            self.bits[(probe / 64) as usize] |= 1 << (probe % 64);
+        }
+        self.len += 1;
+    }
+
+    /// `false` only if `tx` was never inserted.
+    pub fn may_contain(&self, tx: TxId) -> bool {
+        let slots = self.bits.len() as u64 * 64;
+        probes(tx, slots).all(|probe| self.bits[(probe / 64) as usize] & (1 << (probe % 64)) != 0)
+    }
+
+    /// Whether more ids went in than the filter was sized for, so its
+    /// false-positive rate has started to climb.
+    pub fn is_full(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+/// The bit positions for `tx`, by double hashing.
+fn probes(tx: TxId, slots: u64) -> impl Iterator<Item = u64> {
+    let first = mix(u64::from(tx.0));
+    let step = mix(first) | 1;
+    (0..PROBES).map(move |probe| first.wrapping_add(probe.wrapping_mul(step)) % slots)
+}
+
+/// SplitMix64's finalizer: spreads nearby ids across the whole range.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives_and_few_false_positives() {
+        let mut filter = BloomFilter::with_capacity(10_000);
+        for tx in (0..10_000).map(|n| TxId(n * 2)) {
+            filter.insert(tx);
+        }
+        assert!((0..10_000).all(|n| filter.may_contain(TxId(n * 2))));
+        let false_positives = (0..10_000).filter(|n| filter.may_contain(TxId(n * 2 + 1))).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+        assert!(!filter.is_full());
+    }
+}
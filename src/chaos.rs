@@ -0,0 +1,148 @@
+//! Seeded fault injection for input streams, for rehearsing how a deployment
+//! copes with broken vendor files and flaky storage before it meets them.
+
+use std::io::{self, BufRead, BufReader, Read};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Per-row probabilities of each fault; the same seed always produces the
+/// same faults for the same input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    pub io_error_rate: f64,
+    pub truncate_rate: f64,
+    pub duplicate_rate: f64,
+}
+
+impl ChaosConfig {
+    pub fn new(seed: u64) -> Self {
+        ChaosConfig { seed, io_error_rate: 0.001, truncate_rate: 0.001, duplicate_rate: 0.001 }
+    }
+}
+
+/// How many faults of each kind were injected so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub io_errors: u64,
+    pub truncated: u64,
+    pub duplicated: u64,
+}
+
+/// Wraps a line-oriented input and corrupts data rows as it is read. The
+/// first line is treated as the header and always passed through intact.
+pub struct ChaosReader<R> {
+    inner: BufReader<R>,
+    rng: StdRng,
+    config: ChaosConfig,
+    pending: Vec<u8>,
+    offset: usize,
+    header_read: bool,
+    stats: ChaosStats,
+}
+
+impl<R: Read> ChaosReader<R> {
+    pub fn new(inner: R, config: ChaosConfig) -> Self {
+        ChaosReader {
+            inner: BufReader::new(inner),
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            pending: Vec::new(),
+            offset: 0,
+            header_read: false,
+            stats: ChaosStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ChaosStats {
+        self.stats
+    }
+
+    /// Reads the next line and decides its fate. Returns `Ok(false)` at end of input.
+    fn refill(&mut self) -> io::Result<bool> {
+        let mut line = Vec::new();
+        if self.inner.read_until(b'\n', &mut line)? == 0 {
+            return Ok(false);
+        }
+        self.pending.clear();
+        self.offset = 0;
+
+        if !self.header_read {
+            self.header_read = true;
+            self.pending = line;
+            return Ok(true);
+        }
+
+        if self.rng.gen_bool(self.config.io_error_rate) {
+            self.stats.io_errors += 1;
+            return Err(io::Error::other("chaos: injected I/O error"));
+        }
+        if self.rng.gen_bool(self.config.truncate_rate) && line.len() > 2 {
+            self.stats.truncated += 1;
+            let cut = self.rng.gen_range(1..line.len() - 1);
+            line.truncate(cut);
+            line.push(b'\n');
+        }
+        if self.rng.gen_bool(self.config.duplicate_rate) {
+            self.stats.duplicated += 1;
+            self.pending.extend_from_slice(&line);
+        }
+        self.pending.extend_from_slice(&line);
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for ChaosReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.pending.len() && !self.refill()? {
+            return Ok(0);
+        }
+        let available = &self.pending[self.offset..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\ndeposit,1,3,3.0\n";
+
+    fn read_all(config: ChaosConfig) -> (io::Result<String>, ChaosStats) {
+        let mut reader = ChaosReader::new(INPUT.as_bytes(), config);
+        let mut out = String::new();
+        let result = reader.read_to_string(&mut out).map(|_| out);
+        (result, reader.stats())
+    }
+
+    #[test]
+    fn test_zero_rates_pass_through() {
+        let config = ChaosConfig { io_error_rate: 0.0, truncate_rate: 0.0, duplicate_rate: 0.0, ..ChaosConfig::new(1) };
+        assert_eq!(read_all(config).0.unwrap(), INPUT);
+    }
+
+    #[test]
+    fn test_duplicates_keep_header_intact() {
+        let config = ChaosConfig { io_error_rate: 0.0, truncate_rate: 0.0, duplicate_rate: 1.0, ..ChaosConfig::new(1) };
+        let (out, stats) = read_all(config);
+        let out = out.unwrap();
+        assert!(out.starts_with("type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,1,1.0\n"));
+        assert_eq!(stats.duplicated, 3);
+    }
+
+    #[test]
+    fn test_io_error_injected() {
+        let config = ChaosConfig { io_error_rate: 1.0, ..ChaosConfig::new(1) };
+        let (out, stats) = read_all(config);
+        assert!(out.is_err());
+        assert_eq!(stats.io_errors, 1);
+    }
+
+    #[test]
+    fn test_same_seed_same_faults() {
+        let config = ChaosConfig { io_error_rate: 0.0, truncate_rate: 0.5, duplicate_rate: 0.5, ..ChaosConfig::new(42) };
+        assert_eq!(read_all(config.clone()).0.unwrap(), read_all(config).0.unwrap());
+    }
+}
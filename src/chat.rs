@@ -0,0 +1,83 @@
+//! Posts a short end-of-run summary to a Slack-compatible incoming webhook
+//! (`{"text": ...}`), so a run's outcome shows up where operations already
+//! look.
+
+use std::{io, str::FromStr, time::Duration};
+use serde::Serialize;
+
+use crate::{http::{self, Url}, processor::Summary, ClientId};
+
+/// Where to post, from the policy file's `[chat]` section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatConfig {
+    pub webhook: Url,
+    /// Overrides the webhook's default channel where the service allows it.
+    pub channel: Option<String>,
+    pub timeout: Duration,
+}
+
+impl ChatConfig {
+    pub fn new(webhook: Url) -> Self {
+        ChatConfig { webhook, channel: None, timeout: Duration::from_secs(10) }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "webhook" => self.webhook = value.parse()?,
+            "channel" => self.channel = Some(value.to_string()),
+            "timeout_ms" => self.timeout = Duration::from_millis(
+                u64::from_str(value).map_err(|_| format!("invalid value '{}' for chat timeout_ms", value))?,
+            ),
+            other => return Err(format!("unknown chat option '{}'", other)),
+        }
+        Ok(())
+    }
+}
+
+/// The message text for a finished run.
+pub fn summary_text(summary: &Summary, accounts: usize, newly_locked: &[ClientId]) -> String {
+    let mut text = format!(
+        "txflow run finished: {} rows, {} applied, {} skipped, {} rejected; {} accounts processed ({} finalized).",
+        summary.rows, summary.applied, summary.errors.parse_errors(), summary.errors.rejected(),
+        accounts, summary.finalized
+    );
+    if newly_locked.is_empty() {
+        text.push_str(" No accounts were locked.");
+    } else {
+        let clients: Vec<String> = newly_locked.iter().map(|client| client.0.to_string()).collect();
+        text.push_str(&format!(" Newly locked ({}): {}.", clients.len(), clients.join(", ")));
+    }
+    text
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<&'a str>,
+}
+
+pub fn post(config: &ChatConfig, text: &str) -> io::Result<()> {
+    let body = serde_json::to_vec(&Message { text, channel: config.channel.as_deref() }).map_err(io::Error::from)?;
+    match http::post(&config.webhook, "application/json", &body, config.timeout)? {
+        200..=299 => Ok(()),
+        status => Err(io::Error::other(format!("HTTP {}", status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::ErrorCategory;
+
+    #[test]
+    fn test_summary_text() {
+        let mut summary = Summary { rows: 10, applied: 8, finalized: 1, ..Default::default() };
+        summary.errors.record(ErrorCategory::InvalidField);
+        assert_eq!(
+            summary_text(&summary, 4, &[ClientId(2), ClientId(9)]),
+            "txflow run finished: 10 rows, 8 applied, 1 skipped, 0 rejected; 4 accounts processed (1 finalized). Newly locked (2): 2, 9."
+        );
+        assert!(summary_text(&summary, 4, &[]).ends_with("No accounts were locked."));
+    }
+}
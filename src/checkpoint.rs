@@ -0,0 +1,190 @@
+//! Checkpoints that let a run which died part way pick up where it left
+//! off. Every so many rows the report written so far is flushed, and the
+//! engine's state is saved next to it with the rows consumed and the report's
+//! length at that moment. Resuming truncates the report back to that length,
+//! which drops anything written after the checkpoint including a half
+//! written last line, restores the state and reads past the rows it covers,
+//! or seeks past them when the source can. A source that can seek is told
+//! once each checkpoint is on disk, so it may acknowledge what it covers
+//! upstream; the checkpoint, not the acknowledgement, is where a resumed
+//! run starts, so a crash between the two applies no row twice.
+//! Policy limits that count over a window start afresh on resume.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::Error,
+    report::{AccountReport, ReportSink},
+    state::{self, ResumePoint},
+    store::StateStore,
+    ClientId, Engine,
+};
+
+/// Where the checkpoint for a report at `output` lives.
+pub fn path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".checkpoint");
+    PathBuf::from(path)
+}
+
+/// Writes checkpoints of a run as they come due.
+pub struct Checkpoints {
+    path: PathBuf,
+    /// A handle on the report file, to measure what has reached it.
+    output: File,
+    every: Option<u64>,
+    /// Rows a resumed run had already consumed before it started.
+    start: u64,
+    /// Where the source stood at the checkpoint resumed from, if it said.
+    position: Option<u64>,
+    written: u64,
+}
+
+impl Checkpoints {
+    pub fn new(path: impl Into<PathBuf>, output: File, every: Option<u64>) -> Self {
+        Checkpoints { path: path.into(), output, every, start: 0, position: None, written: 0 }
+    }
+
+    /// Continues from `point`: its rows are read past rather than applied.
+    pub fn resuming(mut self, point: ResumePoint) -> Self {
+        self.start = point.rows;
+        self.position = point.position;
+        self
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// The source position to seek to on resume, if the checkpoint recorded one.
+    pub fn position(&self) -> Option<u64> {
+        self.position
+    }
+
+    /// Whether a checkpoint is due after `rows` rows.
+    pub fn due(&self, rows: u64) -> bool {
+        self.every.is_some_and(|every| rows > self.start && rows.is_multiple_of(every.max(1)))
+    }
+
+    /// Flushes `report` and saves `engine` as of `rows` rows, with the
+    /// source at `position` if it has one.
+    pub fn write<S: StateStore, P: ReportSink + ?Sized>(&mut self, engine: &Engine<S>, report: &mut P, rows: u64, position: Option<u64>) -> Result<(), Error> {
+        report.finish()?;
+        let output_bytes = self.output.metadata()?.len();
+        state::save_checkpoint(engine, &self.path, ResumePoint { rows, output_bytes, position })?;
+        self.written += 1;
+        Ok(())
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+/// A partial report made ready to append to, and the state to carry on with.
+pub struct Resumed {
+    pub engine: Engine,
+    pub point: ResumePoint,
+    /// The report, cut back to the checkpoint and positioned at its end.
+    pub output: File,
+    /// The last client the kept part of the report lists.
+    pub last_client: Option<ClientId>,
+}
+
+/// Restores the checkpoint at `checkpoint` and cuts the report at `output`
+/// back to what it held then.
+pub fn resume(output: &Path, checkpoint: &Path) -> Result<Resumed, Error> {
+    let (engine, point) = match state::load_checkpoint(checkpoint) {
+        Ok((engine, Some(point))) => (engine, point),
+        Ok((_, None)) => return Err(Error::Resume(format!("{} is a state file, not a checkpoint", checkpoint.display()))),
+        Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::Resume(format!("no checkpoint at {}; was --checkpoint-every given?", checkpoint.display())))
+        }
+        Err(err) => return Err(err),
+    };
+    let mut file = OpenOptions::new().read(true).write(true).open(output)?;
+    let length = file.metadata()?.len();
+    if length < point.output_bytes {
+        return Err(Error::Resume(format!(
+            "{} holds {} bytes, fewer than the {} its checkpoint recorded", output.display(), length, point.output_bytes
+        )));
+    }
+    file.set_len(point.output_bytes)?;
+    let mut kept = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut kept)?;
+    Ok(Resumed { engine, point, output: file, last_client: last_client(&kept) })
+}
+
+/// The client on the last line of a CSV or JSON lines report.
+fn last_client(report: &[u8]) -> Option<ClientId> {
+    let text = std::str::from_utf8(report).ok()?;
+    let line = text.lines().next_back()?;
+    if line.starts_with('{') {
+        return serde_json::from_str::<AccountReport>(line).ok().map(|report| report.client);
+    }
+    line.split(',').next()?.parse().ok().map(ClientId)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logging::Logger,
+        processor::{self, Options, Sinks},
+        report::ReportWriter,
+    };
+    use std::fs;
+
+    const INPUT: &str = concat!(
+        "type,client,tx,amount\n",
+        "deposit,1,1,10\n",
+        "deposit,2,2,5\n",
+        "finalize,1,3,\n",
+        "deposit,3,4,7\n",
+        "finalize,2,5,\n",
+        "withdrawal,3,6,2\n",
+    );
+
+    fn run(output: &Path, checkpoints: Checkpoints, engine: &mut Engine, header: bool) -> Result<(), Error> {
+        let file = OpenOptions::new().append(true).open(output)?;
+        let mut report = if header { ReportWriter::new(file) } else { ReportWriter::continuing(file, Default::default()) };
+        let mut sinks = Sinks { checkpoints: Some(checkpoints), ..Default::default() };
+        processor::process_csv(INPUT.as_bytes(), engine, &mut report, &mut sinks, &Options::default(), &Logger::default())?;
+        for account in engine.reports().unwrap() {
+            report.write(&account)?;
+        }
+        report.finish()
+    }
+
+    #[test]
+    fn test_resume_continues_a_partial_report() {
+        let output = std::env::temp_dir().join(format!("txflow-resume-{}.csv", std::process::id()));
+        let checkpoint = path(&output);
+        fs::write(&output, "").unwrap();
+        let every = Checkpoints::new(&checkpoint, File::open(&output).unwrap(), Some(4));
+        run(&output, every, &mut Engine::new(), true).unwrap();
+        let complete = fs::read_to_string(&output).unwrap();
+
+        // A crash after the second finalize, half way through writing a line.
+        let point = state::load_checkpoint(&checkpoint).unwrap().1.unwrap();
+        assert_eq!(point.rows, 4);
+        fs::write(&output, format!("{}2,5,0,tr", &complete[..point.output_bytes as usize])).unwrap();
+
+        let resumed = resume(&output, &checkpoint).unwrap();
+        assert_eq!(resumed.last_client, Some(ClientId(1)));
+        let mut engine = resumed.engine;
+        let checkpoints = Checkpoints::new(&checkpoint, File::open(&output).unwrap(), Some(4)).resuming(resumed.point);
+        run(&output, checkpoints, &mut engine, false).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), complete);
+
+        fs::write(&output, "client").unwrap();
+        assert!(matches!(resume(&output, &checkpoint), Err(Error::Resume(_))));
+        fs::remove_file(&output).unwrap();
+        fs::remove_file(&checkpoint).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+//! Account classes (e.g. standard, merchant, settlement): named sets of
+//! clients that get their own limits, locked-account policy and allowed
+//! transaction types in place of the policy-wide ones.
+
+use crate::{
+    policy::{limit, Limits, LockedPolicy},
+    ClientId, TxType,
+};
+
+/// One `[classes.NAME]` policy section. Unset fields fall back to the
+/// policy-wide setting.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountClass {
+    pub limits: Limits,
+    pub locked: Option<LockedPolicy>,
+    /// Transaction types the class accepts; `None` accepts every type.
+    pub allowed: Option<Vec<TxType>>,
+    /// Clients the class applies to.
+    pub clients: Vec<ClientId>,
+}
+
+impl AccountClass {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "max_deposit" => self.limits.max_deposit = Some(limit(value).map_err(|err| format!("{}: {}", key, err))?),
+            "max_withdrawal" => self.limits.max_withdrawal = Some(limit(value).map_err(|err| format!("{}: {}", key, err))?),
+            "locked_accounts" => self.locked = Some(value.parse()?),
+            "allowed" => self.allowed = Some(list(value, |item| item.parse())?),
+            "clients" => self.clients = list(value, |item| {
+                item.parse().map(ClientId).map_err(|_| format!("invalid client id '{}'", item))
+            })?,
+            other => return Err(format!("unknown class option '{}'", other)),
+        }
+        Ok(())
+    }
+
+    pub fn allows(&self, tx_type: TxType) -> bool {
+        self.allowed.as_ref().is_none_or(|allowed| allowed.contains(&tx_type))
+    }
+}
+
+/// Parses a comma-separated list, ignoring spaces around items.
+fn list<T>(value: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(parse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_parses_lists() {
+        let mut class = AccountClass::default();
+        class.set("allowed", "deposit, withdrawal").unwrap();
+        class.set("clients", "7,8").unwrap();
+        assert!(class.allows(TxType::Withdrawal));
+        assert!(!class.allows(TxType::Dispute));
+        assert_eq!(class.clients, vec![ClientId(7), ClientId(8)]);
+        assert_eq!(class.set("allowed", "deposit,refund"), Err("unknown transaction type 'refund'".to_string()));
+    }
+}
@@ -0,0 +1,1219 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr, time::Duration};
+
+#[cfg(feature = "chaos")]
+use txflow::chaos::ChaosConfig;
+#[cfg(feature = "otel")]
+use txflow::otel::OtelConfig;
+use txflow::{
+    admin::Action,
+    aggregate::{AggregateFormat, Bucket},
+    anchor::{AnchorConfig, AnchorTarget},
+    anonymize::AnonymizeConfig,
+    bench::{self, BenchConfig},
+    collections,
+    convert::{Format, Schema},
+    crash::Dsn,
+    delta::DeltaFormat,
+    diff::{DiffFormat, DiffOptions},
+    import::ImportFormat,
+    joint::JointOwners,
+    logging::{Level, LogFormat},
+    parallel,
+    policy::Policy,
+    processor::Options,
+    query::AsOf,
+    report::{FlushEvery, LockedRule, OutputFormat},
+    snapshot::SnapshotEvery,
+    statsd::StatsdConfig,
+    submit::{ApiKey, SubmitConfig},
+    ClientId, Error, TxId,
+};
+
+use crate::completions::Shell;
+
+pub const USAGE: &str = "\
+cargo run -- [GLOBAL] [RUN OPTIONS] [--output-format csv|jsonl|xlsx|html] [--output FILE [--fsync]]
+                             [--flush-every ROWS|SIZE] [--sort-by-client [--sort-buffer ROWS] [--spill-dir DIR]]
+                             [--checkpoint-every ROWS] [--resume] (both need --output) [--merkle-root]
+                             transactions.csv|- > accounts.csv
+       cargo run -- [GLOBAL] watch --dir DIR --done DIR --state FILE [--poll SECS] [--once] [--dead-letter FILE]
+                             [--undo-depth ROWS] [--atomic-per-file] [--health-addr ADDR [--max-backlog FILES]]
+                             [--idempotent] [RUN OPTIONS] (a changed --policy file or SIGHUP reloads the policy)
+       cargo run -- [GLOBAL] batch [--jobs N] [RUN OPTIONS] [--output FILE] FILE... > accounts.csv
+       cargo run -- [GLOBAL] revert --state FILE --last ROWS
+       cargo run -- [GLOBAL] admin --state FILE --operator NAME [--audit FILE] --client ID
+                             unlock|resolve --tx ID|adjust --amount AMOUNT|history
+       cargo run -- [GLOBAL] verify-audit [--head FILE] audit.jsonl
+       cargo run -- [GLOBAL] merge [--locked any|all|strict] [--output FILE] REPORT... > accounts.csv
+       cargo run -- [GLOBAL] merge-state --out FILE [--print-fingerprint] STATE...
+       cargo run -- [GLOBAL] bench [--rows N] [--clients N] [--seed N] [--parse]
+       cargo run -- [GLOBAL] graph [--lenient] [--tolerant-amounts] transactions.csv > disputes.dot
+       cargo run -- [GLOBAL] prove --tx ID [RUN OPTIONS] transactions.csv > proof.json
+       cargo run -- [GLOBAL] query [--as-of-tx ID] [--as-known-at TIME] [--as-effective-at TIME] [--client ID]
+                             [RUN OPTIONS] transactions.csv > accounts.csv
+       cargo run -- [GLOBAL] statement --client ID [--from TIME] [--to TIME] [RUN OPTIONS] transactions.csv > statement.txt
+       cargo run -- [GLOBAL] import --format iso20022|mt940|fix|ofx|qif [--client ID] [--first-tx N] FILE > transactions.csv
+       cargo run -- [GLOBAL] anonymize [--seed N] [--spread FRACTION] [RUN OPTIONS] transactions.csv > fixture.csv
+       cargo run -- [GLOBAL] convert [--schema transactions|report] --from csv|jsonl --to csv|jsonl [RUN OPTIONS] FILE|- > converted
+       cargo run -- [GLOBAL] stats [RUN OPTIONS] transactions.csv
+       cargo run -- [GLOBAL] validate [--report FILE] [RUN OPTIONS] transactions.csv > findings.json
+       cargo run -- [GLOBAL] diff [--tolerance AMOUNT] [--ignore-order] [--format text|jsonl] expected.csv actual.csv
+       cargo run -- [GLOBAL] completions bash|zsh|fish
+       cargo run -- [GLOBAL] manpage > txflow.1
+       cargo run -- [GLOBAL] submit --endpoint URL [--batch-rows N] [--retries N] [--timeout SECS]
+                             [--api-key-file FILE] transactions.csv|-
+
+GLOBAL:      [-v|-vv|-q] [--log-level LEVEL] [--log-format text|json] [--crash-reports DSN]
+RUN OPTIONS: [--policy FILE] [--lenient] [--tolerant-amounts] [--decimal-separator C] [--thousands-separator C]
+             [--error-samples N] [--max-errors N] [--max-error-rate FRACTION] [--stats-every SECS] [--max-memory SIZE]
+             [--retention keep-all|keep-last:N|keep-within:DURATION|keep-undisputed-only]
+             [--require-order tx|timestamp [--reorder-window ROWS]] [--unique-tx-ids] [--escrow-account ID]
+             [--chargeback-fee AMOUNT] [--loss-account ID] [--auto-resolve DURATION] [--provisional-credit]
+             [--max-cycles N] [--dispute-filter] [--wallets] [--joint-owners FILE]
+             [--deltas FILE [--delta-every ROWS] [--delta-format csv|jsonl]] [--declines FILE] [--frozen FILE]
+             [--dead-letter FILE] [--sar FILE] [--sar-rules KEY=VALUE,...] [--settlement FILE]
+             [--aggregates FILE [--bucket day|week|month] [--aggregate-format csv|jsonl]]
+             [--top-clients FILE [--top N]] [--snapshot-every ROWS|DURATION [--snapshot-dir DIR]]
+             [--print-fingerprint] [--anchor FILE|URL [--anchor-every ROWS|DURATION]]
+             [--statsd HOST:PORT [--statsd-prefix NAME] [--statsd-tags KEY:VALUE,...]]
+             [--chaos-seed N] [--chaos-io-error-rate P] [--chaos-truncate-rate P] [--chaos-duplicate-rate P]
+             [--otel-endpoint URL [--otel-sample ROWS]]
+             (chaos and otel options need the features of the same name; flags after --policy override the file)";
+
+#[derive(Debug, Default)]
+pub struct Cli {
+    pub log_format: LogFormat,
+    pub log_level: Level,
+    /// Where to send panics and fatal errors, if anywhere.
+    pub crash_reports: Option<Dsn>,
+    pub command: Command,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Run(Box<RunArgs>),
+    Watch(Box<WatchArgs>),
+    MergeState(MergeStateArgs),
+    Bench(BenchConfig),
+    Import(ImportArgs),
+    Graph(GraphArgs),
+    Prove(ProveArgs),
+    Query(QueryArgs),
+    Statement(StatementArgs),
+    Revert(RevertArgs),
+    Admin(AdminArgs),
+    VerifyAudit(VerifyAuditArgs),
+    Batch(BatchArgs),
+    Submit(SubmitArgs),
+    Diff(DiffArgs),
+    Validate(ValidateArgs),
+    Stats(StatsArgs),
+    Merge(MergeArgs),
+    Anonymize(AnonymizeArgs),
+    Convert(ConvertArgs),
+    Completions(Shell),
+    Manpage,
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Run(Box::default())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RunArgs {
+    pub path: String,
+    pub output_format: OutputFormat,
+    /// Report destination; stdout when unset.
+    pub output: Option<PathBuf>,
+    /// When streamed report rows are handed to the output.
+    pub flush_every: FlushEvery,
+    /// Sync the report file to disk before exiting.
+    pub fsync: bool,
+    /// Rows between checkpoints saved next to the report, for `resume`.
+    pub checkpoint_every: Option<u64>,
+    /// Log the Merkle root of the applied rows with the summary.
+    pub merkle_root: bool,
+    /// Continue a run that died from its last checkpoint instead of starting
+    /// over. Side outputs such as `deltas` only cover the rows after it.
+    pub resume: bool,
+    /// Write CSV and JSON-lines reports in client order, keeping at most
+    /// `sort_buffer` rows in memory (100_000 when unset) and spilling the
+    /// rest to `spill_dir` (the system temp directory when unset).
+    pub sort_by_client: bool,
+    pub sort_buffer: Option<usize>,
+    pub spill_dir: Option<PathBuf>,
+    pub options: Options,
+    pub deltas: Option<String>,
+    pub delta_format: DeltaFormat,
+    pub declines: Option<String>,
+    pub frozen: Option<String>,
+    pub dead_letter: Option<String>,
+    pub sar: Option<String>,
+    /// Where to write merchant settlements.
+    pub settlement: Option<String>,
+    /// Where to write time-bucketed totals, per what span and in which format.
+    pub aggregates: Option<String>,
+    pub bucket: Bucket,
+    pub aggregate_format: AggregateFormat,
+    /// Where to write the top clients per measure, and how many; 10 when unset.
+    pub top_clients: Option<String>,
+    pub top: Option<usize>,
+    /// How often to write intermediate account snapshots, and where.
+    pub snapshot_every: Option<SnapshotEvery>,
+    pub snapshot_dir: Option<PathBuf>,
+    pub print_fingerprint: bool,
+    /// Where to publish state fingerprints, at the end and optionally during the run.
+    pub anchor: Option<AnchorConfig>,
+    /// Where to send StatsD metrics, if anywhere.
+    pub statsd: Option<StatsdConfig>,
+    #[cfg(feature = "chaos")]
+    pub chaos: Option<ChaosConfig>,
+    /// Where to export traces and metrics, and how often to trace a row.
+    #[cfg(feature = "otel")]
+    pub otel: Option<OtelConfig>,
+}
+
+#[derive(Debug)]
+pub struct WatchArgs {
+    pub dir: PathBuf,
+    pub done: PathBuf,
+    pub state: PathBuf,
+    pub poll: Duration,
+    pub once: bool,
+    pub dead_letter: Option<PathBuf>,
+    pub undo_depth: usize,
+    pub atomic_per_file: bool,
+    pub health: Option<String>,
+    pub max_backlog: usize,
+    /// Skip files applied before and refuse reused transaction ids.
+    pub idempotent: bool,
+    /// Where to publish state fingerprints after each file and optionally during it.
+    pub anchor: Option<AnchorConfig>,
+    pub statsd: Option<StatsdConfig>,
+    /// The `--policy` file, watched for changes.
+    pub policy: Option<PathBuf>,
+    pub options: Options,
+}
+
+#[derive(Debug)]
+pub struct ImportArgs {
+    pub format: ImportFormat,
+    pub path: PathBuf,
+    /// Client for statements, which don't name one; FIX fills carry their own.
+    pub client: Option<ClientId>,
+    pub first_tx: u32,
+}
+
+#[derive(Debug)]
+pub struct GraphArgs {
+    pub path: PathBuf,
+    pub options: Options,
+}
+
+/// A transactions file to apply, and the transaction to prove it contains.
+#[derive(Debug)]
+pub struct ProveArgs {
+    pub path: PathBuf,
+    pub tx: TxId,
+    pub options: Options,
+}
+
+/// A transactions file or report, or stdin for `-`, to rewrite in another format.
+#[derive(Debug)]
+pub struct ConvertArgs {
+    pub path: String,
+    pub schema: Schema,
+    pub from: Format,
+    pub to: Format,
+    pub options: Options,
+}
+
+/// A transactions file to turn into a shareable fixture.
+#[derive(Debug)]
+pub struct AnonymizeArgs {
+    pub path: PathBuf,
+    pub config: AnonymizeConfig,
+    pub options: Options,
+}
+
+/// Partial account reports to combine.
+#[derive(Debug)]
+pub struct MergeArgs {
+    pub paths: Vec<PathBuf>,
+    pub locked: LockedRule,
+    pub output: Option<PathBuf>,
+}
+
+/// A transactions file to profile; only the amount options apply.
+#[derive(Debug)]
+pub struct StatsArgs {
+    pub path: PathBuf,
+    pub options: Options,
+}
+
+#[derive(Debug)]
+pub struct QueryArgs {
+    pub path: PathBuf,
+    /// Every account when unset.
+    pub client: Option<ClientId>,
+    pub as_of: AsOf,
+    pub options: Options,
+}
+
+/// Independent files processed on several workers.
+#[derive(Debug)]
+pub struct BatchArgs {
+    pub paths: Vec<PathBuf>,
+    /// Workers; one per core when unset.
+    pub jobs: Option<usize>,
+    pub output: Option<PathBuf>,
+    pub options: Options,
+}
+
+/// A transactions file to check without applying it, and where the findings go.
+#[derive(Debug)]
+pub struct ValidateArgs {
+    pub path: PathBuf,
+    /// Findings destination; stdout when unset.
+    pub report: Option<PathBuf>,
+    pub options: Options,
+}
+
+/// Two account reports to compare; any difference fails the command.
+#[derive(Debug)]
+pub struct DiffArgs {
+    pub expected: PathBuf,
+    pub actual: PathBuf,
+    pub options: DiffOptions,
+    pub format: DiffFormat,
+}
+
+/// A transactions file, or stdin for `-`, to upload to a remote endpoint.
+#[derive(Debug)]
+pub struct SubmitArgs {
+    pub path: String,
+    pub config: SubmitConfig,
+}
+
+#[derive(Debug)]
+pub struct StatementArgs {
+    pub path: PathBuf,
+    pub client: ClientId,
+    /// First and last second covered, inclusive.
+    pub from: i64,
+    pub to: i64,
+    pub options: Options,
+}
+
+#[derive(Debug)]
+pub struct RevertArgs {
+    pub state: PathBuf,
+    pub last: usize,
+}
+
+#[derive(Debug)]
+pub struct AdminArgs {
+    pub state: PathBuf,
+    /// Defaults to `audit.jsonl` next to the state file.
+    pub audit: PathBuf,
+    pub operator: String,
+    pub client: ClientId,
+    pub action: Action,
+}
+
+#[derive(Debug)]
+pub struct VerifyAuditArgs {
+    pub path: PathBuf,
+    /// Defaults to the log's own head file, `audit.jsonl.head`.
+    pub head: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct MergeStateArgs {
+    pub inputs: Vec<PathBuf>,
+    pub out: PathBuf,
+    pub print_fingerprint: bool,
+}
+
+/// Takes the value following `flag` and parses it.
+fn value<T>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let raw = args.next().ok_or_else(|| format!("{} requires a value", flag))?;
+    raw.parse().map_err(|err| format!("invalid {} '{}': {}", flag, raw, err))
+}
+
+/// Takes a value that must lie in `0..=1`.
+fn probability(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<f64, String> {
+    let rate: f64 = value(args, flag)?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(format!("{} must be between 0 and 1, got {}", flag, rate));
+    }
+    Ok(rate)
+}
+
+/// Takes a single character that can separate digits in an amount.
+fn separator(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<char, String> {
+    let separator: char = value(args, flag)?;
+    if separator.is_ascii_digit() || matches!(separator, '-' | '+') {
+        return Err(format!("{} cannot be '{}'", flag, separator));
+    }
+    Ok(separator)
+}
+
+/// Takes a point in time in any form the `timestamp` column accepts.
+fn time(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<i64, String> {
+    let raw: String = value(args, flag)?;
+    txflow::time::parse_timestamp(&raw).ok_or_else(|| format!("invalid {} '{}': not a timestamp", flag, raw))
+}
+
+/// Takes a time like [`time`], reading a bare date as the end of that day.
+fn period_end(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<i64, String> {
+    let raw: String = value(args, flag)?;
+    let end = txflow::time::parse_timestamp(&raw).ok_or_else(|| format!("invalid {} '{}': not a timestamp", flag, raw))?;
+    let date_only = raw.len() == 10 && raw.contains('-');
+    Ok(if date_only { end + 86_399 } else { end })
+}
+
+/// Takes a span like `30d` or `12h`.
+fn duration(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<i64, String> {
+    let raw: String = value(args, flag)?;
+    txflow::time::parse_duration(&raw).ok_or_else(|| format!("invalid {} '{}': not a duration", flag, raw))
+}
+
+/// Parses byte sizes like `512M`, `2G` or `1048576` (binary multiples).
+fn parse_size(raw: &str) -> Result<u64, String> {
+    let upper = raw.to_ascii_uppercase();
+    let digits = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, shift) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 10),
+        Some('M') => (&digits[..digits.len() - 1], 20),
+        Some('G') => (&digits[..digits.len() - 1], 30),
+        Some('T') => (&digits[..digits.len() - 1], 40),
+        _ => (digits, 0),
+    };
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", raw))?;
+    if number < 0.0 {
+        return Err(format!("invalid size '{}'", raw));
+    }
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// A row count such as `10000`, or a size such as `64KB`.
+fn flush_every(raw: &str) -> Result<FlushEvery, String> {
+    if raw.ends_with(|c: char| c.is_ascii_alphabetic()) {
+        return Ok(FlushEvery::Bytes(parse_size(raw)? as usize));
+    }
+    match raw.replace('_', "").parse() {
+        Ok(rows) if rows > 0 => Ok(FlushEvery::Rows(rows)),
+        _ => Err(format!("invalid flush interval '{}' (expected a row count or a size like 64KB)", raw)),
+    }
+}
+
+/// Handles a processing option shared by every command that runs the
+/// engine; returns `false` if `flag` is not one of them.
+fn option(flag: &str, args: &mut impl Iterator<Item = String>, options: &mut Options) -> Result<bool, String> {
+    match flag {
+        "--lenient" => options.lenient = true,
+        "--tolerant-amounts" => options.amounts.tolerant = true,
+        "--decimal-separator" => options.amounts.decimal_separator = separator(args, flag)?,
+        "--thousands-separator" => options.amounts.thousands_separator = Some(separator(args, flag)?),
+        "--error-samples" => options.sample_limit = value(args, flag)?,
+        "--max-errors" => options.max_errors = Some(value(args, flag)?),
+        "--max-error-rate" => options.max_error_rate = Some(probability(args, flag)?),
+        "--delta-every" => options.delta_every = value(args, flag)?,
+        "--stats-every" => {
+            collections::wall_clock(flag)?;
+            options.stats_every = Some(Duration::from_secs_f64(value(args, flag)?));
+        }
+        "--max-memory" => options.max_memory = Some(parse_size(&value::<String>(args, flag)?)?),
+        "--policy" => {
+            let path: PathBuf = value(args, flag)?;
+            options.policy = Policy::load(&path).map_err(|err| match err {
+                Error::Policy(err) => format!("{}: {}", path.display(), err),
+                other => format!("{}: {}", path.display(), other),
+            })?;
+        }
+        "--retention" => options.policy.retention = value(args, flag)?,
+        "--require-order" => options.policy.ordering.require = Some(value(args, flag)?),
+        "--reorder-window" => options.policy.ordering.window = value(args, flag)?,
+        "--unique-tx-ids" => options.policy.unique_tx_ids = true,
+        "--wallets" => options.policy.wallets = true,
+        "--joint-owners" => {
+            let path: PathBuf = value(args, flag)?;
+            options.policy.joint = JointOwners::load(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        }
+        "--escrow-account" => options.policy.escrow_account = Some(ClientId(value(args, flag)?)),
+        "--chargeback-fee" => options.policy.chargeback_fee = value(args, flag)?,
+        "--loss-account" => options.policy.loss_account = Some(ClientId(value(args, flag)?)),
+        "--auto-resolve" => options.policy.auto_resolve = Some(duration(args, flag)?),
+        "--provisional-credit" => options.policy.provisional_credit = true,
+        "--max-cycles" => options.policy.max_cycles = value(args, flag)?,
+        "--dispute-filter" => options.policy.dispute_filter = true,
+        "--sar-rules" => options.policy.sar.set_all(&value::<String>(args, flag)?)?,
+        _ => return Ok(false),
+    }
+    if options.amounts.thousands_separator == Some(options.amounts.decimal_separator) {
+        return Err("--decimal-separator and --thousands-separator must differ".to_string());
+    }
+    Ok(true)
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut cli = Cli::default();
+    let mut verbosity = 0;
+    let mut rest = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-v" => verbosity += 1,
+            "-vv" => verbosity += 2,
+            "-q" | "--quiet" => verbosity -= 1,
+            "--log-level" => cli.log_level = value(&mut args, &arg)?,
+            "--log-format" => cli.log_format = value(&mut args, &arg)?,
+            "--crash-reports" => cli.crash_reports = Some(value(&mut args, &arg)?),
+            _ => rest.push(arg),
+        }
+    }
+    cli.log_level = cli.log_level.adjust(verbosity);
+
+    let mut rest = rest.into_iter().peekable();
+    cli.command = match rest.peek().map(String::as_str) {
+        Some("watch") => Command::Watch(Box::new(parse_watch(rest.skip(1))?)),
+        Some("merge-state") => Command::MergeState(parse_merge_state(rest.skip(1))?),
+        Some("bench") => Command::Bench(parse_bench(rest.skip(1))?),
+        Some("import") => Command::Import(parse_import(rest.skip(1))?),
+        Some("graph") => Command::Graph(parse_graph(rest.skip(1))?),
+        Some("prove") => Command::Prove(parse_prove(rest.skip(1))?),
+        Some("query") => Command::Query(parse_query(rest.skip(1))?),
+        Some("statement") => Command::Statement(parse_statement(rest.skip(1))?),
+        Some("revert") => Command::Revert(parse_revert(rest.skip(1))?),
+        Some("admin") => Command::Admin(parse_admin(rest.skip(1))?),
+        Some("verify-audit") => Command::VerifyAudit(parse_verify_audit(rest.skip(1))?),
+        Some("batch") => Command::Batch(parse_batch(rest.skip(1))?),
+        Some("submit") => Command::Submit(parse_submit(rest.skip(1))?),
+        Some("diff") => Command::Diff(parse_diff(rest.skip(1))?),
+        Some("validate") => Command::Validate(parse_validate(rest.skip(1))?),
+        Some("stats") => Command::Stats(parse_stats(rest.skip(1))?),
+        Some("merge") => Command::Merge(parse_merge(rest.skip(1))?),
+        Some("anonymize") => Command::Anonymize(parse_anonymize(rest.skip(1))?),
+        Some("convert") => Command::Convert(parse_convert(rest.skip(1))?),
+        Some("completions") => {
+            let mut rest = rest.skip(1);
+            let shell = value(&mut rest, "completions")?;
+            match rest.next() {
+                Some(extra) => return Err(format!("unexpected completions argument '{}'", extra)),
+                None => Command::Completions(shell),
+            }
+        }
+        Some("manpage") => match rest.nth(1) {
+            Some(extra) => return Err(format!("unexpected manpage argument '{}'", extra)),
+            None => Command::Manpage,
+        },
+        _ => Command::Run(Box::new(parse_run(rest)?)),
+    };
+    Ok(cli)
+}
+
+fn parse_run(mut args: impl Iterator<Item = String>) -> Result<RunArgs, String> {
+    let mut parsed = RunArgs::default();
+    let mut path = None;
+    let (mut anchor_target, mut anchor_every) = (None, None);
+    let mut statsd = StatsdFlags::default();
+    #[cfg(feature = "otel")]
+    let mut otel_sample = None;
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut parsed.options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--output-format" => parsed.output_format = value(&mut args, &arg)?,
+            "-o" | "--output" => parsed.output = Some(value(&mut args, &arg)?),
+            "--flush-every" => parsed.flush_every = flush_every(&value::<String>(&mut args, &arg)?)?,
+            "--fsync" => parsed.fsync = true,
+            "--checkpoint-every" => parsed.checkpoint_every = Some(value(&mut args, &arg)?),
+            "--resume" => parsed.resume = true,
+            "--merkle-root" => parsed.merkle_root = true,
+            "--sort-by-client" => parsed.sort_by_client = true,
+            "--sort-buffer" => parsed.sort_buffer = Some(value(&mut args, &arg)?),
+            "--spill-dir" => parsed.spill_dir = Some(value(&mut args, &arg)?),
+            "--deltas" => parsed.deltas = Some(value(&mut args, &arg)?),
+            "--delta-format" => parsed.delta_format = value(&mut args, &arg)?,
+            "--declines" => parsed.declines = Some(value(&mut args, &arg)?),
+            "--frozen" => parsed.frozen = Some(value(&mut args, &arg)?),
+            "--dead-letter" => parsed.dead_letter = Some(value(&mut args, &arg)?),
+            "--sar" => parsed.sar = Some(value(&mut args, &arg)?),
+            "--settlement" => parsed.settlement = Some(value(&mut args, &arg)?),
+            "--aggregates" => parsed.aggregates = Some(value(&mut args, &arg)?),
+            "--bucket" => parsed.bucket = value(&mut args, &arg)?,
+            "--aggregate-format" => parsed.aggregate_format = value(&mut args, &arg)?,
+            "--top-clients" => parsed.top_clients = Some(value(&mut args, &arg)?),
+            "--top" => parsed.top = Some(value(&mut args, &arg)?),
+            "--snapshot-every" => parsed.snapshot_every = Some(value(&mut args, &arg)?),
+            "--snapshot-dir" => parsed.snapshot_dir = Some(value(&mut args, &arg)?),
+            "--print-fingerprint" => parsed.print_fingerprint = true,
+            "--anchor" => anchor_target = Some(value(&mut args, &arg)?),
+            "--anchor-every" => anchor_every = Some(value(&mut args, &arg)?),
+            "--statsd" | "--statsd-prefix" | "--statsd-tags" => statsd.set(&arg, value(&mut args, &arg)?),
+            #[cfg(feature = "chaos")]
+            "--chaos-seed" => parsed.chaos.get_or_insert_with(|| ChaosConfig::new(0)).seed = value(&mut args, &arg)?,
+            #[cfg(feature = "chaos")]
+            "--chaos-io-error-rate" => {
+                parsed.chaos.get_or_insert_with(|| ChaosConfig::new(0)).io_error_rate = probability(&mut args, &arg)?
+            }
+            #[cfg(feature = "chaos")]
+            "--chaos-truncate-rate" => {
+                parsed.chaos.get_or_insert_with(|| ChaosConfig::new(0)).truncate_rate = probability(&mut args, &arg)?
+            }
+            #[cfg(feature = "chaos")]
+            "--chaos-duplicate-rate" => {
+                parsed.chaos.get_or_insert_with(|| ChaosConfig::new(0)).duplicate_rate = probability(&mut args, &arg)?
+            }
+            #[cfg(feature = "otel")]
+            "--otel-endpoint" => parsed.otel = Some(OtelConfig::new(value(&mut args, &arg)?)),
+            #[cfg(feature = "otel")]
+            "--otel-sample" => otel_sample = Some(value(&mut args, &arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    parsed.path = path.ok_or("missing transactions file")?;
+    parsed.anchor = anchor(anchor_target, anchor_every)?;
+    parsed.statsd = statsd.config()?;
+    #[cfg(feature = "otel")]
+    match (parsed.otel.as_mut(), otel_sample) {
+        (None, Some(_)) => return Err("--otel-sample requires --otel-endpoint".to_string()),
+        (Some(otel), Some(every)) => otel.sample_every = every,
+        _ => {}
+    }
+    if parsed.fsync && parsed.output.is_none() {
+        return Err("--fsync requires --output".to_string());
+    }
+    if parsed.checkpoint_every.is_some() || parsed.resume {
+        if parsed.output.is_none() {
+            return Err("--checkpoint-every and --resume require --output".to_string());
+        }
+        if parsed.sort_by_client || matches!(parsed.output_format, OutputFormat::Xlsx | OutputFormat::Html) {
+            return Err("checkpoints need a streamed csv or jsonl report without --sort-by-client".to_string());
+        }
+    }
+    Ok(parsed)
+}
+
+fn anchor(target: Option<AnchorTarget>, every: Option<SnapshotEvery>) -> Result<Option<AnchorConfig>, String> {
+    match (target, every) {
+        (None, Some(_)) => Err("--anchor-every requires --anchor".to_string()),
+        (target, every) => Ok(target.map(|target| AnchorConfig { target, every })),
+    }
+}
+
+/// The StatsD flags as given, checked once all are in.
+#[derive(Default)]
+struct StatsdFlags {
+    addr: Option<String>,
+    prefix: Option<String>,
+    tags: Option<String>,
+}
+
+impl StatsdFlags {
+    fn set(&mut self, flag: &str, value: String) {
+        match flag {
+            "--statsd" => self.addr = Some(value),
+            "--statsd-prefix" => self.prefix = Some(value),
+            _ => self.tags = Some(value),
+        }
+    }
+
+    fn config(self) -> Result<Option<StatsdConfig>, String> {
+        let Some(addr) = self.addr else {
+            return match self.prefix.or(self.tags) {
+                Some(_) => Err("--statsd-prefix and --statsd-tags require --statsd".to_string()),
+                None => Ok(None),
+            };
+        };
+        let mut config: StatsdConfig = addr.parse()?;
+        config.prefix = self.prefix.unwrap_or(config.prefix);
+        config.tags = self.tags.iter().flat_map(|tags| tags.split(',')).map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_string).collect();
+        Ok(Some(config))
+    }
+}
+
+fn parse_watch(args: impl Iterator<Item = String>) -> Result<WatchArgs, String> {
+    let mut args = args.peekable();
+    let (mut dir, mut done, mut state) = (None, None, None);
+    let mut poll = Duration::from_secs(5);
+    let mut once = false;
+    let mut dead_letter = None;
+    let mut undo_depth = 0;
+    let mut atomic_per_file = false;
+    let mut idempotent = false;
+    let (mut anchor_target, mut anchor_every) = (None, None);
+    let (mut health, mut max_backlog) = (None, 100);
+    let mut statsd = StatsdFlags::default();
+    let mut policy = None;
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        if arg == "--policy" {
+            policy = args.peek().map(PathBuf::from);
+        }
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--dir" => dir = Some(value::<PathBuf>(&mut args, &arg)?),
+            "--done" => done = Some(value::<PathBuf>(&mut args, &arg)?),
+            "--state" => state = Some(value::<PathBuf>(&mut args, &arg)?),
+            "--poll" => poll = Duration::from_secs_f64(value(&mut args, &arg)?),
+            "--once" => once = true,
+            "--dead-letter" => dead_letter = Some(value::<PathBuf>(&mut args, &arg)?),
+            "--undo-depth" => undo_depth = value(&mut args, &arg)?,
+            "--atomic-per-file" => atomic_per_file = true,
+            "--idempotent" => idempotent = true,
+            "--anchor" => anchor_target = Some(value(&mut args, &arg)?),
+            "--anchor-every" => anchor_every = Some(value(&mut args, &arg)?),
+            "--statsd" | "--statsd-prefix" | "--statsd-tags" => statsd.set(&arg, value(&mut args, &arg)?),
+            "--health-addr" => health = Some(value(&mut args, &arg)?),
+            "--max-backlog" => max_backlog = value(&mut args, &arg)?,
+            other => return Err(format!("unexpected watch argument '{}'", other)),
+        }
+    }
+
+    // Rows an overlapping file repeats show up as reused ids.
+    options.policy.unique_tx_ids |= idempotent;
+    Ok(WatchArgs {
+        dir: dir.ok_or("watch requires --dir")?,
+        done: done.ok_or("watch requires --done")?,
+        state: state.ok_or("watch requires --state")?,
+        poll,
+        once,
+        dead_letter,
+        undo_depth,
+        atomic_per_file,
+        health,
+        max_backlog,
+        idempotent,
+        anchor: anchor(anchor_target, anchor_every)?,
+        statsd: statsd.config()?,
+        policy,
+        options,
+    })
+}
+
+fn parse_merge_state(mut args: impl Iterator<Item = String>) -> Result<MergeStateArgs, String> {
+    let mut inputs = Vec::new();
+    let mut out = None;
+    let mut print_fingerprint = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--out" => out = Some(value::<PathBuf>(&mut args, &arg)?),
+            "--print-fingerprint" => print_fingerprint = true,
+            flag if flag.starts_with('-') => return Err(format!("unknown option '{}'", flag)),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err("merge-state requires at least one state file".to_string());
+    }
+    Ok(MergeStateArgs { inputs, out: out.ok_or("merge-state requires --out")?, print_fingerprint })
+}
+
+fn parse_bench(mut args: impl Iterator<Item = String>) -> Result<BenchConfig, String> {
+    let mut config = BenchConfig::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rows" => config.rows = bench::parse_count(&value::<String>(&mut args, &arg)?)?,
+            "--clients" => {
+                let clients = bench::parse_count(&value::<String>(&mut args, &arg)?)?;
+                config.clients = u32::try_from(clients).ok().filter(|clients| *clients > 0)
+                    .ok_or_else(|| format!("--clients must be between 1 and {}", u32::MAX))?;
+            }
+            "--seed" => config.seed = value(&mut args, &arg)?,
+            "--parse" => config.parse = true,
+            other => return Err(format!("unexpected bench argument '{}'", other)),
+        }
+    }
+
+    if config.rows > u32::MAX as u64 {
+        return Err(format!("--rows must be at most {} (transaction ids are 32-bit)", u32::MAX));
+    }
+    Ok(config)
+}
+
+fn parse_import(mut args: impl Iterator<Item = String>) -> Result<ImportArgs, String> {
+    let (mut format, mut client, mut path) = (None, None, None);
+    let mut first_tx = 1;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = Some(value(&mut args, &arg)?),
+            "--client" => client = Some(ClientId(value(&mut args, &arg)?)),
+            "--first-tx" => first_tx = value(&mut args, &arg)?,
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(ImportArgs {
+        format: format.ok_or("import requires --format")?,
+        path: path.ok_or("import requires a file")?,
+        client,
+        first_tx,
+    })
+}
+
+fn parse_graph(mut args: impl Iterator<Item = String>) -> Result<GraphArgs, String> {
+    let mut options = Options::default();
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(GraphArgs { path: path.ok_or("graph requires a transactions file")?, options })
+}
+
+fn parse_prove(mut args: impl Iterator<Item = String>) -> Result<ProveArgs, String> {
+    let mut options = Options::default();
+    let (mut path, mut tx) = (None, None);
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--tx" => tx = Some(TxId(value(&mut args, &arg)?)),
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(ProveArgs { path: path.ok_or("prove requires a transactions file")?, tx: tx.ok_or("prove requires --tx")?, options })
+}
+
+fn parse_convert(mut args: impl Iterator<Item = String>) -> Result<ConvertArgs, String> {
+    let mut options = Options::default();
+    let (mut path, mut schema, mut from, mut to) = (None, Schema::default(), None, None);
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--schema" => schema = value(&mut args, &arg)?,
+            "--from" => from = Some(value(&mut args, &arg)?),
+            "--to" => to = Some(value(&mut args, &arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(ConvertArgs {
+        path: path.ok_or("convert requires an input file")?,
+        schema,
+        from: from.ok_or("convert requires --from")?,
+        to: to.ok_or("convert requires --to")?,
+        options,
+    })
+}
+
+fn parse_anonymize(mut args: impl Iterator<Item = String>) -> Result<AnonymizeArgs, String> {
+    let (mut options, mut config) = (Options::default(), AnonymizeConfig::default());
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--seed" => config.seed = Some(value(&mut args, &arg)?),
+            "--spread" => config.spread = value(&mut args, &arg)?,
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    if config.spread.is_sign_negative() || config.spread >= rust_decimal::Decimal::ONE {
+        return Err(format!("--spread must be at least 0 and below 1, not {}", config.spread));
+    }
+    Ok(AnonymizeArgs { path: path.ok_or("anonymize requires a transactions file")?, config, options })
+}
+
+fn parse_merge(mut args: impl Iterator<Item = String>) -> Result<MergeArgs, String> {
+    let (mut paths, mut locked, mut output) = (Vec::new(), LockedRule::default(), None);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--locked" => locked = value(&mut args, &arg)?,
+            "-o" | "--output" => output = Some(value(&mut args, &arg)?),
+            flag if flag.starts_with('-') => return Err(format!("unknown option '{}'", flag)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("merge requires at least one report".to_string());
+    }
+    Ok(MergeArgs { paths, locked, output })
+}
+
+fn parse_stats(mut args: impl Iterator<Item = String>) -> Result<StatsArgs, String> {
+    let mut options = Options::default();
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(StatsArgs { path: path.ok_or("stats requires a transactions file")?, options })
+}
+
+fn parse_revert(mut args: impl Iterator<Item = String>) -> Result<RevertArgs, String> {
+    let (mut state, mut last) = (None, None);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--state" => state = Some(value::<PathBuf>(&mut args, &arg)?),
+            "--last" => last = Some(value(&mut args, &arg)?),
+            other => return Err(format!("unexpected revert argument '{}'", other)),
+        }
+    }
+
+    Ok(RevertArgs { state: state.ok_or("revert requires --state")?, last: last.ok_or("revert requires --last")? })
+}
+
+fn parse_admin(mut args: impl Iterator<Item = String>) -> Result<AdminArgs, String> {
+    let (mut state, mut audit, mut operator, mut client) = (None, None, None, None);
+    let (mut action, mut tx, mut amount) = (None, None, None);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--state" => state = Some(value::<PathBuf>(&mut args, &arg)?),
+            "--audit" => audit = Some(value::<PathBuf>(&mut args, &arg)?),
+            "--operator" => operator = Some(value::<String>(&mut args, &arg)?),
+            "--client" => client = Some(ClientId(value(&mut args, &arg)?)),
+            "--tx" => tx = Some(TxId(value(&mut args, &arg)?)),
+            "--amount" => amount = Some(value(&mut args, &arg)?),
+            "unlock" | "resolve" | "adjust" | "history" if action.is_none() => action = Some(arg),
+            other => return Err(format!("unexpected admin argument '{}'", other)),
+        }
+    }
+
+    let state: PathBuf = state.ok_or("admin requires --state")?;
+    let action = match action.as_deref() {
+        Some("unlock") => Action::Unlock,
+        Some("resolve") => Action::Resolve(tx.ok_or("admin resolve requires --tx")?),
+        Some("adjust") => Action::Adjust(amount.ok_or("admin adjust requires --amount")?),
+        Some("history") => Action::History,
+        _ => return Err("admin requires an action: unlock, resolve, adjust or history".to_string()),
+    };
+    Ok(AdminArgs {
+        audit: audit.unwrap_or_else(|| state.with_file_name("audit.jsonl")),
+        state,
+        operator: operator.filter(|operator| !operator.is_empty()).ok_or("admin requires --operator")?,
+        client: client.ok_or("admin requires --client")?,
+        action,
+    })
+}
+
+fn parse_verify_audit(mut args: impl Iterator<Item = String>) -> Result<VerifyAuditArgs, String> {
+    let (mut path, mut head) = (None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--head" => head = Some(value(&mut args, &arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    Ok(VerifyAuditArgs { path: path.ok_or("verify-audit requires an audit log")?, head })
+}
+
+fn parse_query(mut args: impl Iterator<Item = String>) -> Result<QueryArgs, String> {
+    let mut options = Options::default();
+    let (mut path, mut client, mut as_of) = (None, None, AsOf::default());
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--client" => client = Some(ClientId(value(&mut args, &arg)?)),
+            "--as-of-tx" => as_of.tx = Some(TxId(value(&mut args, &arg)?)),
+            "--as-known-at" => as_of.known_at = Some(time(&mut args, &arg)?),
+            "--as-effective-at" => as_of.effective_at = Some(time(&mut args, &arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    if as_of == AsOf::default() {
+        return Err("query requires --as-of-tx, --as-known-at or --as-effective-at".to_string());
+    }
+    Ok(QueryArgs { path: path.ok_or("query requires a transactions file")?, client, as_of, options })
+}
+
+fn parse_batch(mut args: impl Iterator<Item = String>) -> Result<BatchArgs, String> {
+    let mut options = Options::default();
+    let (mut paths, mut jobs, mut output) = (Vec::new(), None, None);
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--jobs" => jobs = Some(value::<usize>(&mut args, &arg)?.max(1)),
+            "-o" | "--output" => output = Some(value(&mut args, &arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("batch requires at least one transactions file".to_string());
+    }
+    if let Some(reason) = parallel::unsupported(&options) {
+        return Err(format!("batch cannot split this run across workers: {}", reason));
+    }
+    Ok(BatchArgs { paths, jobs, output, options })
+}
+
+fn parse_validate(mut args: impl Iterator<Item = String>) -> Result<ValidateArgs, String> {
+    let mut options = Options::default();
+    let (mut path, mut report) = (None, None);
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--report" => report = Some(value(&mut args, &arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(ValidateArgs { path: path.ok_or("validate requires a transactions file")?, report, options })
+}
+
+fn parse_diff(mut args: impl Iterator<Item = String>) -> Result<DiffArgs, String> {
+    let (mut options, mut format, mut paths) = (DiffOptions::default(), DiffFormat::default(), Vec::new());
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tolerance" => options.tolerance = value::<rust_decimal::Decimal>(&mut args, &arg)?.abs(),
+            "--ignore-order" => options.ignore_order = true,
+            "--format" => format = value(&mut args, &arg)?,
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [expected, actual]: [PathBuf; 2] = paths.try_into().map_err(|_| "diff requires an expected and an actual report")?;
+    Ok(DiffArgs { expected, actual, options, format })
+}
+
+fn parse_submit(mut args: impl Iterator<Item = String>) -> Result<SubmitArgs, String> {
+    let (mut path, mut endpoint, mut batch_rows, mut retries, mut timeout) = (None, None, None, None, None);
+    let mut api_key = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--endpoint" => endpoint = Some(value(&mut args, &arg)?),
+            "--batch-rows" => batch_rows = Some(value::<usize>(&mut args, &arg)?.max(1)),
+            "--retries" => retries = Some(value(&mut args, &arg)?),
+            "--timeout" => timeout = Some(Duration::from_secs_f64(value(&mut args, &arg)?)),
+            "--api-key-file" => {
+                let file: PathBuf = value(&mut args, &arg)?;
+                api_key = Some(ApiKey::from_file(&file).map_err(|err| format!("--api-key-file {}: {}", file.display(), err))?);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(arg),
+            other => return Err(format!("unexpected submit argument '{}'", other)),
+        }
+    }
+
+    let mut config = SubmitConfig::new(endpoint.ok_or("submit requires --endpoint")?);
+    config.batch_rows = batch_rows.unwrap_or(config.batch_rows);
+    config.retries = retries.unwrap_or(config.retries);
+    config.timeout = timeout.unwrap_or(config.timeout);
+    config.api_key = api_key;
+    Ok(SubmitArgs { path: path.ok_or("submit requires a transactions file")?, config })
+}
+
+fn parse_statement(mut args: impl Iterator<Item = String>) -> Result<StatementArgs, String> {
+    let mut options = Options::default();
+    let (mut path, mut client, mut from, mut to) = (None, None, i64::MIN, i64::MAX);
+
+    while let Some(arg) = args.next() {
+        if option(&arg, &mut args, &mut options)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--client" => client = Some(ClientId(value(&mut args, &arg)?)),
+            "--from" => from = time(&mut args, &arg)?,
+            "--to" => to = period_end(&mut args, &arg)?,
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(StatementArgs {
+        path: path.ok_or("statement requires a transactions file")?,
+        client: client.ok_or("statement requires --client")?,
+        from,
+        to,
+        options,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_bare_path_runs_file() {
+        let cli = parse(&["-v", "tx.csv", "--lenient"]).unwrap();
+        assert_eq!(cli.log_level, Level::Debug);
+        match cli.command {
+            Command::Run(run) => assert!(run.path == "tx.csv" && run.options.lenient),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_size("512MiB"), Ok(512 << 20));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert!(parse_size("big").is_err());
+    }
+
+    #[test]
+    fn test_flush_every_rows_or_size() {
+        assert_eq!(flush_every("10_000"), Ok(FlushEvery::Rows(10_000)));
+        assert_eq!(flush_every("64KB"), Ok(FlushEvery::Bytes(64 << 10)));
+        assert!(flush_every("0").is_err());
+        assert!(parse(&["--fsync", "tx.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_resume_requires_a_streamed_output_file() {
+        assert!(parse(&["--resume", "tx.csv"]).is_err());
+        assert!(parse(&["--output", "out.xlsx", "--output-format", "xlsx", "--checkpoint-every", "1000", "tx.csv"]).is_err());
+        let cli = parse(&["--output", "out.csv", "--checkpoint-every", "1000", "--resume", "tx.csv"]).unwrap();
+        assert!(matches!(cli.command, Command::Run(args) if args.resume && args.checkpoint_every == Some(1000)));
+    }
+
+    #[test]
+    fn test_watch_requires_directories() {
+        assert!(parse(&["watch", "--dir", "in"]).is_err());
+        let cli = parse(&["watch", "--dir", "in", "--done", "out", "--state", "s.json", "--once"]).unwrap();
+        assert!(matches!(cli.command, Command::Watch(args) if args.once));
+    }
+
+    #[test]
+    fn test_query_requires_a_cut() {
+        assert_eq!(
+            parse(&["query", "--client", "7", "in.csv"]).unwrap_err(),
+            "query requires --as-of-tx, --as-known-at or --as-effective-at"
+        );
+        let cli = parse(&["query", "--as-of-tx", "500000", "--client", "7", "in.csv"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Query(QueryArgs { client: Some(ClientId(7)), as_of: AsOf { tx: Some(TxId(500000)), .. }, .. })
+        ));
+        let cli = parse(&["query", "--as-known-at", "2024-01-02", "in.csv"]).unwrap();
+        assert!(matches!(cli.command, Command::Query(QueryArgs { as_of: AsOf { known_at: Some(1_704_153_600), .. }, .. })));
+    }
+
+    #[test]
+    fn test_statement_period_ends_with_its_last_day() {
+        assert_eq!(parse(&["statement", "in.csv"]).unwrap_err(), "statement requires --client");
+        let cli = parse(&["statement", "--client", "42", "--from", "2024-01-01", "--to", "2024-01-31", "in.csv"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Statement(StatementArgs { client: ClientId(42), from: 1_704_067_200, to: 1_706_745_599, .. })
+        ));
+    }
+
+    #[test]
+    fn test_admin_requires_an_operator() {
+        assert!(parse(&["admin", "--state", "s.json", "--client", "1", "unlock"]).is_err());
+        assert!(matches!(parse(&["verify-audit", "audit.jsonl"]).unwrap().command, Command::VerifyAudit(VerifyAuditArgs { head: None, .. })));
+        assert!(parse(&["admin", "--state", "s.json", "--operator", "ops", "--client", "1", "resolve"]).is_err());
+        match parse(&["admin", "--state", "dir/s.json", "--operator", "ops", "--client", "1", "adjust", "--amount", "-2.5"]).unwrap().command {
+            Command::Admin(args) => {
+                assert_eq!(args.action, Action::Adjust(rust_decimal::Decimal::new(-25, 1)));
+                assert_eq!(args.audit, PathBuf::from("dir/audit.jsonl"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_batch_refuses_policies_spanning_clients() {
+        assert!(parse(&["batch", "--jobs", "4", "a.csv", "b.csv"]).is_ok());
+        assert!(parse(&["batch", "--unique-tx-ids", "a.csv"]).unwrap_err().contains("unique transaction ids"));
+    }
+
+    #[test]
+    fn test_submit_args() {
+        match parse(&["submit", "--endpoint", "http://ledger:8080/transactions", "--batch-rows", "500", "tx.csv"]).unwrap().command {
+            Command::Submit(args) => assert_eq!((args.path.as_str(), args.config.batch_rows, args.config.url.port), ("tx.csv", 500, 8080)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(parse(&["submit", "tx.csv"]).unwrap_err().contains("--endpoint"));
+        assert!(parse(&["submit", "--endpoint", "https://ledger/", "tx.csv"]).unwrap_err().contains("https"));
+    }
+
+    #[test]
+    fn test_flags_after_policy_override_it() {
+        let path = std::env::temp_dir().join(format!("txflow-policy-{}.toml", std::process::id()));
+        std::fs::write(&path, "[disputes]\nretention = \"keep-last:5\"\n[sar]\ncycle_count = 9\n").unwrap();
+        let cli = parse(&["--policy", path.to_str().unwrap(), "--retention", "keep-all", "tx.csv"]);
+        std::fs::remove_file(&path).unwrap();
+        match cli.unwrap().command {
+            Command::Run(run) => {
+                assert_eq!(run.options.policy.retention, txflow::retention::Retention::KeepAll);
+                assert_eq!(run.options.policy.sar.cycle_count, 9);
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+}
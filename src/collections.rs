@@ -0,0 +1,86 @@
+//! The hashed collections txflow keeps its state in. Normally they hash
+//! with std's per-process random keys. The `deterministic` feature swaps in
+//! fixed keys, so every map iterates in an order that depends only on what
+//! was put into it, and runs on the same input match byte for byte on any
+//! machine.
+
+use std::{collections, time::{SystemTime, UNIX_EPOCH}};
+
+#[cfg(not(feature = "deterministic"))]
+pub type Hasher = collections::hash_map::RandomState;
+
+#[cfg(feature = "deterministic")]
+pub type Hasher = std::hash::BuildHasherDefault<collections::hash_map::DefaultHasher>;
+
+pub type HashMap<K, V> = collections::HashMap<K, V, Hasher>;
+pub type HashSet<T> = collections::HashSet<T, Hasher>;
+
+/// Refuses an option whose effect follows the wall clock when the build
+/// promises determinism.
+pub fn wall_clock(option: &str) -> Result<(), String> {
+    if cfg!(feature = "deterministic") {
+        Err(format!("{} follows the wall clock, which the deterministic build does not read", option))
+    } else {
+        Ok(())
+    }
+}
+
+/// The wall-clock time in Unix seconds, for a row that carries no timestamp
+/// of its own; refused like [`wall_clock`] when the build promises determinism.
+pub fn now(option: &str) -> Result<i64, String> {
+    wall_clock(option)?;
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs() as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        logging::Logger,
+        processor::{self, Options, Sinks},
+        report::ReportSink,
+        state, Engine,
+    };
+
+    /// Everything a run leaves behind: the report, the saved state and the fingerprint.
+    fn run(input: &str) -> (Vec<u8>, String, String) {
+        let mut engine = Engine::new();
+        let mut report = Vec::new();
+        let options = Options { lenient: true, ..Default::default() };
+        processor::process_csv(input.as_bytes(), &mut engine, &mut report, &mut Sinks::default(), &options, &Logger::default()).unwrap();
+        let mut csv = crate::report::ReportWriter::new(Vec::new());
+        for account in report.iter().cloned().chain(engine.reports().unwrap()) {
+            csv.write(&account).unwrap();
+        }
+        csv.finish().unwrap();
+        let path = std::env::temp_dir().join(format!("txflow-determinism-{}-{}.json", std::process::id(), input.len()));
+        state::save(&engine, &path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (csv.into_inner().unwrap(), saved, engine.fingerprint().unwrap())
+    }
+
+    #[test]
+    fn test_runs_are_reproducible() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..2_000u32 {
+            let client = tx * 7919 % 97;
+            let row = match tx % 11 {
+                0 => format!("dispute,{},{},\n", client, tx - 3),
+                5 => format!("finalize,{},{},\n", client, tx),
+                7 => format!("withdrawal,{},{},{}.5\n", client, tx, tx % 13),
+                _ => format!("deposit,{},{},{}.25\n", client, tx, tx % 29),
+            };
+            input.push_str(&row);
+        }
+        let first = run(&input);
+        assert_eq!(run(&input), first);
+        assert!(first.0.len() > 1_000);
+    }
+
+    #[cfg(feature = "deterministic")]
+    #[test]
+    fn test_hash_order_is_fixed() {
+        let order = || (0..1_000u32).collect::<super::HashSet<_>>().into_iter().collect::<Vec<_>>();
+        assert_eq!(order(), order());
+    }
+}
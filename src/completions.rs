@@ -0,0 +1,202 @@
+//! Shell completions and a man page, both generated from [`USAGE`] so they
+//! list exactly the subcommands and flags the parser accepts.
+
+use std::{fmt::Write, str::FromStr};
+
+use crate::cli::USAGE;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            other => Err(format!("unknown shell '{}' (expected bash, zsh or fish)", other)),
+        }
+    }
+}
+
+/// One synopsis from the usage text, with its continuation lines.
+struct Entry {
+    /// `None` for the default run command.
+    command: Option<&'static str>,
+    lines: Vec<&'static str>,
+    flags: Vec<&'static str>,
+}
+
+/// The usage text taken apart.
+struct Usage {
+    entries: Vec<Entry>,
+    global: Vec<&'static str>,
+    /// The `GLOBAL:` and `RUN OPTIONS:` blocks as written.
+    options: Vec<&'static str>,
+}
+
+/// Every `-x` or `--flag` in `text`, first occurrence first.
+fn flags(text: &'static str) -> Vec<&'static str> {
+    let bytes = text.as_bytes();
+    let mut found: Vec<&str> = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        let starts = bytes[at] == b'-' && (at == 0 || b" [|(".contains(&bytes[at - 1]));
+        let dashes = if bytes.get(at + 1) == Some(&b'-') { 2 } else { 1 };
+        if starts && bytes.get(at + dashes).is_some_and(u8::is_ascii_lowercase) {
+            let end = (at + dashes..bytes.len()).find(|&i| !(bytes[i].is_ascii_alphanumeric() || bytes[i] == b'-')).unwrap_or(bytes.len());
+            if !found.contains(&&text[at..end]) {
+                found.push(&text[at..end]);
+            }
+            at = end;
+        } else {
+            at += 1;
+        }
+    }
+    found
+}
+
+fn usage() -> Usage {
+    let (commands, options) = USAGE.split_once("\n\n").unwrap_or((USAGE, ""));
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in commands.lines() {
+        match line.trim_start().strip_prefix("cargo run -- [GLOBAL] ") {
+            Some(rest) => {
+                let command = rest.split_whitespace().next().filter(|word| !word.starts_with('['));
+                entries.push(Entry { command, lines: vec![line], flags: Vec::new() });
+            }
+            None => entries.last_mut().expect("usage starts with a synopsis").lines.push(line),
+        }
+    }
+    let (global, run) = options.split_once("RUN OPTIONS:").unwrap_or((options, ""));
+    let (global, run) = (flags(global), flags(run));
+    for entry in &mut entries {
+        let takes_run = entry.lines.iter().any(|line| line.contains("[RUN OPTIONS]"));
+        let own = entry.lines.iter().flat_map(|line| flags(line));
+        for flag in own.chain(run.iter().copied().filter(|_| takes_run)).chain(global.iter().copied()) {
+            if !entry.flags.contains(&flag) {
+                entry.flags.push(flag);
+            }
+        }
+    }
+    Usage { entries, global, options: options.lines().collect() }
+}
+
+impl Usage {
+    fn commands(&self) -> Vec<&'static str> {
+        self.entries.iter().filter_map(|entry| entry.command).collect()
+    }
+
+    /// What a bare `txflow` accepts: the default run's flags.
+    fn top_level(&self) -> &[&'static str] {
+        self.entries.iter().find(|entry| entry.command.is_none()).map_or(&[], |entry| &entry.flags)
+    }
+}
+
+/// A completion script for `shell`.
+pub fn completions(shell: Shell) -> String {
+    let usage = usage();
+    let commands = usage.commands();
+    let mut out = String::new();
+    match shell {
+        Shell::Bash => {
+            out.push_str("_txflow() {\n    local cur=${COMP_WORDS[COMP_CWORD]} command= words\n");
+            out.push_str("    for word in \"${COMP_WORDS[@]:1:COMP_CWORD-1}\"; do\n        case $word in\n");
+            let _ = writeln!(out, "            {}) command=$word; break ;;\n        esac\n    done\n    case $command in", commands.join("|"));
+            for entry in usage.entries.iter().filter(|entry| entry.command.is_some()) {
+                let _ = writeln!(out, "        {}) words=\"{}\" ;;", entry.command.unwrap_or_default(), entry.flags.join(" "));
+            }
+            let _ = writeln!(out, "        *) words=\"{} {}\" ;;\n    esac", commands.join(" "), usage.top_level().join(" "));
+            out.push_str("    COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))\n}\ncomplete -o default -F _txflow txflow\n");
+        }
+        Shell::Zsh => {
+            out.push_str("#compdef txflow\n\n_txflow() {\n");
+            let _ = writeln!(out, "    local command=${{words[(r)({})]}}\n    case $command in", commands.join("|"));
+            for entry in usage.entries.iter().filter(|entry| entry.command.is_some()) {
+                let _ = writeln!(out, "        {}) compadd -- {} ;;", entry.command.unwrap_or_default(), entry.flags.join(" "));
+            }
+            let _ = writeln!(out, "        *) compadd -- {} {} ;;\n    esac\n    _files\n}}\n\n_txflow \"$@\"", commands.join(" "), usage.top_level().join(" "));
+        }
+        Shell::Fish => {
+            let flag = |flag: &str| match flag.strip_prefix("--") {
+                Some(long) => format!("-l {}", long),
+                None if flag.len() == 2 => format!("-s {}", &flag[1..]),
+                None => format!("-o {}", &flag[1..]),
+            };
+            let _ = writeln!(out, "complete -c txflow -n __fish_use_subcommand -a '{}'", commands.join(" "));
+            for name in &usage.global {
+                let _ = writeln!(out, "complete -c txflow {}", flag(name));
+            }
+            for name in usage.top_level().iter().filter(|name| !usage.global.contains(name)) {
+                let _ = writeln!(out, "complete -c txflow -n __fish_use_subcommand {}", flag(name));
+            }
+            for entry in usage.entries.iter().filter(|entry| entry.command.is_some()) {
+                for name in entry.flags.iter().filter(|name| !usage.global.contains(name)) {
+                    let _ = writeln!(out, "complete -c txflow -n '__fish_seen_subcommand_from {}' {}", entry.command.unwrap_or_default(), flag(name));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Escapes a line for roff: hyphens and backslashes literal, no leading control character.
+fn roff(line: &str) -> String {
+    let escaped = line.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with(['.', '\'']) { format!("\\&{}", escaped) } else { escaped }
+}
+
+/// The `txflow(1)` man page.
+pub fn manpage() -> String {
+    let usage = usage();
+    let mut out = String::new();
+    let _ = writeln!(out, ".TH TXFLOW 1 \"\" \"txflow {}\" \"User Commands\"", env!("CARGO_PKG_VERSION"));
+    out.push_str(".SH NAME\ntxflow \\- apply deposits, withdrawals and disputes to client accounts\n");
+    out.push_str(".SH SYNOPSIS\n.nf\n");
+    for entry in &usage.entries {
+        for (index, line) in entry.lines.iter().enumerate() {
+            let line = line.trim_start();
+            let line = if index == 0 { line.replacen("cargo run -- ", "txflow ", 1) } else { format!("    {}", line) };
+            let _ = writeln!(out, "{}", roff(&line));
+        }
+    }
+    out.push_str(".fi\n.SH OPTIONS\n.nf\n");
+    for line in &usage.options {
+        let _ = writeln!(out, "{}", roff(line));
+    }
+    out.push_str(".fi\n.SH EXIT STATUS\n0 on success, 1 when processing fails, 2 when the arguments are not understood.\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completions_cover_commands_and_flags() {
+        let usage = usage();
+        assert!(usage.commands().contains(&"watch") && usage.commands().contains(&"convert"));
+        let watch = usage.entries.iter().find(|entry| entry.command == Some("watch")).unwrap();
+        assert!(watch.flags.contains(&"--health-addr") && watch.flags.contains(&"--lenient") && watch.flags.contains(&"--log-level"));
+        assert!(!watch.flags.contains(&"--"));
+
+        let bash = completions(Shell::Bash);
+        assert!(bash.contains("        revert) words=\"--state --last -v -vv -q --log-level --log-format --crash-reports\" ;;"), "{}", bash);
+        assert!(completions(Shell::Fish).contains("complete -c txflow -n '__fish_seen_subcommand_from diff' -l tolerance"));
+        assert!(completions(Shell::Zsh).starts_with("#compdef txflow\n"));
+    }
+
+    #[test]
+    fn test_manpage_escapes_roff() {
+        let page = manpage();
+        assert!(page.starts_with(".TH TXFLOW 1"));
+        assert!(page.contains("txflow [GLOBAL] watch \\-\\-dir DIR"), "{}", page);
+        assert!(!page.contains("cargo run"));
+    }
+}
@@ -0,0 +1,264 @@
+//! Converts transaction files and account reports between CSV and JSON
+//! lines. Both sides go through the schemas txflow itself reads and
+//! writes, so a converted file is one txflow accepts: CSV transactions
+//! through the row parser, JSON ones through [`TransactionRecord`], and
+//! reports through [`AccountReport`].
+
+use std::{collections::BTreeMap, io::{self, BufRead}, str::FromStr};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, ParseError},
+    parse::AmountFormat,
+    report::{self, AccountReport, JsonReportWriter, ReportSink, ReportWriter},
+    source::{CsvSource, TxSource},
+    time, ClientId, TxId, TxType,
+};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            "parquet" | "arrow" => Err(format!("{} needs the Arrow libraries, which this build does not include", s)),
+            other => Err(format!("unknown format '{}' (expected csv or jsonl)", other)),
+        }
+    }
+}
+
+/// What a file holds.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Schema {
+    #[default]
+    Transactions,
+    Report,
+}
+
+impl FromStr for Schema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "transactions" => Ok(Schema::Transactions),
+            "report" => Ok(Schema::Report),
+            other => Err(format!("unknown schema '{}' (expected transactions or report)", other)),
+        }
+    }
+}
+
+/// A transaction as a JSON line: the CSV columns under the same names,
+/// times as `YYYY-MM-DDTHH:MM:SSZ`, metadata columns alongside.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    #[serde(rename = "type")]
+    pub tx_type: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<String>,
+    #[serde(flatten)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The optional CSV columns a converted file carries.
+#[derive(Debug, Clone, Default)]
+struct Header {
+    timestamp: bool,
+    recorded: bool,
+    extra: Vec<String>,
+}
+
+impl Header {
+    fn of(record: &TransactionRecord) -> Self {
+        Header { timestamp: record.timestamp.is_some(), recorded: record.recorded_at.is_some(), extra: record.metadata.keys().cloned().collect() }
+    }
+
+    fn names(&self) -> Vec<&str> {
+        let mut names = vec!["type", "client", "tx", "amount"];
+        names.extend(self.timestamp.then_some("timestamp"));
+        names.extend(self.recorded.then_some("recorded_at"));
+        names.extend(self.extra.iter().map(String::as_str));
+        names
+    }
+
+    fn row(&self, record: &TransactionRecord, line: u64) -> Result<Vec<String>, ParseError> {
+        if let Some(key) = record.metadata.keys().find(|key| !self.extra.contains(key)) {
+            return Err(ParseError::MalformedRow { line, reason: format!("column '{}' is not in the first row", key) });
+        }
+        let mut row = vec![
+            record.tx_type.name().to_string(),
+            record.client.0.to_string(),
+            record.tx.0.to_string(),
+            record.amount.map(|amount| amount.to_string()).unwrap_or_default(),
+        ];
+        row.extend(self.timestamp.then(|| record.timestamp.clone().unwrap_or_default()));
+        row.extend(self.recorded.then(|| record.recorded_at.clone().unwrap_or_default()));
+        row.extend(self.extra.iter().map(|key| record.metadata.get(key).cloned().unwrap_or_default()));
+        Ok(row)
+    }
+}
+
+/// Checks and normalizes a JSON record's times the way the CSV parser would.
+fn normalize(mut record: TransactionRecord, line: u64) -> Result<TransactionRecord, ParseError> {
+    for (field, value) in [("timestamp", &mut record.timestamp), ("recorded_at", &mut record.recorded_at)] {
+        if let Some(raw) = value.as_deref() {
+            let seconds = time::parse_timestamp(raw).ok_or_else(|| ParseError::InvalidField {
+                line,
+                field: field.to_string(),
+                value: raw.to_string(),
+                expected: "timestamp",
+            })?;
+            *value = Some(time::format_timestamp(seconds));
+        }
+    }
+    Ok(record)
+}
+
+type Records<'a> = Box<dyn Iterator<Item = Result<(TransactionRecord, u64), Error>> + 'a>;
+
+fn read_transactions<'a>(input: impl io::Read + 'a, from: Format, amounts: &AmountFormat) -> Result<(Header, Records<'a>), Error> {
+    match from {
+        Format::Csv => {
+            let mut source = CsvSource::new(input, amounts)?;
+            let columns = source.columns();
+            let header = Header { timestamp: columns.has_timestamp(), recorded: columns.has_recorded(), extra: columns.extra().map(str::to_string).collect() };
+            let records = std::iter::from_fn(move || match source.next_row() {
+                Ok(Some(row)) => {
+                    let transaction = row.transaction;
+                    Some(Ok((TransactionRecord {
+                        tx_type: transaction.tx_type,
+                        client: transaction.client,
+                        tx: transaction.tx,
+                        amount: transaction.amount,
+                        timestamp: transaction.timestamp.map(time::format_timestamp),
+                        recorded_at: transaction.recorded.map(time::format_timestamp),
+                        metadata: transaction.metadata,
+                    }, row.line)))
+                }
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            });
+            Ok((header, Box::new(records)))
+        }
+        Format::Jsonl => {
+            let mut records = io::BufReader::new(input).lines().zip(1..).filter_map(|(line, number)| match line {
+                Ok(line) if line.trim().is_empty() => None,
+                Ok(line) => Some(serde_json::from_str(&line)
+                    .map_err(|err| ParseError::MalformedRow { line: number, reason: err.to_string() })
+                    .and_then(|record| normalize(record, number))
+                    .map(|record| (record, number))
+                    .map_err(Error::from)),
+                Err(err) => Some(Err(err.into())),
+            }).peekable();
+            let header = match records.peek() {
+                Some(Ok((first, _))) => Header::of(first),
+                _ => Header::default(),
+            };
+            Ok((header, Box::new(records)))
+        }
+    }
+}
+
+fn read_reports<'a>(input: impl io::Read + 'a, from: Format) -> Result<Vec<AccountReport>, Error> {
+    match from {
+        Format::Csv => report::read_csv(input),
+        Format::Jsonl => io::BufReader::new(input).lines().zip(1..)
+            .filter(|(line, _)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|(line, number)| serde_json::from_str(&line?)
+                .map_err(|err| ParseError::MalformedRow { line: number, reason: err.to_string() }.into()))
+            .collect(),
+    }
+}
+
+/// Rewrites `input` from one format to the other and returns the rows written.
+pub fn convert(input: impl io::Read, output: impl io::Write, schema: Schema, from: Format, to: Format, amounts: &AmountFormat) -> Result<u64, Error> {
+    let mut rows = 0;
+    match schema {
+        Schema::Transactions => {
+            let (header, records) = read_transactions(input, from, amounts)?;
+            match to {
+                Format::Csv => {
+                    let mut writer = csv::Writer::from_writer(output);
+                    writer.write_record(header.names())?;
+                    for record in records {
+                        let (record, line) = record?;
+                        writer.write_record(header.row(&record, line)?)?;
+                        rows += 1;
+                    }
+                    writer.flush()?;
+                }
+                Format::Jsonl => {
+                    let mut output = io::BufWriter::new(output);
+                    for record in records {
+                        serde_json::to_writer(&mut output, &record?.0).map_err(io::Error::from)?;
+                        io::Write::write_all(&mut output, b"\n")?;
+                        rows += 1;
+                    }
+                    io::Write::flush(&mut output)?;
+                }
+            }
+        }
+        Schema::Report => {
+            let reports = read_reports(input, from)?;
+            let mut sink: Box<dyn ReportSink> = match to {
+                Format::Csv => Box::new(ReportWriter::new(output)),
+                Format::Jsonl => Box::new(JsonReportWriter::new(output)),
+            };
+            for report in &reports {
+                sink.write(report)?;
+            }
+            sink.finish()?;
+            rows = reports.len() as u64;
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &str, schema: Schema, from: Format, to: Format) -> Result<String, Error> {
+        let mut output = Vec::new();
+        convert(input.as_bytes(), &mut output, schema, from, to, &AmountFormat::default())?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_transactions_round_trip() {
+        let csv = "type,client,tx,amount,timestamp,merchant\ndeposit,1,1,1.5,2024-01-02T03:04:05Z,Acme\ndispute,1,1,,,\n";
+        let jsonl = run(csv, Schema::Transactions, Format::Csv, Format::Jsonl).unwrap();
+        assert_eq!(jsonl, concat!(
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5","timestamp":"2024-01-02T03:04:05Z","merchant":"Acme"}"#, "\n",
+            r#"{"type":"dispute","client":1,"tx":1}"#, "\n",
+        ));
+        let back = run(&jsonl, Schema::Transactions, Format::Jsonl, Format::Csv).unwrap();
+        assert_eq!(back, "type,client,tx,amount,timestamp,merchant\ndeposit,1,1,1.5,2024-01-02T03:04:05Z,Acme\ndispute,1,1,,,\n");
+
+        let bad = r#"{"type":"deposit","client":1,"tx":1,"timestamp":"yesterday"}"#;
+        assert!(matches!(run(bad, Schema::Transactions, Format::Jsonl, Format::Csv), Err(Error::Parse(ParseError::InvalidField { line: 1, .. }))));
+        assert!("parquet".parse::<Format>().unwrap_err().contains("Arrow"));
+    }
+
+    #[test]
+    fn test_reports_round_trip() {
+        let csv = "client,available,held,locked\n1,10,2,false\n";
+        let jsonl = run(csv, Schema::Report, Format::Csv, Format::Jsonl).unwrap();
+        assert_eq!(jsonl, "{\"client\":1,\"available\":\"10\",\"held\":\"2\",\"locked\":false}\n");
+        assert_eq!(run(&jsonl, Schema::Report, Format::Jsonl, Format::Csv).unwrap(), csv);
+    }
+}
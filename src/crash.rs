@@ -0,0 +1,184 @@
+//! Crash reports for unattended runs. Once installed with a Sentry DSN, a
+//! panic or an error that ends the process is sent to the project's store
+//! endpoint, so a failure is visible even when nobody reads the job's
+//! output. A report carries the input being read, the rows consumed so far
+//! and a hash of the configuration. The error kind is used as the grouping
+//! fingerprint, so repeats of the same failure gather under one issue.
+//!
+//! As with every outbound connection here, the DSN must be `http://`, to a
+//! self-hosted server or a local forwarding proxy.
+
+use std::{
+    fmt, panic,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use serde_json::{json, Value};
+
+use crate::{
+    http::{self, Url},
+    sha256,
+};
+
+/// Rows consumed by the current input, kept up to date by the processor.
+static ROWS: AtomicU64 = AtomicU64::new(0);
+static INPUT: Mutex<Option<String>> = Mutex::new(None);
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+/// Notes the input a run is reading, and starts its row count again.
+pub fn set_input(input: &str) {
+    if let Ok(mut current) = INPUT.lock() {
+        *current = Some(input.to_string());
+    }
+    ROWS.store(0, Ordering::Relaxed);
+}
+
+pub fn set_rows(rows: u64) {
+    ROWS.store(rows, Ordering::Relaxed);
+}
+
+/// A Sentry DSN, `http://KEY@HOST[:PORT]/PROJECT`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Dsn {
+    store: Url,
+    key: String,
+}
+
+impl FromStr for Dsn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' is not a DSN (expected http://KEY@HOST/PROJECT)", s);
+        let (scheme, rest) = s.split_once("://").ok_or_else(invalid)?;
+        let (key, location) = rest.split_once('@').ok_or_else(invalid)?;
+        let key = key.split(':').next().unwrap_or_default();
+        let (host, project) = location.rsplit_once('/').ok_or_else(invalid)?;
+        if key.is_empty() || project.is_empty() {
+            return Err(invalid());
+        }
+        let store = format!("{}://{}/api/{}/store/", scheme, host, project).parse()?;
+        Ok(Dsn { store, key: key.to_string() })
+    }
+}
+
+impl fmt::Debug for Dsn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Dsn({})", self.store)
+    }
+}
+
+/// Identifies a run's configuration: its command line and policy file.
+pub fn config_hash<'a>(args: impl IntoIterator<Item = &'a str>, policy: Option<&str>) -> String {
+    let mut hasher = sha256::Sha256::new();
+    for arg in args {
+        hasher.update(arg.as_bytes());
+        hasher.update(&[0]);
+    }
+    if let Some(policy) = policy {
+        hasher.update(policy.as_bytes());
+    }
+    sha256::to_hex(&hasher.finalize())[..16].to_string()
+}
+
+struct Reporter {
+    dsn: Dsn,
+    config_hash: String,
+}
+
+impl Reporter {
+    /// An event with the current input and row count.
+    fn current(&self, level: &str, kind: &str, message: &str) -> Value {
+        let input = INPUT.lock().ok().and_then(|input| input.clone());
+        self.event(level, kind, message, input.as_deref(), ROWS.load(Ordering::Relaxed))
+    }
+
+    fn event(&self, level: &str, kind: &str, message: &str, input: Option<&str>, rows: u64) -> Value {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seed = format!("{}:{}:{}", std::process::id(), now.as_nanos(), message);
+        json!({
+            "event_id": &sha256::to_hex(&sha256::digest(seed.as_bytes()))[..32],
+            "timestamp": now.as_secs_f64(),
+            "platform": "native",
+            "logger": "txflow",
+            "level": level,
+            "release": concat!("txflow@", env!("CARGO_PKG_VERSION")),
+            "fingerprint": [kind],
+            "exception": { "values": [{ "type": kind, "value": message }] },
+            "tags": {
+                "input": input.unwrap_or("-"),
+                "config_hash": self.config_hash,
+            },
+            "extra": { "rows": rows },
+        })
+    }
+
+    fn send(&self, event: &Value) -> Result<(), String> {
+        let auth = format!("Sentry sentry_version=7, sentry_key={}, sentry_client=txflow/{}", self.dsn.key, env!("CARGO_PKG_VERSION"));
+        let body = event.to_string();
+        match http::post_with(&self.dsn.store, "application/json", &[("X-Sentry-Auth", &auth)], body.as_bytes(), Duration::from_secs(5)) {
+            Ok(status) if (200..300).contains(&status) => Ok(()),
+            Ok(status) => Err(format!("HTTP {}", status)),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+/// Sends panics, and errors passed to [`report`], to `dsn` for the rest of
+/// the process. A panic is still printed as before.
+pub fn install(dsn: Dsn, config_hash: String) {
+    if REPORTER.set(Reporter { dsn, config_hash }).is_err() {
+        return;
+    }
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Some(reporter) = REPORTER.get() {
+            let message = match info.location() {
+                Some(location) => format!("{} at {}", info.payload_as_str().unwrap_or("panic"), location),
+                None => info.payload_as_str().unwrap_or("panic").to_string(),
+            };
+            if let Err(err) = reporter.send(&reporter.current("fatal", "panic", &message)) {
+                eprintln!("crash report not sent: {}", err);
+            }
+        }
+        previous(info);
+    }));
+}
+
+/// Sends an error that is about to end the process, if reports are on.
+/// `kind` groups it with others of its sort.
+pub fn report(kind: &str, message: &str) -> Result<(), String> {
+    match REPORTER.get() {
+        Some(reporter) => reporter.send(&reporter.current("error", kind, message)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_carry_run_context() {
+        let dsn: Dsn = "http://abc123@sentry.local:9000/42".parse().unwrap();
+        assert_eq!(dsn.store.to_string(), "http://sentry.local:9000/api/42/store/");
+        assert_eq!(format!("{:?}", dsn), "Dsn(http://sentry.local:9000/api/42/store/)");
+        assert!("http://sentry.local/42".parse::<Dsn>().is_err());
+        assert!("https://abc@sentry.io/1".parse::<Dsn>().unwrap_err().contains("https"));
+
+        let hash = config_hash(["--lenient", "tx.csv"], Some("[limits]\n"));
+        assert_eq!(hash.len(), 16);
+        assert_ne!(hash, config_hash(["--lenient", "tx.csv"], None));
+
+        let reporter = Reporter { dsn, config_hash: hash.clone() };
+        let event = reporter.event("error", "Parse", "line 1235: malformed row", Some("tx.csv"), 1_234);
+        assert_eq!(event["tags"]["input"], "tx.csv");
+        assert_eq!(event["tags"]["config_hash"], hash.as_str());
+        assert_eq!(event["extra"]["rows"], 1_234);
+        assert_eq!(event["fingerprint"][0], "Parse");
+        assert_eq!(event["event_id"].as_str().unwrap().len(), 32);
+    }
+}
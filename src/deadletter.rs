@@ -0,0 +1,114 @@
+use std::io;
+use serde::Serialize;
+
+use crate::{diagnostics::ErrorCategory, error::Error};
+
+/// A row the run could not use, with why and where it came from.
+#[derive(Debug, Serialize)]
+struct DeadLetter<'a> {
+    input: &'a str,
+    line: u64,
+    category: String,
+    reason: &'a str,
+    /// The row as read, re-encoded as CSV; empty when the source keeps no raw form.
+    record: &'a str,
+}
+
+/// Writes skipped and rejected rows as CSV so they can be inspected, fixed
+/// and fed back in, instead of only being counted.
+pub struct DeadLetterWriter {
+    writer: csv::Writer<Box<dyn io::Write>>,
+    input: String,
+    rows: u64,
+}
+
+impl DeadLetterWriter {
+    pub fn new(output: Box<dyn io::Write>) -> Self {
+        DeadLetterWriter { writer: csv::Writer::from_writer(output), input: String::new(), rows: 0 }
+    }
+
+    /// Appends to output that already starts with the header row.
+    pub fn continuing(output: Box<dyn io::Write>) -> Self {
+        let writer = csv::WriterBuilder::new().has_headers(false).from_writer(output);
+        DeadLetterWriter { writer, input: String::new(), rows: 0 }
+    }
+
+    /// Names the input that following rows come from; watch mode switches it per file.
+    pub fn set_input(&mut self, input: impl Into<String>) {
+        self.input = input.into();
+    }
+
+    pub fn write(&mut self, line: u64, category: ErrorCategory, reason: &str, raw: Option<&[String]>) -> Result<(), Error> {
+        let record = match raw {
+            Some(fields) => encode(fields)?,
+            None => String::new(),
+        };
+        self.writer.serialize(DeadLetter {
+            input: &self.input,
+            line,
+            category: category.to_string(),
+            reason,
+            record: &record,
+        })?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// One CSV line, quoted as needed and without the terminator.
+fn encode(fields: &[String]) -> Result<String, Error> {
+    let mut writer = csv::WriterBuilder::new().terminator(csv::Terminator::Any(b'\n')).from_writer(Vec::new());
+    writer.write_record(fields)?;
+    let bytes = writer.into_inner().map_err(|err| err.into_error())?;
+    Ok(String::from_utf8_lossy(&bytes).trim_end_matches('\n').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+    use crate::Reject;
+
+    /// Collects written bytes while letting the test read them back.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_rows_keep_the_original_record() {
+        let output = Shared::default();
+        let mut writer = DeadLetterWriter::new(Box::new(output.clone()));
+        writer.set_input("batch.csv");
+        let raw = ["withdrawal", "1", "3", "1,5"].map(String::from);
+        let category = ErrorCategory::Rejected(Reject::InsufficientFunds);
+        writer.write(4, category, "insufficient funds", Some(&raw)).unwrap();
+        writer.write(5, ErrorCategory::MalformedRow, "line 5: wrong number of fields", None).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(writer.rows(), 2);
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone()).unwrap(),
+            "input,line,category,reason,record\n\
+             batch.csv,4,rejected: insufficient funds,insufficient funds,\"withdrawal,1,3,\"\"1,5\"\"\"\n\
+             batch.csv,5,malformed row,line 5: wrong number of fields,\n"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use std::io;
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+use crate::{error::Error, ClientId, TxId};
+
+/// A withdrawal refused for lack of funds, with the balance it was checked against.
+#[derive(Debug, Serialize)]
+struct DeclineRow {
+    client: ClientId,
+    tx: TxId,
+    requested: Decimal,
+    available: Decimal,
+}
+
+/// Writes declined withdrawals as CSV. Declines are ordinary business
+/// outcomes, so they get their own stream instead of being mixed in with
+/// malformed input and other rejects.
+pub struct DeclineWriter {
+    writer: csv::Writer<Box<dyn io::Write>>,
+    rows: u64,
+}
+
+impl DeclineWriter {
+    pub fn new(output: Box<dyn io::Write>) -> Self {
+        DeclineWriter { writer: csv::Writer::from_writer(output), rows: 0 }
+    }
+
+    pub fn write(&mut self, client: ClientId, tx: TxId, requested: Decimal, available: Decimal) -> Result<(), Error> {
+        self.writer.serialize(DeclineRow { client, tx, requested, available })?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
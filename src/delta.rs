@@ -0,0 +1,135 @@
+use std::{io, str::FromStr};
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+use crate::{error::Error, Account, ClientId};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum DeltaFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl FromStr for DeltaFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(DeltaFormat::Csv),
+            "jsonl" => Ok(DeltaFormat::Jsonl),
+            other => Err(format!("unknown delta format '{}' (expected csv or jsonl)", other)),
+        }
+    }
+}
+
+/// An account's state at the end of the interval in which it changed.
+#[derive(Debug, Serialize)]
+struct DeltaRow {
+    interval: u64,
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+/// Emits, per interval, only the accounts that changed during it, so
+/// consumers can follow a long run without diffing full dumps.
+pub struct DeltaWriter {
+    format: DeltaFormat,
+    csv: Option<csv::Writer<Box<dyn io::Write>>>,
+    jsonl: Option<Box<dyn io::Write>>,
+    interval: u64,
+}
+
+impl DeltaWriter {
+    pub fn new(output: Box<dyn io::Write>, format: DeltaFormat) -> Self {
+        let (csv, jsonl) = match format {
+            DeltaFormat::Csv => (Some(csv::Writer::from_writer(output)), None),
+            DeltaFormat::Jsonl => (None, Some(output)),
+        };
+        DeltaWriter { format, csv, jsonl, interval: 0 }
+    }
+
+    pub fn format(&self) -> DeltaFormat {
+        self.format
+    }
+
+    /// Writes one interval's worth of changed accounts and starts the next interval.
+    pub fn write_interval<'a>(&mut self, accounts: impl IntoIterator<Item = &'a Account>) -> Result<(), Error> {
+        self.interval += 1;
+        for account in accounts {
+            let row = DeltaRow {
+                interval: self.interval,
+                client: account.client,
+                available: account.available,
+                held: account.held,
+                locked: account.locked,
+            };
+            if let Some(writer) = self.csv.as_mut() {
+                writer.serialize(&row)?;
+            }
+            if let Some(writer) = self.jsonl.as_mut() {
+                serde_json::to_writer(&mut *writer, &row).map_err(io::Error::from)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        self.flush()
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if let Some(writer) = self.csv.as_mut() {
+            writer.flush()?;
+        }
+        if let Some(writer) = self.jsonl.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+    use rust_decimal::dec;
+
+    /// Collects written bytes while letting the test read them back.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn account(client: u32, available: Decimal) -> Account {
+        let mut account = Account::new(ClientId(client));
+        account.available = available;
+        account
+    }
+
+    #[test]
+    fn test_csv_deltas_number_intervals() {
+        let out = Shared::default();
+        let mut deltas = DeltaWriter::new(Box::new(out.clone()), DeltaFormat::Csv);
+        deltas.write_interval([&account(1, dec!(1.5))]).unwrap();
+        deltas.write_interval([&account(2, dec!(2))]).unwrap();
+        let written = String::from_utf8(out.0.borrow().clone()).unwrap();
+        assert_eq!(written, "interval,client,available,held,locked\n1,1,1.5,0,false\n2,2,2,0,false\n");
+    }
+
+    #[test]
+    fn test_jsonl_deltas() {
+        let out = Shared::default();
+        let mut deltas = DeltaWriter::new(Box::new(out.clone()), DeltaFormat::Jsonl);
+        deltas.write_interval([&account(7, dec!(3.25))]).unwrap();
+        let written = String::from_utf8(out.0.borrow().clone()).unwrap();
+        assert_eq!(written, "{\"interval\":1,\"client\":7,\"available\":\"3.25\",\"held\":\"0\",\"locked\":false}\n");
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{ClientId, Reject, TxId};
+
+/// Coarse bucket a failed row is counted under.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ErrorCategory {
+    /// The row could not be read as CSV at all (bad quoting, wrong field count, invalid UTF-8).
+    MalformedRow,
+    /// The row was well-formed but a field did not deserialize (bad type, amount, id).
+    InvalidField,
+    /// The row parsed but the engine refused to apply it.
+    Rejected(Reject),
+}
+
+impl ErrorCategory {
+    pub fn is_parse_error(self) -> bool {
+        !matches!(self, ErrorCategory::Rejected(_))
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCategory::MalformedRow => f.write_str("malformed row"),
+            ErrorCategory::InvalidField => f.write_str("invalid field"),
+            ErrorCategory::Rejected(reject) => write!(f, "rejected: {}", reject),
+        }
+    }
+}
+
+/// Per-category failure counts for a run, deciding which occurrences are
+/// worth printing so a file with thousands of bad rows does not flood stderr.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorCounts {
+    sample_limit: usize,
+    counts: BTreeMap<ErrorCategory, u64>,
+}
+
+impl ErrorCounts {
+    /// Keeps every count but only reports the first `sample_limit` examples per category.
+    pub fn new(sample_limit: usize) -> Self {
+        ErrorCounts { sample_limit, counts: BTreeMap::new() }
+    }
+
+    /// Counts one occurrence and returns whether it falls within the sample.
+    pub fn record(&mut self, category: ErrorCategory) -> bool {
+        let count = self.counts.entry(category).or_insert(0);
+        *count += 1;
+        *count <= self.sample_limit as u64
+    }
+
+    pub fn count(&self, category: ErrorCategory) -> u64 {
+        self.counts.get(&category).copied().unwrap_or(0)
+    }
+
+    /// Occurrences beyond the sample that were counted but not reported.
+    pub fn suppressed(&self, category: ErrorCategory) -> u64 {
+        self.count(category).saturating_sub(self.sample_limit as u64)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn parse_errors(&self) -> u64 {
+        self.iter().filter(|(category, _)| category.is_parse_error()).map(|(_, count)| count).sum()
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.total() - self.parse_errors()
+    }
+
+    /// Adds another run's counts to these.
+    pub fn merge(&mut self, other: &ErrorCounts) {
+        for (category, count) in other.iter() {
+            *self.counts.entry(category).or_insert(0) += count;
+        }
+    }
+
+    /// Categories with at least one occurrence, in a stable order.
+    pub fn iter(&self) -> impl Iterator<Item = (ErrorCategory, u64)> + '_ {
+        self.counts.iter().map(|(category, count)| (*category, *count))
+    }
+}
+
+/// One failed row, kept for reports that list rejects individually.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectRecord {
+    pub line: u64,
+    /// Unknown when the row failed before its client and id could be read.
+    pub client: Option<ClientId>,
+    pub tx: Option<TxId>,
+    pub category: ErrorCategory,
+    pub reason: String,
+}
+
+/// Failed rows in input order, capped so a hopeless file can't exhaust
+/// memory; rows past the cap are only counted.
+#[derive(Debug, Clone)]
+pub struct RejectLog {
+    records: Vec<RejectRecord>,
+    limit: usize,
+    dropped: u64,
+}
+
+impl RejectLog {
+    pub fn new(limit: usize) -> Self {
+        RejectLog { records: Vec::new(), limit, dropped: 0 }
+    }
+
+    pub fn record(&mut self, record: RejectRecord) {
+        if self.records.len() < self.limit {
+            self.records.push(record);
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    pub fn records(&self) -> &[RejectRecord] {
+        &self.records
+    }
+
+    /// Failed rows seen after the log was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl Default for RejectLog {
+    /// Room for as many rows as fit on one spreadsheet sheet, less its header.
+    fn default() -> Self {
+        RejectLog::new(1_048_575)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_samples_first_n_per_category() {
+        let mut counts = ErrorCounts::new(2);
+        assert!(counts.record(ErrorCategory::InvalidField));
+        assert!(counts.record(ErrorCategory::InvalidField));
+        assert!(!counts.record(ErrorCategory::InvalidField));
+        assert!(counts.record(ErrorCategory::MalformedRow));
+        assert_eq!(counts.count(ErrorCategory::InvalidField), 3);
+        assert_eq!(counts.suppressed(ErrorCategory::InvalidField), 1);
+        assert_eq!(counts.suppressed(ErrorCategory::MalformedRow), 0);
+    }
+
+    #[test]
+    fn test_totals_split_parse_errors_from_rejects() {
+        let mut counts = ErrorCounts::new(0);
+        counts.record(ErrorCategory::MalformedRow);
+        counts.record(ErrorCategory::Rejected(Reject::InsufficientFunds));
+        counts.record(ErrorCategory::Rejected(Reject::UnknownTx));
+        assert_eq!(counts.total(), 3);
+        assert_eq!(counts.parse_errors(), 1);
+        assert_eq!(counts.rejected(), 2);
+    }
+}
@@ -0,0 +1,198 @@
+//! Compares two account reports row by row, keyed by client, so a
+//! reconciliation job can tell whether a rerun, a port or another system
+//! agrees with a reference. Amounts within a tolerance count as equal and
+//! row order can be ignored, so only meaningful differences are reported.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::error::Error;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum DiffFormat {
+    #[default]
+    Text,
+    Jsonl,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DiffFormat::Text),
+            "jsonl" => Ok(DiffFormat::Jsonl),
+            other => Err(format!("unknown diff format '{}' (expected text or jsonl)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiffOptions {
+    /// Amounts that differ by at most this much are equal.
+    pub tolerance: Decimal,
+    /// Don't report rows that match but appear in a different order.
+    pub ignore_order: bool,
+}
+
+/// One way the actual report departs from the expected one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Difference {
+    /// The reports don't carry the same columns; only shared ones are compared.
+    Columns { expected: Vec<String>, actual: Vec<String> },
+    Missing { client: String },
+    Extra { client: String },
+    Changed { client: String, column: String, expected: String, actual: String },
+    /// The first row at which the clients shared by both reports come in a different order.
+    Order { row: usize, expected: String, actual: String },
+}
+
+impl Difference {
+    fn write_text(&self, mut out: impl Write) -> io::Result<()> {
+        match self {
+            Difference::Columns { expected, actual } => {
+                writeln!(out, "columns: expected {}, actual {}", expected.join(","), actual.join(","))
+            }
+            Difference::Missing { client } => writeln!(out, "client {}: missing", client),
+            Difference::Extra { client } => writeln!(out, "client {}: unexpected", client),
+            Difference::Changed { client, column, expected, actual } => {
+                writeln!(out, "client {} {}: expected {}, actual {}", client, column, expected, actual)
+            }
+            Difference::Order { row, expected, actual } => {
+                writeln!(out, "order: row {} is client {}, expected client {}", row, actual, expected)
+            }
+        }
+    }
+}
+
+struct Report {
+    columns: Vec<String>,
+    rows: Vec<csv::StringRecord>,
+    /// Row index by client, first occurrence only.
+    index: HashMap<String, usize>,
+    client: usize,
+}
+
+impl Report {
+    fn read(input: impl Read) -> Result<Self, Error> {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+        let columns: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+        let client = columns.iter().position(|column| column == "client")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "report has no client column"))?;
+        let rows = reader.records().collect::<Result<Vec<_>, _>>()?;
+        let mut index = HashMap::new();
+        for (row, record) in rows.iter().enumerate() {
+            index.entry(record[client].to_string()).or_insert(row);
+        }
+        Ok(Report { columns, rows, index, client })
+    }
+
+    fn clients(&self) -> impl Iterator<Item = &str> + '_ {
+        self.rows.iter().map(|record| &record[self.client])
+    }
+}
+
+fn equal(expected: &str, actual: &str, tolerance: Decimal) -> bool {
+    match (expected.parse::<Decimal>(), actual.parse::<Decimal>()) {
+        (Ok(expected), Ok(actual)) => (expected - actual).abs() <= tolerance,
+        _ => expected == actual,
+    }
+}
+
+/// Lists every difference between two reports, in the expected report's order.
+pub fn diff(expected: impl Read, actual: impl Read, options: &DiffOptions) -> Result<Vec<Difference>, Error> {
+    let (expected, actual) = (Report::read(expected)?, Report::read(actual)?);
+    let mut differences = Vec::new();
+    if expected.columns != actual.columns {
+        differences.push(Difference::Columns { expected: expected.columns.clone(), actual: actual.columns.clone() });
+    }
+    let shared: Vec<(usize, usize)> = expected.columns.iter().enumerate()
+        .filter_map(|(at, column)| Some((at, actual.columns.iter().position(|other| other == column)?)))
+        .filter(|&(at, _)| at != expected.client)
+        .collect();
+
+    for record in &expected.rows {
+        let client = &record[expected.client];
+        let Some(&row) = actual.index.get(client) else {
+            differences.push(Difference::Missing { client: client.to_string() });
+            continue;
+        };
+        for &(left, right) in &shared {
+            let (want, got) = (&record[left], &actual.rows[row][right]);
+            if !equal(want, got, options.tolerance) {
+                differences.push(Difference::Changed {
+                    client: client.to_string(),
+                    column: expected.columns[left].clone(),
+                    expected: want.to_string(),
+                    actual: got.to_string(),
+                });
+            }
+        }
+    }
+    for client in actual.clients().filter(|client| !expected.index.contains_key(*client)) {
+        differences.push(Difference::Extra { client: client.to_string() });
+    }
+
+    if !options.ignore_order {
+        let want = expected.clients().filter(|client| actual.index.contains_key(*client));
+        let got = actual.clients().filter(|client| expected.index.contains_key(*client));
+        if let Some((row, (want, got))) = want.zip(got).enumerate().find(|(_, (want, got))| want != got) {
+            differences.push(Difference::Order { row: row + 1, expected: want.to_string(), actual: got.to_string() });
+        }
+    }
+    Ok(differences)
+}
+
+/// Writes differences one per line, as text or JSON objects.
+pub fn write(differences: &[Difference], format: DiffFormat, mut out: impl Write) -> Result<(), Error> {
+    for difference in differences {
+        match format {
+            DiffFormat::Text => difference.write_text(&mut out)?,
+            DiffFormat::Jsonl => {
+                serde_json::to_writer(&mut out, difference).map_err(io::Error::from)?;
+                out.write_all(b"\n")?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    const EXPECTED: &str = "client,available,held,total,locked\n1,10.0000,0,10.0000,false\n2,5,1,6,false\n3,0,0,0,true\n";
+
+    #[test]
+    fn test_tolerance_and_order() {
+        let actual = "client,available,held,total,locked\n2,5.00005,1,6.00005,false\n1,10,0,10,false\n3,0,0,0,true\n";
+        let strict = diff(EXPECTED.as_bytes(), actual.as_bytes(), &DiffOptions::default()).unwrap();
+        assert_eq!(strict.len(), 3);
+        assert_eq!(strict[2], Difference::Order { row: 1, expected: "1".into(), actual: "2".into() });
+
+        let lenient = DiffOptions { tolerance: dec!(0.0001), ignore_order: true };
+        assert_eq!(diff(EXPECTED.as_bytes(), actual.as_bytes(), &lenient).unwrap(), []);
+    }
+
+    #[test]
+    fn test_missing_extra_and_changed_rows_as_jsonl() {
+        let actual = "client,available,held,total,locked\n1,10,0,10,true\n4,1,0,1,false\n2,5,1,6,false\n";
+        let options = DiffOptions { ignore_order: true, ..Default::default() };
+        let differences = diff(EXPECTED.as_bytes(), actual.as_bytes(), &options).unwrap();
+        let mut out = Vec::new();
+        write(&differences, DiffFormat::Jsonl, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            r#"{"kind":"changed","client":"1","column":"locked","expected":"false","actual":"true"}"#, "\n",
+            r#"{"kind":"missing","client":"3"}"#, "\n",
+            r#"{"kind":"extra","client":"4"}"#, "\n",
+        ));
+    }
+}
@@ -0,0 +1,1673 @@
+use std::{borrow::Cow, collections::{BTreeSet, VecDeque}};
+use rust_decimal::Decimal;
+
+use crate::{
+    bloom::BloomFilter,
+    collections::{self, HashMap, HashSet},
+    class::AccountClass,
+    error::Error,
+    joint::JointOwners,
+    money::{self, MoneyOps},
+    observer::{EngineObserver, Observers},
+    ordering::SequenceKey,
+    policy::{Limits, LockedPolicy, Policy},
+    quota::{Quotas, Usage},
+    report::{self, AccountReport},
+    retention::Retention,
+    script::Rule,
+    sha256::{self, Sha256},
+    stats::MemoryUsage,
+    store::{MemoryStore, StateStore},
+    transfer::{self, Link},
+    account::DisputeState,
+    Account, ClientId, Reject, Transaction, TxId, TxType,
+};
+
+/// Owns every client account and routes transactions to them. Open accounts
+/// live in a [`StateStore`], in memory unless another backend is supplied.
+///
+/// Balances are kept in `A`, `Decimal` unless another [`MoneyOps`] type is
+/// chosen, such as [`Fixed`](crate::fixed::Fixed) minor units with
+/// `Engine<MemoryStore<Fixed>, Fixed>`. Rows, limits and fees stay `Decimal`
+/// and are converted as they are applied; an amount the type can't hold, or
+/// a balance that would overflow it, refuses the row with
+/// [`Reject::AmountOutOfRange`]. Book-wide totals are kept in `Decimal`.
+#[derive(Debug, Default)]
+pub struct Engine<S = MemoryStore, A = Decimal> {
+    pub(crate) store: S,
+    /// Clients whose accounts were finalized; only the id is kept so late rows can be refused.
+    pub(crate) sealed: HashSet<ClientId>,
+    /// Accounts finalized since the last `take_finalized`, waiting to be reported.
+    finalized: Vec<Account<A>>,
+    /// Deposits held across all accounts for dispute lookups, kept as a running total.
+    history_entries: usize,
+    retention: Retention,
+    limits: Limits,
+    locked_policy: LockedPolicy,
+    rules: Vec<Rule>,
+    classes: Vec<AccountClass>,
+    /// Each classed client's index into `classes`.
+    class_of: HashMap<ClientId, usize>,
+    joint: JointOwners,
+    sequence: Option<SequenceKey>,
+    /// Each client's last sequence key, while ordering is enforced.
+    pub(crate) last_keys: HashMap<ClientId, i64>,
+    quotas: Quotas,
+    /// Each client's rows in its current quota windows.
+    pub(crate) quota_usage: HashMap<ClientId, Usage>,
+    unique_tx_ids: bool,
+    /// Ids of every deposit and withdrawal seen, while uniqueness is enforced.
+    pub(crate) seen_tx: HashSet<TxId>,
+    /// SHA-256 of every input file applied whole, as hex, so a resubmitted
+    /// one can be recognized.
+    pub(crate) applied_files: BTreeSet<String>,
+    /// Where disputed funds are shown as moved to, if anywhere.
+    escrow_account: Option<ClientId>,
+    /// Funds held by disputes across the book, finalized accounts included.
+    pub(crate) escrowed: Decimal,
+    chargeback_fee: Decimal,
+    /// Where chargeback losses are shown as booked to, if anywhere.
+    loss_account: Option<ClientId>,
+    /// Deposits charged back plus fees, and provisional credit spent before
+    /// its resolve, across the book.
+    pub(crate) losses: Decimal,
+    /// Seconds an open dispute may go without an outcome before it is resolved.
+    auto_resolve: Option<i64>,
+    /// When each open dispute comes due, with the wallet it is in unless main.
+    dispute_deadlines: BTreeSet<(i64, ClientId, TxId, Option<String>)>,
+    /// Resolves issued for expired disputes since the last `take_auto_resolved`.
+    auto_resolved: Vec<Transaction>,
+    /// Keep withdrawals disputable, crediting the client while a dispute runs.
+    provisional_credit: bool,
+    /// Chargebacks a deposit may go through, representments in between.
+    max_cycles: u32,
+    /// Provisional credits (positive) and their reversals (negative) since
+    /// the last `take_provisional`.
+    provisional: Vec<(ClientId, TxId, Decimal)>,
+    /// Every id kept in history, while dispute-flow rows for unknown ids are
+    /// refused before their account is loaded.
+    dispute_filter: Option<BloomFilter>,
+    /// Rows name the wallet they apply to, each kept in `store` apart.
+    wallets: bool,
+    /// Newest row timestamp seen, the "now" for time-based retention.
+    clock: Option<i64>,
+    /// Transactions applied since time-based retention last swept every account.
+    since_sweep: u32,
+    /// Rows kept in `journal`; zero turns journaling off.
+    undo_depth: usize,
+    /// What the most recent rows changed, oldest first, for [`Engine::revert_last`].
+    pub(crate) journal: VecDeque<Undo<A>>,
+    /// Rows applied since the savepoint, if one is set; these stay journaled whatever the depth.
+    since_savepoint: Option<usize>,
+    observers: Observers<A>,
+    /// The engines clients are sharded with, when this is one of several.
+    link: Option<Box<dyn Link>>,
+    /// The first failure of the store, kept until [`Engine::take_store_error`].
+    store_error: Option<Error>,
+}
+
+/// What one row changed, so it can be put back.
+#[derive(Debug, Clone)]
+pub(crate) struct Undo<A = Decimal> {
+    pub(crate) client: ClientId,
+    /// The wallet the row addressed, unless main.
+    pub(crate) wallet: Option<String>,
+    /// The account before the row; `None` if the row opened it.
+    pub(crate) before: Option<Account<A>>,
+    /// The client's other wallets, when the row finalized them all.
+    pub(crate) others: Vec<Account<A>>,
+    /// The row finalized the account.
+    pub(crate) sealed: bool,
+    /// The id the row claimed for uniqueness checks.
+    pub(crate) claimed: Option<TxId>,
+    /// The client's previous sequence key, when the row moved it.
+    pub(crate) last_key: Option<Option<i64>>,
+    /// What the row added to chargeback losses.
+    pub(crate) lost: Decimal,
+    /// The client a transfer credited here, with its main account before the row.
+    pub(crate) counterpart: Option<(ClientId, Option<Account<A>>)>,
+}
+
+/// The wallet rows without a `wallet` column address, and the one accounts
+/// are stored under while wallets are not kept apart.
+pub const MAIN_WALLET: &str = "main";
+
+/// Passes `amount` through unless it exceeds a configured per-transaction cap.
+fn within(amount: Decimal, limit: Option<Decimal>) -> Result<Decimal, Reject> {
+    match limit {
+        Some(limit) if amount > limit => Err(Reject::LimitExceeded),
+        _ => Ok(amount),
+    }
+}
+
+/// Converts an amount read from input into the engine's amount type.
+fn convert<A: MoneyOps>(amount: Decimal) -> Result<A, Reject> {
+    A::from_decimal(amount).ok_or(Reject::AmountOutOfRange)
+}
+
+/// How far a balance moved, for the totals kept in `Decimal`.
+fn moved<A: MoneyOps>(from: A, to: A) -> Decimal {
+    to.to_decimal() - from.to_decimal()
+}
+
+/// How often time-based retention sweeps all accounts; in between, expired
+/// deposits are only pruned when a dispute looks them up.
+const SWEEP_EVERY: u32 = 65_536;
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_retention(retention: Retention) -> Self {
+        Engine { retention, ..Self::default() }
+    }
+
+    pub fn with_policy(policy: &Policy) -> Self {
+        let mut engine = Self::default();
+        engine.set_policy(policy);
+        engine
+    }
+
+    /// Absorbs an engine that processed a disjoint set of clients, e.g. another
+    /// partition of the same input. Fails without changing `self` if any
+    /// client, active or finalized, is known to both.
+    pub fn merge(&mut self, mut other: Engine) -> Result<(), Error> {
+        let ours: HashSet<ClientId> = self.accounts().map(|account| account.map(|account| account.client))
+            .chain(self.sealed.iter().copied().map(Ok))
+            .collect::<Result<_, Error>>()?;
+        let mut clients: Vec<ClientId> = other.accounts().map(|account| account.map(|account| account.client))
+            .chain(other.sealed.iter().copied().map(Ok))
+            .collect::<Result<_, Error>>()?;
+        clients.sort();
+        if let Some(client) = clients.into_iter().find(|client| ours.contains(client)) {
+            return Err(Error::MergeConflict(client));
+        }
+
+        for account in other.store.drain() {
+            self.store.put(account)?;
+        }
+        self.sealed.extend(other.sealed);
+        self.finalized.extend(other.finalized);
+        self.history_entries += other.history_entries;
+        self.last_keys.extend(other.last_keys);
+        self.quota_usage.extend(other.quota_usage);
+        self.seen_tx.extend(other.seen_tx);
+        self.applied_files.extend(other.applied_files);
+        self.escrowed += other.escrowed;
+        self.losses += other.losses;
+        // Each side's journal assumes only its own rows came after it.
+        self.journal.clear();
+        if self.dispute_filter.is_some() {
+            self.rebuild_filter(0);
+        }
+        Ok(())
+    }
+}
+
+impl<S: StateStore<A>, A: MoneyOps> Engine<S, A> {
+    /// An engine keeping its open accounts in `store`, which may already hold some.
+    pub fn with_store(store: S) -> Self {
+        let mut engine = Engine {
+            store,
+            sealed: HashSet::default(),
+            finalized: Vec::new(),
+            history_entries: 0,
+            retention: Retention::default(),
+            limits: Limits::default(),
+            locked_policy: LockedPolicy::default(),
+            rules: Vec::new(),
+            classes: Vec::new(),
+            class_of: HashMap::default(),
+            joint: JointOwners::default(),
+            sequence: None,
+            last_keys: HashMap::default(),
+            quotas: Quotas::default(),
+            quota_usage: HashMap::default(),
+            unique_tx_ids: false,
+            seen_tx: HashSet::default(),
+            applied_files: BTreeSet::new(),
+            escrow_account: None,
+            escrowed: Decimal::ZERO,
+            chargeback_fee: Decimal::ZERO,
+            loss_account: None,
+            losses: Decimal::ZERO,
+            auto_resolve: None,
+            dispute_deadlines: BTreeSet::new(),
+            auto_resolved: Vec::new(),
+            provisional_credit: false,
+            max_cycles: 0,
+            provisional: Vec::new(),
+            dispute_filter: None,
+            wallets: false,
+            clock: None,
+            since_sweep: 0,
+            undo_depth: 0,
+            journal: VecDeque::new(),
+            since_savepoint: None,
+            observers: Observers::default(),
+            link: None,
+            store_error: None,
+        };
+        if let Err(err) = engine.recount_history() {
+            engine.stash(err);
+        }
+        engine
+    }
+
+    /// Applies the engine-level parts of a policy to subsequent transactions;
+    /// history already pruned stays pruned.
+    pub fn set_policy(&mut self, policy: &Policy) {
+        self.retention = policy.retention;
+        self.limits = policy.limits.clone();
+        self.locked_policy = policy.locked;
+        self.rules = policy.rules.clone();
+        self.classes = policy.classes.values().cloned().collect();
+        self.class_of = self.classes.iter().enumerate()
+            .flat_map(|(index, class)| class.clients.iter().map(move |client| (*client, index)))
+            .collect();
+        self.joint = policy.joint.clone();
+        self.sequence = policy.ordering.require;
+        self.unique_tx_ids = policy.unique_tx_ids;
+        self.quotas = policy.quotas;
+        self.escrow_account = policy.escrow_account;
+        self.chargeback_fee = policy.chargeback_fee;
+        self.loss_account = policy.loss_account;
+        self.auto_resolve = policy.auto_resolve;
+        self.provisional_credit = policy.provisional_credit;
+        self.max_cycles = policy.max_cycles;
+        self.dispute_filter = None;
+        if policy.dispute_filter {
+            self.rebuild_filter(0);
+        }
+        self.schedule_disputes();
+        // Accounts once kept apart stay apart.
+        self.wallets |= policy.wallets;
+    }
+
+    /// Refills the dispute filter from every account's history, pruned ids
+    /// included, sized for at least `capacity` ids.
+    fn rebuild_filter(&mut self, capacity: usize) {
+        let mut filter = BloomFilter::with_capacity(capacity.max(2 * self.history_entries));
+        let filled = self.accounts().try_for_each(|account| {
+            let account = account?;
+            for tx in account.history.keys().chain(account.pruned()) {
+                filter.insert(tx);
+            }
+            Ok::<_, Error>(())
+        });
+        match filled {
+            Ok(()) => self.dispute_filter = Some(filter),
+            // Without a filter every id is looked up, which is only slower.
+            Err(err) => {
+                self.dispute_filter = None;
+                self.stash(err);
+            }
+        }
+    }
+
+    /// Rebuilds the deadlines of every open dispute with a known start.
+    fn schedule_disputes(&mut self) {
+        self.dispute_deadlines.clear();
+        let Some(window) = self.auto_resolve else { return };
+        let mut deadlines = Vec::new();
+        let scheduled = self.accounts().try_for_each(|account| {
+            let account = account?;
+            let wallet = account.wallet.clone().filter(|wallet| wallet != MAIN_WALLET);
+            deadlines.extend(account.history.iter()
+                .filter(|(_, deposit)| deposit.state.is_open())
+                .filter_map(|(tx, deposit)| deposit.opened.map(|opened| (opened + window, account.client, tx, wallet.clone()))));
+            Ok::<_, Error>(())
+        });
+        self.dispute_deadlines.extend(deadlines);
+        if let Err(err) = scheduled {
+            self.stash(err);
+        }
+    }
+
+    /// Resolves, oldest first, the open disputes whose deadline `now` has passed.
+    fn expire_disputes(&mut self, now: i64) {
+        let window = self.auto_resolve.unwrap_or_default();
+        while let Some((due, client, tx, wallet)) = self.dispute_deadlines.first().cloned() {
+            if due > now {
+                break;
+            }
+            self.dispute_deadlines.pop_first();
+            // The dispute may have been settled, or raised again later, since it was scheduled.
+            let still_due = match self.account_in(client, wallet.as_deref()) {
+                Ok(account) => account.and_then(|account| account.history.get(tx))
+                    .is_some_and(|deposit| deposit.state.is_open() && deposit.opened.map(|opened| opened + window) == Some(due)),
+                Err(err) => {
+                    self.stash(err);
+                    break;
+                }
+            };
+            if !still_due {
+                continue;
+            }
+            let mut resolve = Transaction::new(TxType::Resolve, client, tx, None);
+            resolve.metadata.insert("source".to_string(), "auto-resolve".to_string());
+            if let Some(wallet) = wallet {
+                resolve.metadata.insert("wallet".to_string(), wallet);
+            }
+            if self.apply_journaled(&resolve).is_ok() {
+                self.auto_resolved.push(resolve);
+            }
+        }
+    }
+
+    /// Hands over the resolves issued for expired disputes since the last
+    /// call. Each was applied, and journaled, as a row of its own.
+    pub fn take_auto_resolved(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.auto_resolved)
+    }
+
+    /// Hands over the provisional credits granted on disputed withdrawals,
+    /// and the reversals of those whose dispute was resolved against the
+    /// client, since the last call: client, withdrawal and signed amount.
+    pub fn take_provisional(&mut self) -> Vec<(ClientId, TxId, Decimal)> {
+        std::mem::take(&mut self.provisional)
+    }
+
+    /// The wallet other than main that a row addresses, while wallets are kept apart.
+    fn wallet_of<'r>(&self, record: &'r Transaction) -> Option<&'r str> {
+        if !self.wallets {
+            return None;
+        }
+        record.metadata.get("wallet").map(String::as_str).filter(|wallet| *wallet != MAIN_WALLET)
+    }
+
+    fn account_in(&self, client: ClientId, wallet: Option<&str>) -> Result<Option<Cow<'_, Account<A>>>, Error> {
+        self.store.get(client, wallet.unwrap_or(MAIN_WALLET))
+    }
+
+    fn remove_account(&mut self, client: ClientId, wallet: Option<&str>) -> Result<Option<Account<A>>, Error> {
+        self.store.remove(client, wallet.unwrap_or(MAIN_WALLET))
+    }
+
+    /// The client's account in `wallet`, or main, taken out of the store to
+    /// be changed and [kept](Engine::keep); a new one if there is none yet.
+    fn open_account(&mut self, client: ClientId, wallet: Option<&str>) -> Result<Account<A>, Reject> {
+        let wallet = wallet.unwrap_or(MAIN_WALLET);
+        let taken = self.store.take(client, wallet);
+        let tag = self.wallets.then(|| wallet.to_string());
+        Ok(self.stored(taken)?.unwrap_or_else(|| Account { wallet: tag, ..Account::new(client) }))
+    }
+
+    /// Puts back an account from [`Engine::open_account`].
+    fn keep(&mut self, account: Account<A>) -> Result<(), Reject> {
+        let kept = self.store.put(account);
+        self.stored(kept)
+    }
+
+    /// Adds `amount` to the client's available funds in `wallet`, or main.
+    fn credit(&mut self, client: ClientId, wallet: Option<&str>, amount: A) -> Result<(), Reject> {
+        let mut account = self.open_account(client, wallet)?;
+        let credited = money::add(account.available, amount).map(|available| account.available = available);
+        self.keep(account)?;
+        credited
+    }
+
+    /// Passes a store result through, refusing the row if the store failed.
+    fn stored<T>(&mut self, result: Result<T, Error>) -> Result<T, Reject> {
+        result.map_err(|err| self.stash(err))
+    }
+
+    /// Keeps the first store failure; rows are refused until it is taken.
+    fn stash(&mut self, err: Error) -> Reject {
+        if self.store_error.is_none() {
+            self.store_error = Some(err);
+        }
+        Reject::StoreUnavailable
+    }
+
+    /// Hands over the error that made the store unavailable, if it failed.
+    /// Until then every row is refused with [`Reject::StoreUnavailable`].
+    pub fn take_store_error(&mut self) -> Option<Error> {
+        self.store_error.take()
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// Registers an observer to be told about every transaction applied from now on.
+    pub fn add_observer(&mut self, observer: Box<dyn EngineObserver<A>>) {
+        self.observers.0.push(observer);
+    }
+
+    /// Makes this engine one of several clients are sharded across. Rows for
+    /// clients `link` doesn't own only ever arrive as the credit side of a
+    /// transfer, which is booked once the debiting engine agrees.
+    pub fn set_link(&mut self, link: Box<dyn Link>) {
+        self.link = Some(link);
+    }
+
+    /// Keeps enough of the last `depth` rows to revert them; zero turns this
+    /// off. Each row journaled costs a copy of its client's account.
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_depth = depth;
+        self.trim_journal();
+    }
+
+    /// Drops journal entries beyond the undo depth that no savepoint needs.
+    fn trim_journal(&mut self) {
+        let keep = self.undo_depth.max(self.since_savepoint.unwrap_or(0));
+        while self.journal.len() > keep {
+            self.journal.pop_front();
+        }
+    }
+
+    /// Marks the current state so [`Engine::rollback_to_savepoint`] can return
+    /// to it, e.g. before each input file. Replaces any earlier savepoint.
+    /// Every row after it is journaled until it is released.
+    pub fn savepoint(&mut self) {
+        self.since_savepoint = Some(0);
+        self.trim_journal();
+    }
+
+    /// Undoes every row applied since the savepoint, which stays in place,
+    /// and returns how many were undone; zero if no savepoint is set.
+    pub fn rollback_to_savepoint(&mut self) -> usize {
+        let rows = self.since_savepoint.unwrap_or(0);
+        self.revert_last(rows)
+    }
+
+    /// Forgets the savepoint, keeping only the usual undo depth journaled.
+    pub fn release_savepoint(&mut self) {
+        self.since_savepoint = None;
+        self.trim_journal();
+    }
+
+    /// Whether an input file with this SHA-256 (hex) has been applied already.
+    pub fn applied_file(&self, digest: &str) -> bool {
+        self.applied_files.contains(digest)
+    }
+
+    /// Records an input file's SHA-256 (hex) as applied.
+    pub fn record_file(&mut self, digest: String) {
+        self.applied_files.insert(digest);
+    }
+
+    /// Rows that [`Engine::revert_last`] can still take back.
+    pub fn revertible(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Undoes the effects of the last `n` rows applied, newest first, and
+    /// returns how many were undone (fewer if the journal is shorter).
+    /// Rejected rows count too, since they may have claimed an id. Deposits
+    /// pruned from other accounts by a retention sweep stay pruned, and
+    /// reverted accounts are not reported to observers.
+    pub fn revert_last(&mut self, n: usize) -> usize {
+        let mut reverted = 0;
+        while reverted < n {
+            let Some(undo) = self.journal.pop_back() else { break };
+            if let Err(err) = self.revert(undo) {
+                self.stash(err);
+                break;
+            }
+            reverted += 1;
+        }
+        if let Some(since) = self.since_savepoint.as_mut() {
+            *since = since.saturating_sub(reverted);
+        }
+        reverted
+    }
+
+    fn revert(&mut self, undo: Undo<A>) -> Result<(), Error> {
+        if undo.sealed {
+            self.sealed.remove(&undo.client);
+            self.finalized.retain(|account| account.client != undo.client);
+        }
+        if let Some(current) = self.remove_account(undo.client, undo.wallet.as_deref())? {
+            self.history_entries -= current.history.len();
+            self.escrowed -= current.held.to_decimal();
+        }
+        // Finalizing leaves the escrow total alone, so neither does undoing it.
+        for before in undo.before.into_iter().chain(undo.others) {
+            self.history_entries += before.history.len();
+            if !undo.sealed {
+                self.escrowed += before.held.to_decimal();
+            }
+            self.store.put(before)?;
+        }
+        if let Some((to, before)) = undo.counterpart {
+            if let Some(current) = self.remove_account(to, None)? {
+                self.history_entries -= current.history.len();
+                self.escrowed -= current.held.to_decimal();
+            }
+            if let Some(before) = before {
+                self.history_entries += before.history.len();
+                self.escrowed += before.held.to_decimal();
+                self.store.put(before)?;
+            }
+        }
+        self.losses -= undo.lost;
+        if let Some(tx) = undo.claimed {
+            self.seen_tx.remove(&tx);
+        }
+        match undo.last_key {
+            Some(Some(key)) => { self.last_keys.insert(undo.client, key); }
+            Some(None) => { self.last_keys.remove(&undo.client); }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Lifts the lock on a client's main account.
+    pub fn unlock(&mut self, client: ClientId) -> Result<(), Reject> {
+        self.override_account(client, |account| {
+            account.locked = false;
+            Ok(())
+        })
+    }
+
+    /// Resolves an open dispute on a client's main account, lock or no lock.
+    /// Provisional credit the client has already spent is booked to losses.
+    pub fn force_resolve(&mut self, client: ClientId, tx: TxId) -> Result<(), Reject> {
+        let retention = self.retention;
+        let mut lost = Decimal::ZERO;
+        self.override_account(client, |account| {
+            let available = account.available;
+            let credit = account.history.get(tx).filter(|deposit| deposit.withdrawal).map(|deposit| deposit.amount);
+            account.force_resolve(tx)?;
+            lost = credit.map_or(Decimal::ZERO, |credit| credit.to_decimal() + moved(available, account.available));
+            if retention == Retention::KeepUndisputedOnly {
+                account.forget(tx);
+            }
+            Ok(())
+        })?;
+        self.losses += lost;
+        Ok(())
+    }
+
+    /// Corrects a client's main account by `amount`, which may be negative.
+    pub fn adjust(&mut self, client: ClientId, amount: Decimal) -> Result<(), Reject> {
+        let amount = convert(amount)?;
+        self.override_account(client, |account| account.adjust(amount))
+    }
+
+    /// Changes an open account outside the row stream. The undo journal is
+    /// cleared, since its entries assume only rows came after them.
+    fn override_account(&mut self, client: ClientId, change: impl FnOnce(&mut Account<A>) -> Result<(), Reject>) -> Result<(), Reject> {
+        if self.sealed.contains(&client) {
+            return Err(Reject::AccountFinalized);
+        }
+        let taken = self.store.take(client, MAIN_WALLET);
+        let mut account = self.stored(taken)?.ok_or(Reject::UnknownClient)?;
+        let (held, entries) = (account.held, account.history.len());
+        let result = change(&mut account);
+        self.escrowed += moved(held, account.held);
+        self.history_entries = self.history_entries + account.history.len() - entries;
+        let kept = self.store.put(account);
+        self.stored(kept)?;
+        result?;
+        self.journal.clear();
+        self.schedule_disputes();
+        Ok(())
+    }
+
+    /// Applies a single transaction, creating the client's account on first
+    /// sight. A joint account's co-owners' rows apply to it as if it had sent them.
+    pub fn apply(&mut self, record: &Transaction) -> Result<(), Reject> {
+        if self.store_error.is_some() {
+            return Err(Reject::StoreUnavailable);
+        }
+        if let Some(timestamp) = record.timestamp.filter(|_| !self.dispute_deadlines.is_empty()) {
+            self.expire_disputes(timestamp);
+        }
+        match self.joint.account_of(record.client) {
+            Some(client) => self.apply_journaled(&Transaction { client, ..record.clone() }),
+            None => self.apply_journaled(record),
+        }
+    }
+
+    fn apply_journaled(&mut self, record: &Transaction) -> Result<(), Reject> {
+        if self.undo_depth == 0 && self.since_savepoint.is_none() {
+            return self.apply_observed(record);
+        }
+
+        let wallet = self.wallet_of(record).map(str::to_string);
+        let before = self.account_in(record.client, wallet.as_deref()).map(|before| before.map(Cow::into_owned));
+        let before = self.stored(before)?;
+        let was_sealed = self.sealed.contains(&record.client);
+        let had_tx = self.seen_tx.contains(&record.tx);
+        let last_key = self.last_keys.get(&record.client).copied();
+        let losses = self.losses;
+        let counterpart = match self.credited(record) {
+            Some(to) => {
+                let before = self.account_in(to, None).map(|before| before.map(Cow::into_owned));
+                Some((to, self.stored(before)?))
+            }
+            None => None,
+        };
+        let result = self.apply_observed(record);
+        let sealed = !was_sealed && self.sealed.contains(&record.client);
+        let others = if sealed {
+            self.finalized.iter()
+                .filter(|account| account.client == record.client)
+                .filter(|account| account.wallet.as_deref().filter(|held| *held != MAIN_WALLET) != wallet.as_deref())
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let undo = Undo {
+            client: record.client,
+            wallet,
+            before,
+            others,
+            sealed,
+            claimed: (!had_tx && self.seen_tx.contains(&record.tx)).then_some(record.tx),
+            last_key: (self.last_keys.get(&record.client).copied() != last_key).then_some(last_key),
+            lost: self.losses - losses,
+            counterpart,
+        };
+        self.journal.push_back(undo);
+        if let Some(since) = self.since_savepoint.as_mut() {
+            *since += 1;
+        }
+        self.trim_journal();
+        result
+    }
+
+    fn apply_observed(&mut self, record: &Transaction) -> Result<(), Reject> {
+        if self.observers.0.is_empty() {
+            return self.execute(record);
+        }
+
+        // The deposit may be forgotten once charged back, so take its amount first.
+        let wallet = self.wallet_of(record);
+        let before = self.account_in(record.client, wallet).map(|before| before.map_or((false, None), |account| {
+            (account.locked, account.history.get(record.tx).map(|deposit| deposit.amount))
+        }));
+        let (was_locked, charged) = self.stored(before)?;
+
+        let result = self.execute(record);
+        // Observers are set aside while they look at the account they are told about.
+        let mut observers = std::mem::take(&mut self.observers);
+        let account = match self.account_in(record.client, wallet) {
+            Ok(account) => account.or_else(|| self.finalized.last().filter(|account| account.client == record.client).map(Cow::Borrowed)),
+            Err(err) => {
+                self.observers = observers;
+                return Err(self.stash(err));
+            }
+        };
+        for observer in observers.0.iter_mut() {
+            match (result, account.as_deref()) {
+                (Err(reject), _) => observer.on_rejected(record, reject),
+                (Ok(()), Some(account)) => {
+                    observer.on_applied(record, account);
+                    if let (TxType::Chargeback, Some(amount)) = (record.tx_type, charged) {
+                        observer.on_chargeback(record, amount.to_decimal(), account);
+                    }
+                    if account.locked && !was_locked {
+                        observer.on_lock(account);
+                    }
+                }
+                (Ok(()), None) => {}
+            }
+        }
+        self.observers = observers;
+        result
+    }
+
+    /// Applies a row; a transfer whose other side another engine owns is
+    /// settled with it through the link.
+    fn execute(&mut self, record: &Transaction) -> Result<(), Reject> {
+        let Some(link) = self.link.as_ref().filter(|_| record.tx_type == TxType::Transfer) else {
+            return self.execute_row(record);
+        };
+        if !link.owns(record.client) {
+            return self.receive(record);
+        }
+        let Some(to) = transfer::counterpart(record).filter(|to| !link.owns(*to)) else {
+            return self.execute_row(record);
+        };
+        // The debit is prepared by taking the funds, and put back if the other side refuses.
+        let vote = self.execute_row(record);
+        let peer = self.link.as_mut().expect("link was just checked").exchange(to, vote);
+        if let (Ok(()), Err(_)) = (vote, peer) {
+            let wallet = self.wallet_of(record).map(str::to_string);
+            let amount = record.amount.expect("a prepared transfer has an amount");
+            self.credit(record.client, wallet.as_deref(), convert(amount)?)?;
+        }
+        vote.and(peer)
+    }
+
+    /// The credit side of a transfer debited by another engine: votes on
+    /// whether the credit can be booked, and books it if both sides agree.
+    /// Refusing is not a failure, since the row is counted where it was
+    /// debited; only the store failing is.
+    fn receive(&mut self, record: &Transaction) -> Result<(), Reject> {
+        let to = transfer::counterpart(record).expect("only transfers with a counterpart are dealt to both sides");
+        let amount = record.amount.unwrap_or_default();
+        let vote = self.accepts(to, amount).and_then(|()| convert(amount));
+        let peer = self.link.as_mut().expect("only linked engines receive transfers").exchange(record.client, vote.map(drop));
+        if let (Ok(amount), Ok(())) = (vote, peer) {
+            self.credit(to, None, amount)?;
+        }
+        Ok(())
+    }
+
+    /// The client a transfer row credits in this engine, if any.
+    fn credited(&self, record: &Transaction) -> Option<ClientId> {
+        let to = transfer::counterpart(record).filter(|_| record.tx_type == TxType::Transfer)?;
+        let to = self.joint.account_of(to).unwrap_or(to);
+        (to != record.client && self.link.as_ref().is_none_or(|link| link.owns(to))).then_some(to)
+    }
+
+    fn execute_row(&mut self, record: &Transaction) -> Result<(), Reject> {
+        if let Some(timestamp) = record.timestamp {
+            self.clock = Some(self.clock.map_or(timestamp, |clock| clock.max(timestamp)));
+        }
+        if let Retention::KeepWithin(_) = self.retention {
+            self.since_sweep += 1;
+            if self.since_sweep >= SWEEP_EVERY {
+                self.sweep();
+            }
+        }
+        if !self.quotas.is_empty() {
+            let at = match record.timestamp.or(self.clock) {
+                Some(at) => at,
+                None => collections::now("a quota on rows without a timestamp").map_err(|_| Reject::MissingTimestamp)?,
+            };
+            if !self.quotas.admit(self.quota_usage.entry(record.client).or_default(), at) {
+                return Err(Reject::QuotaExceeded);
+            }
+        }
+
+        if self.sealed.contains(&record.client) {
+            return Err(Reject::AccountFinalized);
+        }
+        if self.escrow_account == Some(record.client) {
+            return Err(Reject::EscrowAccount);
+        }
+        if self.loss_account == Some(record.client) {
+            return Err(Reject::LossAccount);
+        }
+        if self.rules.iter().any(|rule| rule.rejects(record)) {
+            return Err(Reject::RuleRejected);
+        }
+        let class = self.class_of.get(&record.client).map(|&index| &self.classes[index]);
+        if class.is_some_and(|class| !class.allows(record.tx_type)) {
+            return Err(Reject::TypeNotAllowed);
+        }
+        let max_deposit = class.and_then(|class| class.limits.max_deposit).or(self.limits.max_deposit);
+        let max_withdrawal = class.and_then(|class| class.limits.max_withdrawal).or(self.limits.max_withdrawal);
+        let locked_policy = class.and_then(|class| class.locked).unwrap_or(self.locked_policy);
+        if let Some(sequence) = self.sequence {
+            if let Some(key) = sequence.of(record) {
+                match self.last_keys.get(&record.client) {
+                    Some(&last) if !sequence.follows(last, key) => return Err(Reject::OutOfOrder),
+                    _ => { self.last_keys.insert(record.client, key); }
+                }
+            }
+        }
+        // Disputes and their follow-ups name an earlier id; only new rows claim one.
+        if self.unique_tx_ids && matches!(record.tx_type, TxType::Deposit | TxType::Withdrawal | TxType::Transfer)
+            && !self.seen_tx.insert(record.tx)
+        {
+            return Err(Reject::DuplicateTx);
+        }
+        if record.tx_type == TxType::Finalize {
+            return self.finalize(record.client);
+        }
+        if DisputeState::after(record.tx_type).is_some() && self.dispute_filter.as_ref().is_some_and(|filter| !filter.may_contain(record.tx)) {
+            return Err(Reject::UnknownTx);
+        }
+        if record.tx_type == TxType::Transfer {
+            return self.transfer(record, max_withdrawal);
+        }
+
+        let wallet = self.wallet_of(record).map(str::to_string);
+        let wallet = wallet.as_deref();
+        let mut opened = self.open_account(record.client, wallet)?;
+        let account = &mut opened;
+        let retention = self.retention;
+        let before = account.history.len();
+        let (available, held) = (account.available, account.held);
+        // A resolve may forget the withdrawal, so note it first.
+        let disputed_withdrawal = account.history.get(record.tx).filter(|deposit| deposit.withdrawal).map(|deposit| deposit.amount);
+        let provisional_credit = self.provisional_credit;
+        let max_cycles = self.max_cycles;
+        // Converted up front, so a fee the amount type can't hold refuses the chargeback.
+        let fee = convert::<A>(self.chargeback_fee);
+
+        let result = match record.tx_type {
+            TxType::Deposit => record.amount.ok_or(Reject::MissingAmount)
+                .and_then(|amount| within(amount, max_deposit))
+                .and_then(convert)
+                .and_then(|amount| match locked_policy {
+                    LockedPolicy::AcceptDeposits if account.locked => account.credit(record.tx, amount, record.timestamp),
+                    _ => account.deposit_at(record.tx, amount, record.timestamp),
+                })
+                .inspect(|_| { retention.prune(account, self.clock); }),
+            TxType::Withdrawal => record.amount.ok_or(Reject::MissingAmount)
+                .and_then(|amount| within(amount, max_withdrawal))
+                .and_then(convert)
+                .and_then(|amount| account.withdrawal(amount).map(|()| amount))
+                .map(|amount| if provisional_credit {
+                    account.keep_withdrawal(record.tx, amount, record.timestamp);
+                    retention.prune(account, self.clock);
+                }),
+            TxType::Dispute => {
+                retention.prune(account, self.clock);
+                account.dispute(record.tx)
+            }
+            TxType::RequestEvidence => account.request_evidence(record.tx),
+            TxType::Review => account.review(record.tx),
+            TxType::Representment => account.represent(record.tx, max_cycles),
+            TxType::Resolve | TxType::Chargeback => {
+                let result = if record.tx_type == TxType::Resolve {
+                    account.resolve(record.tx)
+                } else {
+                    fee.and_then(|_| account.chargeback(record.tx))
+                };
+                // A charged-back deposit with cycles left is kept for its representment.
+                let contestable = record.tx_type == TxType::Chargeback
+                    && account.history.get(record.tx).is_some_and(|deposit| deposit.cycles < max_cycles);
+                if result.is_ok() && retention == Retention::KeepUndisputedOnly && !contestable {
+                    account.forget(record.tx);
+                }
+                result
+            }
+            TxType::Finalize | TxType::Transfer => unreachable!("finalize and transfers are handled before account lookup"),
+        };
+
+        if let (Ok(()), TxType::Dispute | TxType::Representment) = (result, record.tx_type) {
+            if let Some(mut deposit) = account.history.get(record.tx) {
+                deposit.opened = self.clock;
+                account.history.insert(record.tx, deposit);
+            }
+            if let (Some(window), Some(now)) = (self.auto_resolve, self.clock) {
+                self.dispute_deadlines.insert((now + window, record.client, record.tx, wallet.map(str::to_string)));
+            }
+        }
+        if let (Ok(()), Some(case)) = (result, record.metadata.get("case").filter(|case| !case.is_empty())) {
+            if DisputeState::after(record.tx_type).is_some() && account.history.contains(record.tx) {
+                account.set_case(record.tx, case.clone());
+            }
+        }
+        self.history_entries = self.history_entries + account.history.len() - before;
+        if let (Ok(()), Some(amount)) = (result, disputed_withdrawal) {
+            match record.tx_type {
+                TxType::Dispute => self.provisional.push((record.client, record.tx, amount.to_decimal())),
+                TxType::Resolve => {
+                    self.provisional.push((record.client, record.tx, -amount.to_decimal()));
+                    // Credit already spent could not be taken back, so it is lost.
+                    self.losses += amount.to_decimal() + moved(available, account.available);
+                }
+                _ => {}
+            }
+        }
+        // A chargeback on a withdrawal goes the client's way, so it carries no fee.
+        // The fee is taken only as far as the client's funds go; the whole fee
+        // is booked to losses either way, so what was not collected stays a loss.
+        if let (TxType::Chargeback, Ok(()), None, Ok(fee)) = (record.tx_type, result, disputed_withdrawal, fee) {
+            // What is taken never exceeds what is available, so it can't go out of range.
+            let taken = fee.min(account.available.max(A::default()));
+            if let Ok(available) = money::sub(account.available, taken) {
+                account.available = available;
+            }
+            self.losses += moved(account.held, held) + self.chargeback_fee;
+        }
+        if record.tx_type == TxType::Representment && result.is_ok() {
+            self.losses -= moved(held, account.held);
+        }
+        let kept = result.is_ok() && matches!(record.tx_type, TxType::Deposit | TxType::Withdrawal) && account.history.contains(record.tx);
+        self.escrowed += moved(held, account.held);
+        self.keep(opened)?;
+        if let Some(filter) = self.dispute_filter.as_mut().filter(|_| kept) {
+            filter.insert(record.tx);
+            if filter.is_full() {
+                let capacity = 2 * filter.capacity();
+                self.rebuild_filter(capacity);
+            }
+        }
+        result
+    }
+
+    /// Debits the row's client and credits the one in its `to` column, or
+    /// changes neither. With a link and a counterpart another engine owns,
+    /// only the debit is taken here; [`Engine::execute`] settles the rest.
+    fn transfer(&mut self, record: &Transaction, max_withdrawal: Option<Decimal>) -> Result<(), Reject> {
+        let to = transfer::counterpart(record)
+            .map(|to| self.joint.account_of(to).unwrap_or(to))
+            .filter(|to| *to != record.client)
+            .ok_or(Reject::InvalidTransfer)?;
+        let amount = record.amount.ok_or(Reject::MissingAmount).and_then(|amount| within(amount, max_withdrawal))?;
+        let units = convert(amount)?;
+        let wallet = self.wallet_of(record).map(str::to_string);
+        let wallet = wallet.as_deref();
+        let known = self.account_in(record.client, wallet).map(|account| account.is_some());
+        if !self.stored(known)? {
+            return Err(Reject::InsufficientFunds);
+        }
+        let mut account = self.open_account(record.client, wallet)?;
+        let debited = account.withdrawal(units);
+        self.keep(account)?;
+        debited?;
+        if self.link.as_ref().is_some_and(|link| !link.owns(to)) {
+            return Ok(());
+        }
+        if let Err(reject) = self.accepts(to, amount) {
+            self.credit(record.client, wallet, units)?;
+            return Err(reject);
+        }
+        if let Err(reject) = self.credit(to, None, units) {
+            self.credit(record.client, wallet, units)?;
+            return Err(reject);
+        }
+        Ok(())
+    }
+
+    /// Whether `to`'s main account can be credited `amount` by a transfer.
+    fn accepts(&mut self, to: ClientId, amount: Decimal) -> Result<(), Reject> {
+        if self.sealed.contains(&to) {
+            return Err(Reject::AccountFinalized);
+        }
+        if self.escrow_account == Some(to) {
+            return Err(Reject::EscrowAccount);
+        }
+        if self.loss_account == Some(to) {
+            return Err(Reject::LossAccount);
+        }
+        let class = self.class_of.get(&to).map(|&index| &self.classes[index]);
+        within(amount, class.and_then(|class| class.limits.max_deposit).or(self.limits.max_deposit))?;
+        let locked_policy = class.and_then(|class| class.locked).unwrap_or(self.locked_policy);
+        if locked_policy != LockedPolicy::AcceptDeposits {
+            let locked = self.store.get(to, MAIN_WALLET).map(|account| account.is_some_and(|account| account.locked));
+            if self.stored(locked)? {
+                return Err(Reject::AccountLocked);
+            }
+        }
+        Ok(())
+    }
+
+    /// Prunes expired deposits from every account under time-based retention.
+    fn sweep(&mut self) {
+        self.since_sweep = 0;
+        let (retention, clock) = (self.retention, self.clock);
+        let mut pruned = 0;
+        let mut prune = |account: &mut Account<A>| pruned += retention.prune(account, clock);
+        let swept = self.store.update_all(&mut prune);
+        self.history_entries -= pruned;
+        if let Err(err) = swept {
+            self.stash(err);
+        }
+    }
+
+    /// Removes a client's accounts, every wallet's, from the working set,
+    /// dropping their history, and queues them for reporting.
+    fn finalize(&mut self, client: ClientId) -> Result<(), Reject> {
+        let removed = self.store.remove_client(client);
+        let accounts = self.stored(removed)?;
+        if accounts.is_empty() {
+            return Err(Reject::UnknownClient);
+        }
+        for account in accounts {
+            self.history_entries -= account.history.len();
+            self.finalized.push(account);
+        }
+        self.sealed.insert(client);
+        Ok(())
+    }
+
+    /// Hands over accounts finalized since the last call so they can be written out.
+    pub fn take_finalized(&mut self) -> Vec<Account<A>> {
+        std::mem::take(&mut self.finalized)
+    }
+
+    /// Recomputes the running history total after accounts were replaced wholesale.
+    pub(crate) fn recount_history(&mut self) -> Result<(), Error> {
+        self.history_entries = self.accounts().map(|account| account.map(|account| account.history.len())).sum::<Result<_, Error>>()?;
+        Ok(())
+    }
+
+    /// Approximate heap footprint of the state held in memory.
+    pub fn memory_usage(&self) -> Result<MemoryUsage, Error> {
+        Ok(MemoryUsage {
+            accounts: self.len()?,
+            history_entries: self.history_entries,
+            sealed: self.sealed.len(),
+            tx_ids: self.seen_tx.len(),
+            filter_bytes: self.dispute_filter.as_ref().map_or(0, BloomFilter::size_bytes),
+        })
+    }
+
+    /// SHA-256 over a canonical encoding of every account, its dispute-able
+    /// history and the finalized clients, as lowercase hex. Independent of
+    /// map iteration order and of decimal scale (`1.0` and `1.00` hash alike),
+    /// so two runs over the same data can be compared by this value alone.
+    pub fn fingerprint(&self) -> Result<String, Error> {
+        let mut accounts: Vec<Cow<Account<A>>> = self.accounts().collect::<Result<_, _>>()?;
+        accounts.sort_by(|a, b| (a.client, &a.wallet).cmp(&(b.client, &b.wallet)));
+        let mut hasher = Sha256::new();
+
+        for account in accounts {
+            let client = &account.client;
+            if let Some(wallet) = &account.wallet {
+                hasher.update(format!("wallet {}\n", wallet).as_bytes());
+            }
+            hasher.update(format!(
+                "account {} {} {} {}\n",
+                client.0, account.available.to_decimal().normalize(), account.held.to_decimal().normalize(), account.locked
+            ).as_bytes());
+
+            for (tx, deposit) in account.history.iter() {
+                // Only the stages a boolean can't express are spelled out, so older hashes still match.
+                let disputed = match deposit.state {
+                    DisputeState::EvidenceRequested | DisputeState::UnderReview | DisputeState::Represented => deposit.state.to_string(),
+                    state => state.is_open().to_string(),
+                };
+                let disputed = match deposit.cycles {
+                    0 | 1 => disputed,
+                    cycles => format!("{} cycle {}", disputed, cycles),
+                };
+                let kind = if deposit.withdrawal { "withdrawal" } else { "tx" };
+                let line = match deposit.timestamp {
+                    Some(at) => format!("{} {} {} {} {}\n", kind, tx.0, deposit.amount.to_decimal().normalize(), disputed, at),
+                    None => format!("{} {} {} {}\n", kind, tx.0, deposit.amount.to_decimal().normalize(), disputed),
+                };
+                hasher.update(line.as_bytes());
+            }
+
+            let mut pruned: Vec<TxId> = account.pruned().collect();
+            pruned.sort();
+            for tx in pruned {
+                hasher.update(format!("pruned {}\n", tx.0).as_bytes());
+            }
+        }
+
+        let mut sealed: Vec<&ClientId> = self.sealed.iter().collect();
+        sealed.sort();
+        for client in sealed {
+            hasher.update(format!("sealed {}\n", client.0).as_bytes());
+        }
+        Ok(sha256::to_hex(&hasher.finalize()))
+    }
+
+    pub fn account(&self, client: ClientId) -> Result<Option<Cow<'_, Account<A>>>, Error> {
+        self.store.get(client, MAIN_WALLET)
+    }
+
+    /// The open account a row applies to, after joint-owner and wallet routing.
+    pub fn account_for(&self, record: &Transaction) -> Result<Option<Cow<'_, Account<A>>>, Error> {
+        let client = self.joint.account_of(record.client).unwrap_or(record.client);
+        self.account_in(client, self.wallet_of(record))
+    }
+
+    /// Every open account, those of wallets other than main included.
+    pub fn accounts(&self) -> impl Iterator<Item = Result<Cow<'_, Account<A>>, Error>> {
+        self.store.iter()
+    }
+
+    /// Reported figures for every open account, in no particular order,
+    /// followed by the escrow and loss accounts if configured. With wallets,
+    /// each client also gets a [`report::TOTAL_WALLET`] row.
+    pub fn reports(&self) -> Result<impl Iterator<Item = AccountReport> + '_, Error> {
+        let reports = self.accounts().map(|account| account.map(|account| self.report(&account))).collect::<Result<_, _>>()?;
+        Ok(report::with_totals(reports).into_iter().chain(self.escrow()).chain(self.losses()))
+    }
+
+    /// An account's reported figures, with its owners while joint accounts are configured.
+    pub fn report(&self, account: &Account<A>) -> AccountReport {
+        AccountReport { owners: self.owners(account.client), ..AccountReport::of(account) }
+    }
+
+    fn owners(&self, client: ClientId) -> Option<String> {
+        (!self.joint.is_empty()).then(|| self.joint.describe(client))
+    }
+
+    /// The escrow account: every disputed amount in the book as its
+    /// available balance. Client rows still show their share as `held`.
+    pub fn escrow(&self) -> Option<AccountReport> {
+        self.escrow_account.map(|client| self.ledger_report(client, self.escrowed))
+    }
+
+    /// The loss account: every deposit charged back and every chargeback
+    /// fee in the book, as its available balance.
+    pub fn losses(&self) -> Option<AccountReport> {
+        self.loss_account.map(|client| self.ledger_report(client, self.losses))
+    }
+
+    /// A row for an account the engine books to itself rather than a client's.
+    fn ledger_report(&self, client: ClientId, available: Decimal) -> AccountReport {
+        AccountReport {
+            client,
+            available,
+            held: Decimal::ZERO,
+            locked: false,
+            wallet: self.wallets.then(|| MAIN_WALLET.to_string()),
+            owners: self.owners(client),
+        }
+    }
+
+    /// Number of accounts still held in the store, one per wallet; finalized
+    /// accounts are not counted.
+    pub fn len(&self) -> Result<usize, Error> {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::fixed::Fixed;
+
+    fn tx(tx_type: TxType, client: u32, tx: u32, amount: Option<Decimal>) -> Transaction {
+        Transaction::new(tx_type, ClientId(client), TxId(tx), amount)
+    }
+
+    #[test]
+    fn test_apply_routes_to_client_account() {
+        let mut engine = Engine::new();
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5.0)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 2, 2, Some(dec!(7.0)))).unwrap();
+        assert_eq!(engine.len().unwrap(), 2);
+        assert_eq!(engine.account(ClientId(2)).unwrap().unwrap().available, dec!(7.0));
+    }
+
+    #[test]
+    fn test_apply_without_amount_is_rejected() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.apply(&tx(TxType::Withdrawal, 1, 1, None)), Err(Reject::MissingAmount));
+    }
+
+    #[test]
+    fn test_finalize_releases_account_and_seals_client() {
+        let mut engine = Engine::new();
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5.0)))).unwrap();
+        engine.apply(&tx(TxType::Finalize, 1, 0, None)).unwrap();
+
+        assert!(engine.is_empty().unwrap());
+        let finalized = engine.take_finalized();
+        assert_eq!(finalized.len(), 1);
+        assert_eq!(finalized[0].available, dec!(5.0));
+        assert!(engine.take_finalized().is_empty());
+        assert_eq!(engine.apply(&tx(TxType::Deposit, 1, 2, Some(dec!(1.0)))), Err(Reject::AccountFinalized));
+    }
+
+    #[test]
+    fn test_finalize_unknown_client_is_rejected() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.apply(&tx(TxType::Finalize, 9, 0, None)), Err(Reject::UnknownClient));
+    }
+
+    #[test]
+    fn test_dispute_filter_refuses_unknown_ids_early() {
+        let mut engine = Engine::with_policy(&Policy { dispute_filter: true, retention: Retention::KeepUndisputedOnly, ..Default::default() });
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5)))).unwrap();
+        for id in 2..5_000 {
+            engine.apply(&tx(TxType::Deposit, 1, id, Some(dec!(1)))).unwrap();
+        }
+        assert_eq!(engine.apply(&tx(TxType::Dispute, 2, 99_999, None)), Err(Reject::UnknownTx));
+        assert!(engine.account(ClientId(2)).unwrap().is_none());
+
+        engine.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+        engine.apply(&tx(TxType::Resolve, 1, 1, None)).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Dispute, 1, 1, None)), Err(Reject::TxPruned));
+        assert!((2..5_000).all(|id| engine.dispute_filter.as_ref().unwrap().may_contain(TxId(id))));
+    }
+
+    #[test]
+    fn test_merge_disjoint_partitions() {
+        let mut left = Engine::new();
+        left.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5.0)))).unwrap();
+        let mut right = Engine::new();
+        right.apply(&tx(TxType::Deposit, 2, 2, Some(dec!(3.0)))).unwrap();
+        right.apply(&tx(TxType::Dispute, 2, 2, None)).unwrap();
+
+        left.merge(right).unwrap();
+        assert_eq!(left.len().unwrap(), 2);
+        assert_eq!(left.account(ClientId(2)).unwrap().unwrap().held, dec!(3.0));
+        assert_eq!(left.apply(&tx(TxType::Resolve, 2, 2, None)), Ok(()));
+    }
+
+    #[test]
+    fn test_merge_overlapping_client_fails_untouched() {
+        let mut left = Engine::new();
+        left.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5.0)))).unwrap();
+        let mut right = Engine::new();
+        right.apply(&tx(TxType::Deposit, 3, 3, Some(dec!(1.0)))).unwrap();
+        right.apply(&tx(TxType::Deposit, 1, 2, Some(dec!(1.0)))).unwrap();
+
+        assert!(matches!(left.merge(right), Err(Error::MergeConflict(ClientId(1)))));
+        assert_eq!(left.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_order_and_scale() {
+        let mut left = Engine::new();
+        left.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5.0)))).unwrap();
+        left.apply(&tx(TxType::Deposit, 2, 2, Some(dec!(3)))).unwrap();
+        let mut right = Engine::new();
+        right.apply(&tx(TxType::Deposit, 2, 2, Some(dec!(3.00)))).unwrap();
+        right.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5)))).unwrap();
+        assert_eq!(left.fingerprint().unwrap(), right.fingerprint().unwrap());
+
+        right.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+        assert_ne!(left.fingerprint().unwrap(), right.fingerprint().unwrap());
+    }
+
+    #[test]
+    fn test_memory_usage_tracks_history() {
+        let mut engine = Engine::new();
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5.0)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 1, 2, Some(dec!(5.0)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 2, 3, Some(dec!(5.0)))).unwrap();
+        engine.apply(&tx(TxType::Withdrawal, 2, 4, Some(dec!(1.0)))).unwrap();
+        let usage = engine.memory_usage().unwrap();
+        assert_eq!((usage.accounts, usage.history_entries, usage.sealed), (2, 3, 0));
+
+        engine.apply(&tx(TxType::Finalize, 1, 0, None)).unwrap();
+        let usage = engine.memory_usage().unwrap();
+        assert_eq!((usage.accounts, usage.history_entries, usage.sealed), (1, 1, 1));
+        assert!(usage.bytes() > 0);
+    }
+
+    #[test]
+    fn test_keep_undisputed_only_forgets_settled_deposits() {
+        let mut engine = Engine::with_retention(Retention::KeepUndisputedOnly);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(5.0)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+        engine.apply(&tx(TxType::Resolve, 1, 1, None)).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Dispute, 1, 1, None)), Err(Reject::TxPruned));
+        assert_eq!(engine.memory_usage().unwrap().history_entries, 0);
+    }
+
+    #[test]
+    fn test_keep_within_prunes_against_stream_clock() {
+        let mut engine = Engine::with_retention(Retention::KeepWithin(60));
+        let mut old = tx(TxType::Deposit, 1, 1, Some(dec!(5.0)));
+        old.timestamp = Some(1_000);
+        engine.apply(&old).unwrap();
+        let mut recent = tx(TxType::Deposit, 2, 2, Some(dec!(5.0)));
+        recent.timestamp = Some(1_100);
+        engine.apply(&recent).unwrap();
+
+        assert_eq!(engine.apply(&tx(TxType::Dispute, 1, 1, None)), Err(Reject::TxPruned));
+        engine.apply(&tx(TxType::Dispute, 2, 2, None)).unwrap();
+        assert_eq!(engine.memory_usage().unwrap().history_entries, 1);
+    }
+
+    #[test]
+    fn test_quotas_refuse_a_noisy_client_only() {
+        let policy: Policy = "[quotas]\nper_minute = 2\n".parse().unwrap();
+        let mut engine = Engine::with_policy(&policy);
+        let at = |tx_type, client, id, seconds| Transaction { timestamp: Some(seconds), ..tx(tx_type, client, id, Some(dec!(1))) };
+        engine.apply(&at(TxType::Deposit, 1, 1, 0)).unwrap();
+        engine.apply(&at(TxType::Deposit, 1, 2, 10)).unwrap();
+        assert_eq!(engine.apply(&at(TxType::Deposit, 1, 3, 20)), Err(Reject::QuotaExceeded));
+        engine.apply(&at(TxType::Deposit, 2, 4, 20)).unwrap();
+        let untimed = tx(TxType::Deposit, 1, 5, Some(dec!(1)));
+        assert_eq!(engine.apply(&untimed), Err(Reject::QuotaExceeded), "counted at the newest timestamp seen");
+        engine.apply(&at(TxType::Withdrawal, 1, 6, 60)).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(1));
+    }
+
+    #[cfg(feature = "deterministic")]
+    #[test]
+    fn test_quotas_refuse_untimed_rows_without_a_clock() {
+        let policy: Policy = "[quotas]\nper_minute = 2\n".parse().unwrap();
+        let mut engine = Engine::with_policy(&policy);
+        assert_eq!(engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(1)))), Err(Reject::MissingTimestamp));
+    }
+
+    #[test]
+    fn test_policy_limits_and_locked_deposits() {
+        let policy = Policy {
+            limits: Limits { max_deposit: Some(dec!(100)), max_withdrawal: Some(dec!(10)) },
+            locked: LockedPolicy::AcceptDeposits,
+            ..Default::default()
+        };
+        let mut engine = Engine::with_policy(&policy);
+        assert_eq!(engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(100.01)))), Err(Reject::LimitExceeded));
+        engine.apply(&tx(TxType::Deposit, 1, 2, Some(dec!(50)))).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Withdrawal, 1, 3, Some(dec!(20)))), Err(Reject::LimitExceeded));
+
+        engine.apply(&tx(TxType::Dispute, 1, 2, None)).unwrap();
+        engine.apply(&tx(TxType::Chargeback, 1, 2, None)).unwrap();
+        engine.apply(&tx(TxType::Deposit, 1, 4, Some(dec!(5)))).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Withdrawal, 1, 5, Some(dec!(1)))), Err(Reject::AccountLocked));
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(5));
+    }
+
+    #[test]
+    fn test_policy_rules_reject_matching_rows() {
+        let policy = Policy { rules: crate::script::parse_rules("reject if type == \"withdrawal\" && amount >= 5").unwrap(), ..Default::default() };
+        let mut engine = Engine::with_policy(&policy);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(50)))).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Withdrawal, 1, 2, Some(dec!(5)))), Err(Reject::RuleRejected));
+        engine.apply(&tx(TxType::Withdrawal, 1, 3, Some(dec!(4)))).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(46));
+    }
+
+    #[test]
+    fn test_account_classes_override_policy() {
+        let policy: Policy = "[limits]\nmax_withdrawal = \"10\"\n\
+            [classes.merchant]\nclients = \"2\"\nmax_withdrawal = \"100\"\nallowed = \"deposit, withdrawal\"\n".parse().unwrap();
+        let mut engine = Engine::with_policy(&policy);
+        for client in [1, 2] {
+            engine.apply(&tx(TxType::Deposit, client, client, Some(dec!(50)))).unwrap();
+        }
+        assert_eq!(engine.apply(&tx(TxType::Withdrawal, 1, 3, Some(dec!(40)))), Err(Reject::LimitExceeded));
+        engine.apply(&tx(TxType::Withdrawal, 2, 4, Some(dec!(40)))).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Dispute, 2, 2, None)), Err(Reject::TypeNotAllowed));
+        engine.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+    }
+
+    #[test]
+    fn test_joint_owners_share_a_balance() {
+        let mut policy = Policy::default();
+        policy.joint.link(ClientId(1), ClientId(5)).unwrap();
+        let mut engine = Engine::with_policy(&policy);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 5, 1, None)).unwrap();
+        engine.apply(&tx(TxType::Resolve, 1, 1, None)).unwrap();
+        engine.apply(&tx(TxType::Withdrawal, 5, 2, Some(dec!(4)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 2, 3, Some(dec!(1)))).unwrap();
+
+        assert_eq!(engine.len().unwrap(), 2);
+        let mut reports: Vec<_> = engine.reports().unwrap().map(|report| (report.client, report.available, report.owners)).collect();
+        reports.sort_by_key(|report| report.0);
+        assert_eq!(reports, vec![
+            (ClientId(1), dec!(6), Some("1 5".to_string())),
+            (ClientId(2), dec!(1), Some("2".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn test_required_order_rejects_late_ids_per_client() {
+        let mut policy = Policy::default();
+        policy.ordering.require = Some(SequenceKey::TxId);
+        let mut engine = Engine::with_policy(&policy);
+        engine.apply(&tx(TxType::Deposit, 1, 5, Some(dec!(10)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 2, 3, Some(dec!(10)))).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Deposit, 1, 4, Some(dec!(1)))), Err(Reject::OutOfOrder));
+        engine.apply(&tx(TxType::Dispute, 1, 5, None)).unwrap();
+        engine.apply(&tx(TxType::Deposit, 1, 6, Some(dec!(1)))).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().held, dec!(10));
+    }
+
+    #[test]
+    fn test_unique_tx_ids_span_clients() {
+        let policy = Policy { unique_tx_ids: true, ..Default::default() };
+        let mut engine = Engine::with_policy(&policy);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Deposit, 2, 1, Some(dec!(5)))), Err(Reject::DuplicateTx));
+        assert_eq!(engine.apply(&tx(TxType::Withdrawal, 1, 1, Some(dec!(5)))), Err(Reject::DuplicateTx));
+        engine.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+        assert!(engine.account(ClientId(2)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_revert_last_restores_earlier_state() {
+        let policy = Policy { unique_tx_ids: true, ..Default::default() };
+        let mut engine = Engine::with_policy(&policy);
+        engine.set_undo_depth(3);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        let fingerprint = engine.fingerprint().unwrap();
+        engine.apply(&tx(TxType::Deposit, 2, 2, Some(dec!(5)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+        engine.apply(&tx(TxType::Finalize, 1, 0, None)).unwrap();
+        assert_eq!(engine.revertible(), 3);
+
+        assert_eq!(engine.revert_last(5), 3);
+        assert_eq!(engine.fingerprint().unwrap(), fingerprint);
+        assert_eq!(engine.memory_usage().unwrap().history_entries, 1);
+        assert!(engine.take_finalized().is_empty());
+        engine.apply(&tx(TxType::Deposit, 2, 2, Some(dec!(5)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().held, dec!(10));
+    }
+
+    #[test]
+    fn test_escrow_account_tracks_disputed_funds() {
+        let policy = Policy { escrow_account: Some(ClientId(u32::MAX)), ..Default::default() };
+        let mut engine = Engine::with_policy(&policy);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 2, 2, Some(dec!(4)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+        engine.apply(&tx(TxType::Dispute, 2, 2, None)).unwrap();
+        engine.apply(&tx(TxType::Resolve, 2, 2, None)).unwrap();
+        assert_eq!(engine.escrow().map(|escrow| escrow.available), Some(dec!(10)));
+        engine.apply(&tx(TxType::Chargeback, 1, 1, None)).unwrap();
+        assert_eq!(engine.escrow().map(|escrow| escrow.available), Some(dec!(0)));
+
+        assert_eq!(engine.apply(&tx(TxType::Deposit, u32::MAX, 3, Some(dec!(1)))), Err(Reject::EscrowAccount));
+        assert_eq!(engine.reports().unwrap().filter(|report| report.client == ClientId(u32::MAX)).count(), 1);
+        assert!(Engine::new().escrow().is_none());
+    }
+
+    fn transfer(client: u32, tx_id: u32, to: &str, amount: Decimal) -> Transaction {
+        let mut record = tx(TxType::Transfer, client, tx_id, Some(amount));
+        record.metadata.insert("to".to_string(), to.to_string());
+        record
+    }
+
+    #[test]
+    fn test_transfer_moves_funds_or_nothing() {
+        let mut engine = Engine::new();
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.apply(&transfer(1, 2, "2", dec!(4))).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(6));
+        assert_eq!(engine.account(ClientId(2)).unwrap().unwrap().available, dec!(4));
+
+        assert_eq!(engine.apply(&transfer(1, 3, "2", dec!(7))), Err(Reject::InsufficientFunds));
+        assert_eq!(engine.apply(&transfer(1, 4, "1", dec!(1))), Err(Reject::InvalidTransfer));
+        assert_eq!(engine.apply(&tx(TxType::Transfer, 1, 5, Some(dec!(1)))), Err(Reject::InvalidTransfer));
+        assert_eq!(engine.apply(&transfer(9, 6, "2", dec!(1))), Err(Reject::InsufficientFunds));
+        assert!(engine.account(ClientId(9)).unwrap().is_none());
+
+        engine.apply(&tx(TxType::Deposit, 3, 7, Some(dec!(1)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 3, 7, None)).unwrap();
+        engine.apply(&tx(TxType::Chargeback, 3, 7, None)).unwrap();
+        assert_eq!(engine.apply(&transfer(1, 8, "3", dec!(1))), Err(Reject::AccountLocked));
+        engine.apply(&tx(TxType::Finalize, 2, 0, None)).unwrap();
+        assert_eq!(engine.apply(&transfer(1, 9, "2", dec!(1))), Err(Reject::AccountFinalized));
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(6));
+    }
+
+    #[test]
+    fn test_revert_undoes_both_sides_of_a_transfer() {
+        let mut engine = Engine::new();
+        engine.set_undo_depth(2);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        let fingerprint = engine.fingerprint().unwrap();
+        engine.apply(&transfer(1, 2, "2", dec!(4))).unwrap();
+        assert_eq!(engine.revert_last(1), 1);
+        assert_eq!(engine.fingerprint().unwrap(), fingerprint);
+        assert!(engine.account(ClientId(2)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reverting_finalize_keeps_escrow_total() {
+        let policy = Policy { escrow_account: Some(ClientId(u32::MAX)), ..Default::default() };
+        let mut engine = Engine::with_policy(&policy);
+        engine.set_undo_depth(1);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 1, 1, None)).unwrap();
+        engine.apply(&tx(TxType::Finalize, 1, 0, None)).unwrap();
+        assert_eq!(engine.revert_last(1), 1);
+        assert_eq!(engine.escrow().map(|escrow| escrow.available), Some(dec!(10)));
+    }
+
+    #[test]
+    fn test_chargeback_fee_and_losses() {
+        let policy = Policy { chargeback_fee: dec!(15), loss_account: Some(ClientId(u32::MAX)), ..Default::default() };
+        let mut engine = Engine::with_policy(&policy);
+        engine.set_undo_depth(2);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(100)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 1, 2, Some(dec!(20)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 1, 2, None)).unwrap();
+        engine.apply(&tx(TxType::Chargeback, 1, 2, None)).unwrap();
+
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(85));
+        assert_eq!(engine.losses().map(|losses| losses.available), Some(dec!(35)));
+        assert_eq!(engine.apply(&tx(TxType::Deposit, u32::MAX, 3, Some(dec!(1)))), Err(Reject::LossAccount));
+        engine.revert_last(2);
+        assert_eq!(engine.losses().map(|losses| losses.available), Some(dec!(0)));
+
+        engine.apply(&tx(TxType::Deposit, 2, 4, Some(dec!(5)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 2, 5, Some(dec!(20)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 2, 5, None)).unwrap();
+        engine.apply(&tx(TxType::Chargeback, 2, 5, None)).unwrap();
+        assert_eq!(engine.account(ClientId(2)).unwrap().unwrap().available, dec!(0), "the fee stops at what the client has");
+        assert_eq!(engine.losses().map(|losses| losses.available), Some(dec!(35)));
+    }
+
+    #[test]
+    fn test_disputes_auto_resolve_after_timeout() {
+        let at = |tx_type: TxType, client: u32, id: u32, amount: Option<Decimal>, day: i64| Transaction {
+            timestamp: Some(day * 86_400),
+            ..tx(tx_type, client, id, amount)
+        };
+        let mut engine = Engine::with_policy(&Policy { auto_resolve: Some(10 * 86_400), ..Default::default() });
+        engine.apply(&at(TxType::Deposit, 1, 1, Some(dec!(10)), 0)).unwrap();
+        engine.apply(&at(TxType::Deposit, 2, 2, Some(dec!(5)), 0)).unwrap();
+        engine.apply(&at(TxType::Dispute, 1, 1, None, 1)).unwrap();
+        engine.apply(&at(TxType::Dispute, 2, 2, None, 5)).unwrap();
+        engine.apply(&at(TxType::Chargeback, 2, 2, None, 6)).unwrap();
+
+        engine.apply(&at(TxType::Deposit, 3, 3, Some(dec!(1)), 10)).unwrap();
+        assert!(engine.take_auto_resolved().is_empty());
+        engine.apply(&at(TxType::Deposit, 3, 4, Some(dec!(1)), 20)).unwrap();
+        let resolved = engine.take_auto_resolved();
+        assert_eq!(resolved.iter().map(|resolve| resolve.tx).collect::<Vec<_>>(), vec![TxId(1)]);
+        let account = engine.account(ClientId(1)).unwrap().unwrap();
+        assert_eq!((account.held, account.available), (dec!(0), dec!(10)));
+    }
+
+    #[test]
+    fn test_provisional_credit_on_disputed_withdrawals() {
+        let policy = Policy { provisional_credit: true, chargeback_fee: dec!(5), ..Default::default() };
+        let mut engine = Engine::with_policy(&policy);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.apply(&tx(TxType::Withdrawal, 1, 2, Some(dec!(8)))).unwrap();
+        engine.apply(&tx(TxType::Withdrawal, 1, 3, Some(dec!(2)))).unwrap();
+        engine.apply(&tx(TxType::Dispute, 1, 2, None)).unwrap();
+        engine.apply(&tx(TxType::Dispute, 1, 3, None)).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(10));
+
+        engine.apply(&tx(TxType::Resolve, 1, 2, None)).unwrap();
+        engine.apply(&tx(TxType::Chargeback, 1, 3, None)).unwrap();
+        let account = engine.account(ClientId(1)).unwrap().unwrap();
+        assert_eq!((account.available, account.held, account.locked), (dec!(2), dec!(0), false));
+        assert_eq!(engine.take_provisional(), vec![
+            (ClientId(1), TxId(2), dec!(8)),
+            (ClientId(1), TxId(3), dec!(2)),
+            (ClientId(1), TxId(2), dec!(-8)),
+        ]);
+
+        let policy = Policy { provisional_credit: true, loss_account: Some(ClientId(u32::MAX)), ..Default::default() };
+        let mut spent = Engine::with_policy(&policy);
+        spent.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        spent.apply(&tx(TxType::Withdrawal, 1, 2, Some(dec!(8)))).unwrap();
+        spent.apply(&tx(TxType::Dispute, 1, 2, None)).unwrap();
+        spent.apply(&tx(TxType::Withdrawal, 1, 3, Some(dec!(9)))).unwrap();
+        spent.apply(&tx(TxType::Resolve, 1, 2, None)).unwrap();
+        assert_eq!(spent.account(ClientId(1)).unwrap().unwrap().available, dec!(0), "spent credit is not taken back");
+        assert_eq!(spent.losses().map(|losses| losses.available), Some(dec!(7)));
+
+        let mut plain = Engine::new();
+        plain.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        plain.apply(&tx(TxType::Withdrawal, 1, 2, Some(dec!(8)))).unwrap();
+        assert_eq!(plain.apply(&tx(TxType::Dispute, 1, 2, None)), Err(Reject::UnknownTx));
+    }
+
+    #[test]
+    fn test_rollback_to_savepoint() {
+        let mut engine = Engine::new();
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.savepoint();
+        let fingerprint = engine.fingerprint().unwrap();
+        for id in 2..6 {
+            engine.apply(&tx(TxType::Deposit, id, id, Some(dec!(1)))).unwrap();
+        }
+        assert_eq!(engine.rollback_to_savepoint(), 4);
+        assert_eq!(engine.fingerprint().unwrap(), fingerprint);
+
+        engine.apply(&tx(TxType::Withdrawal, 1, 6, Some(dec!(4)))).unwrap();
+        engine.savepoint();
+        assert_eq!(engine.rollback_to_savepoint(), 0);
+        engine.release_savepoint();
+        assert_eq!((engine.revertible(), engine.account(ClientId(1)).unwrap().unwrap().available), (0, dec!(6)));
+    }
+
+    #[test]
+    fn test_wallets_keep_separate_balances() {
+        let in_wallet = |mut record: Transaction, wallet: &str| {
+            record.metadata.insert("wallet".to_string(), wallet.to_string());
+            record
+        };
+        let mut engine = Engine::with_policy(&Policy { wallets: true, ..Default::default() });
+        engine.set_undo_depth(5);
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.apply(&in_wallet(tx(TxType::Deposit, 1, 2, Some(dec!(3))), "bonus")).unwrap();
+        assert_eq!(engine.apply(&in_wallet(tx(TxType::Withdrawal, 1, 3, Some(dec!(5))), "bonus")), Err(Reject::InsufficientFunds));
+        assert_eq!(engine.apply(&in_wallet(tx(TxType::Dispute, 1, 1, None), "bonus")), Err(Reject::UnknownTx));
+        engine.apply(&in_wallet(tx(TxType::Dispute, 1, 2, None), "bonus")).unwrap();
+
+        let mut reports: Vec<_> = engine.reports().unwrap().map(|report| (report.wallet.unwrap(), report.available, report.held)).collect();
+        reports.sort();
+        assert_eq!(reports, vec![
+            ("bonus".to_string(), dec!(0), dec!(3)),
+            ("main".to_string(), dec!(10), dec!(0)),
+            ("total".to_string(), dec!(10), dec!(3)),
+        ]);
+
+        let fingerprint = engine.fingerprint().unwrap();
+        engine.apply(&tx(TxType::Finalize, 1, 0, None)).unwrap();
+        assert!(engine.is_empty().unwrap());
+        assert_eq!(engine.revert_last(1), 1);
+        assert_eq!((engine.len().unwrap(), engine.fingerprint().unwrap()), (2, fingerprint));
+    }
+
+    /// Applies the same rows to an engine keeping balances in `A`.
+    fn settle<A: MoneyOps>(rows: &[Transaction]) -> Engine<MemoryStore<A>, A> {
+        let policy = Policy { chargeback_fee: dec!(2.5), loss_account: Some(ClientId(u32::MAX)), ..Default::default() };
+        let mut engine = Engine::with_store(MemoryStore::new());
+        engine.set_policy(&policy);
+        for row in rows {
+            engine.apply(row).unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn test_fixed_point_engine_matches_decimal() {
+        let rows = [
+            tx(TxType::Deposit, 1, 1, Some(dec!(10.25))),
+            tx(TxType::Deposit, 1, 2, Some(dec!(3))),
+            transfer(1, 3, "2", dec!(1.5)),
+            tx(TxType::Dispute, 1, 1, None),
+            tx(TxType::Chargeback, 1, 1, None),
+        ];
+        let (decimal, mut fixed) = (settle::<Decimal>(&rows), settle::<Fixed>(&rows));
+        let finer = tx(TxType::Deposit, 2, 4, Some(dec!(0.00001)));
+        assert_eq!(fixed.apply(&finer), Err(Reject::AmountOutOfRange), "more places than minor units hold");
+        assert_eq!(fixed.losses().map(|losses| losses.available), Some(dec!(12.75)));
+        assert_eq!(fixed.account(ClientId(2)).unwrap().unwrap().available, Fixed(15_000));
+        assert_eq!(fixed.fingerprint().unwrap(), decimal.fingerprint().unwrap());
+    }
+
+    #[test]
+    fn test_overflow_refuses_the_row_and_keeps_the_account() {
+        let mut engine = Engine::<MemoryStore<Fixed>, Fixed>::with_store(MemoryStore::new());
+        engine.apply(&tx(TxType::Deposit, 1, 1, Some(dec!(900_000_000_000_000)))).unwrap();
+        engine.apply(&tx(TxType::Deposit, 2, 2, Some(dec!(900_000_000_000_000)))).unwrap();
+        assert_eq!(engine.apply(&tx(TxType::Deposit, 1, 3, Some(dec!(100_000_000_000_000)))), Err(Reject::AmountOutOfRange));
+        assert_eq!(engine.apply(&transfer(2, 4, "1", dec!(100_000_000_000_000))), Err(Reject::AmountOutOfRange));
+        assert_eq!(engine.apply(&tx(TxType::Deposit, 3, 5, Some(dec!(1_000_000_000_000_000)))), Err(Reject::AmountOutOfRange));
+        let balances: Vec<_> = [1, 2].map(|client| engine.account(ClientId(client)).unwrap().unwrap().available).into();
+        assert_eq!(balances, [Fixed(9_000_000_000_000_000_000); 2]);
+    }
+}
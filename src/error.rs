@@ -0,0 +1,252 @@
+use std::{fmt, io};
+
+use crate::{ClientId, Reject, TxId};
+
+/// Everything that can stop a run.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// CSV-level failures outside a data row, e.g. an unreadable header, a failed
+    /// write, or the underlying reader failing mid-file.
+    Csv(csv::Error),
+    Parse(ParseError),
+    ErrorLimit(ErrorLimitExceeded),
+    /// A persisted state file could not be encoded or decoded.
+    State(serde_json::Error),
+    /// Two engines being merged both hold the same client.
+    MergeConflict(ClientId),
+    /// In-memory state grew past the configured `--max-memory` budget.
+    MemoryBudget { used: u64, limit: u64 },
+    /// The policy file is not valid TOML or declares something we don't understand.
+    Policy(PolicyError),
+    /// An external statement file could not be imported.
+    Import(ImportError),
+    /// A query named a transaction the input never reaches.
+    TxNotFound(TxId),
+    /// More rows were to be reverted than the undo journal holds.
+    Revert { requested: usize, available: usize },
+    /// The joint owners file links a client in two incompatible ways.
+    JointOwners(String),
+    /// An operator's override was refused.
+    Refused(Reject),
+    /// A batch of rows could not be delivered to a remote endpoint.
+    Submit { batch: u64, accepted: u64, reason: String },
+    /// Two reports being compared disagree in this many ways.
+    ReportsDiffer(usize),
+    /// A transactions file failed validation with this many errors.
+    Invalid(u64),
+    /// Partial reports being merged disagree on whether a client is locked.
+    LockConflict(ClientId),
+    /// A run could not be picked up from its checkpoint.
+    Resume(String),
+    /// An audit log's hash chain is broken.
+    AuditTampered { line: u64, reason: String },
+    /// The backend holding accounts could not be read or written.
+    Store(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Csv(err) => write!(f, "CSV error: {}", err),
+            Error::Parse(err) => err.fmt(f),
+            Error::ErrorLimit(err) => err.fmt(f),
+            Error::State(err) => write!(f, "state file error: {}", err),
+            Error::MergeConflict(client) => write!(f, "client {} is present in more than one state", client.0),
+            Error::MemoryBudget { used, limit } => write!(
+                f, "account state needs ~{} bytes, over the --max-memory budget of {} bytes; \
+                    finalize clients earlier or partition the input", used, limit
+            ),
+            Error::Policy(err) => write!(f, "policy file: {}", err),
+            Error::Import(err) => write!(f, "import: {}", err),
+            Error::TxNotFound(tx) => write!(f, "transaction {} does not appear in the input", tx.0),
+            Error::Revert { requested, available } => write!(
+                f, "cannot revert {} rows, the undo journal holds {}; raise --undo-depth", requested, available
+            ),
+            Error::JointOwners(message) => write!(f, "joint owners: {}", message),
+            Error::Refused(reject) => write!(f, "refused: {}", reject),
+            Error::Submit { batch, accepted, reason } => write!(
+                f, "batch {} was not accepted ({}); the {} rows before it were", batch, reason, accepted
+            ),
+            Error::ReportsDiffer(count) => write!(f, "reports differ (differences: {})", count),
+            Error::Invalid(errors) => write!(f, "input failed validation (errors: {})", errors),
+            Error::LockConflict(client) => write!(f, "client {} is locked in some reports and not in others", client.0),
+            Error::Resume(reason) => write!(f, "cannot resume: {}", reason),
+            Error::AuditTampered { line, reason } => write!(f, "audit log line {}: {}", line, reason),
+            Error::Store(reason) => write!(f, "account store: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Csv(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            Error::ErrorLimit(err) => Some(err),
+            Error::State(err) => Some(err),
+            Error::Policy(err) => Some(err),
+            Error::Import(err) => Some(err),
+            Error::MergeConflict(_) | Error::MemoryBudget { .. } | Error::TxNotFound(_) | Error::Revert { .. } | Error::JointOwners(_)
+            | Error::Refused(_) | Error::Submit { .. }
+            | Error::ReportsDiffer(_) | Error::Invalid(_) | Error::LockConflict(_) | Error::Resume(_)
+            | Error::AuditTampered { .. } | Error::Store(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(err: csv::Error) -> Self {
+        Error::Csv(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::State(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<PolicyError> for Error {
+    fn from(err: PolicyError) -> Self {
+        Error::Policy(err)
+    }
+}
+
+impl From<ImportError> for Error {
+    fn from(err: ImportError) -> Self {
+        Error::Import(err)
+    }
+}
+
+impl From<ErrorLimitExceeded> for Error {
+    fn from(err: ErrorLimitExceeded) -> Self {
+        Error::ErrorLimit(err)
+    }
+}
+
+/// A data row that could not be turned into a transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A field held a value that does not convert to its column's type.
+    InvalidField { line: u64, field: String, value: String, expected: &'static str },
+    /// The row itself was unreadable: wrong field count, bad quoting, invalid UTF-8.
+    MalformedRow { line: u64, reason: String },
+    /// The header row lacks a column every row needs.
+    MissingColumn { column: &'static str },
+}
+
+impl ParseError {
+    pub fn line(&self) -> u64 {
+        match self {
+            ParseError::InvalidField { line, .. } | ParseError::MalformedRow { line, .. } => *line,
+            ParseError::MissingColumn { .. } => 1,
+        }
+    }
+
+    /// Builds an error for a row the reader could not split into fields.
+    pub fn from_read(err: &csv::Error) -> Self {
+        let line = err.position().map_or(0, |pos| pos.line());
+        let reason = match err.kind() {
+            csv::ErrorKind::UnequalLengths { expected_len, len, .. } => {
+                format!("expected {} fields, found {}", expected_len, len)
+            }
+            csv::ErrorKind::Utf8 { .. } => "invalid UTF-8".to_string(),
+            _ => err.to_string(),
+        };
+        ParseError::MalformedRow { line, reason }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidField { line, field, value, expected } => {
+                write!(f, "line {}: {} '{}' is not a valid {}", line, field, value, expected)
+            }
+            ParseError::MalformedRow { line, reason } => write!(f, "line {}: malformed row: {}", line, reason),
+            ParseError::MissingColumn { column } => write!(f, "line 1: header is missing the '{}' column", column),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A run was stopped because too many rows failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorLimitExceeded {
+    Count { errors: u64, max: u64 },
+    Rate { errors: u64, rows: u64, max: f64 },
+}
+
+impl fmt::Display for ErrorLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorLimitExceeded::Count { errors, max } => {
+                write!(f, "aborted after {} failed rows (--max-errors {})", errors, max)
+            }
+            ErrorLimitExceeded::Rate { errors, rows, max } => write!(
+                f, "{} of {} rows failed ({:.4}), above --max-error-rate {}",
+                errors, rows, *errors as f64 / *rows as f64, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ErrorLimitExceeded {}
+
+/// A problem in the policy file, located by line where the parser could tell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// A statement or message file that could not be converted into transactions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ImportError {
+    pub fn at(line: usize, message: impl Into<String>) -> Self {
+        ImportError { line: Some(line), message: message.into() }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
@@ -0,0 +1,144 @@
+//! Client-ordered reports of any size. Reports are buffered up to a row
+//! budget; each full buffer is sorted and spilled to a run file, and the
+//! runs are merged into the output at the end, so the report comes out in
+//! client order without ever holding every account in memory.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Lines, Write},
+    path::PathBuf,
+    process,
+};
+
+use crate::{
+    error::Error,
+    report::{AccountReport, ReportSink},
+    ClientId,
+};
+
+type Key = (ClientId, Option<String>);
+
+fn key(report: &AccountReport) -> Key {
+    (report.client, report.wallet.clone())
+}
+
+/// Passes reports on to `output` ordered by client, then wallet.
+pub struct SortedReport<S: ReportSink> {
+    output: S,
+    buffer: Vec<AccountReport>,
+    capacity: usize,
+    dir: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl<S: ReportSink> SortedReport<S> {
+    /// Keeps at most `capacity` reports in memory, spilling into `dir`.
+    pub fn new(output: S, capacity: usize, dir: impl Into<PathBuf>) -> Self {
+        SortedReport { output, buffer: Vec::new(), capacity: capacity.max(1), dir: dir.into(), runs: Vec::new() }
+    }
+
+    /// Run files spilled so far.
+    pub fn spilled(&self) -> usize {
+        self.runs.len()
+    }
+
+    fn spill(&mut self) -> Result<(), Error> {
+        self.buffer.sort_by_key(key);
+        let path = self.dir.join(format!("txflow-spill-{}-{}.jsonl", process::id(), self.runs.len()));
+        let mut run = BufWriter::new(File::create(&path)?);
+        for report in self.buffer.drain(..) {
+            serde_json::to_writer(&mut run, &report).map_err(io::Error::from)?;
+            run.write_all(b"\n")?;
+        }
+        run.flush()?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Merges every run into the output, smallest key first.
+    fn merge(&mut self) -> Result<(), Error> {
+        let mut readers: Vec<Lines<BufReader<File>>> = Vec::with_capacity(self.runs.len());
+        for path in &self.runs {
+            readers.push(BufReader::new(File::open(path)?).lines());
+        }
+        let mut heads = BinaryHeap::new();
+        let mut pending: Vec<Option<AccountReport>> = Vec::with_capacity(readers.len());
+        for (run, reader) in readers.iter_mut().enumerate() {
+            let next = next_report(reader)?;
+            if let Some(report) = &next {
+                heads.push(Reverse((key(report), run)));
+            }
+            pending.push(next);
+        }
+        while let Some(Reverse((_, run))) = heads.pop() {
+            let report = pending[run].take().expect("a head is pending for every run in the heap");
+            self.output.write(&report)?;
+            pending[run] = next_report(&mut readers[run])?;
+            if let Some(next) = &pending[run] {
+                heads.push(Reverse((key(next), run)));
+            }
+        }
+        for path in self.runs.drain(..) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn next_report(reader: &mut Lines<BufReader<File>>) -> Result<Option<AccountReport>, Error> {
+    match reader.next().transpose()? {
+        Some(line) => Ok(Some(serde_json::from_str(&line).map_err(io::Error::from)?)),
+        None => Ok(None),
+    }
+}
+
+impl<S: ReportSink> ReportSink for SortedReport<S> {
+    fn write(&mut self, report: &AccountReport) -> Result<(), Error> {
+        self.buffer.push(report.clone());
+        if self.buffer.len() >= self.capacity {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.runs.is_empty() {
+            self.buffer.sort_by_key(key);
+            for report in &self.buffer {
+                self.output.write(report)?;
+            }
+            self.buffer.clear();
+        } else {
+            if !self.buffer.is_empty() {
+                self.spill()?;
+            }
+            self.merge()?;
+        }
+        self.output.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Account;
+
+    #[test]
+    fn test_merges_spilled_runs_in_client_order() {
+        let dir = std::env::temp_dir().join(format!("txflow-extsort-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut sorted = SortedReport::new(Vec::new(), 3, &dir);
+        for client in [9, 4, 7, 1, 8, 2, 6, 3] {
+            sorted.write(&AccountReport::from(&Account::new(ClientId(client)))).unwrap();
+        }
+        assert_eq!(sorted.spilled(), 2);
+        sorted.finish().unwrap();
+
+        let clients: Vec<u32> = sorted.output.iter().map(|report| report.client.0).collect();
+        assert_eq!(clients, [1, 2, 3, 4, 6, 7, 8, 9]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
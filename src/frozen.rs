@@ -0,0 +1,37 @@
+use std::io;
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+use crate::{error::Error, ClientId, TxId};
+
+/// An account locked by a chargeback, with where in the input it happened.
+#[derive(Debug, Serialize)]
+struct FrozenRow {
+    client: ClientId,
+    tx: TxId,
+    amount: Decimal,
+    line: u64,
+}
+
+/// Writes one CSV row per account frozen during a run, so the risk team
+/// gets the list directly instead of filtering the full account report.
+/// An account can only be frozen once, so each client appears at most once.
+pub struct FrozenWriter {
+    writer: csv::Writer<Box<dyn io::Write>>,
+}
+
+impl FrozenWriter {
+    pub fn new(output: Box<dyn io::Write>) -> Self {
+        FrozenWriter { writer: csv::Writer::from_writer(output) }
+    }
+
+    pub fn write(&mut self, client: ClientId, tx: TxId, amount: Decimal, line: u64) -> Result<(), Error> {
+        self.writer.serialize(FrozenRow { client, tx, amount, line })?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Builds a Graphviz DOT graph of dispute chains: each disputed deposit
+//! links to its disputes, and each dispute to the resolve or chargeback
+//! that followed, grouped into one cluster per client. Each deposit is
+//! labelled with the dispute state its last step left it in, and each step
+//! with the case reference its row carried, if any. Deposits
+//! nobody disputed are left out to keep the graph readable.
+
+use std::{collections::BTreeMap, fmt::Write as _, io};
+use rust_decimal::Decimal;
+
+use crate::{
+    error::Error,
+    logging::{Level, LogEvent, Logger},
+    processor::Options,
+    account::DisputeState,
+    source::{CsvSource, TxSource},
+    ClientId, Transaction, TxId, TxType,
+};
+
+#[derive(Debug, Default)]
+struct Chain {
+    /// `None` when the deposit never appeared under this client.
+    deposit: Option<(Decimal, u64)>,
+    /// Type, line and case reference of each dispute-flow row.
+    steps: Vec<(TxType, u64, Option<String>)>,
+}
+
+#[derive(Debug, Default)]
+pub struct DisputeGraph {
+    clients: BTreeMap<ClientId, BTreeMap<TxId, Chain>>,
+}
+
+impl DisputeGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a parsed row; `line` is its line in the input.
+    pub fn observe(&mut self, transaction: &Transaction, line: u64) {
+        match (transaction.tx_type, transaction.amount) {
+            (TxType::Deposit, Some(amount)) => {
+                self.chain(transaction).deposit.get_or_insert((amount, line));
+            }
+            (tx_type, _) if DisputeState::after(tx_type).is_some() => {
+                let case = transaction.metadata.get("case").filter(|case| !case.is_empty()).cloned();
+                self.chain(transaction).steps.push((transaction.tx_type, line, case));
+            }
+            _ => {}
+        }
+    }
+
+    fn chain(&mut self, transaction: &Transaction) -> &mut Chain {
+        self.clients.entry(transaction.client).or_default().entry(transaction.tx).or_default()
+    }
+
+    /// Reads a transactions CSV, skipping unparsable rows if `options.lenient` is set.
+    pub fn read<R: io::Read>(input: R, options: &Options, logger: &Logger) -> Result<Self, Error> {
+        let mut source = CsvSource::new(input, &options.amounts)?;
+        let mut graph = DisputeGraph::new();
+        loop {
+            match source.next_row() {
+                Ok(None) => break,
+                Ok(Some(row)) => graph.observe(&row.transaction, row.line),
+                Err(Error::Parse(err)) if options.lenient => logger.log(LogEvent::new(Level::Warn, "skipped").reason(err)),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Number of deposits with at least one dispute-flow row.
+    pub fn chains(&self) -> usize {
+        self.clients.values().flat_map(|chains| chains.values()).filter(|chain| !chain.steps.is_empty()).count()
+    }
+
+    pub fn write_dot<W: io::Write>(&self, mut output: W) -> Result<(), Error> {
+        let mut dot = String::from("digraph disputes {\n  rankdir=LR;\n  node [shape=box, fontname=\"Helvetica\"];\n");
+        for (client, chains) in &self.clients {
+            let disputed: Vec<_> = chains.iter().filter(|(_, chain)| !chain.steps.is_empty()).collect();
+            if disputed.is_empty() {
+                continue;
+            }
+            let _ = writeln!(dot, "  subgraph cluster_client_{0} {{\n    label=\"client {0}\";", client.0);
+            for (tx, chain) in disputed {
+                let origin = format!("c{}_tx{}", client.0, tx.0);
+                let state = chain.steps.last().and_then(|(tx_type, _, _)| DisputeState::after(*tx_type)).unwrap_or_default();
+                let _ = match chain.deposit {
+                    Some((amount, line)) => writeln!(
+                        dot, "    {} [label=\"deposit tx {}\\n{}\\nline {}\\n{}\"];", origin, tx.0, amount, line, state
+                    ),
+                    None => writeln!(dot, "    {} [label=\"tx {}\\nno deposit\", style=dashed];", origin, tx.0),
+                };
+                let mut previous = origin.clone();
+                for (index, (tx_type, line, case)) in chain.steps.iter().enumerate() {
+                    let (name, color) = match tx_type {
+                        TxType::Dispute => ("dispute", "orange"),
+                        TxType::RequestEvidence => ("request-evidence", "goldenrod"),
+                        TxType::Review => ("review", "blue"),
+                        TxType::Representment => ("representment", "purple"),
+                        TxType::Resolve => ("resolve", "darkgreen"),
+                        _ => ("chargeback", "red"),
+                    };
+                    let node = format!("{}_{}", origin, index + 1);
+                    let case = case.as_ref().map(|case| format!("\\ncase {}", case.replace('"', "\\\""))).unwrap_or_default();
+                    let _ = writeln!(dot, "    {} [label=\"{}\\nline {}{}\", color={}];", node, name, line, case, color);
+                    let _ = writeln!(dot, "    {} -> {};", previous, node);
+                    previous = node;
+                }
+            }
+            dot.push_str("  }\n");
+        }
+        dot.push_str("}\n");
+        output.write_all(dot.as_bytes())?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn tx(tx_type: TxType, client: u32, id: u32, amount: Option<Decimal>) -> Transaction {
+        Transaction::new(tx_type, ClientId(client), TxId(id), amount)
+    }
+
+    fn dot(graph: &DisputeGraph) -> String {
+        let mut out = Vec::new();
+        graph.write_dot(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_chain_links_deposit_to_outcome() {
+        let mut graph = DisputeGraph::new();
+        graph.observe(&tx(TxType::Deposit, 1, 1, Some(dec!(5))), 2);
+        graph.observe(&tx(TxType::Deposit, 1, 2, Some(dec!(3))), 3);
+        let mut dispute = tx(TxType::Dispute, 1, 1, None);
+        dispute.metadata.insert("case".to_string(), "CB-17".to_string());
+        graph.observe(&dispute, 4);
+        graph.observe(&tx(TxType::Chargeback, 1, 1, None), 5);
+
+        let dot = dot(&graph);
+        assert_eq!(graph.chains(), 1);
+        assert!(dot.contains("c1_tx1 [label=\"deposit tx 1\\n5\\nline 2\\nlost\"];"));
+        assert!(dot.contains("c1_tx1 -> c1_tx1_1;\n"));
+        assert!(dot.contains("c1_tx1_1 [label=\"dispute\\nline 4\\ncase CB-17\", color=orange];"));
+        assert!(dot.contains("c1_tx1_1 -> c1_tx1_2;\n"));
+        assert!(dot.contains("c1_tx1_2 [label=\"chargeback\\nline 5\", color=red];"));
+        assert!(!dot.contains("c1_tx2"));
+    }
+
+    #[test]
+    fn test_dispute_without_deposit_is_dashed() {
+        let mut graph = DisputeGraph::new();
+        graph.observe(&tx(TxType::Deposit, 1, 1, Some(dec!(5))), 2);
+        graph.observe(&tx(TxType::Dispute, 2, 1, None), 3);
+
+        let dot = dot(&graph);
+        assert!(dot.contains("c2_tx1 [label=\"tx 1\\nno deposit\", style=dashed];"));
+        assert!(!dot.contains("cluster_client_1 "));
+    }
+}
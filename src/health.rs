@@ -0,0 +1,131 @@
+//! Liveness and readiness probes for long-running watch loops, so an
+//! orchestrator can restart a wedged process and hold traffic until it is
+//! ready. `GET /healthz` answers 200 while the process runs; `GET /readyz`
+//! answers 200 once state is loaded, while the backlog of waiting files is
+//! within bounds and the state directory is reachable, and 503 otherwise.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::logging::{Level, LogEvent, Logger};
+
+#[derive(Debug)]
+struct Probes {
+    loaded: AtomicBool,
+    backlog: AtomicUsize,
+    max_backlog: usize,
+    storage: PathBuf,
+}
+
+/// What the probes report, shared between the watch loop and the server.
+#[derive(Debug, Clone)]
+pub struct Health(Arc<Probes>);
+
+impl Health {
+    /// Ready only while at most `max_backlog` files wait and `storage`, the
+    /// directory state is saved in, can be read.
+    pub fn new(max_backlog: usize, storage: impl Into<PathBuf>) -> Self {
+        Health(Arc::new(Probes {
+            loaded: AtomicBool::new(false),
+            backlog: AtomicUsize::new(0),
+            max_backlog,
+            storage: storage.into(),
+        }))
+    }
+
+    pub fn set_loaded(&self) {
+        self.0.loaded.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_backlog(&self, files: usize) {
+        self.0.backlog.store(files, Ordering::Relaxed);
+    }
+
+    /// Why the process isn't ready, if it isn't.
+    pub fn not_ready(&self) -> Option<String> {
+        let backlog = self.0.backlog.load(Ordering::Relaxed);
+        if !self.0.loaded.load(Ordering::Relaxed) {
+            Some("state not loaded".to_string())
+        } else if backlog > self.0.max_backlog {
+            Some(format!("backlog of {} files exceeds {}", backlog, self.0.max_backlog))
+        } else if !fs::metadata(&self.0.storage).is_ok_and(|metadata| metadata.is_dir()) {
+            Some(format!("storage {} unreachable", self.0.storage.display()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Serves the probes from a background thread; returns the bound address.
+pub fn serve(address: &str, health: Health, logger: Logger) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let bound = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &health));
+            if let Err(err) = result {
+                logger.log(LogEvent::new(Level::Debug, "probe-failed").reason(err));
+            }
+        }
+    });
+    Ok(bound)
+}
+
+fn respond(mut stream: TcpStream, health: &Health) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/healthz")) => ("200 OK", "ok".to_string()),
+        (Some("GET"), Some("/readyz")) => match health.not_ready() {
+            None => ("200 OK", "ready".to_string()),
+            Some(reason) => ("503 Service Unavailable", reason),
+        },
+        _ => ("404 Not Found", "not found".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_probes() {
+        let health = Health::new(1, std::env::temp_dir());
+        let address = serve("127.0.0.1:0", health.clone(), Logger::default()).unwrap();
+
+        assert!(get(address, "/healthz").starts_with("HTTP/1.1 200 OK"));
+        assert!(get(address, "/readyz").ends_with("\r\n\r\nstate not loaded"));
+        health.set_loaded();
+        assert!(get(address, "/readyz").starts_with("HTTP/1.1 200 OK"));
+        health.set_backlog(2);
+        assert!(get(address, "/readyz").starts_with("HTTP/1.1 503"));
+        assert!(get(address, "/metrics").starts_with("HTTP/1.1 404"));
+    }
+}
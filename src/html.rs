@@ -0,0 +1,150 @@
+//! Writes a run's results as one self-contained HTML page: headline
+//! figures, the locked accounts, and every account in a table that sorts
+//! when a column header is clicked. Styles and script are inline so the
+//! file can be mailed or opened without anything else alongside it.
+
+use std::io;
+use rust_decimal::Decimal;
+
+use crate::{error::Error, processor::Summary, report::AccountReport};
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+const HEAD: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>txflow report</title>
+<style>
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+.kpis { display: flex; flex-wrap: wrap; gap: 1em; margin-bottom: 2em; }
+.kpi { border: 1px solid #ccd; border-radius: 6px; padding: 0.8em 1.2em; min-width: 8em; }
+.kpi .value { font-size: 1.6em; font-weight: bold; }
+.kpi .label { color: #667; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccd; padding: 0.3em 0.8em; }
+th { background: #d9e1f2; cursor: pointer; user-select: none; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+tr.locked { background: #fde8e8; }
+</style>
+</head>
+<body>
+<h1>txflow report</h1>
+"#;
+
+const SCRIPT: &str = r#"<script>
+document.querySelectorAll("table.sortable th").forEach(function (th, column) {
+  th.addEventListener("click", function () {
+    var body = th.closest("table").tBodies[0];
+    var ascending = th.dataset.order !== "asc";
+    th.closest("tr").querySelectorAll("th").forEach(function (other) { delete other.dataset.order; });
+    th.dataset.order = ascending ? "asc" : "desc";
+    var rows = Array.from(body.rows);
+    rows.sort(function (a, b) {
+      var x = a.cells[column].dataset.sort || a.cells[column].textContent;
+      var y = b.cells[column].dataset.sort || b.cells[column].textContent;
+      var order = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+      return ascending ? order : -order;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+</script>
+</body>
+</html>
+"#;
+
+fn kpi(page: &mut String, label: &str, value: impl ToString) {
+    page.push_str(&format!(
+        "<div class=\"kpi\"><div class=\"value\">{}</div><div class=\"label\">{}</div></div>\n",
+        escape(&value.to_string()), escape(label)
+    ));
+}
+
+fn account_table(page: &mut String, rows: &[&AccountReport]) {
+    page.push_str("<table class=\"sortable\">\n<thead><tr><th>client</th><th>available</th><th>held</th><th>total</th><th>locked</th></tr></thead>\n<tbody>\n");
+    for row in rows {
+        page.push_str(&format!(
+            "<tr{}><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td></tr>\n",
+            if row.locked { " class=\"locked\"" } else { "" },
+            row.client.0, row.available, row.held, row.available + row.held, row.locked
+        ));
+    }
+    page.push_str("</tbody>\n</table>\n");
+}
+
+/// Writes the page for a finished run; `accounts` should include finalized accounts.
+pub fn write<W: io::Write>(mut output: W, accounts: &[AccountReport], summary: &Summary) -> Result<(), Error> {
+    let locked: Vec<&AccountReport> = accounts.iter().filter(|row| row.locked).collect();
+    let mut page = String::from(HEAD);
+
+    page.push_str("<h2>Summary</h2>\n<div class=\"kpis\">\n");
+    kpi(&mut page, "rows", summary.rows);
+    kpi(&mut page, "applied", summary.applied);
+    kpi(&mut page, "skipped", summary.errors.parse_errors());
+    kpi(&mut page, "rejected", summary.errors.rejected());
+    kpi(&mut page, "accounts", accounts.len());
+    kpi(&mut page, "locked", locked.len());
+    kpi(&mut page, "total available", accounts.iter().map(|row| row.available).sum::<Decimal>());
+    kpi(&mut page, "total held", accounts.iter().map(|row| row.held).sum::<Decimal>());
+    page.push_str("</div>\n");
+
+    page.push_str("<h2>Locked accounts</h2>\n");
+    if locked.is_empty() {
+        page.push_str("<p>No accounts are locked.</p>\n");
+    } else {
+        account_table(&mut page, &locked);
+    }
+
+    page.push_str("<h2>Accounts</h2>\n");
+    account_table(&mut page, &accounts.iter().collect::<Vec<_>>());
+
+    let errors: Vec<_> = summary.errors.iter().collect();
+    if !errors.is_empty() {
+        page.push_str("<h2>Errors</h2>\n<table class=\"sortable\">\n<thead><tr><th>category</th><th>count</th></tr></thead>\n<tbody>\n");
+        for (category, count) in errors {
+            page.push_str(&format!("<tr><td>{}</td><td class=\"num\">{}</td></tr>\n", escape(&category.to_string()), count));
+        }
+        page.push_str("</tbody>\n</table>\n");
+    }
+
+    page.push_str(SCRIPT);
+    output.write_all(page.as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientId;
+    use rust_decimal::dec;
+
+    fn row(client: u32, available: Decimal, locked: bool) -> AccountReport {
+        AccountReport { client: ClientId(client), available, held: dec!(0), locked, wallet: None, owners: None }
+    }
+
+    #[test]
+    fn test_page_lists_locked_accounts_and_kpis() {
+        let accounts = [row(1, dec!(2.5), false), row(2, dec!(-1), true)];
+        let mut out = Vec::new();
+        write(&mut out, &accounts, &Summary { rows: 7, ..Default::default() }).unwrap();
+        let page = String::from_utf8(out).unwrap();
+
+        assert!(page.contains("<div class=\"value\">7</div><div class=\"label\">rows</div>"));
+        assert!(page.contains("<div class=\"value\">1.5</div><div class=\"label\">total available</div>"));
+        let locked = &page[page.find("Locked accounts").unwrap()..page.find("<h2>Accounts").unwrap()];
+        assert!(locked.contains("<tr class=\"locked\"><td class=\"num\">2</td>"));
+        assert!(!locked.contains("<td class=\"num\">1</td>"));
+        assert!(page.ends_with("</html>\n"));
+    }
+
+    #[test]
+    fn test_page_without_locked_accounts() {
+        let mut out = Vec::new();
+        write(&mut out, &[row(1, dec!(1), false)], &Summary::default()).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("No accounts are locked."));
+    }
+}
@@ -0,0 +1,114 @@
+//! Just enough HTTP/1.1 to POST a small body to a plain `http://` endpoint
+//! and read back the status. There is no TLS; reach `https://` services
+//! through a local forwarding proxy.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl FromStr for Url {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("https://") {
+            return Err(format!("'{}': https is not supported; point at a local TLS-forwarding proxy instead", s));
+        }
+        let rest = s.strip_prefix("http://").ok_or_else(|| format!("'{}' is not an http:// URL", s))?;
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("'{}' has an invalid port", s))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("'{}' has no host", s));
+        }
+        let path = if path.is_empty() { "/" } else { path };
+        Ok(Url { host: host.to_string(), port, path: path.to_string() })
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// Sends `body` and returns the response status code. `timeout` bounds the
+/// connect and each read or write separately.
+pub fn post(url: &Url, content_type: &str, body: &[u8], timeout: Duration) -> io::Result<u16> {
+    post_with(url, content_type, &[], body, timeout)
+}
+
+/// [`post`] with extra request headers.
+pub fn post_with(url: &Url, content_type: &str, headers: &[(&str, &str)], body: &[u8], timeout: Duration) -> io::Result<u16> {
+    let address = (url.host.as_str(), url.port).to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", url.host)))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let host = if url.port == 80 { url.host.clone() } else { format!("{}:{}", url.host, url.port) };
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: txflow\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path, host, content_type, body.len()
+    )?;
+    for (name, value) in headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    stream.write_all(b"\r\n")?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    status.split_whitespace().nth(1).and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad status line '{}'", status.trim_end())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, net::TcpListener, thread};
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!("http://hooks.local:8080/risk".parse(), Ok(Url { host: "hooks.local".into(), port: 8080, path: "/risk".into() }));
+        assert_eq!("http://hooks.local".parse::<Url>().unwrap().path, "/");
+        assert!("https://hooks.slack.com/x".parse::<Url>().unwrap_err().contains("https"));
+        assert!("ftp://x".parse::<Url>().is_err());
+    }
+
+    #[test]
+    fn test_post_returns_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (mut request, mut chunk) = (Vec::new(), [0; 1024]);
+            while !request.ends_with(b"{}") {
+                let read = stream.read(&mut chunk).unwrap();
+                request.extend_from_slice(&chunk[..read]);
+            }
+            stream.write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        let url: Url = format!("http://127.0.0.1:{}/hook", port).parse().unwrap();
+        assert_eq!(post(&url, "application/json", b"{}", Duration::from_secs(5)).unwrap(), 202);
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Length: 2\r\n") && request.ends_with("\r\n\r\n{}"));
+    }
+}
@@ -0,0 +1,179 @@
+//! Converters from bank and finance file formats into txflow transactions.
+//! Statements describe a single account, so their entries are booked to a
+//! client given by the caller; formats that name accounts (FIX) carry their
+//! own. References, which are strings, are numbered into txflow ids while
+//! the original is kept as `reference` metadata.
+
+use std::{collections::BTreeSet, io, str::FromStr};
+use rust_decimal::Decimal;
+
+use crate::{
+    error::{Error, ImportError},
+    parse::AmountFormat,
+    time, ClientId, Transaction, TxId, TxType,
+};
+
+pub mod fix;
+pub mod iso20022;
+pub mod mt940;
+#[cfg(feature = "personal-finance")]
+pub mod ofx;
+#[cfg(feature = "personal-finance")]
+pub mod qif;
+mod xml;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ImportFormat {
+    /// camt.053 statements and pain.001 credit transfers, told apart by content.
+    Iso20022,
+    /// Execution reports from FIX drop-copy logs.
+    Fix,
+    /// SWIFT MT940/MT942 statements, reconciled against their balances.
+    Mt940,
+    #[cfg(feature = "personal-finance")]
+    Ofx,
+    #[cfg(feature = "personal-finance")]
+    Qif,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "iso20022" | "camt.053" | "pain.001" => Ok(ImportFormat::Iso20022),
+            "fix" => Ok(ImportFormat::Fix),
+            "mt940" | "mt942" => Ok(ImportFormat::Mt940),
+            #[cfg(feature = "personal-finance")]
+            "ofx" => Ok(ImportFormat::Ofx),
+            #[cfg(feature = "personal-finance")]
+            "qif" => Ok(ImportFormat::Qif),
+            #[cfg(not(feature = "personal-finance"))]
+            "ofx" | "qif" => Err(format!("import format '{}' needs the `personal-finance` feature", s)),
+            other => Err(format!("unknown import format '{}' (expected iso20022, mt940, fix, ofx or qif)", other)),
+        }
+    }
+}
+
+/// A single movement read from a statement, before ids are assigned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Deposit for credits, withdrawal for debits.
+    pub tx_type: TxType,
+    pub amount: Decimal,
+    pub timestamp: Option<i64>,
+    pub reference: Option<String>,
+    /// Account named by the entry itself; statements leave this to the caller.
+    pub client: Option<ClientId>,
+}
+
+/// Reads every entry of `input` in the given format.
+pub fn read(format: ImportFormat, input: &str) -> Result<Vec<Entry>, ImportError> {
+    match format {
+        ImportFormat::Iso20022 => iso20022::read(input),
+        ImportFormat::Fix => fix::read(input),
+        ImportFormat::Mt940 => mt940::read(input),
+        #[cfg(feature = "personal-finance")]
+        ImportFormat::Ofx => ofx::read(input),
+        #[cfg(feature = "personal-finance")]
+        ImportFormat::Qif => qif::read(input),
+    }
+}
+
+/// Assigns consecutive ids from `first_tx` and books entries that name no
+/// account to `client`.
+pub fn to_transactions(entries: Vec<Entry>, client: Option<ClientId>, first_tx: u32) -> Result<Vec<Transaction>, ImportError> {
+    entries.into_iter().enumerate()
+        .map(|(index, entry)| {
+            let tx = u32::try_from(index).ok().and_then(|index| first_tx.checked_add(index))
+                .ok_or_else(|| ImportError { line: None, message: "transaction ids overflow u32".to_string() })?;
+            let client = entry.client.or(client).ok_or_else(|| ImportError {
+                line: None,
+                message: "entries name no account; pass --client".to_string(),
+            })?;
+            let mut transaction = Transaction::new(entry.tx_type, client, TxId(tx), Some(entry.amount));
+            transaction.timestamp = entry.timestamp;
+            if let Some(reference) = entry.reference {
+                transaction.metadata.insert("reference".to_string(), reference);
+            }
+            Ok(transaction)
+        })
+        .collect()
+}
+
+/// Writes transactions in txflow's input format, with a column per metadata key.
+pub fn write_csv<W: io::Write>(transactions: &[Transaction], output: W) -> Result<(), Error> {
+    let extra: BTreeSet<&String> = transactions.iter().flat_map(|transaction| transaction.metadata.keys()).collect();
+    let mut writer = csv::Writer::from_writer(output);
+    let mut header = vec!["type", "client", "tx", "amount", "timestamp"];
+    header.extend(extra.iter().map(|key| key.as_str()));
+    writer.write_record(&header)?;
+
+    for transaction in transactions {
+        let mut row = vec![
+            format!("{:?}", transaction.tx_type).to_lowercase(),
+            transaction.client.0.to_string(),
+            transaction.tx.0.to_string(),
+            transaction.amount.map(|amount| amount.to_string()).unwrap_or_default(),
+            transaction.timestamp.map(time::format_timestamp).unwrap_or_default(),
+        ];
+        row.extend(extra.iter().map(|key| transaction.metadata.get(*key).cloned().unwrap_or_default()));
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Parses an amount as written in a statement: plain digits with a `.` decimal point.
+fn amount(raw: &str, line: usize) -> Result<Decimal, ImportError> {
+    match Decimal::from_str(raw.trim()) {
+        Ok(amount) if amount >= Decimal::ZERO => Ok(amount),
+        _ => Err(ImportError::at(line, format!("'{}' is not a valid amount", raw.trim()))),
+    }
+}
+
+/// Parses a signed amount, which may carry thousands separators or a
+/// currency symbol, into a deposit (credit) or withdrawal (debit).
+#[cfg_attr(not(feature = "personal-finance"), allow(dead_code))]
+fn signed(raw: &str, line: usize) -> Result<(TxType, Decimal), ImportError> {
+    let format = AmountFormat { tolerant: true, ..Default::default() };
+    match format.parse(raw) {
+        Some(amount) if amount.is_sign_negative() => Ok((TxType::Withdrawal, -amount)),
+        Some(amount) => Ok((TxType::Deposit, amount)),
+        None => Err(ImportError::at(line, format!("'{}' is not a valid amount", raw.trim()))),
+    }
+}
+
+/// Parses an ISO date or date-time, ignoring any UTC offset.
+fn timestamp(raw: &str, line: usize) -> Result<i64, ImportError> {
+    let raw = raw.trim();
+    let without_offset = match raw.get(19..) {
+        Some(rest) if rest.starts_with(['+', '-']) => &raw[..19],
+        _ => raw,
+    };
+    time::parse_timestamp(without_offset).ok_or_else(|| ImportError::at(line, format!("'{}' is not a valid date", raw)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_write_csv_numbers_entries_and_keeps_references() {
+        let entries = vec![
+            Entry { tx_type: TxType::Deposit, amount: dec!(10.5), timestamp: Some(86_400), reference: Some("A1".into()), client: None },
+            Entry { tx_type: TxType::Withdrawal, amount: dec!(2), timestamp: None, reference: None, client: Some(ClientId(8)) },
+        ];
+        assert!(to_transactions(entries.clone(), None, 1).is_err());
+        let transactions = to_transactions(entries, Some(ClientId(7)), 100).unwrap();
+        let mut out = Vec::new();
+        write_csv(&transactions, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "type,client,tx,amount,timestamp,reference\n\
+             deposit,7,100,10.5,1970-01-02T00:00:00Z,A1\n\
+             withdrawal,8,101,2,,\n"
+        );
+    }
+}
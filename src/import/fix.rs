@@ -0,0 +1,96 @@
+//! FIX drop-copy logs. Every execution report (35=8) that records a fill
+//! becomes a cash movement on the executing account: buys debit it and
+//! sells credit it, for LastQty x LastPx. Log lines may carry a prefix
+//! before `8=FIX`, and fields may be split by SOH, `|` or a literal `^A`.
+
+use std::collections::{HashMap, HashSet};
+use rust_decimal::Decimal;
+
+use super::{timestamp, Entry};
+use crate::{error::ImportError, ClientId, TxType};
+
+pub fn read(input: &str) -> Result<Vec<Entry>, ImportError> {
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+
+    for (index, line) in input.lines().enumerate() {
+        let number = index + 1;
+        let Some(start) = line.find("8=FIX") else { continue };
+        let message = line[start..].replace("^A", "\u{1}");
+        let fields: HashMap<&str, &str> = message.split(['\u{1}', '|'])
+            .filter_map(|field| field.split_once('='))
+            .collect();
+        let field = |tag: &str| fields.get(tag).copied().filter(|value| !value.is_empty());
+
+        // 150=F (4.3+) or 150=1/2 (4.2 partial/full fill); everything else is order state, not money.
+        if field("35") != Some("8") || !matches!(field("150"), Some("F" | "1" | "2")) {
+            continue;
+        }
+        // Drop copies repeat fills on resend; the execution id identifies a fill.
+        if let Some(exec_id) = field("17") {
+            if !seen.insert(exec_id.to_string()) {
+                continue;
+            }
+        }
+
+        let required = |tag: &str, name: &str| field(tag).ok_or_else(|| ImportError::at(number, format!("fill without {} ({})", name, tag)));
+        let decimal = |tag: &str, name: &str| -> Result<Decimal, ImportError> {
+            let raw = required(tag, name)?;
+            raw.parse().map_err(|_| ImportError::at(number, format!("{} '{}' is not a number", name, raw)))
+        };
+        let tx_type = match required("54", "Side")? {
+            "1" => TxType::Withdrawal,
+            "2" | "5" | "6" => TxType::Deposit,
+            other => return Err(ImportError::at(number, format!("unsupported Side '{}'", other))),
+        };
+        let account = required("1", "Account")?;
+        let client = account.parse().map(ClientId)
+            .map_err(|_| ImportError::at(number, format!("Account '{}' is not a numeric client id", account)))?;
+        let transact_time = match field("60") {
+            // 20240102-10:15:30.123
+            Some(raw) if raw.len() >= 17 => Some(timestamp(&format!(
+                "{}-{}-{}T{}", &raw[..4], &raw[4..6], &raw[6..8], &raw[9..17]
+            ), number)?),
+            _ => None,
+        };
+
+        entries.push(Entry {
+            tx_type,
+            amount: decimal("32", "LastQty")? * decimal("31", "LastPx")?,
+            timestamp: transact_time,
+            reference: field("17").map(str::to_string),
+            client: Some(client),
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_fills_become_cash_movements() {
+        let log = "\
+            2024-01-02 10:15:30 IN 8=FIX.4.4|35=8|1=42|17=E1|150=F|54=1|32=10|31=2.5|60=20240102-10:15:30.123|\n\
+            8=FIX.4.4^A35=8^A1=42^A17=E2^A150=0^A54=2^A32=0^A31=0^A\n\
+            8=FIX.4.4|35=8|1=42|17=E1|150=F|54=1|32=10|31=2.5|43=Y|\n\
+            8=FIX.4.2|35=8|1=7|17=E3|150=2|54=2|32=3|31=100|\n\
+            8=FIX.4.4|35=0|\n";
+        let entries = read(log).unwrap();
+        assert_eq!(entries, vec![
+            Entry {
+                tx_type: TxType::Withdrawal, amount: dec!(25.0), timestamp: Some(1_704_190_530),
+                reference: Some("E1".into()), client: Some(ClientId(42)),
+            },
+            Entry { tx_type: TxType::Deposit, amount: dec!(300), timestamp: None, reference: Some("E3".into()), client: Some(ClientId(7)) },
+        ]);
+    }
+
+    #[test]
+    fn test_non_numeric_account_rejected() {
+        let err = read("8=FIX.4.4|35=8|1=ACC-1|150=F|54=1|32=1|31=1|").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: Account 'ACC-1' is not a numeric client id");
+    }
+}
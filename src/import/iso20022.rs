@@ -0,0 +1,148 @@
+//! ISO 20022 XML messages: camt.053 bank-to-customer statements, where each
+//! booked entry is a credit or debit on the statement's account, and pain.001
+//! customer credit transfers, where each instructed payment leaves the
+//! debtor's account.
+
+use rust_decimal::Decimal;
+
+use super::{amount, timestamp, xml::{self, Element}, Entry};
+use crate::{error::ImportError, TxType};
+
+pub fn read(input: &str) -> Result<Vec<Entry>, ImportError> {
+    let document = xml::parse(input)?;
+    let mut entries = Vec::new();
+    let mut currency = None;
+
+    if let Some(statements) = document.child("BkToCstmrStmt") {
+        for entry in statements.children("Stmt").flat_map(|statement| statement.children("Ntry")) {
+            if let Some(read) = statement_entry(entry, &mut currency)? {
+                entries.push(read);
+            }
+        }
+    } else if let Some(initiation) = document.child("CstmrCdtTrfInitn") {
+        for payment in initiation.children("PmtInf") {
+            let executed = match payment.child("ReqdExctnDt") {
+                Some(date) => Some(date_of(date)?),
+                None => None,
+            };
+            for transfer in payment.children("CdtTrfTxInf") {
+                let instructed = transfer.path(&["Amt", "InstdAmt"])
+                    .ok_or_else(|| ImportError::at(transfer.line, "credit transfer without Amt/InstdAmt"))?;
+                entries.push(Entry {
+                    tx_type: TxType::Withdrawal,
+                    amount: checked_amount(instructed, &mut currency)?,
+                    timestamp: executed,
+                    reference: transfer.text_at(&["PmtId", "EndToEndId"]).map(str::to_string),
+                    client: None,
+                });
+            }
+        }
+    } else {
+        return Err(ImportError::at(document.line, "expected a camt.053 (BkToCstmrStmt) or pain.001 (CstmrCdtTrfInitn) message"));
+    }
+    Ok(entries)
+}
+
+/// Reads one `Ntry`, or `None` for entries that are not booked yet.
+fn statement_entry(entry: &Element, currency: &mut Option<String>) -> Result<Option<Entry>, ImportError> {
+    let status = entry.text_at(&["Sts", "Cd"]).or_else(|| entry.text_at(&["Sts"]));
+    if status.is_some_and(|status| !status.is_empty() && status != "BOOK") {
+        return Ok(None);
+    }
+
+    let tx_type = match entry.text_at(&["CdtDbtInd"]) {
+        Some("CRDT") => TxType::Deposit,
+        Some("DBIT") => TxType::Withdrawal,
+        other => return Err(ImportError::at(entry.line, format!("CdtDbtInd must be CRDT or DBIT, found {:?}", other))),
+    };
+    let booked = match entry.child("BookgDt").or_else(|| entry.child("ValDt")) {
+        Some(date) => Some(date_of(date)?),
+        None => None,
+    };
+    let reference = entry.text_at(&["AcctSvcrRef"])
+        .or_else(|| entry.text_at(&["NtryRef"]))
+        .or_else(|| entry.text_at(&["NtryDtls", "TxDtls", "Refs", "EndToEndId"]));
+
+    let amount = entry.child("Amt").ok_or_else(|| ImportError::at(entry.line, "entry without Amt"))?;
+    Ok(Some(Entry {
+        tx_type,
+        amount: checked_amount(amount, currency)?,
+        timestamp: booked,
+        reference: reference.map(str::to_string),
+        client: None,
+    }))
+}
+
+/// Reads an amount element, insisting every amount in the file shares one currency.
+fn checked_amount(element: &Element, currency: &mut Option<String>) -> Result<Decimal, ImportError> {
+    if let Some(code) = element.attribute("Ccy") {
+        match currency {
+            Some(seen) if seen != code => {
+                return Err(ImportError::at(element.line, format!("mixed currencies {} and {}", seen, code)));
+            }
+            _ => *currency = Some(code.to_string()),
+        }
+    }
+    amount(&element.text, element.line)
+}
+
+/// Dates appear bare or wrapped in `Dt`/`DtTm` depending on message version.
+fn date_of(element: &Element) -> Result<i64, ImportError> {
+    let raw = element.text_at(&["Dt"]).or_else(|| element.text_at(&["DtTm"])).unwrap_or(element.text.trim());
+    timestamp(raw, element.line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    const CAMT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Ntry>
+        <Amt Ccy="EUR">100.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>BOOK</Sts>
+        <BookgDt><Dt>2024-01-02</Dt></BookgDt><AcctSvcrRef>REF-1</AcctSvcrRef>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">25.50</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts><Cd>BOOK</Cd></Sts>
+        <BookgDt><DtTm>2024-01-03T10:00:00+01:00</DtTm></BookgDt>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">9.99</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>PDNG</Sts>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    #[test]
+    fn test_camt053_booked_entries() {
+        let entries = read(CAMT).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], Entry {
+            tx_type: TxType::Deposit, amount: dec!(100.00), timestamp: Some(1_704_153_600), reference: Some("REF-1".into()),
+            client: None,
+        });
+        assert_eq!((entries[1].tx_type, entries[1].amount), (TxType::Withdrawal, dec!(25.50)));
+        assert_eq!(entries[1].timestamp, Some(1_704_276_000));
+    }
+
+    #[test]
+    fn test_pain001_transfers_are_withdrawals() {
+        let pain = r#"<Document><CstmrCdtTrfInitn><PmtInf><ReqdExctnDt>2024-02-01</ReqdExctnDt>
+            <CdtTrfTxInf><PmtId><EndToEndId>E2E-9</EndToEndId></PmtId><Amt><InstdAmt Ccy="EUR">12.00</InstdAmt></Amt></CdtTrfTxInf>
+            </PmtInf></CstmrCdtTrfInitn></Document>"#;
+        let entries = read(pain).unwrap();
+        assert_eq!(entries, vec![Entry {
+            tx_type: TxType::Withdrawal, amount: dec!(12.00), timestamp: Some(1_706_745_600), reference: Some("E2E-9".into()),
+            client: None,
+        }]);
+    }
+
+    #[test]
+    fn test_mixed_currencies_rejected() {
+        let camt = CAMT.replacen("Ccy=\"EUR\">25.50", "Ccy=\"USD\">25.50", 1);
+        assert_eq!(read(&camt).unwrap_err().to_string(), "line 10: mixed currencies EUR and USD");
+    }
+}
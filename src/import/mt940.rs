@@ -0,0 +1,185 @@
+//! SWIFT MT940 end-of-day and MT942 interim statements. Each `:61:`
+//! statement line becomes a deposit or withdrawal; when a statement carries
+//! both an opening (`:60F:`/`:60M:`) and closing (`:62F:`/`:62M:`) balance,
+//! its lines must add up from one to the other or the import fails.
+
+use std::str::FromStr;
+use rust_decimal::Decimal;
+
+use super::Entry;
+use crate::{error::ImportError, time, TxType};
+
+/// A field's tag, its text (continuation lines joined by newlines) and the line it starts on.
+struct Field<'a> {
+    tag: &'a str,
+    value: String,
+    line: usize,
+}
+
+fn fields(input: &str) -> Vec<Field<'_>> {
+    let mut fields: Vec<Field> = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim_end();
+        let tag = line.strip_prefix(':').and_then(|rest| rest.find(':').map(|end| &rest[..end]));
+        match tag {
+            Some(tag) if !tag.is_empty() && tag.len() <= 3 => {
+                fields.push(Field { tag, value: line[tag.len() + 2..].to_string(), line: index + 1 });
+            }
+            // Block markers around the text block, e.g. `{4:` and `-}`.
+            _ if line.starts_with('{') || line.starts_with('-') => {}
+            _ => {
+                if let Some(field) = fields.last_mut() {
+                    field.value.push('\n');
+                    field.value.push_str(line);
+                }
+            }
+        }
+    }
+    fields
+}
+
+/// SWIFT amounts use a decimal comma: `1000,00`, `5,`.
+fn amount(raw: &str, line: usize) -> Result<Decimal, ImportError> {
+    let normalized = raw.replace(',', ".");
+    Decimal::from_str(normalized.trim_end_matches('.'))
+        .map_err(|_| ImportError::at(line, format!("'{}' is not a valid SWIFT amount", raw)))
+}
+
+fn date(yymmdd: &str, line: usize) -> Result<i64, ImportError> {
+    let year: i64 = yymmdd.get(..2).and_then(|year| year.parse().ok())
+        .ok_or_else(|| ImportError::at(line, format!("'{}' is not a valid date", yymmdd)))?;
+    let year = if year < 80 { 2000 + year } else { 1900 + year };
+    time::parse_timestamp(&format!("{}-{}-{}", year, &yymmdd[2..4], &yymmdd[4..6]))
+        .ok_or_else(|| ImportError::at(line, format!("'{}' is not a valid date", yymmdd)))
+}
+
+/// `C240101EUR1000,00`: credit/debit mark, date, currency, amount; debit balances are negative.
+fn balance(field: &Field) -> Result<Decimal, ImportError> {
+    let value = field.value.trim();
+    let sign = match value.chars().next() {
+        Some('C') => Decimal::ONE,
+        Some('D') => Decimal::NEGATIVE_ONE,
+        _ => return Err(ImportError::at(field.line, format!("balance '{}' lacks a C/D mark", value))),
+    };
+    let raw = value.get(10..).ok_or_else(|| ImportError::at(field.line, format!("balance '{}' is too short", value)))?;
+    Ok(sign * amount(raw, field.line)?)
+}
+
+/// `2401020102C100,00NTRFREF//BANKREF`: value date, optional entry date, mark, amount, type and references.
+fn statement_line(field: &Field) -> Result<Entry, ImportError> {
+    let value = field.value.lines().next().unwrap_or_default();
+    let invalid = || ImportError::at(field.line, format!("malformed statement line '{}'", value));
+    let booked = date(value.get(..6).ok_or_else(invalid)?, field.line)?;
+
+    let mut rest = &value[6..];
+    if rest.len() >= 4 && rest[..4].bytes().all(|b| b.is_ascii_digit()) {
+        rest = &rest[4..];
+    }
+    let (tx_type, after_mark) = if let Some(after) = rest.strip_prefix("RC") {
+        (TxType::Withdrawal, after)
+    } else if let Some(after) = rest.strip_prefix("RD") {
+        (TxType::Deposit, after)
+    } else if let Some(after) = rest.strip_prefix('C') {
+        (TxType::Deposit, after)
+    } else if let Some(after) = rest.strip_prefix('D') {
+        (TxType::Withdrawal, after)
+    } else {
+        return Err(invalid());
+    };
+    // An optional funds code (the third letter of the currency) may precede the amount.
+    let after_mark = after_mark.strip_prefix(|c: char| c.is_ascii_alphabetic()).unwrap_or(after_mark);
+    let amount_end = after_mark.find(|c: char| !(c.is_ascii_digit() || c == ',')).unwrap_or(after_mark.len());
+    let amount = amount(&after_mark[..amount_end], field.line)?;
+
+    // Four-character transaction type code, then the account owner's reference up to `//`.
+    let references = after_mark.get(amount_end + 4..).unwrap_or_default();
+    let customer = references.split("//").next().unwrap_or_default();
+    let reference = Some(customer).filter(|reference| !reference.is_empty() && *reference != "NONREF").map(str::to_string);
+
+    Ok(Entry { tx_type, amount, timestamp: Some(booked), reference, client: None })
+}
+
+pub fn read(input: &str) -> Result<Vec<Entry>, ImportError> {
+    let mut entries = Vec::new();
+    let mut opening: Option<Decimal> = None;
+    let mut movement = Decimal::ZERO;
+
+    for field in fields(input) {
+        match field.tag {
+            "20" => {
+                opening = None;
+                movement = Decimal::ZERO;
+            }
+            "60F" | "60M" => {
+                opening = Some(balance(&field)?);
+                movement = Decimal::ZERO;
+            }
+            "61" => {
+                let entry = statement_line(&field)?;
+                movement += if entry.tx_type == TxType::Deposit { entry.amount } else { -entry.amount };
+                entries.push(entry);
+            }
+            "62F" | "62M" => {
+                let closing = balance(&field)?;
+                if let Some(opening) = opening {
+                    if opening + movement != closing {
+                        return Err(ImportError::at(field.line, format!(
+                            "balance mismatch: opening {} plus movements {} is {}, but the statement closes at {}",
+                            opening, movement, opening + movement, closing
+                        )));
+                    }
+                }
+                // An intermediate closing balance (62M) is the next page's opening (60M).
+                opening = None;
+            }
+            _ => {}
+        }
+    }
+
+    if entries.is_empty() && !input.contains(":20:") {
+        return Err(ImportError { line: None, message: "not an MT940/MT942 statement (no :20: field)".to_string() });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    const MT940: &str = "{1:F01BANKBEBBAXXX0000000000}{2:O940}{4:\n\
+        :20:STMT-1\n\
+        :25:BE68539007547034\n\
+        :28C:1/1\n\
+        :60F:C240101EUR1000,00\n\
+        :61:2401020102C250,NTRFINV-7//B1\n\
+        :86:Invoice 7\n\
+        continued\n\
+        :61:240103D100,50NCHGNONREF\n\
+        :62F:C240103EUR1149,50\n\
+        -}";
+
+    #[test]
+    fn test_statement_lines_reconcile() {
+        let entries = read(MT940).unwrap();
+        assert_eq!(entries, vec![
+            Entry { tx_type: TxType::Deposit, amount: dec!(250), timestamp: Some(1_704_153_600), reference: Some("INV-7".into()), client: None },
+            Entry { tx_type: TxType::Withdrawal, amount: dec!(100.50), timestamp: Some(1_704_240_000), reference: None, client: None },
+        ]);
+    }
+
+    #[test]
+    fn test_balance_mismatch_is_reported() {
+        let err = read(&MT940.replace("1149,50", "1149,00")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 10: balance mismatch: opening 1000.00 plus movements 149.50 is 1149.50, but the statement closes at 1149.00"
+        );
+    }
+
+    #[test]
+    fn test_interim_report_without_balances() {
+        let mt942 = ":20:INTRADAY\n:25:ACC\n:13D:2401021200+0100\n:61:240102RD5,NMSCX\n:90C:1EUR5,\n";
+        assert_eq!(read(mt942).unwrap()[0].tx_type, TxType::Deposit);
+    }
+}
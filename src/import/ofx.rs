@@ -0,0 +1,85 @@
+//! OFX statements, both SGML-style 1.x (leaf tags left unclosed) and XML
+//! 2.x. Each `STMTTRN` becomes a deposit or withdrawal by the sign of
+//! `TRNAMT`; the bank's `FITID` is kept as the reference.
+
+use super::{signed, Entry};
+use crate::{error::ImportError, time};
+
+pub fn read(input: &str) -> Result<Vec<Entry>, ImportError> {
+    let line_at = |pos: usize| input[..pos].matches('\n').count() + 1;
+    let upper = input.to_ascii_uppercase();
+    let mut entries = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = upper[pos..].find("<STMTTRN>").map(|offset| pos + offset) {
+        let line = line_at(start);
+        let end = upper[start..].find("</STMTTRN>").map(|offset| start + offset)
+            .ok_or_else(|| ImportError::at(line, "<STMTTRN> is never closed"))?;
+        let block = &input[start + "<STMTTRN>".len()..end];
+        let field = |name: &str| leaf(block, name);
+
+        let raw_amount = field("TRNAMT").ok_or_else(|| ImportError::at(line, "transaction without TRNAMT"))?;
+        let (tx_type, amount) = signed(raw_amount, line)?;
+        let timestamp = match field("DTPOSTED") {
+            Some(raw) => Some(date(raw).ok_or_else(|| ImportError::at(line, format!("'{}' is not a valid DTPOSTED", raw)))?),
+            None => None,
+        };
+        entries.push(Entry { tx_type, amount, timestamp, reference: field("FITID").map(str::to_string), client: None });
+        pos = end;
+    }
+
+    if entries.is_empty() && !upper.contains("<OFX>") {
+        return Err(ImportError { line: None, message: "not an OFX file (no <OFX> element)".to_string() });
+    }
+    Ok(entries)
+}
+
+/// Value of a leaf element: everything after `<NAME>` up to the next tag.
+fn leaf<'a>(block: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = block.to_ascii_uppercase().find(&open)? + open.len();
+    let value = &block[start..];
+    Some(value[..value.find('<').unwrap_or(value.len())].trim())
+}
+
+/// OFX datetimes are `YYYYMMDD[HHMMSS[.XXX]][[offset:TZ]]`; the offset is ignored.
+fn date(raw: &str) -> Option<i64> {
+    let digits: String = raw.chars().take_while(char::is_ascii_digit).collect();
+    if digits.len() < 8 {
+        return None;
+    }
+    let date = format!("{}-{}-{}", &digits[..4], &digits[4..6], &digits[6..8]);
+    match digits.get(8..14) {
+        Some(clock) => time::parse_timestamp(&format!("{}T{}:{}:{}", date, &clock[..2], &clock[2..4], &clock[4..6])),
+        None => time::parse_timestamp(&date),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TxType;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_sgml_statement() {
+        let ofx = "OFXHEADER:100\nDATA:OFXSGML\n\n<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>\n\
+            <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240102120000.000[-5:EST]<TRNAMT>1,250.00<FITID>F1<NAME>Payroll\n</STMTTRN>\n\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240103<TRNAMT>-42.10<FITID>F2\n</STMTTRN>\n\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>";
+        let entries = read(ofx).unwrap();
+        assert_eq!(entries[0], Entry {
+            tx_type: TxType::Deposit, amount: dec!(1250.00), timestamp: Some(1_704_196_800), reference: Some("F1".into()),
+            client: None,
+        });
+        assert_eq!((entries[1].tx_type, entries[1].amount, entries[1].timestamp), (TxType::Withdrawal, dec!(42.10), Some(1_704_240_000)));
+    }
+
+    #[test]
+    fn test_xml_statement_and_missing_amount() {
+        let ofx = "<OFX><STMTTRN><TRNAMT>5</TRNAMT><FITID>X</FITID></STMTTRN></OFX>";
+        assert_eq!(read(ofx).unwrap()[0].amount, dec!(5));
+        let err = read("<OFX>\n<STMTTRN><FITID>X</FITID></STMTTRN></OFX>").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: transaction without TRNAMT");
+    }
+}
@@ -0,0 +1,79 @@
+//! QIF bank registers: one field per line keyed by its first character,
+//! records ended by `^`. Dates are US month-first as written by Quicken.
+
+use super::{signed, Entry};
+use crate::{error::ImportError, time};
+
+pub fn read(input: &str) -> Result<Vec<Entry>, ImportError> {
+    let mut entries = Vec::new();
+    let (mut amount, mut date, mut reference) = (None, None, None);
+    let mut record_line = 1;
+
+    for (index, line) in input.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim_end();
+        let Some(code) = line.chars().next() else { continue };
+        let value = line[code.len_utf8()..].trim();
+        if amount.is_none() && date.is_none() && reference.is_none() {
+            record_line = number;
+        }
+        match code {
+            '!' if value.starts_with("Type:") && !matches!(value, "Type:Bank" | "Type:Cash" | "Type:CCard") => {
+                return Err(ImportError::at(number, format!("unsupported QIF section '{}'", value)));
+            }
+            'T' | 'U' => amount = Some(signed(value, number)?),
+            'D' => date = Some(parse_date(value).ok_or_else(|| ImportError::at(number, format!("'{}' is not a valid date", value)))?),
+            'N' => reference = Some(value.to_string()),
+            '^' => {
+                let (tx_type, amount) = amount.take()
+                    .ok_or_else(|| ImportError::at(record_line, "record without an amount (T line)"))?;
+                entries.push(Entry { tx_type, amount, timestamp: date.take(), reference: reference.take(), client: None });
+            }
+            _ => {}
+        }
+    }
+
+    if amount.is_some() {
+        return Err(ImportError::at(record_line, "last record is not terminated by '^'"));
+    }
+    Ok(entries)
+}
+
+/// Accepts `1/2/2024`, `01/02/24` and Quicken's `1/ 2'24`.
+fn parse_date(raw: &str) -> Option<i64> {
+    let cleaned: String = raw.chars().filter(|c| !c.is_whitespace()).map(|c| if c == '\'' { '/' } else { c }).collect();
+    let mut parts = cleaned.splitn(3, '/');
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let year: i64 = match parts.next()? {
+        short if short.len() <= 2 => {
+            let year: i64 = short.parse().ok()?;
+            if year < 70 { 2000 + year } else { 1900 + year }
+        }
+        long => long.parse().ok()?,
+    };
+    time::parse_timestamp(&format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TxType;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_bank_register() {
+        let qif = "!Type:Bank\nD1/ 2'24\nT1,000.00\nPEmployer\nN1001\n^\nD01/03/2024\nT-12.50\nMGroceries\n^\n";
+        let entries = read(qif).unwrap();
+        assert_eq!(entries, vec![
+            Entry { tx_type: TxType::Deposit, amount: dec!(1000.00), timestamp: Some(1_704_153_600), reference: Some("1001".into()), client: None },
+            Entry { tx_type: TxType::Withdrawal, amount: dec!(12.50), timestamp: Some(1_704_240_000), reference: None, client: None },
+        ]);
+    }
+
+    #[test]
+    fn test_unterminated_and_investment_sections_rejected() {
+        assert_eq!(read("!Type:Bank\nD1/2/24\nT5\n").unwrap_err().to_string(), "line 2: last record is not terminated by '^'");
+        assert!(read("!Type:Invst\n").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Just enough XML for bank messages: elements, attributes, text, comments,
+//! CDATA and the predefined entities. Namespace prefixes are dropped since
+//! the message schemas never reuse a local name across namespaces. DTDs and
+//! processing instructions are skipped, not interpreted.
+
+use crate::error::ImportError;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub text: String,
+    pub children: Vec<Element>,
+    /// Line of the opening tag, for error messages.
+    pub line: usize,
+}
+
+impl Element {
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Follows a chain of child names, e.g. `["BookgDt", "Dt"]`.
+    pub fn path(&self, names: &[&str]) -> Option<&Element> {
+        names.iter().try_fold(self, |element, name| element.child(name))
+    }
+
+    /// Trimmed text at the end of `names`.
+    pub fn text_at(&self, names: &[&str]) -> Option<&str> {
+        self.path(names).map(|element| element.text.trim())
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+fn local(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn unescape(raw: &str, line: usize) -> Result<String, ImportError> {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find(';').ok_or_else(|| ImportError::at(line, "unterminated entity"))? + start;
+        let entity = &rest[start + 1..end];
+        let decoded = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+                    .ok_or_else(|| ImportError::at(line, format!("unknown entity '&{};'", entity)))?
+            }
+        };
+        out.push(decoded);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Parses a whole document and returns its root element.
+pub fn parse(input: &str) -> Result<Element, ImportError> {
+    let mut stack: Vec<Element> = vec![Element::default()];
+    let mut pos = 0;
+    let line_at = |pos: usize| input[..pos].matches('\n').count() + 1;
+
+    while pos < input.len() {
+        let Some(offset) = input[pos..].find('<') else {
+            stack.last_mut().expect("document element").text.push_str(&unescape(&input[pos..], line_at(pos))?);
+            break;
+        };
+        let text = &input[pos..pos + offset];
+        stack.last_mut().expect("document element").text.push_str(&unescape(text, line_at(pos))?);
+        pos += offset;
+        let line = line_at(pos);
+        let rest = &input[pos..];
+
+        let skip_to = |terminator: &str| {
+            rest.find(terminator).map(|end| end + terminator.len())
+                .ok_or_else(|| ImportError::at(line, format!("missing '{}'", terminator)))
+        };
+        if rest.starts_with("<!--") {
+            pos += skip_to("-->")?;
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").ok_or_else(|| ImportError::at(line, "unterminated CDATA"))?;
+            stack.last_mut().expect("document element").text.push_str(&cdata[..end]);
+            pos += "<![CDATA[".len() + end + 3;
+        } else if rest.starts_with("<?") {
+            pos += skip_to("?>")?;
+        } else if rest.starts_with("<!") {
+            pos += skip_to(">")?;
+        } else if let Some(closing) = rest.strip_prefix("</") {
+            let end = closing.find('>').ok_or_else(|| ImportError::at(line, "unterminated closing tag"))?;
+            let name = local(closing[..end].trim());
+            let element = stack.pop().filter(|_| !stack.is_empty())
+                .ok_or_else(|| ImportError::at(line, format!("unexpected </{}>", name)))?;
+            if element.name != name {
+                return Err(ImportError::at(line, format!("expected </{}>, found </{}>", element.name, name)));
+            }
+            stack.last_mut().expect("document element").children.push(element);
+            pos += 2 + end + 1;
+        } else {
+            let end = tag_end(rest).ok_or_else(|| ImportError::at(line, "unterminated tag"))?;
+            let inner = &rest[1..end];
+            let (inner, self_closing) = match inner.strip_suffix('/') {
+                Some(inner) => (inner, true),
+                None => (inner, false),
+            };
+            let element = open_tag(inner, line)?;
+            if self_closing {
+                stack.last_mut().expect("document element").children.push(element);
+            } else {
+                stack.push(element);
+            }
+            pos += end + 1;
+        }
+    }
+
+    if stack.len() > 1 {
+        let open = stack.last().expect("open element");
+        return Err(ImportError::at(open.line, format!("<{}> is never closed", open.name)));
+    }
+    let mut document = stack.pop().expect("document element");
+    match document.children.len() {
+        1 => Ok(document.children.remove(0)),
+        0 => Err(ImportError { line: None, message: "no root element".to_string() }),
+        _ => Err(ImportError::at(document.children[1].line, "more than one root element")),
+    }
+}
+
+/// Index of the `>` closing a tag, skipping any inside quoted attribute values.
+fn tag_end(rest: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, c) in rest.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '>') => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn open_tag(inner: &str, line: usize) -> Result<Element, ImportError> {
+    let inner = inner.trim();
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = local(&inner[..name_end]);
+    if name.is_empty() {
+        return Err(ImportError::at(line, "empty tag name"));
+    }
+
+    let mut attributes = Vec::new();
+    let mut rest = inner[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=').ok_or_else(|| ImportError::at(line, format!("malformed attribute in <{}>", name)))?;
+        let key = local(rest[..eq].trim()).to_string();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| matches!(c, '"' | '\''))
+            .ok_or_else(|| ImportError::at(line, format!("unquoted attribute '{}' in <{}>", key, name)))?;
+        let close = value[1..].find(quote).ok_or_else(|| ImportError::at(line, "unterminated attribute value"))? + 1;
+        attributes.push((key, unescape(&value[1..close], line)?));
+        rest = value[close + 1..].trim_start();
+    }
+
+    Ok(Element { name: name.to_string(), attributes, line, ..Default::default() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nested_elements() {
+        let root = parse(
+            "<?xml version=\"1.0\"?>\n<!-- statement -->\n<doc:Document xmlns:doc=\"urn:x\">\
+             <Amt Ccy=\"EUR\">1.50</Amt><Nm>A &amp; B</Nm><Empty/><Note><![CDATA[<raw>]]></Note></doc:Document>"
+        ).unwrap();
+        assert_eq!(root.name, "Document");
+        assert_eq!(root.child("Amt").unwrap().attribute("Ccy"), Some("EUR"));
+        assert_eq!(root.text_at(&["Nm"]), Some("A & B"));
+        assert_eq!(root.text_at(&["Note"]), Some("<raw>"));
+        assert!(root.child("Empty").is_some());
+    }
+
+    #[test]
+    fn test_mismatched_tag_names_line() {
+        let err = parse("<a>\n<b>\n</a>").unwrap_err();
+        assert_eq!(err.to_string(), "line 3: expected </b>, found </a>");
+    }
+}
@@ -0,0 +1,96 @@
+//! Joint accounts: one account owned by several client ids. Ownership comes
+//! from a sidecar CSV with `account,owner` rows; the account is named by its
+//! primary client id, and every owner's rows apply to its balance.
+
+use std::{collections::BTreeMap, fs::File, io, path::Path};
+use serde::Deserialize;
+
+use crate::{collections::HashMap, error::Error, ClientId};
+
+#[derive(Debug, Deserialize)]
+struct Link {
+    account: ClientId,
+    owner: ClientId,
+}
+
+/// Which account each co-owner's rows go to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JointOwners {
+    account_of: HashMap<ClientId, ClientId>,
+    /// Co-owners of each joint account, primary excluded, in ascending order.
+    owners: BTreeMap<ClientId, Vec<ClientId>>,
+}
+
+impl JointOwners {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::read(File::open(path)?)
+    }
+
+    /// Reads `account,owner` rows. An owner may belong to one account only,
+    /// and a primary can't also be another account's owner.
+    pub fn read<R: io::Read>(input: R) -> Result<Self, Error> {
+        let mut joint = JointOwners::default();
+        for link in csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input).deserialize() {
+            let Link { account, owner } = link?;
+            joint.link(account, owner)?;
+        }
+        Ok(joint)
+    }
+
+    pub fn link(&mut self, account: ClientId, owner: ClientId) -> Result<(), Error> {
+        if account == owner {
+            return Ok(());
+        }
+        if let Some(other) = self.account_of.get(&owner).filter(|other| **other != account) {
+            return Err(Error::JointOwners(format!("client {} owns both account {} and account {}", owner.0, other.0, account.0)));
+        }
+        if self.owners.contains_key(&owner) {
+            return Err(Error::JointOwners(format!("client {} is a joint account and can't also own account {}", owner.0, account.0)));
+        }
+        if let Some(other) = self.account_of.get(&account) {
+            return Err(Error::JointOwners(format!("client {} owns account {} and can't also be a joint account", account.0, other.0)));
+        }
+        self.account_of.insert(owner, account);
+        let owners = self.owners.entry(account).or_default();
+        if let Err(index) = owners.binary_search(&owner) {
+            owners.insert(index, owner);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+
+    /// The account a client's rows apply to, if it co-owns one.
+    pub fn account_of(&self, client: ClientId) -> Option<ClientId> {
+        self.account_of.get(&client).copied()
+    }
+
+    /// Every owner of `account`, primary first, as space-separated ids.
+    pub fn describe(&self, account: ClientId) -> String {
+        let owners = self.owners.get(&account).map_or(&[][..], Vec::as_slice);
+        std::iter::once(&account).chain(owners).map(|client| client.0.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_links_owners_to_accounts() {
+        let joint = JointOwners::read("account,owner\n1, 7\n1,3\n".as_bytes()).unwrap();
+        assert_eq!(joint.account_of(ClientId(7)), Some(ClientId(1)));
+        assert_eq!(joint.account_of(ClientId(1)), None);
+        assert_eq!(joint.describe(ClientId(1)), "1 3 7");
+        assert_eq!(joint.describe(ClientId(2)), "2");
+    }
+
+    #[test]
+    fn test_owner_of_two_accounts_is_an_error() {
+        let err = JointOwners::read("account,owner\n1,7\n2,7\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "joint owners: client 7 owns both account 1 and account 2");
+        assert!(JointOwners::read("account,owner\n1,7\n7,9\n".as_bytes()).is_err());
+    }
+}
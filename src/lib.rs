@@ -0,0 +1,76 @@
+pub mod admin;
+pub mod aggregate;
+pub mod alert;
+pub mod anchor;
+pub mod anonymize;
+pub mod bench;
+pub mod bloom;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod chat;
+pub mod checkpoint;
+pub mod class;
+pub mod collections;
+pub mod convert;
+pub mod crash;
+pub mod deadletter;
+pub mod declines;
+pub mod delta;
+pub mod diagnostics;
+pub mod diff;
+pub mod engine;
+pub mod error;
+pub mod extsort;
+pub mod frozen;
+pub mod graph;
+pub mod health;
+pub mod html;
+pub mod http;
+pub mod import;
+pub mod joint;
+pub mod logging;
+pub mod merkle;
+pub mod observer;
+pub mod ordering;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod parallel;
+pub mod parse;
+pub mod policy;
+pub mod processor;
+pub mod profile;
+pub mod query;
+pub mod queue;
+pub mod quota;
+pub mod report;
+pub mod retention;
+pub mod sar;
+pub mod script;
+pub mod settlement;
+pub mod sha256;
+pub mod signals;
+pub mod slab;
+pub mod smtp;
+pub mod snapshot;
+pub mod state;
+pub mod statement;
+pub mod statsd;
+pub mod source;
+pub mod stats;
+pub mod store;
+pub mod submit;
+pub mod time;
+pub mod top;
+pub mod transfer;
+pub mod transaction;
+pub mod validate;
+pub mod watch;
+pub mod webhook;
+pub mod xlsx;
+pub mod zip;
+
+pub use txflow_core::{account, fixed, history, money};
+pub use account::{Account, Reject};
+pub use engine::Engine;
+pub use error::Error;
+pub use transaction::{ClientId, Transaction, TxId, TxType};
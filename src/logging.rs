@@ -0,0 +1,233 @@
+use std::{fmt, io::{self, Write}, str::FromStr};
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+use crate::{ClientId, TxId};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format '{}' (expected text or json)", other)),
+        }
+    }
+}
+
+/// Verbosity threshold, ordered from least to most chatty.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Shifts the level by a number of `-v` (positive) or `-q` (negative) flags.
+    pub fn adjust(self, steps: i32) -> Self {
+        const LEVELS: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+        let index = (self as i32 + steps).clamp(0, LEVELS.len() as i32 - 1);
+        LEVELS[index as usize]
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            other => Err(format!("unknown log level '{}' (expected error, warn, info, debug or trace)", other)),
+        }
+    }
+}
+
+/// A single diagnostic line. Everything past `level` and `event` is optional so the
+/// same shape covers row-level decisions and run-level failures.
+#[derive(Debug, Serialize, Default)]
+pub struct LogEvent<'a> {
+    pub level: Level,
+    pub event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<ClientId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx: Option<TxId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The account's balances after the row, on per-transaction events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub held: Option<Decimal>,
+}
+
+impl<'a> LogEvent<'a> {
+    pub fn new(level: Level, event: &'a str) -> Self {
+        LogEvent { level, event, ..Default::default() }
+    }
+
+    pub fn client(mut self, client: ClientId) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn tx(mut self, tx: TxId) -> Self {
+        self.tx = Some(tx);
+        self
+    }
+
+    pub fn reason(mut self, reason: impl fmt::Display) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn balance(mut self, available: Decimal, held: Decimal) -> Self {
+        self.available = Some(available);
+        self.held = Some(held);
+        self
+    }
+}
+
+/// Writes diagnostics at or above a verbosity threshold to stderr in either
+/// human-readable or JSON-lines form.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Logger {
+    format: LogFormat,
+    level: Level,
+}
+
+impl Logger {
+    pub fn new(format: LogFormat, level: Level) -> Self {
+        Logger { format, level }
+    }
+
+    /// Whether events at `level` would be written; lets hot paths skip
+    /// building events nobody will see.
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    pub fn log(&self, event: LogEvent) {
+        if !self.enabled(event.level) { return; }
+        let line = self.format_line(&event);
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+
+    fn format_line(&self, event: &LogEvent) -> String {
+        match self.format {
+            LogFormat::Json => serde_json::to_string(event)
+                .unwrap_or_else(|_| format!("{{\"level\":\"{}\",\"event\":\"{}\"}}", event.level.as_str(), event.event)),
+            LogFormat::Text => {
+                let mut line = format!("[{}] {}", event.level.as_str(), event.event);
+                if let Some(client) = event.client {
+                    line.push_str(&format!(" client={}", client.0));
+                }
+                if let Some(tx) = event.tx {
+                    line.push_str(&format!(" tx={}", tx.0));
+                }
+                if let (Some(available), Some(held)) = (event.available, event.held) {
+                    line.push_str(&format!(" available={} held={}", available, held));
+                }
+                if let Some(reason) = &event.reason {
+                    line.push_str(&format!(": {}", reason));
+                }
+                line
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_line_has_structured_fields() {
+        let logger = Logger::new(LogFormat::Json, Level::Info);
+        let event = LogEvent::new(Level::Debug, "rejected").client(ClientId(7)).tx(TxId(42)).reason("insufficient funds");
+        assert_eq!(
+            logger.format_line(&event),
+            r#"{"level":"debug","event":"rejected","client":7,"tx":42,"reason":"insufficient funds"}"#
+        );
+    }
+
+    #[test]
+    fn test_json_line_omits_missing_fields() {
+        let logger = Logger::new(LogFormat::Json, Level::Info);
+        assert_eq!(
+            logger.format_line(&LogEvent::new(Level::Error, "error").reason("boom")),
+            r#"{"level":"error","event":"error","reason":"boom"}"#
+        );
+    }
+
+    #[test]
+    fn test_text_line() {
+        let logger = Logger::new(LogFormat::Text, Level::Info);
+        let event = LogEvent::new(Level::Debug, "rejected").client(ClientId(1)).tx(TxId(3)).reason("account locked");
+        assert_eq!(logger.format_line(&event), "[debug] rejected client=1 tx=3: account locked");
+    }
+
+    #[test]
+    fn test_balance_fields() {
+        let event = || LogEvent::new(Level::Trace, "applied").client(ClientId(1)).tx(TxId(3))
+            .balance(Decimal::new(75, 1), Decimal::ZERO).reason("deposit");
+        assert_eq!(
+            Logger::new(LogFormat::Text, Level::Trace).format_line(&event()),
+            "[trace] applied client=1 tx=3 available=7.5 held=0: deposit"
+        );
+        assert_eq!(
+            Logger::new(LogFormat::Json, Level::Trace).format_line(&event()),
+            r#"{"level":"trace","event":"applied","client":1,"tx":3,"reason":"deposit","available":"7.5","held":"0"}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_level_threshold() {
+        let logger = Logger::new(LogFormat::Text, Level::Info);
+        assert!(logger.enabled(Level::Error));
+        assert!(logger.enabled(Level::Info));
+        assert!(!logger.enabled(Level::Debug));
+    }
+
+    #[test]
+    fn test_level_adjust_clamps() {
+        assert_eq!(Level::Info.adjust(1), Level::Debug);
+        assert_eq!(Level::Info.adjust(2), Level::Trace);
+        assert_eq!(Level::Info.adjust(5), Level::Trace);
+        assert_eq!(Level::Info.adjust(-1), Level::Warn);
+        assert_eq!(Level::Info.adjust(-9), Level::Error);
+    }
+}
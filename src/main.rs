@@ -1,253 +1,539 @@
-use std::{collections::HashMap, env, error::Error, fs::File, io};
-use serde::{Deserialize, Serialize};
-use rust_decimal::Decimal;
-
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum TxType {
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback,
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Default)]
-pub struct ClientId(u32);
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
-pub struct TxId(pub u32);
-
-#[derive(Debug, Deserialize)]
-struct Transaction {
-    #[serde(rename = "type")]
-    tx_type: TxType,
-    client: ClientId,
-    tx: TxId,
-    amount: Option<Decimal>,
-}
-
-#[derive(Debug, Serialize, Default)]
-struct Account {
-    client: ClientId,
-    available: Decimal,
-    held: Decimal,
-    locked: bool,
-
-    #[serde(skip)]
-    history: HashMap<TxId, (Decimal, bool)>, // (amount, disputed?)
-}
-
-impl Account {
-    fn deposit(&mut self, tx: TxId, amount: Decimal) {
-        if self.locked { return; }
-        self.available += amount;
-        self.history.insert(tx, (amount, false));
-    }
-
-    fn withdrawal(&mut self, amount: Decimal) {
-        if self.locked || self.available < amount { return; }
-        self.available -= amount;
-    }
-
-    fn dispute(&mut self, tx: TxId) {
-        if self.locked { return; }
-        if let Some((amount, disputed)) = self.history.get_mut(&tx) {
-            if !*disputed && self.available >= *amount {
-                self.available -= *amount;
-                self.held += *amount;
-                *disputed = true;
-            }
-        }
+mod cli;
+mod completions;
+
+use std::{env, fs::{self, File}, io::{self, Read, Write}, process};
+
+use txflow::{
+    admin,
+    aggregate::AggregateWriter,
+    anchor::Anchors,
+    anonymize,
+    bench::{self, BenchConfig},
+    checkpoint::{self, Checkpoints},
+    crash,
+    convert,
+    deadletter::DeadLetterWriter,
+    diff,
+    extsort::SortedReport,
+    declines::DeclineWriter,
+    diagnostics::RejectLog,
+    delta::DeltaWriter,
+    frozen::FrozenWriter,
+    graph::DisputeGraph,
+    import,
+    logging::{Level, LogEvent, Logger},
+    merkle::{self, MerkleTree},
+    observer::Notifiers,
+    parallel,
+    processor::{self, Sinks},
+    profile::Profile,
+    query,
+    report::{self, JsonReportWriter, OutputFormat, ReportSink, ReportWriter},
+    sar::SarMonitor,
+    settlement::SettlementLedger,
+    signals,
+    snapshot::Snapshots,
+    state,
+    statement,
+    statsd::Statsd,
+    submit,
+    top::TopClients,
+    validate,
+    watch::{self, WatchConfig},
+    html, xlsx, Engine, Error,
+};
+
+use cli::{AdminArgs, AnonymizeArgs, BatchArgs, Command, ConvertArgs, DiffArgs, GraphArgs, ImportArgs, MergeArgs, MergeStateArgs, ProveArgs, QueryArgs, RevertArgs, RunArgs, StatementArgs, StatsArgs, SubmitArgs, ValidateArgs, VerifyAuditArgs, WatchArgs, USAGE};
+
+/// Opens the transactions file, or stdin when the path is `-`.
+fn open_input(args: &RunArgs, logger: &Logger) -> Result<Box<dyn Read>, Error> {
+    let file: Box<dyn Read> = if args.path == "-" { Box::new(io::stdin()) } else { Box::new(File::open(&args.path)?) };
+    logger.log(LogEvent::new(Level::Debug, "opened").reason(&args.path));
+
+    #[cfg(feature = "chaos")]
+    if let Some(config) = &args.chaos {
+        logger.log(LogEvent::new(Level::Warn, "chaos").reason(format!("injecting faults: {:?}", config)));
+        return Ok(Box::new(txflow::chaos::ChaosReader::new(file, config.clone())));
     }
+    Ok(file)
+}
 
-    fn resolve(&mut self, tx: TxId) {
-        if self.locked { return; }
-        if let Some((amount, disputed)) = self.history.get_mut(&tx) {
-            if *disputed {
-                self.available += *amount;
-                self.held -= *amount;
-                *disputed = false;
-            }
-        }
+fn process_transactions(args: &RunArgs, logger: &Logger) -> Result<(), Error> {
+    let input = open_input(args, logger)?;
+
+    let mut sinks = Sinks::default();
+    if let Some(path) = &args.deltas {
+        sinks.deltas = Some(DeltaWriter::new(Box::new(File::create(path)?), args.delta_format));
+    }
+    if let Some(path) = &args.declines {
+        sinks.declines = Some(DeclineWriter::new(Box::new(File::create(path)?)));
+    }
+    if let Some(path) = &args.frozen {
+        sinks.frozen = Some(FrozenWriter::new(Box::new(File::create(path)?)));
+    }
+    if let Some(path) = &args.dead_letter {
+        let mut dead_letter = DeadLetterWriter::new(Box::new(File::create(path)?));
+        dead_letter.set_input(&args.path);
+        sinks.dead_letter = Some(dead_letter);
+    }
+    if let Some(path) = &args.sar {
+        sinks.sar = Some(SarMonitor::new(Box::new(File::create(path)?), args.options.policy.sar.clone()));
+    }
+    if let Some(path) = &args.settlement {
+        sinks.settlement = Some(SettlementLedger::new(Box::new(File::create(path)?), args.options.policy.settlement.clone()));
     }
 
-    fn chargeback(&mut self, tx: TxId) {
-        if self.locked { return; }
-        if let Some((amount, disputed)) = self.history.get_mut(&tx) {
-            if *disputed {
-                self.held -= *amount;
-                self.locked = true;
-                *disputed = false;
-            }
+    if let Some(path) = &args.aggregates {
+        sinks.aggregates = Some(AggregateWriter::new(Box::new(File::create(path)?), args.bucket, args.aggregate_format));
+    }
+    if let Some(path) = &args.top_clients {
+        sinks.top = Some(TopClients::new(Box::new(File::create(path)?), args.top.unwrap_or(10)));
+    }
+    if let Some(every) = args.snapshot_every {
+        let dir = args.snapshot_dir.clone().unwrap_or_else(|| ".".into());
+        fs::create_dir_all(&dir)?;
+        sinks.snapshots = Some(Snapshots::new(dir, every));
+    }
+
+    let mut engine = Engine::with_policy(&args.options.policy);
+    let mut resumed_at = None;
+    let file = match (&args.output, args.resume) {
+        (Some(path), true) => {
+            let resumed = checkpoint::resume(path, &checkpoint::path(path))?;
+            let last = resumed.last_client.map_or("none yet".to_string(), |client| client.0.to_string());
+            logger.log(LogEvent::new(Level::Info, "resuming").reason(format!(
+                "after {} rows, last client written {}", resumed.point.rows, last
+            )));
+            engine = resumed.engine;
+            engine.set_policy(&args.options.policy);
+            resumed_at = Some(resumed.point);
+            Some(resumed.output)
+        }
+        (path, _) => path.as_ref().map(File::create).transpose()?,
+    };
+    if let (Some(file), Some(path)) = (&file, &args.output) {
+        if args.checkpoint_every.is_some() || resumed_at.is_some() {
+            let checkpoints = Checkpoints::new(checkpoint::path(path), file.try_clone()?, args.checkpoint_every);
+            sinks.checkpoints = Some(match resumed_at {
+                Some(point) => checkpoints.resuming(point),
+                None => checkpoints,
+            });
         }
     }
+    // A second handle on the report file, to sync it once everything is written.
+    let synced = match (&file, args.fsync) {
+        (Some(file), true) => Some(file.try_clone()?),
+        _ => None,
+    };
+    let output: Box<dyn Write> = match file {
+        Some(file) => Box::new(file),
+        None => Box::new(io::stdout()),
+    };
+    if args.merkle_root {
+        sinks.merkle = Some(MerkleTree::default());
+    }
+    if let Some(anchor) = &args.anchor {
+        sinks.anchors = Some(Anchors::open(anchor)?);
+    }
+    if let Some(statsd) = &args.statsd {
+        sinks.statsd = Some(Statsd::open(statsd.clone())?);
+    }
+    #[cfg(feature = "otel")]
+    if let Some(config) = &args.otel {
+        sinks.telemetry = Some(txflow::otel::Telemetry::new(config.clone(), &args.path));
+    }
+    if args.output_format == OutputFormat::Xlsx {
+        sinks.rejects = Some(RejectLog::default());
+    }
+    // Workbooks and pages are written in one piece at the end, so their reports are collected first.
+    let mut kept = Vec::new();
+    let mut streamed: Option<Box<dyn ReportSink>> = None;
+    let (report, document): (&mut dyn ReportSink, _) = match args.output_format {
+        OutputFormat::Csv if resumed_at.is_some_and(|point| point.output_bytes > 0) => {
+            (streamed.insert(sorted(args, ReportWriter::continuing(output, args.flush_every))).as_mut(), None)
+        }
+        OutputFormat::Csv => (streamed.insert(sorted(args, ReportWriter::with_flush(output, args.flush_every))).as_mut(), None),
+        OutputFormat::Jsonl => (streamed.insert(sorted(args, JsonReportWriter::with_flush(output, args.flush_every))).as_mut(), None),
+        OutputFormat::Xlsx | OutputFormat::Html => (&mut kept, Some(output)),
+    };
+
+    let notifiers = Notifiers::start(&args.options.policy, &mut engine, logger);
+    let summary = processor::process_csv(input, &mut engine, report, &mut sinks, &args.options, logger);
+    if let Ok(summary) = &summary {
+        notifiers.post_summary(summary, summary.finalized as usize + engine.len()?, logger);
+    }
+    notifiers.finish(logger);
+    let summary = summary?;
+    for account in engine.reports()? {
+        report.write(&account)?;
+    }
+    report.finish()?;
+    if let Some(output) = document {
+        kept.sort_by_key(|account| account.client);
+        match args.output_format {
+            OutputFormat::Xlsx => xlsx::write(output, &kept, &sinks.rejects.take().unwrap_or_default(), &summary)?,
+            _ => html::write(output, &kept, &summary)?,
+        }
+    }
+    if let Some(file) = synced {
+        file.sync_all()?;
+    }
+    // The report is complete, so there is nothing left to resume.
+    if let (Some(path), Some(_)) = (&args.output, &sinks.checkpoints) {
+        match fs::remove_file(checkpoint::path(path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    logger.log(LogEvent::new(Level::Debug, "written").reason(format!("{} accounts", summary.finalized + engine.len()? as u64)));
+    if args.print_fingerprint {
+        logger.log(LogEvent::new(Level::Info, "fingerprint").reason(engine.fingerprint()?));
+    }
+    Ok(())
 }
 
-fn process_transactions(path: &str) -> Result<(), Box<dyn Error>> {
-    let file = File::open(path)?;
-    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(file);
-    let mut accounts: HashMap<ClientId, Account> = HashMap::new();
-
-    for result in reader.deserialize() {
-        let record: Transaction = result?;
-        let account = accounts.entry(record.client).or_insert(Account {
-            client: record.client, ..Default::default()
-        });
-
-        match record.tx_type {
-            TxType::Deposit => {
-                if let Some(amount) = record.amount {
-                    account.deposit(record.tx, amount);
-                }
-            },
-            TxType::Withdrawal => {
-                if let Some(amount) = record.amount {
-                    account.withdrawal(amount);
-                }
-            },
-            TxType::Dispute => account.dispute(record.tx),
-            TxType::Resolve => account.resolve(record.tx),
-            TxType::Chargeback => account.chargeback(record.tx),
-        }
+/// Puts a streamed report in client order when asked to.
+fn sorted<S: ReportSink + 'static>(args: &RunArgs, report: S) -> Box<dyn ReportSink> {
+    if !args.sort_by_client {
+        return Box::new(report);
     }
+    let dir = args.spill_dir.clone().unwrap_or_else(env::temp_dir);
+    Box::new(SortedReport::new(report, args.sort_buffer.unwrap_or(100_000), dir))
+}
 
-    let mut writer = csv::Writer::from_writer(io::stdout());
-    for account in accounts.values() {
-        writer.serialize(account)?;
+fn watch_directory(args: WatchArgs, logger: &Logger) -> Result<(), Error> {
+    let config = WatchConfig {
+        dir: args.dir,
+        done: args.done,
+        state: args.state,
+        poll: args.poll,
+        once: args.once,
+        dead_letter: args.dead_letter,
+        undo_depth: args.undo_depth,
+        atomic: args.atomic_per_file,
+        health: args.health,
+        max_backlog: args.max_backlog,
+        policy: args.policy,
+        idempotent: args.idempotent,
+        anchor: args.anchor,
+        statsd: args.statsd,
+    };
+    // A reload parses the command line again, so flags after --policy still override the file.
+    let reload = || match cli::parse_args(env::args().skip(1))?.command {
+        Command::Watch(args) => Ok(args.options),
+        _ => Err("not a watch command".to_string()),
+    };
+    watch::run(&config, &args.options, Some(&reload), logger)
+}
+
+/// Combines per-partition state files; inputs are merged in the order given.
+fn merge_states(args: &MergeStateArgs, logger: &Logger) -> Result<(), Error> {
+    let mut merged = Engine::new();
+    for path in &args.inputs {
+        merged.merge(state::load(path)?)?;
+        logger.log(LogEvent::new(Level::Debug, "merged").reason(path.display()));
+    }
+    state::save(&merged, &args.out)?;
+    if args.print_fingerprint {
+        logger.log(LogEvent::new(Level::Info, "fingerprint").reason(merged.fingerprint()?));
     }
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!(
+        "merged {} states, {} accounts", args.inputs.len(), merged.len()?
+    )));
+    Ok(())
+}
 
-    writer.flush()?;
+/// Takes back the last rows applied to a state file, e.g. the tail of a bad input.
+fn revert_state(args: &RevertArgs, logger: &Logger) -> Result<(), Error> {
+    let mut engine = state::load(&args.state)?;
+    if engine.revertible() < args.last {
+        return Err(Error::Revert { requested: args.last, available: engine.revertible() });
+    }
+    engine.revert_last(args.last);
+    if let Some(err) = engine.take_store_error() {
+        return Err(err);
+    }
+    state::save(&engine, &args.state)?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!(
+        "reverted {} rows, {} more can be reverted", args.last, engine.revertible()
+    )));
     Ok(())
 }
 
-fn main() {
-    if let Some(path) = env::args().nth(1) {
-        if let Err(err) = process_transactions(&path) {
-            eprintln!("Error processing transactions: {}", err);
+/// Checks an audit log's hash chain, and against its head that nothing was cut off the end.
+fn verify_audit(args: &VerifyAuditArgs, logger: &Logger) -> Result<(), Error> {
+    let head_path = args.head.clone().unwrap_or_else(|| admin::head_path(&args.path));
+    let head = match admin::Head::read(&head_path) {
+        Ok(head) => Some(head),
+        Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound && args.head.is_none() => {
+            logger.log(LogEvent::new(Level::Warn, "no-head").reason(format!(
+                "{} not found; lines cut off the end cannot be detected", head_path.display()
+            )));
+            None
         }
-    } else {
-        eprintln!("Usage: cargo run -- transactions.csv > accounts.csv");
+        Err(err) => return Err(err),
+    };
+    let verified = admin::verify(File::open(&args.path)?, head.as_ref())?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!(
+        "{} entries, chain intact, last hash {}", verified.entries, verified.hash
+    )));
+    Ok(())
+}
+
+/// Applies an operator's override to saved state, or shows an account's
+/// history; either way the audit log records it.
+fn administer(args: &AdminArgs, logger: &Logger) -> Result<(), Error> {
+    let mut engine = state::load(&args.state)?;
+    let mut audit = admin::AuditLog::open(&args.audit)?;
+    admin::perform(&mut engine, args.client, &args.action, &args.operator, &mut audit, io::stdout().lock())?;
+    if args.action != admin::Action::History {
+        state::save(&engine, &args.state)?;
     }
+    logger.log(LogEvent::new(Level::Info, "admin").client(args.client).reason(format!("{} by {}", args.action.name(), args.operator)));
+    Ok(())
+}
+
+fn run_bench(config: &BenchConfig, logger: &Logger) -> Result<(), Error> {
+    logger.log(LogEvent::new(Level::Info, "bench").reason(format!(
+        "{} rows across {} clients{}", config.rows, config.clients, if config.parse { " via the CSV parser" } else { "" }
+    )));
+    println!("{}", bench::run(config, logger)?);
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rust_decimal::dec;
+/// Converts a bank file into txflow CSV on stdout.
+fn import_statement(args: &ImportArgs, logger: &Logger) -> Result<(), Error> {
+    let input = fs::read_to_string(&args.path)?;
+    let entries = import::read(args.format, &input)?;
+    let transactions = import::to_transactions(entries, args.client, args.first_tx)?;
+    import::write_csv(&transactions, io::stdout().lock())?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!("imported {} transactions", transactions.len())));
+    Ok(())
+}
 
-    fn test_account(client: ClientId) -> Account {
-        Account { client, ..Default::default() }
-    }
+/// Writes the dispute chains in a transactions file as DOT on stdout.
+fn graph_disputes(args: &GraphArgs, logger: &Logger) -> Result<(), Error> {
+    let graph = DisputeGraph::read(File::open(&args.path)?, &args.options, logger)?;
+    graph.write_dot(io::stdout().lock())?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!("{} dispute chains", graph.chains())));
+    Ok(())
+}
 
-    #[test]
-    fn test_deposit() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(10.0));
-        assert_eq!(account.available, dec!(10.0));
-        assert_eq!(account.held, dec!(0.0));
+/// Applies the input and writes the Merkle root with a proof for every
+/// applied row naming the transaction.
+fn prove_transaction(args: &ProveArgs, logger: &Logger) -> Result<(), Error> {
+    let mut engine = Engine::with_policy(&args.options.policy);
+    let mut sinks = Sinks { merkle: Some(MerkleTree::default()), ..Default::default() };
+    processor::process_csv(File::open(&args.path)?, &mut engine, &mut Vec::new(), &mut sinks, &args.options, logger)?;
+    let merkle = sinks.merkle.unwrap_or_default();
+    let proofs = merkle.proofs(args.tx);
+    if proofs.is_empty() {
+        return Err(Error::TxNotFound(args.tx));
     }
-
-    #[test]
-    fn test_withdrawal() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(10.0));
-        account.withdrawal(dec!(4.0));
-        assert_eq!(account.available, dec!(6.0));
+    #[derive(serde::Serialize)]
+    struct Proven<'a> {
+        root: String,
+        rows: usize,
+        proofs: &'a [merkle::Proof],
     }
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &Proven { root: merkle.root(), rows: merkle.len(), proofs: &proofs })?;
+    writeln!(out)?;
+    logger.log(LogEvent::new(Level::Info, "summary").tx(args.tx).reason(format!("{} proofs", proofs.len())));
+    Ok(())
+}
 
-    #[test]
-    fn test_withdrawal_insufficient() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(2.0));
-        account.withdrawal(dec!(3.0));
-        assert_eq!(account.available, dec!(2.0));
+/// Writes accounts, or one client's, as they stood at a cut of the input.
+fn query_balance(args: &QueryArgs, logger: &Logger) -> Result<(), Error> {
+    let snapshot = query::replay(File::open(&args.path)?, &args.as_of, &args.options, logger)?;
+    let mut report = ReportWriter::new(io::stdout().lock());
+    let accounts = snapshot.accounts.iter().filter(|account| args.client.is_none_or(|client| account.client == client));
+    let mut written = 0;
+    for account in accounts {
+        report.write(account)?;
+        written += 1;
     }
+    report.flush()?;
+    logger.log(LogEvent::new(Level::Info, "as-of").reason(format!("{} accounts as of line {}", written, snapshot.line)));
+    Ok(())
+}
 
-    #[test]
-    fn test_dispute_valid() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(10.0));
-        account.dispute(TxId(1));
-        assert_eq!(account.available, dec!(0.0));
-        assert_eq!(account.held, dec!(10.0));
-    }
+/// Writes one client's statement for a period on stdout.
+fn write_statement(args: &StatementArgs, logger: &Logger) -> Result<(), Error> {
+    let statement = statement::build(File::open(&args.path)?, args.client, args.from, args.to, &args.options, logger)?;
+    statement.write_text(io::stdout().lock())?;
+    logger.log(LogEvent::new(Level::Info, "statement").client(args.client).reason(format!("{} transactions", statement.lines.len())));
+    Ok(())
+}
 
-    #[test]
-    fn test_resolve() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(10.0));
-        account.dispute(TxId(1));
-        account.resolve(TxId(1));
-        assert_eq!(account.available, dec!(10.0));
-        assert_eq!(account.held, dec!(0.0));
+/// Processes independent files on all cores into one CSV report.
+fn process_batch(args: &BatchArgs, logger: &Logger) -> Result<(), Error> {
+    let jobs = args.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |cores| cores.get()));
+    let (accounts, _) = parallel::process_files(&args.paths, jobs, &args.options, logger)?;
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut report = ReportWriter::new(output);
+    for account in &accounts {
+        report.write(account)?;
     }
+    report.finish()?;
+    Ok(())
+}
 
-    #[test]
-    fn test_chargeback() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(10.0));
-        account.dispute(TxId(1));
-        account.chargeback(TxId(1));
-        assert_eq!(account.held, dec!(0.0));
-        assert!(account.locked);
-    }
+/// Rewrites a transactions file or report in another format on stdout.
+fn convert_input(args: &ConvertArgs, logger: &Logger) -> Result<(), Error> {
+    let input: Box<dyn Read> = if args.path == "-" { Box::new(io::stdin()) } else { Box::new(File::open(&args.path)?) };
+    let rows = convert::convert(input, io::stdout().lock(), args.schema, args.from, args.to, &args.options.amounts)?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!("converted {} rows", rows)));
+    Ok(())
+}
 
-    #[test]
-    fn test_locked_account_blocks_deposit() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(10.0));
-        account.dispute(TxId(1));
-        account.chargeback(TxId(1));
-        account.deposit(TxId(2), dec!(10.0));
-        assert_eq!(account.available, dec!(0.0));
-    }
+/// Writes an anonymized copy of a transactions file on stdout.
+fn anonymize_input(args: &AnonymizeArgs, logger: &Logger) -> Result<(), Error> {
+    let anonymized = anonymize::anonymize(File::open(&args.path)?, io::stdout().lock(), &args.config, &args.options.amounts)?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!(
+        "{} rows for {} clients, {} unreadable rows left out", anonymized.rows, anonymized.clients, anonymized.skipped
+    )));
+    Ok(())
+}
 
-    #[test]
-    fn test_dispute_nonexistent_tx() {
-        let mut account = test_account(ClientId(1));
-        account.dispute(TxId(99)); // No tx inserted
-        assert_eq!(account.available, dec!(0.0));
-        assert_eq!(account.held, dec!(0.0));
+/// Sums partial account reports into one CSV report.
+fn merge_reports(args: &MergeArgs, logger: &Logger) -> Result<(), Error> {
+    let mut rows = Vec::new();
+    for path in &args.paths {
+        rows.extend(report::read_csv(File::open(path)?)?);
     }
-
-    #[test]
-    fn test_dispute_on_withdrawal_should_be_ignored() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(10.0));
-        account.withdrawal(dec!(5.0)); // No tx id stored for withdrawal
-        account.dispute(TxId(2)); // Attempt to dispute non-existent withdrawal
-        assert_eq!(account.available, dec!(5.0));
-        assert_eq!(account.held, dec!(0.0));
+    let merged = report::merge(rows, args.locked)?;
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut report = ReportWriter::new(output);
+    for account in &merged {
+        report.write(account)?;
     }
+    report.finish()?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!("merged {} reports into {} rows", args.paths.len(), merged.len())));
+    Ok(())
+}
 
-    #[test]
-    fn test_dispute_tx_not_owned_by_client_is_ignored() {
-        let mut acc1 = test_account(ClientId(1));
-        let mut acc2 = test_account(ClientId(2));
+/// Profiles a transactions file on stdout without applying it.
+fn profile_input(args: &StatsArgs, logger: &Logger) -> Result<(), Error> {
+    let profile = Profile::read(File::open(&args.path)?, &args.options.amounts)?;
+    profile.write_text(io::stdout().lock())?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!("profiled {} rows", profile.rows)));
+    Ok(())
+}
 
-        // Only acc1 has tx 100
-        acc1.deposit(TxId(100), dec!(15.0));
+/// Writes what is wrong with a transactions file as JSON, failing if anything
+/// would stop a row from applying.
+fn validate_input(args: &ValidateArgs, logger: &Logger) -> Result<(), Error> {
+    let findings = validate::validate(File::open(&args.path)?, &args.options.amounts, args.options.sample_limit)?;
+    let mut output: Box<dyn Write> = match &args.report {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    serde_json::to_writer_pretty(&mut output, &findings).map_err(io::Error::from)?;
+    writeln!(output)?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!(
+        "{} rows, {} errors, {} warnings", findings.rows, findings.errors, findings.warnings
+    )));
+    if !findings.is_valid() {
+        return Err(Error::Invalid(findings.errors));
+    }
+    Ok(())
+}
+
+/// Writes how two reports differ on stdout, failing if they do.
+fn diff_reports(args: &DiffArgs, logger: &Logger) -> Result<(), Error> {
+    let differences = diff::diff(File::open(&args.expected)?, File::open(&args.actual)?, &args.options)?;
+    diff::write(&differences, args.format, io::stdout().lock())?;
+    if !differences.is_empty() {
+        return Err(Error::ReportsDiffer(differences.len()));
+    }
+    logger.log(LogEvent::new(Level::Info, "summary").reason("reports match"));
+    Ok(())
+}
 
-        // acc2 tries to dispute tx 100 (which it doesn't own)
-        acc2.dispute(TxId(100));
-
-        // Assert acc1 remains unchanged
-        assert_eq!(acc1.available, dec!(15.0));
-        assert_eq!(acc1.held, dec!(0.0));
+/// Uploads a transactions file to a remote endpoint in batches.
+fn submit_transactions(args: &SubmitArgs, logger: &Logger) -> Result<(), Error> {
+    let input: Box<dyn Read> = if args.path == "-" { Box::new(io::stdin()) } else { Box::new(File::open(&args.path)?) };
+    let submitted = submit::submit(input, &args.config, logger)?;
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!(
+        "submitted {} rows ({} bytes) in {} batches to {}", submitted.rows, submitted.bytes, submitted.batches, args.config.url
+    )));
+    Ok(())
+}
 
-        // Assert acc2 remains unchanged
-        assert_eq!(acc2.available, dec!(0.0));
-        assert_eq!(acc2.held, dec!(0.0));
-    }
-
-    #[test]
-    fn test_dispute_after_funds_already_withdrawn_should_fail() {
-        let mut account = test_account(ClientId(1));
-        account.deposit(TxId(1), dec!(10.0));
-        account.withdrawal(dec!(10.0));
-        account.dispute(TxId(1)); // Should be ignored
-        assert_eq!(account.available, dec!(0.0));
-        assert_eq!(account.held, dec!(0.0));
+/// Lets SIGTERM and SIGINT stop a run or watch loop cleanly; returns whether
+/// the handler is in place.
+fn handle_signals(logger: &Logger) -> bool {
+    match signals::install() {
+        Ok(()) => true,
+        Err(err) => {
+            logger.log(LogEvent::new(Level::Warn, "signals").reason(format!("no graceful shutdown: {}", err)));
+            false
+        }
     }
+}
 
+fn main() {
+    let cli = match cli::parse_args(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            Logger::default().log(LogEvent::new(Level::Error, "usage").reason(format!("{}; usage: {}", err, USAGE)));
+            process::exit(2);
+        }
+    };
+
+    let logger = Logger::new(cli.log_format, cli.log_level);
+    if let Some(dsn) = cli.crash_reports {
+        let args: Vec<String> = env::args().skip(1).collect();
+        let policy = args.iter().position(|arg| arg == "--policy")
+            .and_then(|at| args.get(at + 1))
+            .and_then(|path| fs::read_to_string(path).ok());
+        crash::install(dsn, crash::config_hash(args.iter().map(String::as_str), policy.as_deref()));
+    }
+    let result = match cli.command {
+        Command::Run(mut args) => {
+            args.options.interruptible = handle_signals(&logger);
+            crash::set_input(&args.path);
+            process_transactions(&args, &logger)
+        }
+        Command::Watch(mut args) => {
+            args.options.interruptible = handle_signals(&logger);
+            watch_directory(*args, &logger)
+        }
+        Command::MergeState(args) => merge_states(&args, &logger),
+        Command::Bench(config) => run_bench(&config, &logger),
+        Command::Import(args) => import_statement(&args, &logger),
+        Command::Graph(args) => graph_disputes(&args, &logger),
+        Command::Prove(args) => prove_transaction(&args, &logger),
+        Command::Query(args) => query_balance(&args, &logger),
+        Command::Statement(args) => write_statement(&args, &logger),
+        Command::Revert(args) => revert_state(&args, &logger),
+        Command::Admin(args) => administer(&args, &logger),
+        Command::VerifyAudit(args) => verify_audit(&args, &logger),
+        Command::Batch(args) => process_batch(&args, &logger),
+        Command::Submit(args) => submit_transactions(&args, &logger),
+        Command::Diff(args) => diff_reports(&args, &logger),
+        Command::Validate(args) => validate_input(&args, &logger),
+        Command::Stats(args) => profile_input(&args, &logger),
+        Command::Merge(args) => merge_reports(&args, &logger),
+        Command::Anonymize(args) => anonymize_input(&args, &logger),
+        Command::Convert(args) => convert_input(&args, &logger),
+        Command::Completions(shell) => io::stdout().write_all(completions::completions(shell).as_bytes()).map_err(Error::from),
+        Command::Manpage => io::stdout().write_all(completions::manpage().as_bytes()).map_err(Error::from),
+    };
+    if let Err(err) = result {
+        logger.log(LogEvent::new(Level::Error, "error").reason(format!("error processing transactions: {}", err)));
+        // The variant name, e.g. `Parse`, groups reports of the same failure.
+        let kind = format!("{:?}", err).split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string();
+        if let Err(reason) = crash::report(&kind, &err.to_string()) {
+            logger.log(LogEvent::new(Level::Warn, "crash-report").reason(format!("not sent: {}", reason)));
+        }
+        process::exit(1);
+    }
 }
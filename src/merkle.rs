@@ -0,0 +1,168 @@
+//! A Merkle tree over the rows a run applied, in the order it applied them,
+//! so a counterparty holding the root can be shown that one of their
+//! transactions was processed without seeing any other row.
+//!
+//! A leaf is the SHA-256 of `0x00` followed by the row as
+//! `type,client,tx,amount`, the amount normalized and empty when absent; an
+//! inner node hashes `0x01`, its left child and its right child. An odd node
+//! at the end of a level is carried up unchanged.
+
+use serde::Serialize;
+
+use crate::{sha256::{self, Digest, Sha256}, Transaction, TxId};
+
+fn leaf(transaction: &Transaction) -> Digest {
+    let amount = transaction.amount.map(|amount| amount.normalize().to_string()).unwrap_or_default();
+    let row = format!("{},{},{},{}", transaction.tx_type.name(), transaction.client.0, transaction.tx.0, amount);
+    let mut hasher = Sha256::new();
+    hasher.update(&[0]);
+    hasher.update(row.as_bytes());
+    hasher.finalize()
+}
+
+fn node(left: &Digest, right: &Digest) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(&[1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize()
+}
+
+/// A sibling on the way from a leaf to the root, and which side it is on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sibling {
+    Left(String),
+    Right(String),
+}
+
+/// Shows that one applied row is under a root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Proof {
+    pub tx: TxId,
+    /// Position of the row among those applied.
+    pub index: usize,
+    pub leaf: String,
+    /// Siblings from the leaf up.
+    pub path: Vec<Sibling>,
+}
+
+impl Proof {
+    /// Whether the path leads from the leaf to `root` (both hex).
+    pub fn verify(&self, root: &str) -> bool {
+        let mut hash = match sha256::from_hex(&self.leaf) {
+            Some(hash) => hash,
+            None => return false,
+        };
+        for sibling in &self.path {
+            hash = match sibling {
+                Sibling::Left(left) => match sha256::from_hex(left) {
+                    Some(left) => node(&left, &hash),
+                    None => return false,
+                },
+                Sibling::Right(right) => match sha256::from_hex(right) {
+                    Some(right) => node(&hash, &right),
+                    None => return false,
+                },
+            };
+        }
+        sha256::to_hex(&hash).eq_ignore_ascii_case(root)
+    }
+}
+
+/// Leaves of the rows applied so far.
+#[derive(Debug, Default, Clone)]
+pub struct MerkleTree {
+    leaves: Vec<Digest>,
+    txs: Vec<TxId>,
+}
+
+impl MerkleTree {
+    pub fn push(&mut self, transaction: &Transaction) {
+        self.leaves.push(leaf(transaction));
+        self.txs.push(transaction.tx);
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Every level, leaves first and the root alone last.
+    fn levels(&self) -> Vec<Vec<Digest>> {
+        let mut levels = vec![self.leaves.clone()];
+        while levels.last().is_some_and(|level| level.len() > 1) {
+            let below = levels.last().expect("checked above");
+            let above = below.chunks(2).map(|pair| match pair {
+                [left, right] => node(left, right),
+                [odd] => *odd,
+                _ => unreachable!("chunks of two"),
+            }).collect();
+            levels.push(above);
+        }
+        levels
+    }
+
+    /// The root as hex; the hash of nothing for a run that applied no rows.
+    pub fn root(&self) -> String {
+        match self.levels().last().and_then(|level| level.first()) {
+            Some(root) => sha256::to_hex(root),
+            None => sha256::to_hex(&sha256::digest(b"")),
+        }
+    }
+
+    /// A proof for every applied row naming `tx`: the deposit or withdrawal
+    /// itself, and any dispute, resolve or chargeback of it.
+    pub fn proofs(&self, tx: TxId) -> Vec<Proof> {
+        let levels = self.levels();
+        self.txs.iter().enumerate().filter(|(_, id)| **id == tx).map(|(index, _)| {
+            let mut path = Vec::new();
+            let mut at = index;
+            for level in &levels[..levels.len() - 1] {
+                let sibling = at ^ 1;
+                if let Some(hash) = level.get(sibling) {
+                    let hash = sha256::to_hex(hash);
+                    path.push(if sibling < at { Sibling::Left(hash) } else { Sibling::Right(hash) });
+                }
+                at /= 2;
+            }
+            Proof { tx, index, leaf: sha256::to_hex(&self.leaves[index]), path }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientId, TxType};
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_every_row_proves_against_the_root() {
+        let mut tree = MerkleTree::default();
+        for tx in 1..=5 {
+            tree.push(&Transaction::new(TxType::Deposit, ClientId(1), TxId(tx), Some(dec!(2.50))));
+        }
+        tree.push(&Transaction::new(TxType::Dispute, ClientId(1), TxId(3), None));
+        let root = tree.root();
+        for tx in 1..=5 {
+            let proofs = tree.proofs(TxId(tx));
+            assert_eq!(proofs.len(), if tx == 3 { 2 } else { 1 });
+            assert!(proofs.iter().all(|proof| proof.verify(&root)), "tx {}", tx);
+        }
+        assert!(tree.proofs(TxId(9)).is_empty());
+
+        let mut forged = tree.proofs(TxId(2)).remove(0);
+        forged.leaf = sha256::to_hex(&leaf(&Transaction::new(TxType::Deposit, ClientId(1), TxId(2), Some(dec!(25)))));
+        assert!(!forged.verify(&root));
+        let mut same = MerkleTree::default();
+        for tx in 1..=5 {
+            same.push(&Transaction::new(TxType::Deposit, ClientId(1), TxId(tx), Some(dec!(2.5))));
+        }
+        same.push(&Transaction::new(TxType::Dispute, ClientId(1), TxId(3), None));
+        assert_eq!(same.root(), root);
+    }
+}
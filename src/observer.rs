@@ -0,0 +1,157 @@
+//! Callbacks fired by the engine as it applies transactions, for metrics,
+//! alerting or other side effects that shouldn't live in the processing loop.
+
+use std::{cell::RefCell, fmt, rc::Rc};
+use rust_decimal::Decimal;
+
+use crate::{
+    alert::Alerter,
+    chat::{self, ChatConfig},
+    logging::{Level, LogEvent, Logger},
+    policy::Policy,
+    processor::Summary,
+    store::StateStore,
+    webhook::Webhook,
+    Account, ClientId, Engine, Reject, Transaction,
+};
+
+/// Receives engine events; every callback defaults to doing nothing, so an
+/// observer only implements the ones it cares about. Callbacks run inline
+/// with [`Engine::apply`](crate::Engine::apply) and should be cheap. An
+/// observer sees accounts in the engine's amount type; amounts it is told
+/// about directly are `Decimal` whatever that type is.
+pub trait EngineObserver<A = Decimal> {
+    /// A transaction was applied; `account` is its state afterwards.
+    fn on_applied(&mut self, _transaction: &Transaction, _account: &Account<A>) {}
+
+    fn on_rejected(&mut self, _transaction: &Transaction, _reject: Reject) {}
+
+    /// An account went from unlocked to locked.
+    fn on_lock(&mut self, _account: &Account<A>) {}
+
+    /// A chargeback reversed a deposit of `amount`; fires before `on_lock`.
+    fn on_chargeback(&mut self, _transaction: &Transaction, _amount: Decimal, _account: &Account<A>) {}
+}
+
+/// The observers registered on an engine.
+pub(crate) struct Observers<A = Decimal>(pub(crate) Vec<Box<dyn EngineObserver<A>>>);
+
+impl<A> Default for Observers<A> {
+    fn default() -> Self {
+        Observers(Vec::new())
+    }
+}
+
+impl<A> fmt::Debug for Observers<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} observers", self.0.len())
+    }
+}
+
+/// The notification observers a policy asks for, attached to an engine for
+/// the length of a run.
+#[derive(Default)]
+pub struct Notifiers {
+    webhook: Option<Webhook>,
+    alerts: Option<Alerter>,
+    chat: Option<(ChatConfig, Rc<RefCell<Vec<ClientId>>>)>,
+}
+
+/// Notes clients whose accounts lock, for the end-of-run summary.
+struct LockTracker(Rc<RefCell<Vec<ClientId>>>);
+
+impl EngineObserver for LockTracker {
+    fn on_lock(&mut self, account: &Account) {
+        self.0.borrow_mut().push(account.client);
+    }
+}
+
+impl Notifiers {
+    pub fn start<S: StateStore>(policy: &Policy, engine: &mut Engine<S>, logger: &Logger) -> Self {
+        let webhook = policy.webhook.clone().map(|config| Webhook::start(config, *logger));
+        let alerts = policy.alerts.clone().map(|config| Alerter::start(config, *logger));
+        if let Some(webhook) = &webhook {
+            engine.add_observer(Box::new(webhook.observer()));
+        }
+        if let Some(alerts) = &alerts {
+            engine.add_observer(Box::new(alerts.observer()));
+        }
+        let chat = policy.chat.clone().map(|config| {
+            let locked = Rc::new(RefCell::new(Vec::new()));
+            engine.add_observer(Box::new(LockTracker(locked.clone())));
+            (config, locked)
+        });
+        Notifiers { webhook, alerts, chat }
+    }
+
+    /// Posts the run's outcome to chat, if configured; `accounts` counts
+    /// open and finalized accounts alike.
+    pub fn post_summary(&self, summary: &Summary, accounts: usize, logger: &Logger) {
+        if let Some((config, locked)) = &self.chat {
+            let mut locked = locked.borrow().clone();
+            locked.sort();
+            if let Err(err) = chat::post(config, &chat::summary_text(summary, accounts, &locked)) {
+                logger.log(LogEvent::new(Level::Warn, "chat").reason(format!("{}: {}", config.webhook, err)));
+            }
+        }
+    }
+
+    /// Waits for queued notifications to go out and logs how delivery went.
+    pub fn finish(self, logger: &Logger) {
+        if let Some(webhook) = self.webhook {
+            let deliveries = webhook.finish();
+            logger.log(LogEvent::new(Level::Info, "webhook").reason(format!("{} sent, {} dropped", deliveries.sent, deliveries.dropped)));
+        }
+        if let Some(alerts) = self.alerts {
+            logger.log(LogEvent::new(Level::Info, "alerts").reason(format!("{} notifications sent", alerts.finish())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::{TxId, TxType};
+
+    #[derive(Default)]
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl EngineObserver for Recorder {
+        fn on_applied(&mut self, transaction: &Transaction, account: &Account) {
+            self.0.borrow_mut().push(format!("applied {} {}", transaction.tx.0, account.available));
+        }
+        fn on_rejected(&mut self, transaction: &Transaction, reject: Reject) {
+            self.0.borrow_mut().push(format!("rejected {} {}", transaction.tx.0, reject));
+        }
+        fn on_lock(&mut self, account: &Account) {
+            self.0.borrow_mut().push(format!("locked {}", account.client.0));
+        }
+        fn on_chargeback(&mut self, transaction: &Transaction, amount: Decimal, _account: &Account) {
+            self.0.borrow_mut().push(format!("chargeback {} {}", transaction.tx.0, amount));
+        }
+    }
+
+    #[test]
+    fn test_observer_sees_each_event() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(Recorder(events.clone())));
+        for (tx_type, tx, amount) in [
+            (TxType::Deposit, 1, Some(dec!(5))),
+            (TxType::Withdrawal, 2, Some(dec!(9))),
+            (TxType::Dispute, 1, None),
+            (TxType::Chargeback, 1, None),
+        ] {
+            let _ = engine.apply(&Transaction::new(tx_type, ClientId(1), TxId(tx), amount));
+        }
+        assert_eq!(*events.borrow(), [
+            "applied 1 5",
+            "rejected 2 insufficient funds",
+            "applied 1 0",
+            "applied 1 0",
+            "chargeback 1 5",
+            "locked 1",
+        ]);
+    }
+}
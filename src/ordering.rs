@@ -0,0 +1,217 @@
+//! Per-client ordering guarantees for inputs merged from several partitions:
+//! a [`Reorder`] buffer sorts rows that arrive a little late, and the engine
+//! rejects whatever is still out of order with [`crate::Reject::OutOfOrder`].
+
+use std::{cmp::Reverse, collections::BinaryHeap, str::FromStr};
+
+use crate::{
+    error::Error,
+    logging::{Level, LogEvent, Logger},
+    source::{Row, TxSource},
+    Transaction, TxType,
+};
+
+/// The field that must increase from one row of a client to the next.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SequenceKey {
+    /// Strictly increasing ids on deposits and withdrawals; disputes and
+    /// their follow-ups refer back to earlier ids and are not checked.
+    TxId,
+    /// Non-decreasing `timestamp` values; rows without one are not checked.
+    Timestamp,
+}
+
+impl SequenceKey {
+    /// The row's position in the sequence, if it has one.
+    pub fn of(self, record: &Transaction) -> Option<i64> {
+        match self {
+            SequenceKey::TxId => matches!(record.tx_type, TxType::Deposit | TxType::Withdrawal | TxType::Transfer)
+                .then_some(i64::from(record.tx.0)),
+            SequenceKey::Timestamp => record.timestamp,
+        }
+    }
+
+    /// Whether `key` may follow `last` for the same client.
+    pub fn follows(self, last: i64, key: i64) -> bool {
+        match self {
+            SequenceKey::TxId => key > last,
+            SequenceKey::Timestamp => key >= last,
+        }
+    }
+}
+
+impl FromStr for SequenceKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tx" => Ok(SequenceKey::TxId),
+            "timestamp" => Ok(SequenceKey::Timestamp),
+            other => Err(format!("unknown ordering key '{}' (expected tx or timestamp)", other)),
+        }
+    }
+}
+
+/// The `[ordering]` policy section.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct OrderingPolicy {
+    /// Unset leaves rows in arrival order and unchecked.
+    pub require: Option<SequenceKey>,
+    /// Rows held back so late arrivals can be slotted in before the rows they precede.
+    pub window: usize,
+}
+
+impl OrderingPolicy {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "require" => self.require = Some(value.parse()?),
+            "window" => self.window = value.parse().map_err(|_| format!("invalid value '{}' for ordering window", value))?,
+            other => return Err(format!("unknown ordering option '{}'", other)),
+        }
+        Ok(())
+    }
+}
+
+/// A row waiting in the buffer, ordered by sequence key then arrival.
+#[derive(Debug)]
+struct Pending {
+    key: i64,
+    arrival: u64,
+    row: Row,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        (self.key, self.arrival) == (other.key, other.arrival)
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.key, self.arrival).cmp(&(other.key, other.arrival))
+    }
+}
+
+/// Holds up to `window` rows and releases them in sequence order. Rows
+/// without a key (disputes, in tx mode) keep their place behind everything
+/// that arrived before them. In tx mode, ids skipped in the released stream
+/// are logged as sequence gaps.
+pub struct Reorder<T> {
+    source: T,
+    key: SequenceKey,
+    window: usize,
+    pending: BinaryHeap<Reverse<Pending>>,
+    arrivals: u64,
+    newest: i64,
+    released: Option<i64>,
+    gaps: u64,
+    logger: Logger,
+}
+
+impl<T: TxSource> Reorder<T> {
+    pub fn new(source: T, key: SequenceKey, window: usize, logger: Logger) -> Self {
+        Reorder {
+            source,
+            key,
+            window,
+            pending: BinaryHeap::new(),
+            arrivals: 0,
+            newest: i64::MIN,
+            released: None,
+            gaps: 0,
+            logger,
+        }
+    }
+
+    /// Gaps seen so far in the released tx ids.
+    pub fn gaps(&self) -> u64 {
+        self.gaps
+    }
+
+    fn release(&mut self) -> Option<Row> {
+        let Reverse(Pending { row, .. }) = self.pending.pop()?;
+        if let (SequenceKey::TxId, Some(key)) = (self.key, self.key.of(&row.transaction)) {
+            match self.released {
+                Some(last) if key > last + 1 => {
+                    self.gaps += 1;
+                    let missing = if key == last + 2 { (last + 1).to_string() } else { format!("{} to {}", last + 1, key - 1) };
+                    self.logger.log(LogEvent::new(Level::Debug, "sequence-gap").tx(row.transaction.tx)
+                        .reason(format!("tx {} missing", missing)));
+                }
+                _ => {}
+            }
+            self.released = Some(self.released.map_or(key, |last| last.max(key)));
+        }
+        Some(row)
+    }
+}
+
+impl<T: TxSource> TxSource for Reorder<T> {
+    fn next_row(&mut self) -> Result<Option<Row>, Error> {
+        while self.pending.len() <= self.window {
+            match self.source.next_row()? {
+                Some(row) => {
+                    let key = self.key.of(&row.transaction).unwrap_or(self.newest);
+                    self.newest = self.newest.max(key);
+                    self.arrivals += 1;
+                    self.pending.push(Reverse(Pending { key, arrival: self.arrivals, row }));
+                }
+                None => {
+                    let row = self.release();
+                    if row.is_none() && self.gaps > 0 {
+                        self.logger.log(LogEvent::new(Level::Warn, "sequence-gaps").reason(format!("gaps in tx ids: {}", self.gaps)));
+                        self.gaps = 0;
+                    }
+                    return Ok(row);
+                }
+            }
+        }
+        Ok(self.release())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{source::IterSource, ClientId, TxId};
+
+    fn row(tx_type: TxType, tx: u32) -> Transaction {
+        Transaction::new(tx_type, ClientId(1), TxId(tx), None)
+    }
+
+    fn order(rows: Vec<Transaction>, window: usize) -> Vec<u32> {
+        let mut source = Reorder::new(IterSource::new(rows), SequenceKey::TxId, window, Logger::default());
+        std::iter::from_fn(|| source.next_row().unwrap()).map(|row| row.transaction.tx.0).collect()
+    }
+
+    #[test]
+    fn test_window_sorts_late_rows_into_place() {
+        let rows = vec![row(TxType::Deposit, 2), row(TxType::Deposit, 1), row(TxType::Deposit, 4), row(TxType::Deposit, 3)];
+        assert_eq!(order(rows.clone(), 1), vec![1, 2, 3, 4]);
+        assert_eq!(order(rows, 0), vec![2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn test_disputes_stay_behind_earlier_rows() {
+        let rows = vec![row(TxType::Deposit, 5), row(TxType::Dispute, 5), row(TxType::Deposit, 3)];
+        assert_eq!(order(rows, 5), vec![3, 5, 5]);
+    }
+
+    #[test]
+    fn test_counts_gaps_in_released_ids() {
+        let rows = vec![row(TxType::Deposit, 1), row(TxType::Deposit, 2), row(TxType::Deposit, 5), row(TxType::Withdrawal, 7)];
+        let mut source = Reorder::new(IterSource::new(rows), SequenceKey::TxId, 2, Logger::default());
+        for _ in 0..4 {
+            source.next_row().unwrap();
+        }
+        assert_eq!(source.gaps(), 2);
+    }
+}
@@ -0,0 +1,309 @@
+//! OpenTelemetry export, so runs show up next to the services around them.
+//! Traces and metrics go to an OTLP/HTTP collector as JSON, which collectors
+//! accept alongside protobuf, over the plain `http://` client in
+//! [`crate::http`].
+//!
+//! A run is one trace. There is a `file` span for the input, a `batch` span
+//! for each block of rows under it, and a `transaction` span under the batch
+//! for every so many rows. Spans go out whenever enough have built up and
+//! when the input ends. The metrics are the run's row, applied and rejected
+//! counts, rejections broken down by reason, sent once at the end as
+//! cumulative sums.
+
+use std::{
+    io,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use serde_json::{json, Value};
+
+use crate::{
+    error::Error,
+    http::{self, Url},
+    processor::Summary,
+    sha256, webhook, Reject, Transaction,
+};
+
+/// Spans held before they are sent without waiting for the input to end.
+const PENDING_SPANS: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtelConfig {
+    /// The collector's base URL; `/v1/traces` and `/v1/metrics` are added.
+    pub endpoint: Url,
+    pub service: String,
+    /// Trace one row in this many; zero traces none.
+    pub sample_every: u64,
+    /// Rows per batch span.
+    pub batch_rows: u64,
+}
+
+impl OtelConfig {
+    pub fn new(endpoint: Url) -> Self {
+        OtelConfig { endpoint, service: "txflow".to_string(), sample_every: 1_000, batch_rows: 10_000 }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| u64::try_from(since.as_nanos()).unwrap_or(u64::MAX))
+}
+
+/// `bytes` random-looking bytes as hex, different for every call in any process.
+fn id(bytes: usize) -> String {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    let seed = format!("{}:{}:{}", process::id(), now(), CALLS.fetch_add(1, Ordering::Relaxed));
+    sha256::to_hex(&sha256::digest(seed.as_bytes()))[..bytes * 2].to_string()
+}
+
+fn attribute(key: &str, value: impl Into<Value>) -> Value {
+    match value.into() {
+        Value::Number(number) => json!({ "key": key, "value": { "intValue": number.to_string() } }),
+        Value::String(text) => json!({ "key": key, "value": { "stringValue": text } }),
+        other => json!({ "key": key, "value": { "stringValue": other.to_string() } }),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+    id: String,
+    parent: Option<String>,
+    name: &'static str,
+    start: u64,
+    end: u64,
+    attributes: Vec<Value>,
+    /// Why the span failed, if it did.
+    error: Option<String>,
+}
+
+impl Span {
+    fn open(name: &'static str, parent: Option<&Span>) -> Self {
+        Span { id: id(8), parent: parent.map(|parent| parent.id.clone()), name, start: now(), end: 0, attributes: Vec::new(), error: None }
+    }
+
+    fn to_json(&self, trace: &str) -> Value {
+        let mut span = json!({
+            "traceId": trace,
+            "spanId": self.id,
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": self.end.max(self.start).to_string(),
+            "attributes": self.attributes,
+        });
+        if let Some(parent) = &self.parent {
+            span["parentSpanId"] = json!(parent);
+        }
+        if let Some(message) = &self.error {
+            span["status"] = json!({ "code": 2, "message": message });
+        }
+        span
+    }
+}
+
+/// The current batch and the row numbers it covers.
+#[derive(Debug)]
+struct Batch {
+    index: u64,
+    span: Span,
+    rows: u64,
+}
+
+/// Collects one input's spans and sends them with its metrics.
+#[derive(Debug)]
+pub struct Telemetry {
+    config: OtelConfig,
+    trace: String,
+    file: Span,
+    batch: Option<Batch>,
+    pending: Vec<Span>,
+    started: u64,
+    /// Why spans sent before the end were lost, if any were.
+    lost: Option<Error>,
+}
+
+impl Telemetry {
+    /// Opens the trace for `input`, named as the run was given it.
+    pub fn new(config: OtelConfig, input: &str) -> Self {
+        let mut file = Span::open("file", None);
+        file.attributes.push(attribute("txflow.input", input));
+        Telemetry { config, trace: id(16), started: file.start, file, batch: None, pending: Vec::new(), lost: None }
+    }
+
+    /// Notes that row `row` (from one) is about to be applied, and returns a
+    /// span for it if it is sampled.
+    pub fn row(&mut self, row: u64) -> Option<TxSpan> {
+        let index = row.saturating_sub(1) / self.config.batch_rows.max(1);
+        if self.batch.as_ref().is_none_or(|batch| batch.index != index) {
+            self.close_batch();
+            if self.pending.len() >= PENDING_SPANS {
+                if let Err(err) = self.send_spans() {
+                    self.lost = Some(err);
+                }
+            }
+            self.batch = Some(Batch { index, span: Span::open("batch", Some(&self.file)), rows: 0 });
+        }
+        let batch = self.batch.as_mut().expect("opened above");
+        batch.rows += 1;
+        let sampled = self.config.sample_every > 0 && row.is_multiple_of(self.config.sample_every);
+        sampled.then(|| TxSpan(Span::open("transaction", Some(&batch.span))))
+    }
+
+    /// Ends a sampled row's span with how the row went.
+    pub fn applied(&mut self, span: TxSpan, record: &Transaction, line: u64, result: Result<(), Reject>) {
+        let mut span = span.0;
+        span.end = now();
+        span.attributes.extend([
+            attribute("txflow.type", record.tx_type.name()),
+            attribute("txflow.client", record.client.0),
+            attribute("txflow.tx", record.tx.0),
+            attribute("txflow.line", line),
+        ]);
+        span.error = result.err().map(|reject| reject.to_string());
+        self.pending.push(span);
+    }
+
+    fn close_batch(&mut self) {
+        if let Some(mut batch) = self.batch.take() {
+            batch.span.end = now();
+            batch.span.attributes.push(attribute("txflow.rows", batch.rows));
+            self.pending.push(batch.span);
+        }
+    }
+
+    fn resource(&self) -> Value {
+        json!({ "attributes": [attribute("service.name", self.config.service.as_str())] })
+    }
+
+    fn scope() -> Value {
+        json!({ "name": "txflow", "version": env!("CARGO_PKG_VERSION") })
+    }
+
+    fn post(&self, path: &str, body: &Value) -> Result<(), Error> {
+        let mut url = self.config.endpoint.clone();
+        url.path = format!("{}{}", url.path.trim_end_matches('/'), path);
+        let body = body.to_string();
+        let post = || match http::post(&url, "application/json", body.as_bytes(), Duration::from_secs(10)) {
+            Ok(status) if (200..300).contains(&status) => Ok(()),
+            Ok(status) => Err(format!("HTTP {}", status)),
+            Err(err) => Err(err.to_string()),
+        };
+        webhook::deliver(post, 2, Duration::from_millis(500), thread::sleep)
+            .map_err(|err| Error::Io(io::Error::other(format!("exporting to {}: {}", url, err))))
+    }
+
+    fn send_spans(&mut self) -> Result<(), Error> {
+        let spans: Vec<Value> = self.pending.drain(..).map(|span| span.to_json(&self.trace)).collect();
+        self.post("/v1/traces", &json!({
+            "resourceSpans": [{ "resource": self.resource(), "scopeSpans": [{ "scope": Self::scope(), "spans": spans }] }]
+        }))
+    }
+
+    /// Ends the trace and sends what is left of it along with the run's
+    /// metrics; fails if anything sent now or earlier did not get through.
+    pub fn finish(&mut self, summary: &Summary) -> Result<(), Error> {
+        self.close_batch();
+        let end = now();
+        self.file.end = end;
+        self.file.attributes.extend([
+            attribute("txflow.rows", summary.rows),
+            attribute("txflow.applied", summary.applied),
+            attribute("txflow.rejected", summary.errors.total()),
+        ]);
+        self.pending.push(self.file.clone());
+        self.send_spans()?;
+
+        let point = |count: u64, attributes: Vec<Value>| json!({
+            "asInt": count.to_string(),
+            "startTimeUnixNano": self.started.to_string(),
+            "timeUnixNano": end.to_string(),
+            "attributes": attributes,
+        });
+        let sum = |name: &str, points: Vec<Value>| json!({
+            "name": name,
+            "unit": "{row}",
+            "sum": { "aggregationTemporality": 2, "isMonotonic": true, "dataPoints": points },
+        });
+        let rejected = summary.errors.iter().map(|(category, count)| point(count, vec![attribute("reason", category.to_string())])).collect();
+        let metrics = vec![
+            sum("txflow.rows", vec![point(summary.rows, Vec::new())]),
+            sum("txflow.applied", vec![point(summary.applied, Vec::new())]),
+            sum("txflow.rejected", rejected),
+        ];
+        self.post("/v1/metrics", &json!({
+            "resourceMetrics": [{ "resource": self.resource(), "scopeMetrics": [{ "scope": Self::scope(), "metrics": metrics }] }]
+        }))?;
+        self.lost.take().map_or(Ok(()), Err)
+    }
+}
+
+/// A sampled row's span, open while the row is applied.
+#[derive(Debug)]
+pub struct TxSpan(Span);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logging::Logger,
+        processor::{self, Options, Sinks},
+        Engine,
+    };
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn test_exports_spans_and_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://127.0.0.1:{}/otlp/", listener.local_addr().unwrap().port()).parse().unwrap();
+        let collector = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let (mut path, mut length) = (String::new(), 0);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.starts_with("POST ") {
+                        path = line.split_whitespace().nth(1).unwrap().to_string();
+                    }
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                requests.push((path, serde_json::from_slice::<Value>(&body).unwrap()));
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            requests
+        });
+
+        let config = OtelConfig { sample_every: 2, batch_rows: 2, ..OtelConfig::new(endpoint) };
+        let mut sinks = Sinks { telemetry: Some(Telemetry::new(config, "tx.csv")), ..Default::default() };
+        let input = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,50\ndeposit,2,3,5\n";
+        processor::process_csv(input.as_bytes(), &mut Engine::new(), &mut Vec::new(), &mut sinks, &Options::default(), &Logger::default()).unwrap();
+
+        let requests = collector.join().unwrap();
+        assert_eq!((requests[0].0.as_str(), requests[1].0.as_str()), ("/otlp/v1/traces", "/otlp/v1/metrics"));
+        let spans = requests[0].1["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap().clone();
+        let names: Vec<&str> = spans.iter().map(|span| span["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["transaction", "batch", "batch", "file"]);
+        assert_eq!(spans[0]["status"]["message"], "insufficient funds");
+        assert_eq!(spans[0]["parentSpanId"], spans[1]["spanId"]);
+        assert_eq!(spans[1]["parentSpanId"], spans[3]["spanId"]);
+        assert!(spans.iter().all(|span| span["traceId"] == spans[0]["traceId"]));
+
+        let metrics = &requests[1].1["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(metrics[1]["name"], "txflow.applied");
+        assert_eq!(metrics[1]["sum"]["dataPoints"][0]["asInt"], "2");
+        assert_eq!(metrics[2]["sum"]["dataPoints"][0]["attributes"][0]["value"]["stringValue"], "rejected: insufficient funds");
+    }
+}
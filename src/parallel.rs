@@ -0,0 +1,301 @@
+//! Many input files on all cores. Rows are read in the files' declared
+//! order and dealt out to one engine per worker by client, so each client's
+//! rows reach a single engine in the same order as a sequential run; the
+//! workers' accounts, which no two share, then make up the report. Policies
+//! that tie clients together can't be split this way and are refused. A
+//! transfer between clients of different workers is dealt to both, which
+//! settle it with a prepare/commit vote (see [`crate::transfer`]).
+
+use std::{
+    fs::File,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    thread,
+};
+
+use crate::{
+    error::{Error, ParseError},
+    logging::{Level, LogEvent, Logger},
+    processor::{self, Options, Sinks, Summary},
+    report::AccountReport,
+    retention::Retention,
+    source::{Row, TxSource},
+    transfer::{self, Link},
+    ClientId, Engine, Reject, TxType,
+};
+
+/// Rows sent to a worker at a time.
+const BATCH: usize = 1024;
+
+type Batch = Vec<Result<Row, ParseError>>;
+
+/// Why `options` can't be split across workers, if they can't.
+pub fn unsupported(options: &Options) -> Option<&'static str> {
+    let policy = &options.policy;
+    if policy.unique_tx_ids {
+        Some("unique transaction ids are checked across clients")
+    } else if policy.escrow_account.is_some() || policy.loss_account.is_some() {
+        Some("escrow and loss accounts total every client")
+    } else if !policy.joint.is_empty() {
+        Some("joint owners route rows between clients")
+    } else if policy.auto_resolve.is_some() || matches!(policy.retention, Retention::KeepWithin(_)) {
+        Some("auto-resolve and time-based retention follow the clock of every row")
+    } else if options.max_errors.is_some() {
+        Some("the error limit counts rows of every client")
+    } else {
+        None
+    }
+}
+
+/// The worker whose engine applies `client`'s rows.
+fn worker_of(client: ClientId, jobs: usize) -> usize {
+    client.0 as usize % jobs
+}
+
+/// One worker's lines to every worker, itself included, for transfer votes.
+#[derive(Debug)]
+struct Peers {
+    worker: usize,
+    votes: Vec<Sender<Result<(), Reject>>>,
+    replies: Vec<Receiver<Result<(), Reject>>>,
+}
+
+impl Peers {
+    /// Lines between every pair of `jobs` workers, one set per worker.
+    fn connect(jobs: usize) -> Vec<Peers> {
+        let mut peers: Vec<Peers> = (0..jobs).map(|worker| Peers { worker, votes: Vec::new(), replies: Vec::new() }).collect();
+        for from in 0..jobs {
+            for to in 0..jobs {
+                let (vote, reply) = mpsc::channel();
+                peers[from].votes.push(vote);
+                peers[to].replies.push(reply);
+            }
+        }
+        peers
+    }
+}
+
+impl Link for Peers {
+    fn owns(&self, client: ClientId) -> bool {
+        worker_of(client, self.votes.len()) == self.worker
+    }
+
+    fn exchange(&mut self, peer: ClientId, vote: Result<(), Reject>) -> Result<(), Reject> {
+        let peer = worker_of(peer, self.votes.len());
+        // A worker that has gone has given up on the run, which fails anyway.
+        let _ = self.votes[peer].send(vote);
+        self.replies[peer].recv().unwrap_or(Err(Reject::TransferAborted))
+    }
+}
+
+/// The rows dealt to one worker.
+struct Shard {
+    rows: Receiver<Batch>,
+    pending: std::vec::IntoIter<Result<Row, ParseError>>,
+}
+
+impl TxSource for Shard {
+    fn next_row(&mut self) -> Result<Option<Row>, Error> {
+        loop {
+            if let Some(row) = self.pending.next() {
+                return row.map(Some).map_err(Error::Parse);
+            }
+            match self.rows.recv() {
+                Ok(batch) => self.pending = batch.into_iter(),
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Processes `paths` as if concatenated, on `jobs` workers, and returns
+/// every account, finalized or open, ordered by client, with the combined
+/// summary. The accounts and counts are those of a sequential run.
+pub fn process_files(paths: &[PathBuf], jobs: usize, options: &Options, logger: &Logger) -> Result<(Vec<AccountReport>, Summary), Error> {
+    let jobs = jobs.max(1);
+    // The rate is only meaningful over the combined rows, so it is checked once at the end.
+    let worker_options = Options { max_error_rate: None, ..options.clone() };
+    let (reports, summary) = thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(jobs);
+        let mut workers = Vec::with_capacity(jobs);
+        for peers in Peers::connect(jobs) {
+            let (sender, receiver) = mpsc::sync_channel::<Batch>(4);
+            senders.push(sender);
+            let options = &worker_options;
+            workers.push(scope.spawn(move || {
+                let mut engine = Engine::with_policy(&options.policy);
+                engine.set_link(Box::new(peers));
+                let mut reports = Vec::new();
+                let shard = Shard { rows: receiver, pending: Vec::new().into_iter() };
+                let summary = processor::process(shard, &mut engine, &mut reports, &mut Sinks::default(), options, logger)?;
+                reports.extend(engine.reports()?);
+                Ok::<_, Error>((reports, summary))
+            }));
+        }
+
+        let read = deal(paths, &senders, options, logger);
+        drop(senders);
+        let mut reports = Vec::new();
+        let mut summary = Summary::default();
+        let (credits, mut failed) = match read {
+            Ok(credits) => (credits, None),
+            Err(err) => (0, Some(err)),
+        };
+        for worker in workers {
+            match worker.join().expect("worker panicked") {
+                Ok((mut partial, shard)) => {
+                    reports.append(&mut partial);
+                    summary.merge(&shard);
+                }
+                Err(err) => failed = failed.or(Some(err)),
+            }
+        }
+        // The credit side of a transfer dealt to a second worker counts as applied
+        // there; the row itself is counted once, by the worker that debited it.
+        summary.rows = summary.rows.saturating_sub(credits);
+        summary.applied = summary.applied.saturating_sub(credits);
+        match failed {
+            Some(err) => Err(err),
+            None => Ok((reports, summary)),
+        }
+    })?;
+
+    let mut reports = reports;
+    reports.sort_by_key(|report| report.client);
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!(
+        "{} files on {} workers: {} rows, {} applied, {} skipped, {} rejected",
+        paths.len(), jobs, summary.rows, summary.applied, summary.errors.parse_errors(), summary.errors.rejected()
+    )));
+    options.check_rate(&summary.errors, summary.rows)?;
+    Ok((reports, summary))
+}
+
+/// Reads every file in order and deals its rows out by client. Rows that
+/// fail to parse go to the first worker, which counts or rejects them. A
+/// transfer to another worker's client is dealt to that worker too, and
+/// both batches are sent at once so neither waits on a vote that is still
+/// being dealt. Stops early once a worker has given up. Returns the number
+/// of transfers dealt twice.
+fn deal(paths: &[PathBuf], senders: &[SyncSender<Batch>], options: &Options, logger: &Logger) -> Result<u64, Error> {
+    let mut batches: Vec<Batch> = senders.iter().map(|_| Vec::with_capacity(BATCH)).collect();
+    let send = |worker: usize, batch: &mut Batch| senders[worker].send(std::mem::replace(batch, Vec::with_capacity(BATCH))).is_ok();
+    let mut credits = 0;
+    for path in paths {
+        logger.log(LogEvent::new(Level::Debug, "opened").reason(path.display()));
+        let mut source = processor::csv_source(File::open(path)?, options, logger)?;
+        loop {
+            let (worker, row) = match source.next_row() {
+                Ok(None) => break,
+                Ok(Some(row)) => (worker_of(row.transaction.client, senders.len()), Ok(row)),
+                Err(Error::Parse(err)) => (0, Err(err)),
+                Err(err) => return Err(err),
+            };
+            let credited = match &row {
+                Ok(row) if row.transaction.tx_type == TxType::Transfer => transfer::counterpart(&row.transaction)
+                    .map(|to| worker_of(to, senders.len()))
+                    .filter(|to| *to != worker),
+                _ => None,
+            };
+            if let (Some(to), Ok(row)) = (credited, &row) {
+                batches[to].push(Ok(row.clone()));
+                credits += 1;
+            }
+            batches[worker].push(row);
+            if let Some(to) = credited {
+                if !send(worker, &mut batches[worker]) || !send(to, &mut batches[to]) {
+                    return Ok(credits);
+                }
+            } else if batches[worker].len() == BATCH && !send(worker, &mut batches[worker]) {
+                return Ok(credits);
+            }
+        }
+    }
+    for (worker, batch) in batches.iter_mut().enumerate() {
+        if !batch.is_empty() && !send(worker, batch) {
+            return Ok(credits);
+        }
+    }
+    Ok(credits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{policy::Policy, report::ReportSink};
+
+    #[test]
+    fn test_matches_a_sequential_run() {
+        let dir = std::env::temp_dir().join(format!("txflow-parallel-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let days = [
+            "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\ndeposit,3,3,7\nwithdrawal,2,4,9\n",
+            "type,client,tx,amount\ndispute,1,1,\ndeposit,4,5,1\nfinalize,3,0,\nbogus,1,6,1\n",
+            "type,client,tx,amount\nchargeback,1,1,\ndeposit,2,7,2.5\n",
+        ];
+        let mut paths = Vec::new();
+        for (day, rows) in days.iter().enumerate() {
+            let path = dir.join(format!("day{}.csv", day));
+            fs::write(&path, rows).unwrap();
+            paths.push(path);
+        }
+        let options = Options { lenient: true, ..Default::default() };
+
+        let mut engine = Engine::new();
+        let mut sequential = Vec::new();
+        let all: String = days.iter().enumerate().map(|(day, rows)| if day == 0 { rows } else { rows.split_once('\n').unwrap().1 }).collect();
+        let expected = processor::process_csv(all.as_bytes(), &mut engine, &mut sequential, &mut Sinks::default(), &options, &Logger::default()).unwrap();
+        for account in engine.reports().unwrap() {
+            sequential.write(&account).unwrap();
+        }
+        sequential.sort_by_key(|report| report.client);
+
+        let (reports, summary) = process_files(&paths, 3, &options, &Logger::default()).unwrap();
+        assert_eq!(reports, sequential);
+        assert_eq!((summary.rows, summary.applied, summary.finalized), (expected.rows, expected.applied, expected.finalized));
+        assert_eq!((summary.errors.parse_errors(), summary.errors.rejected()), (1, 1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_transfers_across_workers_match_a_sequential_run() {
+        let dir = std::env::temp_dir().join(format!("txflow-parallel-transfers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rows = "type,client,tx,amount,to\n\
+            deposit,1,1,10,\n\
+            deposit,2,2,5,\n\
+            transfer,1,3,4,2\n\
+            transfer,2,4,20,1\n\
+            transfer,2,5,9,3\n\
+            deposit,4,6,1,\n\
+            finalize,4,0,,\n\
+            transfer,1,7,1,4\n\
+            transfer,1,8,1,6\n\
+            withdrawal,2,9,9,\n\
+            transfer,3,10,2,\n";
+        let path = dir.join("day.csv");
+        fs::write(&path, rows).unwrap();
+        let options = Options::default();
+
+        let mut engine = Engine::new();
+        let mut sequential = Vec::new();
+        let expected = processor::process_csv(rows.as_bytes(), &mut engine, &mut sequential, &mut Sinks::default(), &options, &Logger::default()).unwrap();
+        for account in engine.reports().unwrap() {
+            sequential.write(&account).unwrap();
+        }
+        sequential.sort_by_key(|report| report.client);
+
+        let (reports, summary) = process_files(&[path], 2, &options, &Logger::default()).unwrap();
+        assert_eq!(reports, sequential);
+        assert_eq!((summary.rows, summary.applied), (expected.rows, expected.applied));
+        assert_eq!(summary.errors.rejected(), expected.errors.rejected());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refuses_policies_spanning_clients() {
+        assert!(unsupported(&Options::default()).is_none());
+        let options = Options { policy: Policy { unique_tx_ids: true, ..Default::default() }, ..Default::default() };
+        assert!(unsupported(&options).is_some());
+    }
+}
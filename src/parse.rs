@@ -0,0 +1,262 @@
+use std::{collections::BTreeMap, str::FromStr};
+use rust_decimal::Decimal;
+
+use crate::{error::ParseError, time, ClientId, Transaction, TxId, TxType};
+
+/// Where each field txflow reads lives in a row, resolved once from the header.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    timestamp: Option<usize>,
+    recorded: Option<usize>,
+    /// Every other column, by index and header, captured as metadata.
+    extra: Vec<(usize, String)>,
+}
+
+const KNOWN: [&str; 6] = ["type", "client", "tx", "amount", "timestamp", "recorded_at"];
+
+impl Columns {
+    pub fn from_headers(headers: &csv::StringRecord) -> Result<Self, ParseError> {
+        let find = |name| headers.iter().position(|header| header == name);
+        let require = |name| find(name).ok_or(ParseError::MissingColumn { column: name });
+        Ok(Columns {
+            tx_type: require("type")?,
+            client: require("client")?,
+            tx: require("tx")?,
+            amount: find("amount"),
+            timestamp: find("timestamp"),
+            recorded: find("recorded_at"),
+            extra: headers.iter().enumerate()
+                .filter(|(_, header)| !header.is_empty() && !KNOWN.contains(header))
+                .map(|(index, header)| (index, header.to_string()))
+                .collect(),
+        })
+    }
+
+    pub fn has_timestamp(&self) -> bool {
+        self.timestamp.is_some()
+    }
+
+    pub fn has_recorded(&self) -> bool {
+        self.recorded.is_some()
+    }
+
+    /// Headers of the columns carried as metadata, in input order.
+    pub fn extra(&self) -> impl Iterator<Item = &str> + '_ {
+        self.extra.iter().map(|(_, header)| header.as_str())
+    }
+}
+
+/// How amount fields are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountFormat {
+    /// Also accept bank-export forms: thousands separators (`1,234.56`),
+    /// accounting negatives (`(12.50)`) and currency symbols or codes
+    /// (`$12.50`, `12.50 EUR`).
+    pub tolerant: bool,
+    /// `,` for European-style input such as `12,50`.
+    pub decimal_separator: char,
+    /// Digit grouping character; when unset, tolerant mode assumes whichever
+    /// of `,` and `.` is not the decimal separator.
+    pub thousands_separator: Option<char>,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat {
+            tolerant: false,
+            decimal_separator: '.',
+            thousands_separator: None,
+        }
+    }
+}
+
+impl AmountFormat {
+    fn thousands(&self) -> Option<char> {
+        match (self.thousands_separator, self.tolerant) {
+            (Some(separator), _) => Some(separator),
+            (None, true) if self.decimal_separator == ',' => Some('.'),
+            (None, true) => Some(','),
+            (None, false) => None,
+        }
+    }
+
+    pub fn parse(&self, raw: &str) -> Option<Decimal> {
+        let thousands = self.thousands();
+        if self.decimal_separator == '.' && thousands.is_none() {
+            return Decimal::from_str(raw).ok();
+        }
+
+        let mut text = raw.trim();
+        let negative = self.tolerant && text.starts_with('(') && text.ends_with(')');
+        if negative {
+            text = &text[1..text.len() - 1];
+        }
+        // Currency symbols and codes are decoration; anything else unexpected fails the field.
+        let mut core = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '0'..='9' | '-' | '+' => core.push(c),
+                c if c == self.decimal_separator || Some(c) == thousands => core.push(c),
+                '$' | '€' | '£' | '¥' if self.tolerant => {}
+                c if self.tolerant && (c.is_alphabetic() || c.is_whitespace()) => {}
+                _ => return None,
+            }
+        }
+        let core = core.trim();
+
+        let (whole, fraction) = core.split_once(self.decimal_separator)
+            .map_or((core, None), |(whole, fraction)| (whole, Some(fraction)));
+        let groups: Vec<&str> = match thousands {
+            Some(separator) => whole.split(separator).collect(),
+            None => vec![whole],
+        };
+        let digits = |group: &str| group.trim_start_matches(['-', '+']).len();
+        if groups.len() > 1 && (!(1..=3).contains(&digits(groups[0])) || groups[1..].iter().any(|group| group.len() != 3)) {
+            return None;
+        }
+        let mut plain = groups.concat();
+        if let Some(fraction) = fraction {
+            plain.push('.');
+            plain.push_str(fraction);
+        }
+
+        let amount = Decimal::from_str(&plain).ok()?;
+        Some(if negative { -amount } else { amount })
+    }
+}
+
+/// Converts one CSV row into a transaction, naming the exact field on failure.
+pub fn parse_record(record: &csv::StringRecord, columns: &Columns, amounts: &AmountFormat) -> Result<Transaction, ParseError> {
+    let invalid = |index: usize, field: &str, expected: &'static str| ParseError::InvalidField {
+        line: record.position().map_or(0, |pos| pos.line()),
+        field: field.to_string(),
+        value: get(record, index).to_string(),
+        expected,
+    };
+
+    let tx_type = TxType::from_str(get(record, columns.tx_type))
+        .map_err(|_| invalid(columns.tx_type, "type", "transaction type"))?;
+    let client = get(record, columns.client).parse().map(ClientId)
+        .map_err(|_| invalid(columns.client, "client", "client id"))?;
+    let tx = get(record, columns.tx).parse().map(TxId)
+        .map_err(|_| invalid(columns.tx, "tx", "transaction id"))?;
+    let amount = match columns.amount {
+        Some(index) if !get(record, index).is_empty() => Some(
+            amounts.parse(get(record, index)).ok_or_else(|| invalid(index, "amount", "decimal"))?
+        ),
+        _ => None,
+    };
+    let time = |column: Option<usize>, field: &str| match column {
+        Some(index) if !get(record, index).is_empty() => {
+            time::parse_timestamp(get(record, index)).map(Some).ok_or_else(|| invalid(index, field, "timestamp"))
+        }
+        _ => Ok(None),
+    };
+    let timestamp = time(columns.timestamp, "timestamp")?;
+    let recorded = time(columns.recorded, "recorded_at")?;
+
+    let metadata: BTreeMap<String, String> = columns.extra.iter()
+        .filter(|(index, _)| !get(record, *index).is_empty())
+        .map(|(index, header)| (header.clone(), get(record, *index).to_string()))
+        .collect();
+
+    Ok(Transaction { tx_type, client, tx, amount, timestamp, recorded, metadata })
+}
+
+fn get(record: &csv::StringRecord, index: usize) -> &str {
+    record.get(index).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn parse(row: &[&str]) -> Result<Transaction, ParseError> {
+        let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        parse_record(&csv::StringRecord::from(row.to_vec()), &Columns::from_headers(&headers).unwrap(), &AmountFormat::default())
+    }
+
+    #[test]
+    fn test_parse_valid_row() {
+        let record = parse(&["withdrawal", "3", "9", "1.5"]).unwrap();
+        assert_eq!((record.tx_type, record.client, record.tx, record.amount), (TxType::Withdrawal, ClientId(3), TxId(9), Some(dec!(1.5))));
+    }
+
+    #[test]
+    fn test_empty_amount_is_none() {
+        assert_eq!(parse(&["dispute", "1", "2", ""]).unwrap().amount, None);
+    }
+
+    #[test]
+    fn test_bad_amount_names_field() {
+        let err = parse(&["deposit", "1", "2", "12,50"]).unwrap_err();
+        assert_eq!(err, ParseError::InvalidField { line: 0, field: "amount".into(), value: "12,50".into(), expected: "decimal" });
+    }
+
+    #[test]
+    fn test_tolerant_amounts() {
+        let tolerant = AmountFormat { tolerant: true, ..Default::default() };
+        assert_eq!(tolerant.parse("1,234.56"), Some(dec!(1234.56)));
+        assert_eq!(tolerant.parse("(12.50)"), Some(dec!(-12.50)));
+        assert_eq!(tolerant.parse("$ 7.25"), Some(dec!(7.25)));
+        assert_eq!(tolerant.parse("12.50 EUR"), Some(dec!(12.50)));
+        assert_eq!(tolerant.parse("1.2.3"), None);
+        assert_eq!(tolerant.parse("12;50"), None);
+        assert_eq!(tolerant.parse("12,50"), None);
+        assert_eq!(AmountFormat::default().parse("1,234.56"), None);
+    }
+
+    #[test]
+    fn test_decimal_comma() {
+        let european = AmountFormat { decimal_separator: ',', ..Default::default() };
+        assert_eq!(european.parse("12,50"), Some(dec!(12.50)));
+        assert_eq!(european.parse("12.50"), None);
+
+        let grouped = AmountFormat { thousands_separator: Some('.'), ..european };
+        assert_eq!(grouped.parse("1.234,5"), Some(dec!(1234.5)));
+        assert_eq!(grouped.parse("12.34,5"), None);
+
+        let tolerant = AmountFormat { tolerant: true, ..european };
+        assert_eq!(tolerant.parse("(1.234,50 €)"), Some(dec!(-1234.50)));
+    }
+
+    #[test]
+    fn test_optional_timestamp_column() {
+        let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount", "timestamp"]);
+        let columns = Columns::from_headers(&headers).unwrap();
+        let row = csv::StringRecord::from(vec!["deposit", "1", "2", "1", "2024-01-01"]);
+        assert_eq!(parse_record(&row, &columns, &AmountFormat::default()).unwrap().timestamp, Some(1_704_067_200));
+        let row = csv::StringRecord::from(vec!["deposit", "1", "2", "1", "noon"]);
+        assert!(parse_record(&row, &columns, &AmountFormat::default()).is_err());
+    }
+
+    #[test]
+    fn test_optional_recorded_at_column() {
+        let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount", "timestamp", "recorded_at"]);
+        let columns = Columns::from_headers(&headers).unwrap();
+        let row = csv::StringRecord::from(vec!["deposit", "1", "2", "1", "2024-01-01", "2024-01-03T09:00:00Z"]);
+        let parsed = parse_record(&row, &columns, &AmountFormat::default()).unwrap();
+        assert_eq!((parsed.timestamp, parsed.recorded), (Some(1_704_067_200), Some(1_704_272_400)));
+        assert!(parsed.metadata.is_empty());
+    }
+
+    #[test]
+    fn test_extra_columns_become_metadata() {
+        let headers = csv::StringRecord::from(vec!["type", "merchant", "client", "tx", "amount", "reference"]);
+        let columns = Columns::from_headers(&headers).unwrap();
+        let row = csv::StringRecord::from(vec!["deposit", "acme", "1", "2", "1", ""]);
+        let record = parse_record(&row, &columns, &AmountFormat::default()).unwrap();
+        assert_eq!(record.metadata.into_iter().collect::<Vec<_>>(), vec![("merchant".to_string(), "acme".to_string())]);
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let headers = csv::StringRecord::from(vec!["type", "tx", "amount"]);
+        assert_eq!(Columns::from_headers(&headers).unwrap_err(), ParseError::MissingColumn { column: "client" });
+    }
+}
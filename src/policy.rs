@@ -0,0 +1,503 @@
+use std::{collections::BTreeMap, fs, ops::Range, path::Path, str::FromStr};
+use rust_decimal::Decimal;
+use toml_edit::{ImDocument, Item, Table, Value};
+
+use crate::{
+    alert::AlertConfig,
+    chat::ChatConfig,
+    class::AccountClass,
+    error::{Error, PolicyError},
+    joint::JointOwners,
+    ordering::OrderingPolicy,
+    quota::Quotas,
+    retention::Retention,
+    sar::SarRules,
+    script::{self, Rule},
+    settlement::SettlementFees,
+    time,
+    webhook::WebhookConfig,
+    ClientId,
+};
+
+/// Per-transaction caps; `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub max_deposit: Option<Decimal>,
+    pub max_withdrawal: Option<Decimal>,
+}
+
+/// What a locked (charged-back) account still accepts.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum LockedPolicy {
+    /// Refuse every transaction.
+    #[default]
+    Freeze,
+    /// Let deposits through so funds can still be recovered; everything else is refused.
+    AcceptDeposits,
+}
+
+impl FromStr for LockedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "freeze" => Ok(LockedPolicy::Freeze),
+            "accept-deposits" => Ok(LockedPolicy::AcceptDeposits),
+            other => Err(format!("unknown locked-account policy '{}' (expected freeze or accept-deposits)", other)),
+        }
+    }
+}
+
+/// Business rules that compliance can tune without a rebuild. Loaded from a
+/// TOML file shaped like:
+///
+/// ```toml
+/// [limits]
+/// max_deposit = "10000"
+/// max_withdrawal = "5000"
+///
+/// [disputes]
+/// retention = "keep-within:30d"
+/// escrow_account = 4294967295
+/// chargeback_fee = "15"
+/// loss_account = 4294967294
+/// auto_resolve = "45d"
+/// provisional_credit = true
+/// max_cycles = 2
+/// dispute_filter = true
+///
+/// [locked_accounts]
+/// policy = "accept-deposits"
+///
+/// [classes.merchant]
+/// clients = "100, 101"
+/// max_withdrawal = "50000"
+/// locked_accounts = "freeze"
+/// allowed = "deposit, withdrawal, finalize"
+///
+/// [sar]
+/// cycle_count = 5
+/// structuring_limit = "10000"
+///
+/// [settlement]
+/// fee_rate = "0.029"
+/// fixed_fee = "0.30"
+///
+/// [ordering]
+/// require = "tx"
+/// window = 1000
+///
+/// [tx_ids]
+/// unique = true
+///
+/// [quotas]
+/// per_minute = 600
+/// per_day = 100000
+///
+/// [wallets]
+/// enabled = true
+///
+/// [rules]
+/// script = """
+/// reject if amount > 10_000 && client_tier == "basic"
+/// """
+///
+/// [webhooks]
+/// url = "http://risk.internal:8080/txflow"
+/// large_withdrawal = "5000"
+/// retries = 5
+///
+/// [alerts]
+/// window = "1h"
+/// max_chargebacks = 20
+/// smtp_server = "relay.internal:25"
+/// smtp_from = "txflow@example.com"
+/// smtp_to = "risk@example.com"
+///
+/// [chat]
+/// webhook = "http://chat-proxy.internal/hooks/ops"
+/// ```
+///
+/// Every section and key is optional; anything unrecognised is an error
+/// rather than silently ignored, so a typo can't quietly disable a rule.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    pub limits: Limits,
+    pub retention: Retention,
+    pub locked: LockedPolicy,
+    /// Per-class overrides of the above, by class name; see [`crate::class`].
+    pub classes: BTreeMap<String, AccountClass>,
+    pub sar: SarRules,
+    /// What merchants are charged when settled.
+    pub settlement: SettlementFees,
+    pub ordering: OrderingPolicy,
+    /// A client id reserved to report every disputed amount under.
+    pub escrow_account: Option<ClientId>,
+    /// Charged to the client on top of each chargeback, up to what the
+    /// client has available.
+    pub chargeback_fee: Decimal,
+    /// A client id reserved to report chargeback losses, fees included, under.
+    pub loss_account: Option<ClientId>,
+    /// Seconds after which a dispute with no outcome is resolved in the
+    /// client's favour; needs timestamps.
+    pub auto_resolve: Option<i64>,
+    /// Let clients dispute withdrawals, crediting the amount back while the
+    /// dispute runs; a resolve reverses the credit, a chargeback keeps it.
+    /// Credit spent before a resolve is booked to losses.
+    pub provisional_credit: bool,
+    /// Chargebacks a deposit may go through, each after the first following
+    /// a representment; up to 1 makes the first chargeback final.
+    pub max_cycles: u32,
+    /// Refuse dispute-flow rows for ids no deposit used with a Bloom filter
+    /// lookup, before loading the account; see [`crate::bloom`]. Such a row
+    /// then no longer opens an empty account for a client never seen.
+    pub dispute_filter: bool,
+    /// Refuse a deposit or withdrawal whose id any earlier row already used, for any client.
+    pub unique_tx_ids: bool,
+    /// Rows each client may submit per minute and per day; see [`crate::quota`].
+    pub quotas: Quotas,
+    /// Keep a separate account per client and `wallet` column value.
+    pub wallets: bool,
+    /// Co-owners whose rows apply to another client's account; read from
+    /// a sidecar file rather than the policy file.
+    pub joint: JointOwners,
+    /// Acceptance rules checked against every row; see [`crate::script`].
+    pub rules: Vec<Rule>,
+    /// Where to post chargebacks, locks and large withdrawals, if anywhere.
+    pub webhook: Option<WebhookConfig>,
+    /// Chargeback-spike thresholds and where to send alerts, if anywhere.
+    pub alerts: Option<AlertConfig>,
+    /// Where to post the end-of-run summary, if anywhere.
+    pub chat: Option<ChatConfig>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Policy, Error> {
+        Ok(fs::read_to_string(path)?.parse()?)
+    }
+}
+
+/// A key in a section, its value as text, and where it was written.
+type Entry<'t> = (&'t str, String, Option<Range<usize>>);
+
+/// Maps spans in the source back to line numbers for error messages.
+struct Source<'a>(&'a str);
+
+impl Source<'_> {
+    fn error(&self, span: Option<Range<usize>>, message: impl Into<String>) -> PolicyError {
+        let line = span.map(|span| self.0[..span.start.min(self.0.len())].matches('\n').count() + 1);
+        PolicyError { line, message: message.into() }
+    }
+
+    fn section<'t>(&self, root: &'t Table, name: &str) -> Result<Option<&'t Table>, PolicyError> {
+        match root.get(name) {
+            None => Ok(None),
+            Some(item) => item.as_table().map(Some)
+                .ok_or_else(|| self.error(item.span(), format!("'{}' must be a [{}] section", name, name))),
+        }
+    }
+
+    /// Iterates a section's keys as strings, whatever TOML type they were written as.
+    fn entries<'t>(&self, section: &'t Table, name: &str) -> Result<Vec<Entry<'t>>, PolicyError> {
+        section.iter()
+            .map(|(key, item)| {
+                let text = match item {
+                    Item::Value(Value::String(value)) => value.value().clone(),
+                    Item::Value(Value::Integer(value)) => value.value().to_string(),
+                    Item::Value(Value::Float(value)) => value.value().to_string(),
+                    Item::Value(Value::Boolean(value)) => value.value().to_string(),
+                    _ => return Err(self.error(item.span(), format!("[{}] {} must be a string or number", name, key))),
+                };
+                Ok((key, text, item.span()))
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn limit(raw: &str) -> Result<Decimal, String> {
+    match raw.parse::<Decimal>() {
+        Ok(amount) if amount > Decimal::ZERO => Ok(amount),
+        _ => Err(format!("'{}' is not a positive amount", raw)),
+    }
+}
+
+impl FromStr for Policy {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = Source(s);
+        let document = ImDocument::parse(s).map_err(|err| source.error(err.span(), err.message()))?;
+        let root = document.as_table();
+        let mut policy = Policy::default();
+
+        for (name, item) in root.iter() {
+            if !matches!(name, "limits" | "disputes" | "locked_accounts" | "classes" | "sar" | "settlement" | "ordering" | "tx_ids" | "quotas" | "wallets" | "rules" | "webhooks" | "alerts" | "chat") {
+                return Err(source.error(item.span(), format!(
+                    "unknown section [{}] (expected limits, disputes, locked_accounts, classes, sar, settlement, ordering, tx_ids, quotas, wallets, rules, webhooks, alerts or chat)", name
+                )));
+            }
+        }
+
+        if let Some(section) = source.section(root, "limits")? {
+            for (key, value, span) in source.entries(section, "limits")? {
+                let slot = match key {
+                    "max_deposit" => &mut policy.limits.max_deposit,
+                    "max_withdrawal" => &mut policy.limits.max_withdrawal,
+                    _ => return Err(source.error(span, format!("unknown key '{}' in [limits]", key))),
+                };
+                *slot = Some(limit(&value).map_err(|err| source.error(span, format!("[limits] {}: {}", key, err)))?);
+            }
+        }
+
+        if let Some(section) = source.section(root, "disputes")? {
+            for (key, value, span) in source.entries(section, "disputes")? {
+                match key {
+                    "retention" => policy.retention = value.parse().map_err(|err: String| source.error(span, err))?,
+                    "escrow_account" => policy.escrow_account = Some(ClientId(value.parse()
+                        .map_err(|_| source.error(span, format!("[disputes] escrow_account must be a client id, got '{}'", value)))?)),
+                    "chargeback_fee" => policy.chargeback_fee = limit(&value)
+                        .map_err(|err| source.error(span, format!("[disputes] chargeback_fee: {}", err)))?,
+                    "auto_resolve" => policy.auto_resolve = Some(time::parse_duration(&value)
+                        .ok_or_else(|| source.error(span, format!("[disputes] auto_resolve must be a duration, got '{}'", value)))?),
+                    "provisional_credit" => policy.provisional_credit = value.parse()
+                        .map_err(|_| source.error(span, format!("[disputes] provisional_credit must be true or false, got '{}'", value)))?,
+                    "max_cycles" => policy.max_cycles = value.parse()
+                        .map_err(|_| source.error(span, format!("[disputes] max_cycles must be a count, got '{}'", value)))?,
+                    "dispute_filter" => policy.dispute_filter = value.parse()
+                        .map_err(|_| source.error(span, format!("[disputes] dispute_filter must be true or false, got '{}'", value)))?,
+                    "loss_account" => policy.loss_account = Some(ClientId(value.parse()
+                        .map_err(|_| source.error(span, format!("[disputes] loss_account must be a client id, got '{}'", value)))?)),
+                    _ => return Err(source.error(span, format!("unknown key '{}' in [disputes]", key))),
+                }
+            }
+        }
+
+        if let Some(section) = source.section(root, "locked_accounts")? {
+            for (key, value, span) in source.entries(section, "locked_accounts")? {
+                match key {
+                    "policy" => policy.locked = value.parse().map_err(|err: String| source.error(span, err))?,
+                    _ => return Err(source.error(span, format!("unknown key '{}' in [locked_accounts]", key))),
+                }
+            }
+        }
+
+        if let Some(section) = source.section(root, "sar")? {
+            for (key, value, span) in source.entries(section, "sar")? {
+                policy.sar.set(key, &value).map_err(|err| source.error(span, err))?;
+            }
+        }
+
+        if let Some(section) = source.section(root, "settlement")? {
+            for (key, value, span) in source.entries(section, "settlement")? {
+                policy.settlement.set(key, &value).map_err(|err| source.error(span, err))?;
+            }
+        }
+
+        if let Some(section) = source.section(root, "ordering")? {
+            for (key, value, span) in source.entries(section, "ordering")? {
+                policy.ordering.set(key, &value).map_err(|err| source.error(span, err))?;
+            }
+        }
+
+        if let Some(section) = source.section(root, "tx_ids")? {
+            for (key, value, span) in source.entries(section, "tx_ids")? {
+                match key {
+                    "unique" => policy.unique_tx_ids = value.parse()
+                        .map_err(|_| source.error(span, format!("[tx_ids] unique must be true or false, got '{}'", value)))?,
+                    _ => return Err(source.error(span, format!("unknown key '{}' in [tx_ids]", key))),
+                }
+            }
+        }
+
+        if let Some(classes) = source.section(root, "classes")? {
+            let mut owners: BTreeMap<ClientId, &str> = BTreeMap::new();
+            for (name, item) in classes.iter() {
+                let table = item.as_table()
+                    .ok_or_else(|| source.error(item.span(), format!("'{}' must be a [classes.{}] section", name, name)))?;
+                let section = format!("classes.{}", name);
+                let mut class = AccountClass::default();
+                for (key, value, span) in source.entries(table, &section)? {
+                    class.set(key, &value).map_err(|err| source.error(span, format!("[{}] {}", section, err)))?;
+                }
+                for client in &class.clients {
+                    if let Some(other) = owners.insert(*client, name) {
+                        return Err(source.error(item.span(), format!("client {} is in both [classes.{}] and [classes.{}]", client.0, other, name)));
+                    }
+                }
+                policy.classes.insert(name.to_string(), class);
+            }
+        }
+
+        if let Some(section) = source.section(root, "quotas")? {
+            for (key, value, span) in source.entries(section, "quotas")? {
+                policy.quotas.set(key, &value).map_err(|err| source.error(span, err))?;
+            }
+        }
+
+        if let Some(section) = source.section(root, "wallets")? {
+            for (key, value, span) in source.entries(section, "wallets")? {
+                match key {
+                    "enabled" => policy.wallets = value.parse()
+                        .map_err(|_| source.error(span, format!("[wallets] enabled must be true or false, got '{}'", value)))?,
+                    _ => return Err(source.error(span, format!("unknown key '{}' in [wallets]", key))),
+                }
+            }
+        }
+
+        if let Some(section) = source.section(root, "rules")? {
+            for (key, value, span) in source.entries(section, "rules")? {
+                match key {
+                    "script" => policy.rules = script::parse_rules(&value).map_err(|err| source.error(span, err))?,
+                    _ => return Err(source.error(span, format!("unknown key '{}' in [rules]", key))),
+                }
+            }
+        }
+
+        if let Some(section) = source.section(root, "webhooks")? {
+            let entries = source.entries(section, "webhooks")?;
+            let url = entries.iter().find(|(key, _, _)| *key == "url")
+                .ok_or_else(|| source.error(root.get("webhooks").and_then(Item::span), "[webhooks] requires url"))?;
+            let mut webhook = WebhookConfig::new(url.1.parse().map_err(|err| source.error(url.2.clone(), err))?);
+            for (key, value, span) in entries {
+                webhook.set(key, &value).map_err(|err| source.error(span, err))?;
+            }
+            policy.webhook = Some(webhook);
+        }
+
+        if let Some(section) = source.section(root, "alerts")? {
+            let mut alerts = AlertConfig::default();
+            for (key, value, span) in source.entries(section, "alerts")? {
+                alerts.set(key, &value).map_err(|err| source.error(span, err))?;
+            }
+            alerts.validate().map_err(|err| source.error(root.get("alerts").and_then(Item::span), err))?;
+            policy.alerts = Some(alerts);
+        }
+
+        if let Some(section) = source.section(root, "chat")? {
+            let entries = source.entries(section, "chat")?;
+            let webhook = entries.iter().find(|(key, _, _)| *key == "webhook")
+                .ok_or_else(|| source.error(root.get("chat").and_then(Item::span), "[chat] requires webhook"))?;
+            let mut chat = ChatConfig::new(webhook.1.parse().map_err(|err| source.error(webhook.2.clone(), err))?);
+            for (key, value, span) in entries {
+                chat.set(key, &value).map_err(|err| source.error(span, err))?;
+            }
+            policy.chat = Some(chat);
+        }
+
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_parse_full_policy() {
+        let policy: Policy = "\
+            [limits]\n\
+            max_withdrawal = \"5000.50\"\n\
+            [disputes]\n\
+            retention = \"keep-last:10\"\n\
+            [locked_accounts]\n\
+            policy = \"accept-deposits\"\n\
+            [sar]\n\
+            cycle_count = 5\n".parse().unwrap();
+        assert_eq!(policy.limits, Limits { max_deposit: None, max_withdrawal: Some(dec!(5000.50)) });
+        assert_eq!(policy.retention, Retention::KeepLast(10));
+        assert_eq!(policy.locked, LockedPolicy::AcceptDeposits);
+        assert_eq!(policy.sar.cycle_count, 5);
+    }
+
+    #[test]
+    fn test_empty_policy_is_default() {
+        assert_eq!("".parse::<Policy>().unwrap(), Policy::default());
+    }
+
+    #[test]
+    fn test_errors_point_at_the_line() {
+        let err = "[limits]\nmax_deposit = 100\nmax_withdrawl = 5\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 3: unknown key 'max_withdrawl' in [limits]");
+
+        let err = "[limits]\nmax_deposit = -1\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: [limits] max_deposit: '-1' is not a positive amount");
+
+        let err = "[sar]\ncycle_count = \"many\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid value 'many' for SAR rule cycle_count");
+
+        let err = "[fraud]\n".parse::<Policy>().unwrap_err();
+        assert!(err.message.starts_with("unknown section [fraud]"));
+
+        assert!("[limits\n".parse::<Policy>().unwrap_err().line.is_some());
+    }
+
+    #[test]
+    fn test_rules_script() {
+        let policy: Policy = "[rules]\nscript = '''\nreject if amount > 100\n# note\nreject if tx == 7\n'''\n".parse().unwrap();
+        assert_eq!(policy.rules.len(), 2);
+        let err = "[rules]\nscript = \"reject if amount >\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: expression ends early");
+    }
+
+    #[test]
+    fn test_escrow_account() {
+        let policy: Policy = "[disputes]\nescrow_account = 999\n".parse().unwrap();
+        assert_eq!(policy.escrow_account, Some(ClientId(999)));
+        let err = "[disputes]\nescrow_account = \"escrow\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: [disputes] escrow_account must be a client id, got 'escrow'");
+    }
+
+    #[test]
+    fn test_ordering_section() {
+        let policy: Policy = "[ordering]\nrequire = \"timestamp\"\nwindow = 50\n".parse().unwrap();
+        assert_eq!(policy.ordering, OrderingPolicy { require: Some(crate::ordering::SequenceKey::Timestamp), window: 50 });
+        let err = "[ordering]\nrequire = \"line\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown ordering key 'line' (expected tx or timestamp)");
+    }
+
+    #[test]
+    fn test_tx_ids_section() {
+        assert!("[tx_ids]\nunique = true\n".parse::<Policy>().unwrap().unique_tx_ids);
+        let err = "[tx_ids]\nunique = \"yes\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: [tx_ids] unique must be true or false, got 'yes'");
+        assert!("[wallets]\nenabled = true\n".parse::<Policy>().unwrap().wallets);
+    }
+
+    #[test]
+    fn test_classes_section() {
+        let policy: Policy = "[classes.merchant]\nclients = \"7, 8\"\nmax_withdrawal = \"500\"\n".parse().unwrap();
+        let merchant = &policy.classes["merchant"];
+        assert_eq!((merchant.clients.len(), merchant.limits.max_withdrawal), (2, Some(dec!(500))));
+        let err = "[classes.merchant]\nclients = \"7\"\n[classes.settlement]\nclients = \"7\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 3: client 7 is in both [classes.merchant] and [classes.settlement]");
+        let err = "[classes.merchant]\nallowed = \"refund\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: [classes.merchant] unknown transaction type 'refund'");
+    }
+
+    #[test]
+    fn test_webhooks_section() {
+        let policy: Policy = "[webhooks]\nretries = 1\nurl = \"http://localhost:9000/hook\"\n".parse().unwrap();
+        let webhook = policy.webhook.unwrap();
+        assert_eq!((webhook.url.port, webhook.retries), (9000, 1));
+        let err = "[webhooks]\nretries = 1\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 1: [webhooks] requires url");
+    }
+
+    #[test]
+    fn test_alerts_section() {
+        let policy: Policy = "[alerts]\nwindow = \"10m\"\nmax_chargebacks = 3\nwebhook = \"http://localhost/alerts\"\n".parse().unwrap();
+        assert_eq!(policy.alerts.map(|alerts| (alerts.window, alerts.max_chargebacks)), Some((600, Some(3))));
+        let err = "[alerts]\nwindow = \"10m\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 1: [alerts] needs max_chargebacks or max_chargeback_value");
+    }
+
+    #[test]
+    fn test_chat_section() {
+        let policy: Policy = "[chat]\nwebhook = \"http://localhost:9000/hooks/ops\"\nchannel = \"#risk\"\n".parse().unwrap();
+        let chat = policy.chat.unwrap();
+        assert_eq!((chat.webhook.path.as_str(), chat.channel.as_deref()), ("/hooks/ops", Some("#risk")));
+        let err = "[chat]\nchannel = \"#risk\"\n".parse::<Policy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 1: [chat] requires webhook");
+    }
+}
@@ -0,0 +1,818 @@
+use std::{borrow::Cow, collections::{BTreeMap, BTreeSet}, io, time::{Duration, Instant}};
+use rust_decimal::Decimal;
+
+use crate::{
+    aggregate::AggregateWriter,
+    anchor::Anchors,
+    checkpoint::Checkpoints,
+    crash,
+    deadletter::DeadLetterWriter,
+    declines::DeclineWriter,
+    delta::DeltaWriter,
+    diagnostics::{ErrorCategory, ErrorCounts, RejectLog, RejectRecord},
+    error::{Error, ErrorLimitExceeded, ParseError},
+    frozen::FrozenWriter,
+    merkle::MerkleTree,
+    ordering::Reorder,
+    parse::AmountFormat,
+    policy::Policy,
+    report::{self, AccountReport, ReportSink},
+    sar::SarMonitor,
+    settlement::SettlementLedger,
+    signals,
+    snapshot::Snapshots,
+    statsd::Statsd,
+    source::{CsvSource, Row, TxSource},
+    stats::{LatencyHistogram, MemoryUsage},
+    top::TopClients,
+    logging::{Level, LogEvent, Logger},
+    store::StateStore,
+    Account, ClientId, Engine, Reject, TxType,
+};
+#[cfg(feature = "otel")]
+use crate::otel::Telemetry;
+
+/// How a run reacts to rows it cannot use.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Skip and count rows that fail to parse instead of aborting the run.
+    pub lenient: bool,
+    /// Number of examples logged per error category before going quiet.
+    pub sample_limit: usize,
+    /// Abort once more than this many rows have been skipped or rejected.
+    pub max_errors: Option<u64>,
+    /// Abort if the share of skipped or rejected rows ends up above this fraction.
+    pub max_error_rate: Option<f64>,
+    /// Rows per delta interval when a delta sink is attached.
+    pub delta_every: u64,
+    /// Time per throughput/latency log line; `None` skips timing altogether.
+    pub stats_every: Option<Duration>,
+    /// Abort once the engine's estimated state size exceeds this many bytes.
+    pub max_memory: Option<u64>,
+    /// How amount fields are read.
+    pub amounts: AmountFormat,
+    /// Business rules; the engine-level parts are applied by whoever builds the engine.
+    pub policy: Policy,
+    /// Stop at the next row boundary once a termination signal arrives.
+    pub interruptible: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            lenient: false,
+            sample_limit: 10,
+            max_errors: None,
+            max_error_rate: None,
+            delta_every: 10_000,
+            stats_every: None,
+            max_memory: None,
+            amounts: AmountFormat::default(),
+            policy: Policy::default(),
+            interruptible: false,
+        }
+    }
+}
+
+/// Optional outputs a run feeds besides the final account report.
+#[derive(Default)]
+pub struct Sinks {
+    pub deltas: Option<DeltaWriter>,
+    pub declines: Option<DeclineWriter>,
+    pub frozen: Option<FrozenWriter>,
+    pub sar: Option<SarMonitor>,
+    pub settlement: Option<SettlementLedger>,
+    /// Every failed row, for reports that list them.
+    pub rejects: Option<RejectLog>,
+    /// Skipped and rejected rows with their reasons, for replay once fixed.
+    pub dead_letter: Option<DeadLetterWriter>,
+    /// Totals per day, week or month, written when the run ends.
+    pub aggregates: Option<AggregateWriter>,
+    /// The clients with the most activity, written when the run ends.
+    pub top: Option<TopClients>,
+    /// Intermediate account snapshots, written as they come due.
+    pub snapshots: Option<Snapshots>,
+    /// Checkpoints to resume from should the run die, and where a resumed run starts.
+    pub checkpoints: Option<Checkpoints>,
+    /// Every applied row, for a root and inclusion proofs once the run ends.
+    pub merkle: Option<MerkleTree>,
+    /// Where state fingerprints are published as they come due and at the end.
+    pub anchors: Option<Anchors>,
+    /// Spans and metrics for an OpenTelemetry collector, sent as they build up and at the end.
+    #[cfg(feature = "otel")]
+    pub telemetry: Option<Telemetry>,
+    /// Counters and timers for a StatsD agent, sent about once a second.
+    pub statsd: Option<Statsd>,
+}
+
+/// Rows between memory budget checks; the estimate is cheap but not free.
+const MEMORY_CHECK_EVERY: u64 = 1024;
+
+impl Options {
+    fn check_memory<S: StateStore>(&self, engine: &Engine<S>) -> Result<(), Error> {
+        let Some(limit) = self.max_memory else { return Ok(()) };
+        match engine.memory_usage()?.bytes() {
+            used if used > limit => Err(Error::MemoryBudget { used, limit }),
+            _ => Ok(()),
+        }
+    }
+    fn check_count(&self, errors: &ErrorCounts) -> Result<(), ErrorLimitExceeded> {
+        match self.max_errors {
+            Some(max) if errors.total() > max => Err(ErrorLimitExceeded::Count { errors: errors.total(), max }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_rate(&self, errors: &ErrorCounts, rows: u64) -> Result<(), ErrorLimitExceeded> {
+        match self.max_error_rate {
+            Some(max) if rows > 0 && errors.total() as f64 / rows as f64 > max => {
+                Err(ErrorLimitExceeded::Rate { errors: errors.total(), rows, max })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What happened to the rows of one input.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub rows: u64,
+    pub applied: u64,
+    pub finalized: u64,
+    pub errors: ErrorCounts,
+    /// Time spent applying each parsed row; filled only when `stats_every` is set.
+    pub latency: LatencyHistogram,
+    /// The same broken down by operation: each transaction type, and
+    /// `snapshot` for intermediate snapshot writes.
+    pub operations: BTreeMap<&'static str, LatencyHistogram>,
+}
+
+impl Summary {
+    /// Adds the counts of a run over other rows of the same input.
+    pub fn merge(&mut self, other: &Summary) {
+        self.rows += other.rows;
+        self.applied += other.applied;
+        self.finalized += other.finalized;
+        self.errors.merge(&other.errors);
+        self.latency.merge(&other.latency);
+        for (operation, latency) in &other.operations {
+            self.operations.entry(operation).or_default().merge(latency);
+        }
+    }
+}
+
+/// Rolling throughput and latency since the last periodic stats line.
+struct Interval {
+    started: Instant,
+    rows: u64,
+    latency: LatencyHistogram,
+}
+
+impl Interval {
+    fn new(rows: u64) -> Self {
+        Interval { started: Instant::now(), rows, latency: LatencyHistogram::new() }
+    }
+
+    fn log_if_due(&mut self, every: Duration, rows: u64, memory: MemoryUsage, logger: &Logger) {
+        let elapsed = self.started.elapsed();
+        if elapsed < every {
+            return;
+        }
+        let rate = (rows - self.rows) as f64 / elapsed.as_secs_f64();
+        logger.log(LogEvent::new(Level::Info, "stats").reason(format!(
+            "{:.0} tx/s, {}, {} rows so far, memory {}", rate, self.latency, rows, memory
+        )));
+        *self = Interval::new(rows);
+    }
+}
+
+/// Feeds every row of a CSV input through the engine. Accounts finalized
+/// along the way are written to `report` immediately.
+pub fn process_csv<R: io::Read, P: ReportSink + ?Sized, S: StateStore>(
+    input: R,
+    engine: &mut Engine<S>,
+    report: &mut P,
+    sinks: &mut Sinks,
+    options: &Options,
+    logger: &Logger,
+) -> Result<Summary, Error> {
+    process(csv_source(input, options, logger)?, engine, report, sinks, options, logger)
+}
+
+/// The rows of a CSV input as `options` would have them applied: reordered
+/// first when the policy requires an order.
+pub fn csv_source<'a, R: io::Read + 'a>(input: R, options: &Options, logger: &Logger) -> Result<Box<dyn TxSource + 'a>, Error> {
+    let source = CsvSource::new(input, &options.amounts)?;
+    Ok(match options.policy.ordering.require {
+        Some(key) => Box::new(Reorder::new(source, key, options.policy.ordering.window, *logger)),
+        None => Box::new(source),
+    })
+}
+
+/// Feeds every row of `source` through the engine; see [`process_csv`].
+pub fn process<T: TxSource, P: ReportSink + ?Sized, S: StateStore>(
+    source: T,
+    engine: &mut Engine<S>,
+    report: &mut P,
+    sinks: &mut Sinks,
+    options: &Options,
+    logger: &Logger,
+) -> Result<Summary, Error> {
+    let (summary, result) = process_partial(source, engine, report, sinks, options, logger);
+    result.map(|()| summary)
+}
+
+/// Like [`process`], but hands back the counts so far when the input fails
+/// part-way, for callers that keep the rows applied before the failure.
+pub fn process_partial<T: TxSource, P: ReportSink + ?Sized, S: StateStore>(
+    source: T,
+    engine: &mut Engine<S>,
+    report: &mut P,
+    sinks: &mut Sinks,
+    options: &Options,
+    logger: &Logger,
+) -> (Summary, Result<(), Error>) {
+    let mut summary = Summary { errors: ErrorCounts::new(options.sample_limit), ..Default::default() };
+    let result = process_rows(source, engine, report, sinks, options, logger, &mut summary);
+    (summary, result)
+}
+
+fn process_rows<T: TxSource, P: ReportSink + ?Sized, S: StateStore>(
+    mut source: T,
+    engine: &mut Engine<S>,
+    report: &mut P,
+    sinks: &mut Sinks,
+    options: &Options,
+    logger: &Logger,
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    let mut changed = BTreeSet::new();
+    let mut interval = Interval::new(0);
+    // Rows covered by the last anchor, so the final one isn't published twice.
+    let mut anchored = None;
+
+    if let Some(checkpoints) = sinks.checkpoints.as_ref().filter(|checkpoints| checkpoints.start() > 0) {
+        let start = checkpoints.start();
+        if let Some(position) = checkpoints.position() {
+            if source.seek(position)? {
+                summary.rows = start;
+            }
+        }
+        while summary.rows < start {
+            match source.next_row() {
+                Ok(Some(_)) | Err(Error::Parse(_)) => summary.rows += 1,
+                Ok(None) => return Err(Error::Resume(format!("the input ends after {} rows, before the checkpoint's {}", summary.rows, start))),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    loop {
+        crash::set_rows(summary.rows);
+        if let Some(every) = options.stats_every {
+            interval.log_if_due(every, summary.rows, engine.memory_usage()?, logger);
+        }
+        if summary.rows.is_multiple_of(MEMORY_CHECK_EVERY) {
+            if let Err(err) = options.check_memory(engine) {
+                log_summary(summary, engine, logger);
+                return Err(err);
+            }
+        }
+        if let Some(deltas) = sinks.deltas.as_mut() {
+            if summary.rows > 0 && summary.rows.is_multiple_of(options.delta_every.max(1)) {
+                deltas.write_interval(open_accounts(engine, &changed)?.iter().map(AsRef::as_ref))?;
+                changed.clear();
+            }
+        }
+
+        if options.interruptible && signals::shutdown_requested() {
+            logger.log(LogEvent::new(Level::Warn, "interrupted").reason(format!("after {} rows", summary.rows)));
+            if let Some(snapshots) = sinks.snapshots.as_mut() {
+                let path = snapshots.write(engine, summary.rows)?;
+                logger.log(LogEvent::new(Level::Info, "snapshot").reason(format!("{} after {} rows", path.display(), summary.rows)));
+            }
+            break;
+        }
+
+        if let Some(snapshots) = sinks.snapshots.as_mut().filter(|snapshots| snapshots.due(summary.rows)) {
+            let started = options.stats_every.map(|_| Instant::now());
+            let path = snapshots.write(engine, summary.rows)?;
+            if let Some(started) = started {
+                summary.operations.entry("snapshot").or_default().record(started.elapsed());
+            }
+            logger.log(LogEvent::new(Level::Info, "snapshot").reason(format!("{} after {} rows", path.display(), summary.rows)));
+        }
+        if let Some(checkpoints) = sinks.checkpoints.as_mut().filter(|checkpoints| checkpoints.due(summary.rows)) {
+            let position = source.position();
+            checkpoints.write(engine, report, summary.rows, position)?;
+            if let Some(position) = position {
+                source.commit(position)?;
+            }
+            logger.log(LogEvent::new(Level::Debug, "checkpoint").reason(format!("after {} rows", summary.rows)));
+        }
+        if let Some(anchors) = sinks.anchors.as_mut().filter(|anchors| anchors.due(summary.rows)) {
+            anchors.publish(engine, summary.rows, sinks.merkle.as_ref())?;
+            anchored = Some(summary.rows);
+            logger.log(LogEvent::new(Level::Debug, "anchored").reason(format!("after {} rows", summary.rows)));
+        }
+
+        let parsed = match source.next_row() {
+            Ok(None) => break,
+            Ok(Some(row)) => Ok(row),
+            Err(Error::Parse(err)) => Err(err),
+            Err(err) => return Err(err),
+        };
+
+        summary.rows += 1;
+        let Row { transaction: record, line } = match parsed {
+            Ok(row) => row,
+            Err(err) if options.lenient => {
+                let category = match err {
+                    ParseError::InvalidField { .. } => ErrorCategory::InvalidField,
+                    ParseError::MalformedRow { .. } | ParseError::MissingColumn { .. } => ErrorCategory::MalformedRow,
+                };
+                if let Some(rejects) = sinks.rejects.as_mut() {
+                    rejects.record(RejectRecord { line: err.line(), client: None, tx: None, category, reason: err.to_string() });
+                }
+                if let Some(dead_letter) = sinks.dead_letter.as_mut() {
+                    dead_letter.write(err.line(), category, &err.to_string(), source.raw().as_deref())?;
+                }
+                if summary.errors.record(category) {
+                    logger.log(LogEvent::new(Level::Warn, "skipped").reason(err));
+                }
+                if let Err(exceeded) = options.check_count(&summary.errors) {
+                    log_summary(summary, engine, logger);
+                    return Err(exceeded.into());
+                }
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        // The chargeback row carries no amount; look up the deposit before it can be pruned.
+        let charged_back = match (sinks.frozen.is_some() || sinks.settlement.is_some() || sinks.aggregates.is_some(), record.tx_type) {
+            (true, TxType::Chargeback) => engine.account_for(&record)?
+                .and_then(|account| account.history.get(record.tx))
+                .filter(|deposit| !deposit.withdrawal)
+                .map(|deposit| deposit.amount),
+            _ => None,
+        };
+
+        #[cfg(feature = "otel")]
+        let traced = sinks.telemetry.as_mut().and_then(|telemetry| telemetry.row(summary.rows));
+        let started = (options.stats_every.is_some() || sinks.statsd.is_some()).then(Instant::now);
+        let result = engine.apply(&record);
+        let latency = started.map(|started| started.elapsed());
+        if let Some(err) = engine.take_store_error() {
+            log_summary(summary, engine, logger);
+            return Err(err);
+        }
+        #[cfg(feature = "otel")]
+        if let (Some(telemetry), Some(span)) = (sinks.telemetry.as_mut(), traced) {
+            telemetry.applied(span, &record, line, result);
+        }
+        if let Some(statsd) = sinks.statsd.as_mut() {
+            statsd.observe(&record, result, latency);
+        }
+        if let Some(latency) = latency.filter(|_| options.stats_every.is_some()) {
+            summary.latency.record(latency);
+            summary.operations.entry(record.tx_type.name()).or_default().record(latency);
+            interval.latency.record(latency);
+        }
+        for resolve in engine.take_auto_resolved() {
+            logger.log(LogEvent::new(Level::Info, "auto-resolved").client(resolve.client).tx(resolve.tx)
+                .reason("dispute expired without a chargeback"));
+        }
+        for (client, tx, amount) in engine.take_provisional() {
+            let event = if amount.is_sign_negative() { "provisional-reversal" } else { "provisional-credit" };
+            logger.log(LogEvent::new(Level::Info, event).client(client).tx(tx).reason(format!("{} pending the dispute", amount)));
+        }
+
+        match result {
+            Ok(()) => {
+                summary.applied += 1;
+                if sinks.deltas.is_some() {
+                    changed.insert(engine.account_for(&record)?.map_or(record.client, |account| account.client));
+                }
+                if let (Some(frozen), Some(amount)) = (sinks.frozen.as_mut(), charged_back) {
+                    frozen.write(record.client, record.tx, amount, line)?;
+                }
+                if let Some(sar) = sinks.sar.as_mut() {
+                    sar.observe(&record, line)?;
+                }
+                if let Some(settlement) = sinks.settlement.as_mut() {
+                    settlement.observe(&record, charged_back);
+                }
+                if let Some(aggregates) = sinks.aggregates.as_mut() {
+                    aggregates.observe(&record, charged_back);
+                }
+                if let Some(top) = sinks.top.as_mut() {
+                    top.observe(&record);
+                }
+                if let Some(merkle) = sinks.merkle.as_mut() {
+                    merkle.push(&record);
+                }
+                if logger.enabled(Level::Trace) {
+                    let mut applied = LogEvent::new(Level::Trace, "applied").client(record.client).tx(record.tx);
+                    if let Some(account) = engine.account_for(&record)? {
+                        applied = applied.balance(account.available, account.held);
+                    }
+                    let kind = record.tx_type.name();
+                    match record.metadata.get("case") {
+                        Some(case) => logger.log(applied.reason(format!("{} case {}", kind, case))),
+                        None => logger.log(applied.reason(kind)),
+                    }
+                }
+                let finalized = engine.take_finalized();
+                if let Some(top) = sinks.top.as_mut() {
+                    finalized.iter().for_each(|account| top.observe_account(account));
+                }
+                let finalized: Vec<AccountReport> = finalized.iter().map(|account| engine.report(account)).collect();
+                summary.finalized += finalized.len() as u64;
+                for account in report::with_totals(finalized) {
+                    report.write(&account)?;
+                }
+            }
+            Err(reject) => {
+                if let (Some(declines), TxType::Withdrawal, Reject::InsufficientFunds, Some(amount)) =
+                    (sinks.declines.as_mut(), record.tx_type, reject, record.amount)
+                {
+                    let available = engine.account_for(&record)?.map_or(Decimal::ZERO, |account| account.available);
+                    declines.write(record.client, record.tx, amount, available)?;
+                }
+                if let Some(rejects) = sinks.rejects.as_mut() {
+                    rejects.record(RejectRecord {
+                        line,
+                        client: Some(record.client),
+                        tx: Some(record.tx),
+                        category: ErrorCategory::Rejected(reject),
+                        reason: reject.to_string(),
+                    });
+                }
+                if let Some(dead_letter) = sinks.dead_letter.as_mut() {
+                    dead_letter.write(line, ErrorCategory::Rejected(reject), &reject.to_string(), source.raw().as_deref())?;
+                }
+                if summary.errors.record(ErrorCategory::Rejected(reject)) {
+                    logger.log(LogEvent::new(Level::Debug, "rejected").client(record.client).tx(record.tx).reason(reject));
+                }
+                if let Err(exceeded) = options.check_count(&summary.errors) {
+                    log_summary(summary, engine, logger);
+                    return Err(exceeded.into());
+                }
+            }
+        }
+    }
+
+    if let Some(deltas) = sinks.deltas.as_mut().filter(|_| !changed.is_empty()) {
+        deltas.write_interval(open_accounts(engine, &changed)?.iter().map(AsRef::as_ref))?;
+    }
+    if let Some(declines) = sinks.declines.as_mut() {
+        declines.flush()?;
+    }
+    if let Some(frozen) = sinks.frozen.as_mut() {
+        frozen.flush()?;
+    }
+    if let Some(dead_letter) = sinks.dead_letter.as_mut() {
+        dead_letter.flush()?;
+    }
+    if let Some(sar) = sinks.sar.as_mut() {
+        sar.finish()?;
+        logger.log(LogEvent::new(Level::Info, "sar").reason(format!("{} clients flagged", sar.flagged())));
+    }
+    if let Some(settlement) = sinks.settlement.as_mut() {
+        settlement.finish()?;
+        logger.log(LogEvent::new(Level::Info, "settlement").reason(format!("{} merchants settled", settlement.merchants())));
+    }
+    if let Some(aggregates) = sinks.aggregates.as_mut() {
+        aggregates.finish()?;
+        logger.log(LogEvent::new(Level::Info, "aggregates").reason(format!(
+            "{} buckets, {} rows without a timestamp left out", aggregates.buckets(), aggregates.untimed()
+        )));
+    }
+    if let Some(top) = sinks.top.as_mut() {
+        for account in engine.accounts() {
+            let account = account?;
+            top.observe_account(&account);
+        }
+        top.finish()?;
+    }
+    if let Some(snapshots) = sinks.snapshots.as_ref() {
+        logger.log(LogEvent::new(Level::Debug, "snapshots").reason(format!("{} written", snapshots.written())));
+    }
+    if let Some(anchors) = sinks.anchors.as_mut() {
+        if anchored != Some(summary.rows) {
+            anchors.publish(engine, summary.rows, sinks.merkle.as_ref())?;
+        }
+        logger.log(LogEvent::new(Level::Debug, "anchors").reason(format!("{} published", anchors.published())));
+    }
+    if let Some(statsd) = sinks.statsd.as_mut() {
+        statsd.flush();
+    }
+    if let Some(checkpoints) = sinks.checkpoints.as_ref() {
+        logger.log(LogEvent::new(Level::Debug, "checkpoints").reason(format!("{} written", checkpoints.written())));
+    }
+
+    log_summary(summary, engine, logger);
+    #[cfg(feature = "otel")]
+    if let Some(telemetry) = sinks.telemetry.as_mut() {
+        // Losing telemetry is no reason to fail a run that otherwise succeeded.
+        if let Err(err) = telemetry.finish(summary) {
+            logger.log(LogEvent::new(Level::Warn, "otel").reason(err));
+        }
+    }
+    if let Some(merkle) = sinks.merkle.as_ref() {
+        logger.log(LogEvent::new(Level::Info, "merkle-root").reason(format!("{} over {} applied rows", merkle.root(), merkle.len())));
+    }
+    options.check_memory(engine)?;
+    if options.stats_every.is_some() {
+        logger.log(LogEvent::new(Level::Info, "latency").reason(&summary.latency));
+        for (operation, latency) in &summary.operations {
+            logger.log(LogEvent::new(Level::Info, "latency").reason(format!("{} ({} samples): {}", operation, latency.count(), latency)));
+        }
+    }
+    options.check_rate(&summary.errors, summary.rows)?;
+    Ok(())
+}
+
+fn log_summary<S: StateStore>(summary: &Summary, engine: &Engine<S>, logger: &Logger) {
+    for (category, count) in summary.errors.iter() {
+        let level = if category.is_parse_error() { Level::Warn } else { Level::Info };
+        let suppressed = summary.errors.suppressed(category);
+        let reason = if suppressed > 0 {
+            format!("{}: {} ({} not shown)", category, count, suppressed)
+        } else {
+            format!("{}: {}", category, count)
+        };
+        logger.log(LogEvent::new(level, "error-total").reason(reason));
+    }
+
+    let accounts = match engine.memory_usage() {
+        Ok(memory) => {
+            logger.log(LogEvent::new(Level::Debug, "memory").reason(memory));
+            memory.accounts.to_string()
+        }
+        Err(err) => {
+            logger.log(LogEvent::new(Level::Warn, "store").reason(err));
+            "unknown".to_string()
+        }
+    };
+    logger.log(LogEvent::new(Level::Info, "summary").reason(format!(
+        "{} rows, {} applied, {} skipped, {} rejected, {} accounts, {} finalized",
+        summary.rows, summary.applied, summary.errors.parse_errors(), summary.errors.rejected(), accounts,
+        summary.finalized
+    )));
+}
+
+/// The open accounts of `clients`; those finalized since are left out.
+fn open_accounts<'e, S: StateStore>(engine: &'e Engine<S>, clients: &BTreeSet<ClientId>) -> Result<Vec<Cow<'e, Account>>, Error> {
+    clients.iter().filter_map(|client| engine.account(*client).transpose()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{report::ReportWriter, ClientId};
+    use rust_decimal::dec;
+
+    const INPUT: &str = "type,client,tx,amount\n\
+        deposit,1,1,10.0\n\
+        deposit,1,2,abc\n\
+        withdrawal,1,3,50.0\n\
+        bogus,1,4,1.0\n\
+        deposit,1,5,2.5\n";
+
+    fn run(input: &str, engine: &mut Engine, options: &Options) -> Result<Summary, Error> {
+        let mut report = ReportWriter::new(io::sink());
+        process_csv(input.as_bytes(), engine, &mut report, &mut Sinks::default(), options, &Logger::default())
+    }
+
+    #[test]
+    fn test_strict_mode_aborts_on_bad_row() {
+        let mut engine = Engine::new();
+        let err = run(INPUT, &mut engine, &Options::default()).unwrap_err();
+        assert_eq!(err.to_string(), "line 3: amount 'abc' is not a valid decimal");
+    }
+
+    #[test]
+    fn test_parse_error_names_field_and_value() {
+        let input = "type,client,tx,amount\nbogus,1,4,1.0\n";
+        let err = run(input, &mut Engine::new(), &Options::default()).unwrap_err();
+        match err {
+            Error::Parse(ParseError::InvalidField { line, field, value, expected }) => {
+                assert_eq!((line, field.as_str(), value.as_str(), expected), (2, "type", "bogus", "transaction type"));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wrong_field_count_is_malformed_row() {
+        let input = "type,client,tx,amount\ndeposit,1\n";
+        let err = run(input, &mut Engine::new(), &Options::default()).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: malformed row: expected 4 fields, found 2");
+    }
+
+    #[test]
+    fn test_lenient_mode_counts_and_continues() {
+        let mut engine = Engine::new();
+        let options = Options { lenient: true, sample_limit: 1, ..Default::default() };
+        let summary = run(INPUT, &mut engine, &options).unwrap();
+
+        assert_eq!(summary.rows, 5);
+        assert_eq!(summary.applied, 2);
+        assert_eq!(summary.errors.count(ErrorCategory::InvalidField), 2);
+        assert_eq!(summary.errors.suppressed(ErrorCategory::InvalidField), 1);
+        assert_eq!(summary.errors.count(ErrorCategory::Rejected(Reject::InsufficientFunds)), 1);
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(12.5));
+    }
+
+    #[test]
+    fn test_max_errors_aborts_run() {
+        let mut engine = Engine::new();
+        let options = Options { lenient: true, max_errors: Some(2), ..Default::default() };
+        let err = run(INPUT, &mut engine, &options).unwrap_err();
+        assert!(matches!(err, Error::ErrorLimit(ErrorLimitExceeded::Count { errors: 3, max: 2 })));
+    }
+
+    #[test]
+    fn test_max_error_rate_checked_against_all_rows() {
+        let options = Options { lenient: true, max_error_rate: Some(0.5), ..Default::default() };
+        assert!(run(INPUT, &mut Engine::new(), &options).is_err());
+
+        let options = Options { lenient: true, max_error_rate: Some(0.6), ..Default::default() };
+        assert!(run(INPUT, &mut Engine::new(), &options).is_ok());
+    }
+
+    #[test]
+    fn test_deltas_hold_only_accounts_changed_in_interval() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1\ndeposit,2,2,1\ndeposit,1,3,1\nwithdrawal,3,4,1\n";
+        let path = std::env::temp_dir().join(format!("txflow-deltas-{}.csv", std::process::id()));
+        let mut sinks = Sinks {
+            deltas: Some(DeltaWriter::new(Box::new(std::fs::File::create(&path).unwrap()), crate::delta::DeltaFormat::Csv)),
+            ..Default::default()
+        };
+        let options = Options { delta_every: 2, ..Default::default() };
+        let mut report = ReportWriter::new(io::sink());
+        process_csv(input.as_bytes(), &mut Engine::new(), &mut report, &mut sinks, &options, &Logger::default()).unwrap();
+        drop(sinks);
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "interval,client,available,held,locked\n1,1,1,0,false\n1,2,1,0,false\n2,1,2,0,false\n");
+    }
+
+    #[test]
+    fn test_dead_letter_keeps_skipped_and_rejected_rows() {
+        let path = std::env::temp_dir().join(format!("txflow-dead-letter-{}.csv", std::process::id()));
+        let mut sinks = Sinks {
+            dead_letter: Some(DeadLetterWriter::new(Box::new(std::fs::File::create(&path).unwrap()))),
+            ..Default::default()
+        };
+        let options = Options { lenient: true, ..Default::default() };
+        let mut report = ReportWriter::new(io::sink());
+        process_csv(INPUT.as_bytes(), &mut Engine::new(), &mut report, &mut sinks, &options, &Logger::default()).unwrap();
+        drop(sinks);
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "input,line,category,reason,record\n\
+             ,3,invalid field,line 3: amount 'abc' is not a valid decimal,\"deposit,1,2,abc\"\n\
+             ,4,rejected: insufficient funds,insufficient funds,\"withdrawal,1,3,50.0\"\n\
+             ,5,invalid field,line 5: type 'bogus' is not a valid transaction type,\"bogus,1,4,1.0\"\n"
+        );
+    }
+
+    #[test]
+    fn test_latency_recorded_only_when_stats_enabled() {
+        let options = Options { lenient: true, ..Default::default() };
+        assert_eq!(run(INPUT, &mut Engine::new(), &options).unwrap().latency.count(), 0);
+
+        let options = Options { lenient: true, stats_every: Some(Duration::from_secs(60)), ..Default::default() };
+        let summary = run(INPUT, &mut Engine::new(), &options).unwrap();
+        assert_eq!(summary.latency.count(), 3);
+        let counts: Vec<_> = summary.operations.iter().map(|(operation, latency)| (*operation, latency.count())).collect();
+        assert_eq!(counts, [("deposit", 2), ("withdrawal", 1)]);
+    }
+
+    #[test]
+    fn test_memory_budget_aborts_run() {
+        let options = Options { lenient: true, max_memory: Some(1), ..Default::default() };
+        let err = run(INPUT, &mut Engine::new(), &options).unwrap_err();
+        assert!(matches!(err, Error::MemoryBudget { limit: 1, .. }));
+    }
+
+    #[test]
+    fn test_finalized_accounts_are_written_immediately() {
+        let input = "type,client,tx,amount\ndeposit,1,1,3.0\nfinalize,1,0,\ndeposit,2,2,4.0\n";
+        let mut engine = Engine::new();
+        let mut report = ReportWriter::new(Vec::new());
+        let (mut sinks, options) = (Sinks::default(), Options::default());
+        let summary = process_csv(input.as_bytes(), &mut engine, &mut report, &mut sinks, &options, &Logger::default()).unwrap();
+
+        assert_eq!(summary.finalized, 1);
+        assert_eq!(report.rows(), 1);
+        assert_eq!(engine.len().unwrap(), 1);
+        assert!(engine.account(ClientId(1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_declined_withdrawals_go_to_their_own_stream() {
+        let path = std::env::temp_dir().join(format!("txflow-declines-{}.csv", std::process::id()));
+        let mut sinks = Sinks {
+            declines: Some(DeclineWriter::new(Box::new(std::fs::File::create(&path).unwrap()))),
+            ..Default::default()
+        };
+        let options = Options { lenient: true, ..Default::default() };
+        let mut report = ReportWriter::new(io::sink());
+        process_csv(INPUT.as_bytes(), &mut Engine::new(), &mut report, &mut sinks, &options, &Logger::default()).unwrap();
+        drop(sinks);
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "client,tx,requested,available\n1,3,50.0,10.0\n");
+    }
+
+    #[test]
+    fn test_frozen_report_names_triggering_chargeback() {
+        let input = "type,client,tx,amount\ndeposit,1,1,4.0\ndeposit,2,2,1.0\ndispute,1,1,\nchargeback,1,1,\nchargeback,2,2,\n";
+        let path = std::env::temp_dir().join(format!("txflow-frozen-{}.csv", std::process::id()));
+        let mut sinks = Sinks {
+            frozen: Some(FrozenWriter::new(Box::new(std::fs::File::create(&path).unwrap()))),
+            ..Default::default()
+        };
+        let options = Options { lenient: true, ..Default::default() };
+        let mut report = ReportWriter::new(io::sink());
+        process_csv(input.as_bytes(), &mut Engine::new(), &mut report, &mut sinks, &options, &Logger::default()).unwrap();
+        drop(sinks);
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "client,tx,amount,line\n1,1,4.0,5\n");
+    }
+
+    #[test]
+    fn test_co_owner_rows_report_the_joint_account() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,2,2,15.0\ndeposit,2,3,4.0\ndispute,2,3,\nchargeback,2,3,\n";
+        let frozen = std::env::temp_dir().join(format!("txflow-joint-frozen-{}.csv", std::process::id()));
+        let declines = std::env::temp_dir().join(format!("txflow-joint-declines-{}.csv", std::process::id()));
+        let deltas = std::env::temp_dir().join(format!("txflow-joint-deltas-{}.csv", std::process::id()));
+        let mut sinks = Sinks {
+            frozen: Some(FrozenWriter::new(Box::new(std::fs::File::create(&frozen).unwrap()))),
+            declines: Some(DeclineWriter::new(Box::new(std::fs::File::create(&declines).unwrap()))),
+            deltas: Some(DeltaWriter::new(Box::new(std::fs::File::create(&deltas).unwrap()), crate::delta::DeltaFormat::Csv)),
+            ..Default::default()
+        };
+        let mut options = Options { lenient: true, delta_every: 10, ..Default::default() };
+        options.policy.joint.link(ClientId(1), ClientId(2)).unwrap();
+        let mut engine = Engine::with_policy(&options.policy);
+        let mut report = ReportWriter::new(io::sink());
+        process_csv(input.as_bytes(), &mut engine, &mut report, &mut sinks, &options, &Logger::default()).unwrap();
+        drop(sinks);
+
+        assert_eq!(std::fs::read_to_string(&declines).unwrap(), "client,tx,requested,available\n2,2,15.0,10.0\n");
+        assert_eq!(std::fs::read_to_string(&frozen).unwrap(), "client,tx,amount,line\n2,3,4.0,6\n");
+        assert_eq!(std::fs::read_to_string(&deltas).unwrap(), "interval,client,available,held,locked\n1,1,10.0,0.0,true\n");
+        for path in [frozen, declines, deltas] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_wallet_rows_report_their_own_wallet() {
+        let input = "type,client,tx,amount,wallet\ndeposit,1,1,10.0,main\ndeposit,1,2,4.0,bonus\n\
+            withdrawal,1,3,6.0,bonus\ndispute,1,2,,bonus\nchargeback,1,2,,bonus\n";
+        let frozen = std::env::temp_dir().join(format!("txflow-wallet-frozen-{}.csv", std::process::id()));
+        let declines = std::env::temp_dir().join(format!("txflow-wallet-declines-{}.csv", std::process::id()));
+        let mut sinks = Sinks {
+            frozen: Some(FrozenWriter::new(Box::new(std::fs::File::create(&frozen).unwrap()))),
+            declines: Some(DeclineWriter::new(Box::new(std::fs::File::create(&declines).unwrap()))),
+            ..Default::default()
+        };
+        let options = Options { lenient: true, ..Default::default() };
+        let mut engine = Engine::with_policy(&Policy { wallets: true, ..Default::default() });
+        let mut report = ReportWriter::new(io::sink());
+        process_csv(input.as_bytes(), &mut engine, &mut report, &mut sinks, &options, &Logger::default()).unwrap();
+        drop(sinks);
+
+        assert_eq!(std::fs::read_to_string(&declines).unwrap(), "client,tx,requested,available\n1,3,6.0,4.0\n");
+        assert_eq!(std::fs::read_to_string(&frozen).unwrap(), "client,tx,amount,line\n1,2,4.0,6\n");
+        std::fs::remove_file(&frozen).unwrap();
+        std::fs::remove_file(&declines).unwrap();
+    }
+
+    #[test]
+    fn test_process_any_source() {
+        use crate::{source::IterSource, Transaction, TxId};
+        let rows = [
+            Transaction::new(TxType::Deposit, ClientId(1), TxId(1), Some(dec!(4))),
+            Transaction::new(TxType::Withdrawal, ClientId(1), TxId(2), Some(dec!(5))),
+        ];
+        let mut engine = Engine::new();
+        let mut report = ReportWriter::new(io::sink());
+        let summary = process(IterSource::new(rows), &mut engine, &mut report, &mut Sinks::default(), &Options::default(), &Logger::default()).unwrap();
+        assert_eq!((summary.rows, summary.applied, summary.errors.rejected()), (2, 1, 1));
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(4));
+    }
+}
@@ -0,0 +1,198 @@
+//! A quick profile of a raw transactions file: rows per type, distinct
+//! clients, how amounts are distributed and what time span the rows cover.
+//! Rows are only parsed, never applied, so it runs in one pass with memory
+//! bounded by the number of clients, well before committing to a full run.
+
+use std::{collections::BTreeMap, io};
+use rust_decimal::Decimal;
+
+use crate::{
+    collections::HashSet,
+    error::Error,
+    parse::AmountFormat,
+    source::{CsvSource, TxSource},
+    time, ClientId, Transaction, TxType,
+};
+
+/// Amount bands by power of ten: below 0.01, then [0.01, 0.1) up to
+/// [100_000, 1_000_000), then 1_000_000 and above.
+const BANDS: usize = 10;
+
+fn band(amount: Decimal) -> usize {
+    let mut bound = Decimal::new(1, 2);
+    for band in 0..BANDS - 1 {
+        if amount < bound {
+            return band;
+        }
+        bound *= Decimal::TEN;
+    }
+    BANDS - 1
+}
+
+fn band_label(band: usize) -> String {
+    let bound = |band: usize| (Decimal::new(1, 2) * Decimal::from(10u64.pow(band as u32))).normalize();
+    match band {
+        0 => "< 0.01".to_string(),
+        _ if band == BANDS - 1 => format!(">= {}", bound(band - 1)),
+        _ => format!("{} - {}", bound(band - 1), bound(band)),
+    }
+}
+
+/// Amounts seen on one transaction type.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Amounts {
+    pub count: u64,
+    pub total: Decimal,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+    pub bands: [u64; BANDS],
+}
+
+impl Amounts {
+    fn add(&mut self, amount: Decimal) {
+        self.count += 1;
+        self.total += amount;
+        self.min = Some(self.min.map_or(amount, |min| min.min(amount)));
+        self.max = Some(self.max.map_or(amount, |max| max.max(amount)));
+        self.bands[band(amount.abs())] += 1;
+    }
+
+    pub fn mean(&self) -> Option<Decimal> {
+        (self.count > 0).then(|| (self.total / Decimal::from(self.count)).round_dp(4))
+    }
+}
+
+/// Earliest and latest value of a time column, and rows that left it empty.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub first: Option<i64>,
+    pub last: Option<i64>,
+    pub missing: u64,
+}
+
+impl Span {
+    fn add(&mut self, time: Option<i64>) {
+        match time {
+            Some(time) => {
+                self.first = Some(self.first.map_or(time, |first| first.min(time)));
+                self.last = Some(self.last.map_or(time, |last| last.max(time)));
+            }
+            None => self.missing += 1,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub rows: u64,
+    /// Rows that did not parse.
+    pub unreadable: u64,
+    pub types: BTreeMap<&'static str, u64>,
+    pub clients: HashSet<ClientId>,
+    pub deposits: Amounts,
+    pub withdrawals: Amounts,
+    pub timestamps: Span,
+    pub recorded: Span,
+}
+
+impl Profile {
+    /// Profiles every row of `input`, reading amounts as `amounts` describes.
+    pub fn read(input: impl io::Read, amounts: &AmountFormat) -> Result<Self, Error> {
+        let mut source = CsvSource::new(input, amounts)?;
+        let mut profile = Profile::default();
+        loop {
+            match source.next_row() {
+                Ok(Some(row)) => profile.add(&row.transaction),
+                Ok(None) => return Ok(profile),
+                Err(Error::Parse(_)) => {
+                    profile.rows += 1;
+                    profile.unreadable += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn add(&mut self, transaction: &Transaction) {
+        self.rows += 1;
+        *self.types.entry(transaction.tx_type.name()).or_insert(0) += 1;
+        self.clients.insert(transaction.client);
+        match (transaction.tx_type, transaction.amount) {
+            (TxType::Deposit, Some(amount)) => self.deposits.add(amount),
+            (TxType::Withdrawal, Some(amount)) => self.withdrawals.add(amount),
+            _ => {}
+        }
+        self.timestamps.add(transaction.timestamp);
+        self.recorded.add(transaction.recorded);
+    }
+
+    pub fn write_text<W: io::Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "rows        {}", self.rows)?;
+        writeln!(out, "unreadable  {}", self.unreadable)?;
+        writeln!(out, "clients     {}", self.clients.len())?;
+        writeln!(out)?;
+        for (name, count) in &self.types {
+            writeln!(out, "{:<18}{:>12}", name, count)?;
+        }
+        writeln!(out)?;
+        writeln!(out, "{:<18}{:>12}{:>12}", "amounts", "deposit", "withdrawal")?;
+        let cell = |value: Option<Decimal>| value.map_or("-".to_string(), |value| value.normalize().to_string());
+        for (label, deposit, withdrawal) in [
+            ("count", Some(self.deposits.count.into()), Some(self.withdrawals.count.into())),
+            ("min", self.deposits.min, self.withdrawals.min),
+            ("mean", self.deposits.mean(), self.withdrawals.mean()),
+            ("max", self.deposits.max, self.withdrawals.max),
+            ("total", Some(self.deposits.total), Some(self.withdrawals.total)),
+        ] {
+            writeln!(out, "{:<18}{:>12}{:>12}", label, cell(deposit), cell(withdrawal))?;
+        }
+        for band in 0..BANDS {
+            let (deposits, withdrawals) = (self.deposits.bands[band], self.withdrawals.bands[band]);
+            if deposits + withdrawals > 0 {
+                writeln!(out, "{:<18}{:>12}{:>12}", band_label(band), deposits, withdrawals)?;
+            }
+        }
+        writeln!(out)?;
+        for (label, span) in [("timestamp", self.timestamps), ("recorded_at", self.recorded)] {
+            match (span.first, span.last) {
+                (Some(first), Some(last)) => writeln!(
+                    out, "{:<12}{} .. {} ({} rows without)",
+                    label, time::format_timestamp(first), time::format_timestamp(last), span.missing
+                )?,
+                _ => writeln!(out, "{:<12}none", label)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_profile_counts_types_clients_amounts_and_time() {
+        let input = concat!(
+            "type,client,tx,amount,timestamp\n",
+            "deposit,1,1,0.005,2024-03-01T10:00:00Z\n",
+            "deposit,2,2,250,2024-01-15T00:00:00Z\n",
+            "withdrawal,1,3,12.5,\n",
+            "dispute,2,2,,2024-02-01T00:00:00Z\n",
+            "deposit,x,4,1,\n",
+        );
+        let profile = Profile::read(input.as_bytes(), &AmountFormat::default()).unwrap();
+        assert_eq!((profile.rows, profile.unreadable, profile.clients.len()), (5, 1, 2));
+        assert_eq!(profile.types.get("deposit"), Some(&2));
+        assert_eq!((profile.deposits.min, profile.deposits.max), (Some(dec!(0.005)), Some(dec!(250))));
+        assert_eq!(profile.deposits.bands[0] + profile.deposits.bands[5], 2);
+        assert_eq!(profile.timestamps.first, time::parse_timestamp("2024-01-15T00:00:00Z"));
+        assert_eq!(profile.timestamps.missing, 1);
+
+        let mut text = Vec::new();
+        profile.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("100 - 1000"), "{}", text);
+        assert!(text.contains("timestamp   2024-01-15T00:00:00Z .. 2024-03-01T10:00:00Z (1 rows without)"), "{}", text);
+    }
+}
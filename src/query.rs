@@ -0,0 +1,152 @@
+//! Point-in-time balances: replays an input up to a cut and reports accounts
+//! as they stood there, for tracing when a balance diverged and for
+//! restatements. The input itself serves as the event log.
+//!
+//! Cuts are bitemporal: "as known at T" leaves out rows recorded after T
+//! (processing time, the `recorded_at` column), "as effective at T" leaves
+//! out rows whose business `timestamp` is after T. A row without
+//! `recorded_at` counts as recorded when it took effect.
+
+use std::io;
+
+use crate::{
+    error::Error,
+    logging::Logger,
+    processor::{self, Options, Sinks},
+    report::AccountReport,
+    source::{Row, TxSource},
+    ClientId, Engine, Transaction, TxId,
+};
+
+/// Where a replay stops and which rows it sees; unset fields don't restrict.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct AsOf {
+    /// Stop right after the first row carrying this id.
+    pub tx: Option<TxId>,
+    /// Leave out rows recorded after this time.
+    pub known_at: Option<i64>,
+    /// Leave out rows taking effect after this time.
+    pub effective_at: Option<i64>,
+}
+
+impl AsOf {
+    /// Whether `record` had been recorded and taken effect by the cut's times.
+    pub fn includes(&self, record: &Transaction) -> bool {
+        let before = |time: Option<i64>, cut: Option<i64>| match (time, cut) {
+            (Some(time), Some(cut)) => time <= cut,
+            _ => true,
+        };
+        before(record.recorded.or(record.timestamp), self.known_at) && before(record.timestamp, self.effective_at)
+    }
+}
+
+/// Passes the rows a cut includes, up to and including the first carrying its tx.
+struct Cut<T> {
+    source: T,
+    as_of: AsOf,
+    /// Line of the last row passed on.
+    line: u64,
+    reached: bool,
+}
+
+impl<T: TxSource> TxSource for Cut<T> {
+    fn next_row(&mut self) -> Result<Option<Row>, Error> {
+        while !self.reached {
+            let Some(row) = self.source.next_row()? else { break };
+            if !self.as_of.includes(&row.transaction) {
+                continue;
+            }
+            self.line = row.line;
+            self.reached = self.as_of.tx == Some(row.transaction.tx);
+            return Ok(Some(row));
+        }
+        Ok(None)
+    }
+
+    fn raw(&self) -> Option<Vec<String>> {
+        self.source.raw()
+    }
+}
+
+/// Accounts at a cut.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Input line of the last row applied.
+    pub line: u64,
+    /// Open and finalized accounts alike, by client.
+    pub accounts: Vec<AccountReport>,
+}
+
+impl Snapshot {
+    /// `None` if the client had no account yet.
+    pub fn account(&self, client: ClientId) -> Option<&AccountReport> {
+        self.accounts.iter().find(|account| account.client == client)
+    }
+}
+
+/// Replays `input` under `options`, applying the rows `as_of` includes
+/// exactly as a normal run would, including other clients', so policies
+/// that span clients hold.
+pub fn replay<R: io::Read>(input: R, as_of: &AsOf, options: &Options, logger: &Logger) -> Result<Snapshot, Error> {
+    let mut source = Cut { source: processor::csv_source(input, options, logger)?, as_of: *as_of, line: 0, reached: false };
+    let mut engine = Engine::with_policy(&options.policy);
+    let mut accounts = Vec::new();
+    processor::process(&mut source, &mut engine, &mut accounts, &mut Sinks::default(), options, logger)?;
+
+    if let (Some(tx), false) = (as_of.tx, source.reached) {
+        return Err(Error::TxNotFound(tx));
+    }
+    accounts.extend(engine.reports()?);
+    accounts.sort_by_key(|account| account.client);
+    Ok(Snapshot { line: source.line, accounts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    const INPUT: &str = "type,client,tx,amount\n\
+        deposit,7,1,10\n\
+        deposit,8,2,3\n\
+        withdrawal,7,3,4\n\
+        dispute,7,1,\n";
+
+    fn query(client: u32, tx: u32) -> Result<Option<AccountReport>, Error> {
+        let as_of = AsOf { tx: Some(TxId(tx)), ..Default::default() };
+        let snapshot = replay(INPUT.as_bytes(), &as_of, &Options::default(), &Logger::default())?;
+        Ok(snapshot.account(ClientId(client)).cloned())
+    }
+
+    #[test]
+    fn test_balances_as_of_a_transaction() {
+        assert_eq!(query(7, 2).unwrap().map(|account| account.available), Some(dec!(10)));
+        let account = query(7, 3).unwrap().unwrap();
+        assert_eq!((account.available, account.held), (dec!(6), dec!(0)));
+        assert!(query(8, 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_unknown_tx_is_an_error() {
+        assert!(matches!(query(7, 99), Err(Error::TxNotFound(TxId(99)))));
+    }
+
+    #[test]
+    fn test_known_versus_effective() {
+        // A deposit effective on the 1st but only recorded on the 5th, restating the balance.
+        let input = "type,client,tx,amount,timestamp,recorded_at\n\
+            deposit,1,1,10,2024-01-01,2024-01-01\n\
+            deposit,1,2,5,2024-01-01,2024-01-05\n\
+            deposit,1,3,1,2024-01-04,\n";
+        let available = |as_of: AsOf| {
+            let snapshot = replay(input.as_bytes(), &as_of, &Options::default(), &Logger::default()).unwrap();
+            snapshot.account(ClientId(1)).unwrap().available
+        };
+        let day = |day: u32| Some(crate::time::days_from_civil(2024, 1, day) * 86_400);
+
+        assert_eq!(available(AsOf { effective_at: day(2), ..Default::default() }), dec!(15));
+        assert_eq!(available(AsOf { known_at: day(2), ..Default::default() }), dec!(10));
+        assert_eq!(available(AsOf { known_at: day(4), effective_at: day(2), ..Default::default() }), dec!(10));
+        assert_eq!(available(AsOf::default()), dec!(16));
+    }
+}
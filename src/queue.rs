@@ -0,0 +1,189 @@
+//! Rows from a message queue whose messages are addressed by offset, such
+//! as a Kafka partition. Each message carries one CSV row under a header
+//! agreed up front. The queue's client stays outside txflow behind
+//! [`MessageLog`]; [`LogSource`] turns it into a [`TxSource`] whose position
+//! is the next offset, so checkpoints record the offset with the state they
+//! cover and a resumed run seeks straight back to it. Offsets are committed
+//! to the queue only after the checkpoint holding them is on disk, which
+//! makes processing effectively once across restarts.
+
+use crate::{
+    error::{Error, ParseError},
+    parse::{self, AmountFormat, Columns},
+    source::{Row, TxSource},
+};
+
+/// A queue client reading one partition.
+pub trait MessageLog {
+    /// The next message and its offset, or `None` once nothing is left.
+    fn poll(&mut self) -> Result<Option<(u64, Vec<u8>)>, Error>;
+
+    /// Makes `offset` the first one [`poll`](MessageLog::poll) can return.
+    fn seek(&mut self, offset: u64) -> Result<(), Error>;
+
+    /// Commits `offset` as the next one the consumer group needs.
+    fn commit(&mut self, offset: u64) -> Result<(), Error>;
+}
+
+/// The rows of a [`MessageLog`]. A row's line is its message's offset plus
+/// one, as lines count from one.
+pub struct LogSource<L> {
+    log: L,
+    columns: Columns,
+    amounts: AmountFormat,
+    /// The offset after the message last read.
+    next: Option<u64>,
+    raw: csv::StringRecord,
+}
+
+impl<L: MessageLog> LogSource<L> {
+    /// Reads messages with the columns of `header`, a CSV header row.
+    pub fn new(log: L, header: &str, amounts: &AmountFormat) -> Result<Self, Error> {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(header.as_bytes());
+        let columns = Columns::from_headers(reader.headers()?)?;
+        Ok(LogSource { log, columns, amounts: *amounts, next: None, raw: csv::StringRecord::new() })
+    }
+
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+}
+
+impl<L: MessageLog> TxSource for LogSource<L> {
+    fn next_row(&mut self) -> Result<Option<Row>, Error> {
+        let Some((offset, message)) = self.log.poll()? else { return Ok(None) };
+        let line = offset + 1;
+        self.next = Some(line);
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).trim(csv::Trim::All).from_reader(message.as_slice());
+        let mut position = csv::Position::new();
+        position.set_line(line);
+        match reader.read_record(&mut self.raw) {
+            Ok(true) => {
+                self.raw.set_position(Some(position));
+                let transaction = parse::parse_record(&self.raw, &self.columns, &self.amounts)?;
+                Ok(Some(Row { transaction, line }))
+            }
+            Ok(false) => Err(ParseError::MalformedRow { line, reason: "empty message".to_string() }.into()),
+            Err(err) if matches!(err.kind(), csv::ErrorKind::Io(_)) => Err(err.into()),
+            Err(err) => Err(ParseError::MalformedRow { line, reason: err.to_string() }.into()),
+        }
+    }
+
+    fn raw(&self) -> Option<Vec<String>> {
+        Some(self.raw.iter().map(str::to_string).collect())
+    }
+
+    fn position(&self) -> Option<u64> {
+        self.next
+    }
+
+    fn seek(&mut self, position: u64) -> Result<bool, Error> {
+        self.log.seek(position)?;
+        self.next = Some(position);
+        Ok(true)
+    }
+
+    fn commit(&mut self, position: u64) -> Result<(), Error> {
+        self.log.commit(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File, OpenOptions};
+    use crate::{
+        checkpoint::{self, Checkpoints},
+        logging::Logger,
+        processor::{self, Options, Sinks},
+        report::{ReportSink, ReportWriter},
+        state, Engine, TxId,
+    };
+
+    /// A partition held in memory; offsets skip where compaction removed messages.
+    #[derive(Clone, Default)]
+    struct VecLog {
+        messages: Vec<(u64, Vec<u8>)>,
+        next: usize,
+        seeks: Vec<u64>,
+        committed: Option<u64>,
+    }
+
+    impl MessageLog for VecLog {
+        fn poll(&mut self) -> Result<Option<(u64, Vec<u8>)>, Error> {
+            let message = self.messages.get(self.next).cloned();
+            self.next += 1;
+            Ok(message)
+        }
+
+        fn seek(&mut self, offset: u64) -> Result<(), Error> {
+            self.seeks.push(offset);
+            self.next = self.messages.iter().position(|(at, _)| *at >= offset).unwrap_or(self.messages.len());
+            Ok(())
+        }
+
+        fn commit(&mut self, offset: u64) -> Result<(), Error> {
+            self.committed = Some(offset);
+            Ok(())
+        }
+    }
+
+    const HEADER: &str = "type,client,tx,amount";
+
+    fn partition() -> VecLog {
+        let rows = ["deposit,1,1,10", "deposit,2,2,5", "finalize,1,3,", "deposit,3,4,7", "finalize,2,5,", "withdrawal,3,6,2"];
+        let offsets = [0, 1, 4, 5, 8, 9];
+        VecLog { messages: offsets.into_iter().zip(rows.map(|row| row.as_bytes().to_vec())).collect(), ..Default::default() }
+    }
+
+    fn run(log: VecLog, output: &std::path::Path, checkpoints: Checkpoints, engine: &mut Engine, header: bool) -> VecLog {
+        let file = OpenOptions::new().append(true).open(output).unwrap();
+        let mut report = if header { ReportWriter::new(file) } else { ReportWriter::continuing(file, Default::default()) };
+        let mut sinks = Sinks { checkpoints: Some(checkpoints), ..Default::default() };
+        let mut source = LogSource::new(log, HEADER, &AmountFormat::default()).unwrap();
+        processor::process(&mut source, engine, &mut report, &mut sinks, &Options::default(), &Logger::default()).unwrap();
+        for account in engine.reports().unwrap() {
+            report.write(&account).unwrap();
+        }
+        report.finish().unwrap();
+        source.into_inner()
+    }
+
+    #[test]
+    fn test_rows_are_numbered_by_offset() {
+        let mut log = partition();
+        log.messages.push((12, b"bogus,1,7,1".to_vec()));
+        let mut source = LogSource::new(log, HEADER, &AmountFormat::default()).unwrap();
+        let row = source.next_row().unwrap().unwrap();
+        assert_eq!((row.transaction.tx, row.line, source.position()), (TxId(1), 1, Some(1)));
+        assert!(source.seek(9).unwrap());
+        assert_eq!(source.next_row().unwrap().unwrap().transaction.tx, TxId(6));
+        assert!(matches!(source.next_row(), Err(Error::Parse(ParseError::InvalidField { line: 13, .. }))));
+        assert!(source.next_row().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_resume_seeks_to_the_checkpointed_offset() {
+        let output = std::env::temp_dir().join(format!("txflow-queue-{}.csv", std::process::id()));
+        let path = checkpoint::path(&output);
+        fs::write(&output, "").unwrap();
+        let every = Checkpoints::new(&path, File::open(&output).unwrap(), Some(4));
+        let log = run(partition(), &output, every, &mut Engine::new(), true);
+        let complete = fs::read_to_string(&output).unwrap();
+        assert_eq!(log.committed, Some(6));
+
+        // A crash after the checkpoint; the queue may have committed less.
+        let point = state::load_checkpoint(&path).unwrap().1.unwrap();
+        assert_eq!((point.rows, point.position), (4, Some(6)));
+        fs::write(&output, &complete[..point.output_bytes as usize]).unwrap();
+        let resumed = checkpoint::resume(&output, &path).unwrap();
+        let mut engine = resumed.engine;
+        let checkpoints = Checkpoints::new(&path, File::open(&output).unwrap(), Some(4)).resuming(resumed.point);
+        let log = run(partition(), &output, checkpoints, &mut engine, false);
+
+        assert_eq!(log.seeks, [6]);
+        assert_eq!(fs::read_to_string(&output).unwrap(), complete);
+        fs::remove_file(&output).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
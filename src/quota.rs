@@ -0,0 +1,90 @@
+//! Per-client quotas on how many rows a client may submit in a minute and
+//! in a day, so one noisy client in a shared deployment cannot crowd out the
+//! others. Windows follow the rows' own `timestamp` values rather than the
+//! wall clock, so a replay refuses exactly what the original run refused.
+//! A row without a timestamp counts at the newest timestamp seen, or at
+//! wall-clock time if there has been none. A refused row does not count
+//! towards the window it was refused in. Usage is saved with the state, so
+//! a restart does not open a client's windows again.
+
+use serde::{Deserialize, Serialize};
+
+/// Seconds in a day window.
+const DAY: i64 = 86_400;
+
+/// The `[quotas]` policy section.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Quotas {
+    pub per_minute: Option<u32>,
+    pub per_day: Option<u32>,
+}
+
+impl Quotas {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let slot = match key {
+            "per_minute" => &mut self.per_minute,
+            "per_day" => &mut self.per_day,
+            other => return Err(format!("unknown key '{}' in [quotas] (expected per_minute or per_day)", other)),
+        };
+        *slot = Some(value.parse().map_err(|_| format!("[quotas] {} must be a count, got '{}'", key, value))?);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.per_minute.is_none() && self.per_day.is_none()
+    }
+
+    /// Counts a row at `timestamp` against `usage`, unless either window is
+    /// already full.
+    pub fn admit(&self, usage: &mut Usage, timestamp: i64) -> bool {
+        let minute = usage.minute.at(timestamp.div_euclid(60));
+        let day = usage.day.at(timestamp.div_euclid(DAY));
+        if self.per_minute.is_some_and(|limit| minute.count >= limit) || self.per_day.is_some_and(|limit| day.count >= limit) {
+            return false;
+        }
+        minute.count += 1;
+        day.count += 1;
+        true
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
+struct Window {
+    /// Which minute or day since the epoch the count is for.
+    index: i64,
+    count: u32,
+}
+
+impl Window {
+    /// The window holding `index`, emptied if it is a new one.
+    fn at(&mut self, index: i64) -> &mut Self {
+        if self.index != index {
+            *self = Window { index, count: 0 };
+        }
+        self
+    }
+}
+
+/// One client's rows in its current minute and day.
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    minute: Window,
+    day: Window,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_fill_and_roll_over() {
+        let quotas = Quotas { per_minute: Some(2), per_day: Some(3) };
+        let mut usage = Usage::default();
+        assert!(quotas.admit(&mut usage, 0) && quotas.admit(&mut usage, 59));
+        assert!(!quotas.admit(&mut usage, 30));
+        assert!(quotas.admit(&mut usage, 60));
+        assert!(!quotas.admit(&mut usage, 120), "the day is full");
+        assert!(quotas.admit(&mut usage, DAY));
+        assert_eq!(Quotas::default().set("per_hour", "1").unwrap_err(), "unknown key 'per_hour' in [quotas] (expected per_minute or per_day)");
+    }
+}
@@ -0,0 +1,345 @@
+use std::{collections::{btree_map::Entry, BTreeMap}, io::{self, Write}, str::FromStr};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, money::MoneyOps, Account, ClientId};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum OutputFormat {
+    /// Account rows streamed as CSV.
+    #[default]
+    Csv,
+    /// Account rows streamed as JSON lines.
+    Jsonl,
+    /// A workbook with accounts, rejects and summary sheets, written at the end of the run.
+    Xlsx,
+    /// A self-contained HTML page with summary figures and sortable account tables.
+    Html,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "xlsx" => Ok(OutputFormat::Xlsx),
+            "html" => Ok(OutputFormat::Html),
+            other => Err(format!("unknown output format '{}' (expected csv, jsonl, xlsx or html)", other)),
+        }
+    }
+}
+
+/// An account's reported figures, detached from its dispute history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountReport {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+    /// Set only while the engine keeps wallets apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet: Option<String>,
+    /// Every owner's client id, set only while joint accounts are configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owners: Option<String>,
+}
+
+impl AccountReport {
+    /// The figures of an account in any amount type.
+    pub fn of<A: MoneyOps>(account: &Account<A>) -> Self {
+        AccountReport {
+            client: account.client,
+            available: account.available.to_decimal(),
+            held: account.held.to_decimal(),
+            locked: account.locked,
+            wallet: account.wallet.clone(),
+            owners: None,
+        }
+    }
+}
+
+impl From<&Account> for AccountReport {
+    fn from(account: &Account) -> Self {
+        AccountReport::of(account)
+    }
+}
+
+/// The `wallet` value of the row rolling up a client's wallets.
+pub const TOTAL_WALLET: &str = "total";
+
+/// Follows wallet rows with one [`TOTAL_WALLET`] row per client summing
+/// them, locked if any wallet is. Rows without a wallet pass through alone.
+pub fn with_totals(mut reports: Vec<AccountReport>) -> Vec<AccountReport> {
+    let mut totals: BTreeMap<ClientId, AccountReport> = BTreeMap::new();
+    for report in reports.iter().filter(|report| report.wallet.is_some()) {
+        let total = totals.entry(report.client).or_insert_with(|| AccountReport {
+            client: report.client,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            locked: false,
+            wallet: Some(TOTAL_WALLET.to_string()),
+            owners: report.owners.clone(),
+        });
+        total.available += report.available;
+        total.held += report.held;
+        total.locked |= report.locked;
+    }
+    reports.extend(totals.into_values());
+    reports
+}
+
+/// How a merge settles `locked` for an account the partial reports disagree on.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum LockedRule {
+    /// Locked if any report has it locked.
+    #[default]
+    Any,
+    /// Locked only if every report has it locked.
+    All,
+    /// Refuse to merge.
+    Strict,
+}
+
+impl FromStr for LockedRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(LockedRule::Any),
+            "all" => Ok(LockedRule::All),
+            "strict" => Ok(LockedRule::Strict),
+            other => Err(format!("unknown locked rule '{}' (expected any, all or strict)", other)),
+        }
+    }
+}
+
+/// Reads the rows of a CSV account report.
+pub fn read_csv(input: impl io::Read) -> Result<Vec<AccountReport>, Error> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+    Ok(reader.deserialize().collect::<Result<_, _>>()?)
+}
+
+/// Combines partial reports, such as those of shards processed apart, into
+/// one row per client and wallet in client order: balances are summed and
+/// `locked` settled by `rule`. A client's owners are taken from the first
+/// report listing them.
+pub fn merge(reports: impl IntoIterator<Item = AccountReport>, rule: LockedRule) -> Result<Vec<AccountReport>, Error> {
+    let mut merged: BTreeMap<(ClientId, Option<String>), AccountReport> = BTreeMap::new();
+    for report in reports {
+        match merged.entry((report.client, report.wallet.clone())) {
+            Entry::Vacant(entry) => {
+                entry.insert(report);
+            }
+            Entry::Occupied(mut entry) => {
+                let into = entry.get_mut();
+                if into.locked != report.locked && rule == LockedRule::Strict {
+                    return Err(Error::LockConflict(report.client));
+                }
+                into.available += report.available;
+                into.held += report.held;
+                into.locked = match rule {
+                    LockedRule::All => into.locked && report.locked,
+                    LockedRule::Any | LockedRule::Strict => into.locked || report.locked,
+                };
+                if into.owners.is_none() {
+                    into.owners = report.owners;
+                }
+            }
+        }
+    }
+    Ok(merged.into_values().collect())
+}
+
+/// Where account reports go. Finalized accounts arrive during the run and
+/// the rest at the end, so a sink that needs every account at once (a
+/// workbook, a page) should collect them and render in [`ReportSink::finish`].
+pub trait ReportSink {
+    fn write(&mut self, report: &AccountReport) -> Result<(), Error>;
+
+    /// Called once after the last report.
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Collects reports in memory, in arrival order.
+impl ReportSink for Vec<AccountReport> {
+    fn write(&mut self, report: &AccountReport) -> Result<(), Error> {
+        self.push(report.clone());
+        Ok(())
+    }
+}
+
+/// How often a streamed report hands its buffered rows to the output: after
+/// so many rows, or whenever so many bytes have built up. Either way the
+/// last rows go out when the report finishes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FlushEvery {
+    Rows(u64),
+    Bytes(usize),
+}
+
+impl Default for FlushEvery {
+    fn default() -> Self {
+        FlushEvery::Bytes(DEFAULT_BUFFER)
+    }
+}
+
+const DEFAULT_BUFFER: usize = 8 * 1024;
+
+impl FlushEvery {
+    fn buffer(self) -> usize {
+        match self {
+            FlushEvery::Rows(_) => DEFAULT_BUFFER,
+            FlushEvery::Bytes(bytes) => bytes.max(1),
+        }
+    }
+
+    /// Whether `pending` rows call for a flush.
+    fn due(self, pending: u64) -> bool {
+        matches!(self, FlushEvery::Rows(rows) if pending >= rows)
+    }
+}
+
+/// Writes account rows as CSV, either one at a time as accounts are
+/// finalized or all at once at the end of a run.
+pub struct ReportWriter<W: io::Write> {
+    writer: csv::Writer<W>,
+    rows: u64,
+    flush_every: FlushEvery,
+    pending: u64,
+}
+
+impl<W: io::Write> ReportWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self::with_flush(output, FlushEvery::default())
+    }
+
+    pub fn with_flush(output: W, flush_every: FlushEvery) -> Self {
+        let writer = csv::WriterBuilder::new().buffer_capacity(flush_every.buffer()).from_writer(output);
+        ReportWriter { writer, rows: 0, flush_every, pending: 0 }
+    }
+
+    /// Appends to a report that already has its header, e.g. a resumed one.
+    pub fn continuing(output: W, flush_every: FlushEvery) -> Self {
+        let writer = csv::WriterBuilder::new().has_headers(false).buffer_capacity(flush_every.buffer()).from_writer(output);
+        ReportWriter { writer, rows: 0, flush_every, pending: 0 }
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying output.
+    pub fn into_inner(self) -> Result<W, Error> {
+        self.writer.into_inner().map_err(|err| Error::Io(err.into_error()))
+    }
+}
+
+impl<W: io::Write> ReportSink for ReportWriter<W> {
+    fn write(&mut self, report: &AccountReport) -> Result<(), Error> {
+        self.writer.serialize(report)?;
+        self.rows += 1;
+        self.pending += 1;
+        if self.flush_every.due(self.pending) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+}
+
+/// Writes one JSON object per account per line.
+pub struct JsonReportWriter<W: io::Write> {
+    output: io::BufWriter<W>,
+    flush_every: FlushEvery,
+    pending: u64,
+}
+
+impl<W: io::Write> JsonReportWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self::with_flush(output, FlushEvery::default())
+    }
+
+    pub fn with_flush(output: W, flush_every: FlushEvery) -> Self {
+        JsonReportWriter { output: io::BufWriter::with_capacity(flush_every.buffer(), output), flush_every, pending: 0 }
+    }
+}
+
+impl<W: io::Write> ReportSink for JsonReportWriter<W> {
+    fn write(&mut self, report: &AccountReport) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.output, report).map_err(io::Error::from)?;
+        self.output.write_all(b"\n")?;
+        self.pending += 1;
+        if self.flush_every.due(self.pending) {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.output.flush()?;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientId;
+
+    #[test]
+    fn test_header_written_once_across_rows() {
+        let mut report = ReportWriter::new(Vec::new());
+        report.write(&AccountReport::from(&Account::new(ClientId(1)))).unwrap();
+        report.write(&AccountReport::from(&Account::new(ClientId(2)))).unwrap();
+        assert_eq!(report.rows(), 2);
+        let output = String::from_utf8(report.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "client,available,held,locked\n1,0,0,false\n2,0,0,false\n");
+    }
+
+    #[test]
+    fn test_merge_sums_shards_and_settles_locked() {
+        let a = read_csv("client,available,held,locked\n1,10,2,false\n3,1,0,true\n".as_bytes()).unwrap();
+        let b = read_csv("client,available,held,locked\n3,4.5,1,false\n2,7,0,false\n".as_bytes()).unwrap();
+        let merged = merge(a.iter().chain(&b).cloned(), LockedRule::Any).unwrap();
+        let mut report = ReportWriter::new(Vec::new());
+        for row in &merged {
+            report.write(row).unwrap();
+        }
+        let output = String::from_utf8(report.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "client,available,held,locked\n1,10,2,false\n2,7,0,false\n3,5.5,1,true\n");
+
+        assert!(!merge(a.iter().chain(&b).cloned(), LockedRule::All).unwrap()[2].locked);
+        assert!(matches!(merge(a.into_iter().chain(b), LockedRule::Strict), Err(Error::LockConflict(ClientId(3)))));
+    }
+
+    #[test]
+    fn test_flushes_every_n_rows() {
+        let mut output = Vec::new();
+        let mut report = JsonReportWriter::with_flush(&mut output, FlushEvery::Rows(2));
+        report.write(&AccountReport::from(&Account::new(ClientId(1)))).unwrap();
+        assert!(report.output.get_ref().is_empty());
+        report.write(&AccountReport::from(&Account::new(ClientId(2)))).unwrap();
+        assert_eq!(report.output.get_ref().iter().filter(|byte| **byte == b'\n').count(), 2);
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let mut output = Vec::new();
+        JsonReportWriter::new(&mut output).write(&AccountReport::from(&Account::new(ClientId(3)))).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"client\":3,\"available\":\"0\",\"held\":\"0\",\"locked\":false}\n");
+    }
+}
@@ -0,0 +1,77 @@
+use std::{fmt, str::FromStr};
+
+use crate::{money::MoneyOps, time, Account};
+
+/// Which deposits stay in an account's history, and so stay dispute-able.
+/// Deposits under dispute are always kept regardless of policy, since their
+/// funds are held until the dispute settles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Retention {
+    #[default]
+    KeepAll,
+    /// Keep the N most recent undisputed deposits per client.
+    KeepLast(usize),
+    /// Keep deposits whose timestamp is within this many seconds of the
+    /// newest timestamp seen; deposits without a timestamp never expire.
+    KeepWithin(i64),
+    /// Forget a deposit once a dispute on it has been resolved or charged back.
+    KeepUndisputedOnly,
+}
+
+impl FromStr for Retention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = s.split_once(':').map_or((s, None), |(name, arg)| (name, Some(arg)));
+        match (name, arg) {
+            ("keep-all", None) => Ok(Retention::KeepAll),
+            ("keep-undisputed-only", None) => Ok(Retention::KeepUndisputedOnly),
+            ("keep-last", Some(n)) => n.parse().map(Retention::KeepLast)
+                .map_err(|_| format!("invalid count in retention policy '{}'", s)),
+            ("keep-within", Some(span)) => time::parse_duration(span).map(Retention::KeepWithin)
+                .ok_or_else(|| format!("invalid duration in retention policy '{}'", s)),
+            _ => Err(format!(
+                "unknown retention policy '{}' (expected keep-all, keep-last:N, keep-within:DURATION or keep-undisputed-only)", s
+            )),
+        }
+    }
+}
+
+impl Retention {
+    /// Drops the undisputed deposits the policy no longer keeps from
+    /// `account`, given the newest timestamp seen so far. Returns how many
+    /// entries were removed.
+    pub(crate) fn prune<A: MoneyOps>(self, account: &mut Account<A>, now: Option<i64>) -> usize {
+        match (self, now) {
+            (Retention::KeepLast(keep), _) => account.keep_last(keep),
+            (Retention::KeepWithin(window), Some(now)) => account.expire_before(now - window),
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for Retention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Retention::KeepAll => f.write_str("keep-all"),
+            Retention::KeepLast(n) => write!(f, "keep-last:{}", n),
+            Retention::KeepWithin(seconds) => write!(f, "keep-within:{}s", seconds),
+            Retention::KeepUndisputedOnly => f.write_str("keep-undisputed-only"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policies() {
+        assert_eq!("keep-all".parse(), Ok(Retention::KeepAll));
+        assert_eq!("keep-last:3".parse(), Ok(Retention::KeepLast(3)));
+        assert_eq!("keep-within:2d".parse(), Ok(Retention::KeepWithin(172_800)));
+        assert_eq!("keep-undisputed-only".parse(), Ok(Retention::KeepUndisputedOnly));
+        assert!("keep-last".parse::<Retention>().is_err());
+        assert!("keep-some".parse::<Retention>().is_err());
+    }
+}
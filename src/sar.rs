@@ -0,0 +1,300 @@
+use std::{fmt, io, str::FromStr};
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+use crate::{collections::HashMap, error::Error, ClientId, Transaction, TxType};
+
+/// Thresholds for the suspicious-activity rules. Each rule flags a client
+/// at most once per run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SarRules {
+    /// A withdrawal within this many of the client's transactions after a deposit closes a cycle...
+    pub cycle_window: u64,
+    /// ...if it takes out at least this share of the deposit.
+    pub cycle_ratio: Decimal,
+    /// Cycles before the client is flagged.
+    pub cycle_count: u32,
+    /// Disputes per deposit above which a client is flagged...
+    pub max_dispute_rate: Decimal,
+    /// ...once it has made at least this many deposits.
+    pub min_deposits: u64,
+    /// Reporting threshold that structured deposits stay just under.
+    pub structuring_limit: Decimal,
+    /// How far under the limit, as a share of it, still counts as "just under".
+    pub structuring_margin: Decimal,
+    /// Just-under-limit deposits before the client is flagged.
+    pub structuring_count: u32,
+}
+
+impl Default for SarRules {
+    fn default() -> Self {
+        SarRules {
+            cycle_window: 2,
+            cycle_ratio: Decimal::new(9, 1),
+            cycle_count: 3,
+            max_dispute_rate: Decimal::new(2, 1),
+            min_deposits: 5,
+            structuring_limit: Decimal::new(10_000, 0),
+            structuring_margin: Decimal::new(1, 1),
+            structuring_count: 3,
+        }
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}' for SAR rule {}", value, key))
+}
+
+impl SarRules {
+    /// Sets one threshold by name, e.g. `cycle_count` to `5`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "cycle_window" => self.cycle_window = parse(key, value)?,
+            "cycle_ratio" => self.cycle_ratio = parse(key, value)?,
+            "cycle_count" => self.cycle_count = parse(key, value)?,
+            "max_dispute_rate" => self.max_dispute_rate = parse(key, value)?,
+            "min_deposits" => self.min_deposits = parse(key, value)?,
+            "structuring_limit" => self.structuring_limit = parse(key, value)?,
+            "structuring_margin" => self.structuring_margin = parse(key, value)?,
+            "structuring_count" => self.structuring_count = parse(key, value)?,
+            other => return Err(format!("unknown SAR rule '{}'", other)),
+        }
+        Ok(())
+    }
+
+    /// Applies comma-separated `key=value` overrides.
+    pub fn set_all(&mut self, pairs: &str) -> Result<(), String> {
+        for pair in pairs.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected key=value, got '{}'", pair))?;
+            self.set(key.trim(), value.trim())?;
+        }
+        Ok(())
+    }
+}
+
+/// Comma-separated `key=value` overrides on top of the defaults.
+impl FromStr for SarRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = SarRules::default();
+        rules.set_all(s)?;
+        Ok(rules)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SarRule {
+    RapidCycle,
+    DisputeRate,
+    Structuring,
+}
+
+impl SarRule {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for SarRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SarRule::RapidCycle => "rapid-cycle",
+            SarRule::DisputeRate => "dispute-rate",
+            SarRule::Structuring => "structuring",
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SarRow {
+    client: ClientId,
+    rule: SarRule,
+    line: u64,
+    detail: String,
+}
+
+/// What the rules need to remember about one client.
+#[derive(Debug, Default)]
+struct Activity {
+    transactions: u64,
+    /// Transaction count and amount of the latest deposit.
+    last_deposit: Option<(u64, Decimal)>,
+    cycles: u32,
+    deposits: u64,
+    disputes: u64,
+    last_dispute_line: u64,
+    near_limit: u32,
+    flagged: u8,
+}
+
+/// Watches applied transactions and writes a SAR-style CSV row whenever a
+/// client trips a rule. Rules that need the whole run (dispute rate) are
+/// evaluated by [`SarMonitor::finish`].
+pub struct SarMonitor {
+    rules: SarRules,
+    writer: csv::Writer<Box<dyn io::Write>>,
+    activity: HashMap<ClientId, Activity>,
+    flagged: u64,
+}
+
+impl SarMonitor {
+    pub fn new(output: Box<dyn io::Write>, rules: SarRules) -> Self {
+        SarMonitor { rules, writer: csv::Writer::from_writer(output), activity: HashMap::default(), flagged: 0 }
+    }
+
+    /// Feeds one applied transaction, read from input line `line`.
+    pub fn observe(&mut self, record: &Transaction, line: u64) -> Result<(), Error> {
+        let rules = &self.rules;
+        let activity = self.activity.entry(record.client).or_default();
+        activity.transactions += 1;
+        let mut tripped = None;
+
+        match (record.tx_type, record.amount) {
+            (TxType::Deposit, Some(amount)) => {
+                activity.deposits += 1;
+                activity.last_deposit = Some((activity.transactions, amount));
+                let floor = rules.structuring_limit - rules.structuring_limit * rules.structuring_margin;
+                if amount >= floor && amount < rules.structuring_limit {
+                    activity.near_limit += 1;
+                    if activity.near_limit >= rules.structuring_count {
+                        tripped = Some((SarRule::Structuring, format!(
+                            "{} deposits between {} and {}", activity.near_limit, floor, rules.structuring_limit
+                        )));
+                    }
+                }
+            }
+            (TxType::Withdrawal, Some(amount)) => {
+                if let Some((at, deposited)) = activity.last_deposit {
+                    if activity.transactions - at <= rules.cycle_window && amount >= deposited * rules.cycle_ratio {
+                        activity.cycles += 1;
+                        activity.last_deposit = None;
+                        if activity.cycles >= rules.cycle_count {
+                            tripped = Some((SarRule::RapidCycle, format!(
+                                "{} deposits withdrawn within {} transactions", activity.cycles, rules.cycle_window
+                            )));
+                        }
+                    }
+                }
+            }
+            (TxType::Dispute, _) => {
+                activity.disputes += 1;
+                activity.last_dispute_line = line;
+            }
+            _ => {}
+        }
+
+        if let Some((rule, detail)) = tripped.filter(|(rule, _)| activity.flagged & rule.bit() == 0) {
+            activity.flagged |= rule.bit();
+            self.flagged += 1;
+            self.writer.serialize(SarRow { client: record.client, rule, line, detail })?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates the whole-run rules and flushes the report.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        let mut clients: Vec<&ClientId> = self.activity.keys().collect();
+        clients.sort();
+        for client in clients {
+            let activity = &self.activity[client];
+            if activity.deposits < self.rules.min_deposits || activity.flagged & SarRule::DisputeRate.bit() != 0 {
+                continue;
+            }
+            let rate = Decimal::from(activity.disputes) / Decimal::from(activity.deposits);
+            if rate > self.rules.max_dispute_rate {
+                self.flagged += 1;
+                self.writer.serialize(SarRow {
+                    client: *client,
+                    rule: SarRule::DisputeRate,
+                    line: activity.last_dispute_line,
+                    detail: format!("{} disputes over {} deposits", activity.disputes, activity.deposits),
+                })?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Rows written so far.
+    pub fn flagged(&self) -> u64 {
+        self.flagged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+    use rust_decimal::dec;
+    use crate::TxId;
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn feed(rules: SarRules, rows: &[(TxType, u32, Option<Decimal>)]) -> String {
+        let out = Shared::default();
+        let mut monitor = SarMonitor::new(Box::new(out.clone()), rules);
+        for (index, (tx_type, client, amount)) in rows.iter().enumerate() {
+            let record = Transaction::new(*tx_type, ClientId(*client), TxId(index as u32 + 1), *amount);
+            monitor.observe(&record, index as u64 + 2).unwrap();
+        }
+        monitor.finish().unwrap();
+        let written = out.0.borrow().clone();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[test]
+    fn test_rapid_cycles_flagged_once() {
+        let rules: SarRules = "cycle_count=2".parse().unwrap();
+        let mut rows = Vec::new();
+        for _ in 0..3 {
+            rows.push((TxType::Deposit, 1, Some(dec!(100))));
+            rows.push((TxType::Withdrawal, 1, Some(dec!(95))));
+        }
+        assert_eq!(
+            feed(rules, &rows),
+            "client,rule,line,detail\n1,rapid-cycle,5,2 deposits withdrawn within 2 transactions\n"
+        );
+    }
+
+    #[test]
+    fn test_structuring_counts_just_under_limit_deposits() {
+        let rules: SarRules = "structuring_limit=1000,structuring_count=2".parse().unwrap();
+        let rows = [
+            (TxType::Deposit, 1, Some(dec!(950))),
+            (TxType::Deposit, 1, Some(dec!(1000))),
+            (TxType::Deposit, 1, Some(dec!(999.99))),
+        ];
+        assert!(feed(rules, &rows).ends_with("1,structuring,4,2 deposits between 900.0 and 1000\n"));
+    }
+
+    #[test]
+    fn test_dispute_rate_evaluated_at_finish() {
+        let rules: SarRules = "min_deposits=2,max_dispute_rate=0.4".parse().unwrap();
+        let rows = [
+            (TxType::Deposit, 1, Some(dec!(1))),
+            (TxType::Deposit, 1, Some(dec!(1))),
+            (TxType::Dispute, 1, None),
+            (TxType::Deposit, 2, Some(dec!(1))),
+        ];
+        assert!(feed(rules, &rows).ends_with("1,dispute-rate,4,1 disputes over 2 deposits\n"));
+    }
+
+    #[test]
+    fn test_unknown_rule_is_rejected() {
+        assert!("cycle_speed=3".parse::<SarRules>().is_err());
+        assert!("cycle_count=x".parse::<SarRules>().is_err());
+    }
+}
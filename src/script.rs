@@ -0,0 +1,295 @@
+//! A small expression language for acceptance rules, so a policy file can
+//! refuse transactions on conditions no fixed setting covers:
+//!
+//! ```text
+//! reject if amount > 10_000 && client_tier == "basic"
+//! reject if type == "withdrawal" && (region == "XX" || !kyc)
+//! ```
+//!
+//! Names resolve to the row's `type`, `client`, `tx`, `amount` and
+//! `timestamp`, or else to an extra input column of that name. A name with
+//! no value on the row (no amount on a dispute, a column the input lacks)
+//! makes every comparison it takes part in false.
+
+use std::{cmp::Ordering, fmt, str::FromStr};
+use rust_decimal::Decimal;
+
+use crate::Transaction;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Decimal),
+    Text(String),
+    Name(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+const OPERATORS: [&str; 10] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "="];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let taken = if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            1
+        } else if c == '"' {
+            let end = rest[1..].find('"').ok_or("unterminated string")?;
+            tokens.push(Token::Text(rest[1..=end].to_string()));
+            end + 2
+        } else if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_')).unwrap_or(rest.len());
+            let number = rest[..end].replace('_', "");
+            tokens.push(Token::Number(number.parse().map_err(|_| format!("invalid number '{}'", &rest[..end]))?));
+            end
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..end].to_string()));
+            end
+        } else {
+            let op = OPERATORS.iter().find(|op| rest.starts_with(**op)).ok_or_else(|| format!("unexpected '{}'", c))?;
+            if *op == "=" {
+                return Err("'=' is not an operator; use '==' to compare".to_string());
+            }
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[taken..].trim_start();
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(Decimal),
+    Text(String),
+    Bool(bool),
+    Name(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(&'static str, Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        let matched = matches!(self.peek(), Some(Token::Op(found)) if *found == op);
+        self.at += usize::from(matched);
+        matched
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.not()?;
+        while self.eat("&&") {
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        let left = self.primary()?;
+        for op in ["==", "!=", "<=", ">=", "<", ">"] {
+            if self.eat(op) {
+                return Ok(Expr::Compare(op, Box::new(left), Box::new(self.primary()?)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned().ok_or("expression ends early")?;
+        self.at += 1;
+        match token {
+            Token::Number(number) => Ok(Expr::Number(number)),
+            Token::Text(text) => Ok(Expr::Text(text)),
+            Token::Name(name) if name == "true" || name == "false" => Ok(Expr::Bool(name == "true")),
+            Token::Name(name) => Ok(Expr::Name(name)),
+            Token::Open => {
+                let inner = self.or()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.at += 1;
+                        Ok(inner)
+                    }
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Token::Close => Err("unexpected ')'".to_string()),
+            Token::Op(op) => Err(format!("unexpected '{}'", op)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Number(Decimal),
+    Text(String),
+    Bool(bool),
+    Missing,
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+
+    /// Orders two values of compatible kinds; text that reads as a number
+    /// compares numerically against a number.
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Number(b)) => a.parse::<Decimal>().ok().map(|a| a.cmp(b)),
+            (Value::Number(a), Value::Text(b)) => b.parse::<Decimal>().ok().map(|b| a.cmp(&b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+fn lookup(name: &str, transaction: &Transaction) -> Value {
+    match name {
+        "type" => Value::Text(format!("{:?}", transaction.tx_type).to_lowercase()),
+        "client" => Value::Number(transaction.client.0.into()),
+        "tx" => Value::Number(transaction.tx.0.into()),
+        "amount" => transaction.amount.map_or(Value::Missing, Value::Number),
+        "timestamp" => transaction.timestamp.map_or(Value::Missing, |at| Value::Number(at.into())),
+        _ => match transaction.metadata.get(name).map(String::as_str) {
+            None => Value::Missing,
+            Some("true") => Value::Bool(true),
+            Some("false") => Value::Bool(false),
+            Some(text) => Value::Text(text.to_string()),
+        },
+    }
+}
+
+impl Expr {
+    fn eval(&self, transaction: &Transaction) -> Value {
+        match self {
+            Expr::Number(number) => Value::Number(*number),
+            Expr::Text(text) => Value::Text(text.clone()),
+            Expr::Bool(value) => Value::Bool(*value),
+            Expr::Name(name) => lookup(name, transaction),
+            Expr::Not(inner) => Value::Bool(!inner.eval(transaction).truthy()),
+            Expr::And(left, right) => Value::Bool(left.eval(transaction).truthy() && right.eval(transaction).truthy()),
+            Expr::Or(left, right) => Value::Bool(left.eval(transaction).truthy() || right.eval(transaction).truthy()),
+            Expr::Compare(op, left, right) => {
+                let ordering = left.eval(transaction).compare(&right.eval(transaction));
+                Value::Bool(ordering.is_some_and(|ordering| match *op {
+                    "==" => ordering.is_eq(),
+                    "!=" => ordering.is_ne(),
+                    "<" => ordering.is_lt(),
+                    "<=" => ordering.is_le(),
+                    ">" => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                }))
+            }
+        }
+    }
+}
+
+/// One `reject if CONDITION` statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    source: String,
+    condition: Expr,
+}
+
+impl Rule {
+    pub fn rejects(&self, transaction: &Transaction) -> bool {
+        self.condition.eval(transaction).truthy()
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s.trim();
+        let condition = source.strip_prefix("reject if ")
+            .ok_or_else(|| format!("rule '{}' must start with 'reject if'", source))?;
+        let mut parser = Parser { tokens: tokenize(condition)?, at: 0 };
+        let condition = parser.or()?;
+        if parser.at < parser.tokens.len() {
+            return Err(format!("unexpected {:?} after the end of the condition", parser.tokens[parser.at]));
+        }
+        Ok(Rule { source: source.to_string(), condition })
+    }
+}
+
+/// Parses a script of one rule per line; blank lines and `#` comments are skipped.
+pub fn parse_rules(script: &str) -> Result<Vec<Rule>, String> {
+    script.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::{ClientId, TxId, TxType};
+
+    fn row(tx_type: TxType, amount: Option<Decimal>, metadata: &[(&str, &str)]) -> Transaction {
+        let mut transaction = Transaction::new(tx_type, ClientId(1), TxId(1), amount);
+        transaction.metadata = metadata.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        transaction
+    }
+
+    #[test]
+    fn test_rule_on_amount_and_column() {
+        let rule: Rule = r#"reject if amount > 10_000 && client_tier == "basic""#.parse().unwrap();
+        assert!(rule.rejects(&row(TxType::Deposit, Some(dec!(10000.01)), &[("client_tier", "basic")])));
+        assert!(!rule.rejects(&row(TxType::Deposit, Some(dec!(10000)), &[("client_tier", "basic")])));
+        assert!(!rule.rejects(&row(TxType::Deposit, Some(dec!(20000)), &[("client_tier", "gold")])));
+        assert!(!rule.rejects(&row(TxType::Deposit, Some(dec!(20000)), &[])));
+    }
+
+    #[test]
+    fn test_precedence_and_negation() {
+        let rule: Rule = r#"reject if type == "withdrawal" && (risk >= 7 || !kyc)"#.parse().unwrap();
+        assert!(rule.rejects(&row(TxType::Withdrawal, None, &[("risk", "8"), ("kyc", "true")])));
+        assert!(rule.rejects(&row(TxType::Withdrawal, None, &[("risk", "1"), ("kyc", "false")])));
+        assert!(!rule.rejects(&row(TxType::Withdrawal, None, &[("risk", "1"), ("kyc", "true")])));
+        assert!(!rule.rejects(&row(TxType::Deposit, None, &[("risk", "9")])));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("amount > 5".parse::<Rule>().unwrap_err().contains("reject if"));
+        assert!("reject if amount = 5".parse::<Rule>().unwrap_err().contains("=="));
+        assert!("reject if (amount > 5".parse::<Rule>().unwrap_err().contains("')'"));
+        assert!("reject if amount > 5 5".parse::<Rule>().is_err());
+        assert_eq!(parse_rules("# comment\n\nreject if amount > 1\nreject if tx == 2\n").unwrap().len(), 2);
+    }
+}
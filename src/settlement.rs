@@ -0,0 +1,147 @@
+//! Merchant settlement: customer withdrawals that name a merchant (the
+//! `merchant` column) are payments owed to it. At the end of the run each
+//! merchant is paid its takings net of chargebacks and fees.
+
+use std::{collections::BTreeMap, io};
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+use crate::{error::Error, Transaction, TxType};
+
+/// What a merchant is charged per payment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SettlementFees {
+    /// Share of each payment, e.g. `0.029`.
+    pub rate: Decimal,
+    /// Flat amount per payment.
+    pub fixed: Decimal,
+}
+
+impl SettlementFees {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let amount = value.parse::<Decimal>().ok().filter(|amount| !amount.is_sign_negative())
+            .ok_or_else(|| format!("invalid value '{}' for settlement {}", value, key))?;
+        match key {
+            "fee_rate" => self.rate = amount,
+            "fixed_fee" => self.fixed = amount,
+            other => return Err(format!("unknown settlement option '{}'", other)),
+        }
+        Ok(())
+    }
+
+    fn on(&self, amount: Decimal) -> Decimal {
+        amount * self.rate + self.fixed
+    }
+}
+
+/// One merchant's totals for the run.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Payable {
+    pub payments: u64,
+    pub gross: Decimal,
+    pub chargebacks: Decimal,
+    pub fees: Decimal,
+}
+
+impl Payable {
+    /// What the merchant is paid; negative when chargebacks and fees exceed takings.
+    pub fn payout(&self) -> Decimal {
+        self.gross - self.chargebacks - self.fees
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SettlementRow<'a> {
+    merchant: &'a str,
+    payments: u64,
+    gross: Decimal,
+    chargebacks: Decimal,
+    fees: Decimal,
+    payout: Decimal,
+}
+
+/// Accumulates merchant payables from applied rows and writes the
+/// settlement report, one row per merchant, when the run ends.
+pub struct SettlementLedger {
+    fees: SettlementFees,
+    writer: csv::Writer<Box<dyn io::Write>>,
+    merchants: BTreeMap<String, Payable>,
+}
+
+impl SettlementLedger {
+    pub fn new(output: Box<dyn io::Write>, fees: SettlementFees) -> Self {
+        SettlementLedger { fees, writer: csv::Writer::from_writer(output), merchants: BTreeMap::new() }
+    }
+
+    /// Feeds one applied row. `charged_back` is the amount of the deposit a
+    /// chargeback reversed, looked up before the engine could forget it; a
+    /// chargeback counts against the merchant its row names.
+    pub fn observe(&mut self, record: &Transaction, charged_back: Option<Decimal>) {
+        let Some(merchant) = record.metadata.get("merchant").filter(|merchant| !merchant.is_empty()) else { return };
+        match (record.tx_type, record.amount, charged_back) {
+            (TxType::Withdrawal, Some(amount), _) => {
+                let payable = self.merchants.entry(merchant.clone()).or_default();
+                payable.payments += 1;
+                payable.gross += amount;
+                payable.fees += self.fees.on(amount);
+            }
+            (TxType::Chargeback, _, Some(amount)) => {
+                self.merchants.entry(merchant.clone()).or_default().chargebacks += amount;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn payable(&self, merchant: &str) -> Option<&Payable> {
+        self.merchants.get(merchant)
+    }
+
+    pub fn merchants(&self) -> usize {
+        self.merchants.len()
+    }
+
+    /// Writes every merchant's settlement, by merchant id, and flushes.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        for (merchant, payable) in &self.merchants {
+            self.writer.serialize(SettlementRow {
+                merchant,
+                payments: payable.payments,
+                gross: payable.gross,
+                chargebacks: payable.chargebacks,
+                fees: payable.fees,
+                payout: payable.payout(),
+            })?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::{ClientId, TxId};
+
+    fn row(tx_type: TxType, amount: Option<Decimal>, merchant: &str) -> Transaction {
+        let mut record = Transaction::new(tx_type, ClientId(1), TxId(1), amount);
+        record.metadata.insert("merchant".to_string(), merchant.to_string());
+        record
+    }
+
+    #[test]
+    fn test_payout_is_net_of_chargebacks_and_fees() {
+        let fees = SettlementFees { rate: dec!(0.02), fixed: dec!(0.5) };
+        let mut ledger = SettlementLedger::new(Box::new(io::sink()), fees);
+        ledger.observe(&row(TxType::Withdrawal, Some(dec!(100)), "acme"), None);
+        ledger.observe(&row(TxType::Withdrawal, Some(dec!(50)), "acme"), None);
+        ledger.observe(&row(TxType::Chargeback, None, "acme"), Some(dec!(20)));
+        ledger.observe(&row(TxType::Deposit, Some(dec!(70)), "acme"), None);
+        ledger.observe(&row(TxType::Withdrawal, Some(dec!(5)), ""), None);
+
+        let acme = ledger.payable("acme").unwrap();
+        assert_eq!((acme.payments, acme.gross, acme.fees), (2, dec!(150), dec!(4)));
+        assert_eq!(acme.payout(), dec!(126));
+        assert_eq!(ledger.merchants(), 1);
+    }
+}
@@ -0,0 +1,62 @@
+//! Signal handling for long runs. SIGTERM and SIGINT ask for a graceful
+//! shutdown: the run and watch loops check for it between rows or files, so
+//! whatever is in flight finishes, state is checkpointed and sinks are
+//! closed before the process exits, and a redeploy loses nothing. SIGHUP
+//! asks a watch loop to reload its policy. The handlers only raise flags.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    let flag = if signal == libc::SIGHUP { &RELOAD } else { &SHUTDOWN };
+    flag.store(true, Ordering::SeqCst);
+}
+
+/// Routes SIGTERM, SIGINT and SIGHUP to their flags instead of killing the
+/// process outright.
+#[cfg(unix)]
+pub fn install() -> std::io::Result<()> {
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        // SAFETY: the handler only stores to atomics, which is async-signal-safe.
+        let previous = unsafe { libc::signal(signal, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Signals keep their default behaviour where there is no handler to install.
+#[cfg(not(unix))]
+pub fn install() -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Whether a termination signal has arrived.
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Whether a reload was asked for since the last call.
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signals_raise_flags() {
+        install().unwrap();
+        assert!(!shutdown_requested() && !take_reload());
+        // SAFETY: raising signals whose handler was just installed.
+        assert_eq!(unsafe { libc::raise(libc::SIGHUP) }, 0);
+        assert!(take_reload() && !take_reload());
+        assert_eq!(unsafe { libc::raise(libc::SIGTERM) }, 0);
+        assert!(shutdown_requested());
+    }
+}
@@ -0,0 +1,125 @@
+//! A slab: values in one growable vec, addressed by slot index, with freed
+//! slots chained into a free list and handed out again before the vec
+//! grows. Tens of millions of records then cost a handful of large
+//! allocations instead of one each, and churn reuses memory in place
+//! instead of fragmenting the heap.
+
+#[derive(Debug, Clone)]
+enum Slot<T> {
+    Occupied(T),
+    /// Free, with the next free slot.
+    Vacant(Option<usize>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    /// The most recently freed slot.
+    free: Option<usize>,
+    len: usize,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Slab { slots: Vec::new(), free: None, len: 0 }
+    }
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores `value` and returns its slot, reusing a freed one if any.
+    pub fn insert(&mut self, value: T) -> usize {
+        self.len += 1;
+        match self.free {
+            Some(key) => {
+                let Slot::Vacant(next) = self.slots[key] else { unreachable!("free list points at an occupied slot") };
+                self.free = next;
+                self.slots[key] = Slot::Occupied(value);
+                key
+            }
+            None => {
+                self.slots.push(Slot::Occupied(value));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    pub fn get(&self, key: usize) -> Option<&T> {
+        match self.slots.get(key) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut T> {
+        match self.slots.get_mut(key) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Frees the slot for reuse and returns what it held.
+    pub fn remove(&mut self, key: usize) -> Option<T> {
+        let slot = self.slots.get_mut(key)?;
+        if let Slot::Vacant(_) = slot {
+            return None;
+        }
+        let Slot::Occupied(value) = std::mem::replace(slot, Slot::Vacant(self.free)) else { unreachable!() };
+        self.free = Some(key);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.slots.iter().filter_map(|slot| match slot {
+            Slot::Occupied(value) => Some(value),
+            Slot::Vacant(_) => None,
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        self.slots.iter_mut().filter_map(|slot| match slot {
+            Slot::Occupied(value) => Some(value),
+            Slot::Vacant(_) => None,
+        })
+    }
+
+    /// Moves every value out, keeping the slab's memory for reuse.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.free = None;
+        self.len = 0;
+        self.slots.drain(..).filter_map(|slot| match slot {
+            Slot::Occupied(value) => Some(value),
+            Slot::Vacant(_) => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freed_slots_are_reused() {
+        let mut slab = Slab::new();
+        let keys: Vec<usize> = ["a", "b", "c"].into_iter().map(|value| slab.insert(value)).collect();
+        assert_eq!(slab.remove(keys[1]), Some("b"));
+        assert_eq!(slab.remove(keys[1]), None);
+        assert_eq!(slab.insert("d"), keys[1]);
+        assert_eq!(slab.insert("e"), 3);
+        assert_eq!((slab.len(), slab.get(keys[1]), slab.get(9)), (4, Some(&"d"), None));
+        assert_eq!(slab.drain().collect::<Vec<_>>(), ["a", "d", "c", "e"]);
+        assert!(slab.is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+//! Just enough SMTP to hand a plain-text message to a relay: no TLS and no
+//! authentication, so point it at a local or internal relay that accepts
+//! mail from this host.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// A relay and the envelope to send through it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    /// `host:port` of the relay.
+    pub server: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Reads one (possibly multi-line) reply and fails unless its code is `expected`.
+fn expect(reader: &mut impl BufRead, expected: &str) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "relay closed the connection"));
+        }
+        if !line.starts_with(expected) {
+            return Err(io::Error::other(format!("relay replied '{}', expected {}", line.trim_end(), expected)));
+        }
+        // "250-..." continues a multi-line reply; "250 ..." ends it.
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+impl Mail {
+    pub fn send(&self, subject: &str, body: &str, timeout: Duration) -> io::Result<()> {
+        let address = self.server.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", self.server)))?;
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        expect(&mut reader, "220")?;
+        let mut command = |line: String, expected: &str| -> io::Result<()> {
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\r\n")?;
+            expect(&mut reader, expected)
+        };
+        command("EHLO txflow".to_string(), "250")?;
+        command(format!("MAIL FROM:<{}>", self.from), "250")?;
+        for to in &self.to {
+            command(format!("RCPT TO:<{}>", to), "25")?;
+        }
+        command("DATA".to_string(), "354")?;
+
+        let mut message = format!("From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n", self.from, self.to.join(", "), subject);
+        for line in body.lines() {
+            // Dot-stuffing: a leading '.' would otherwise be read as end of data.
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message.push('.');
+        command(message, "250")?;
+        command("QUIT".to_string(), "221")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn test_send_walks_the_dialogue() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let relay = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut transcript = String::new();
+            writer.write_all(b"220 relay\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let reply: &[u8] = match line.trim_end() {
+                    "." if in_data => { in_data = false; b"250 queued\r\n" }
+                    _ if in_data => continue,
+                    "EHLO txflow" => b"250-relay\r\n250 SIZE 1000\r\n",
+                    "DATA" => { in_data = true; b"354 go\r\n" }
+                    "QUIT" => b"221 bye\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                writer.write_all(reply).unwrap();
+            }
+            transcript
+        });
+
+        let mail = Mail { server, from: "txflow@ops".into(), to: vec!["risk@ops".into()] };
+        mail.send("spike", "3 chargebacks\n.hidden", Duration::from_secs(5)).unwrap();
+        let transcript = relay.join().unwrap();
+        assert!(transcript.contains("MAIL FROM:<txflow@ops>\r\nRCPT TO:<risk@ops>\r\nDATA\r\n"));
+        assert!(transcript.contains("Subject: spike\r\n\r\n3 chargebacks\r\n..hidden\r\n.\r\nQUIT\r\n"));
+    }
+}
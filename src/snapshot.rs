@@ -0,0 +1,134 @@
+//! Periodic account snapshots: every so many rows or seconds of a run, the
+//! open accounts are written to a timestamped CSV of their own, so a long
+//! run shows its progress and its partial results survive a crash.
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    collections,
+    error::Error,
+    report::{ReportSink, ReportWriter},
+    store::StateStore,
+    time, Engine,
+};
+
+/// How often to snapshot: a row count such as `1_000_000`, or a duration
+/// such as `30s` or `5m`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SnapshotEvery {
+    Rows(u64),
+    Interval(Duration),
+}
+
+impl FromStr for SnapshotEvery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid snapshot interval '{}' (expected a row count or a duration like 30s)", s);
+        if s.ends_with(|c: char| c.is_ascii_alphabetic()) {
+            collections::wall_clock("a snapshot interval in time")?;
+            let seconds = time::parse_duration(s).filter(|seconds| *seconds > 0).ok_or_else(invalid)?;
+            return Ok(SnapshotEvery::Interval(Duration::from_secs(seconds as u64)));
+        }
+        match s.replace('_', "").parse::<u64>() {
+            Ok(rows) if rows > 0 => Ok(SnapshotEvery::Rows(rows)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Writes snapshots into a directory as they come due.
+pub struct Snapshots {
+    dir: PathBuf,
+    every: SnapshotEvery,
+    last: Instant,
+    written: u64,
+}
+
+impl Snapshots {
+    pub fn new(dir: impl Into<PathBuf>, every: SnapshotEvery) -> Self {
+        Snapshots { dir: dir.into(), every, last: Instant::now(), written: 0 }
+    }
+
+    /// Whether a snapshot is due after `rows` rows.
+    pub fn due(&self, rows: u64) -> bool {
+        match self.every {
+            SnapshotEvery::Rows(every) => rows > 0 && rows.is_multiple_of(every),
+            SnapshotEvery::Interval(every) => self.last.elapsed() >= every,
+        }
+    }
+
+    /// Writes the engine's open accounts to `snapshot-<time>-<rows>.csv`
+    /// (`snapshot-<rows>.csv` in the deterministic build), atomically so a
+    /// crash never leaves half a snapshot, and returns its path.
+    pub fn write<S: StateStore>(&mut self, engine: &Engine<S>, rows: u64) -> Result<PathBuf, Error> {
+        let name = if cfg!(feature = "deterministic") {
+            format!("snapshot-{}.csv", rows)
+        } else {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64);
+            format!("snapshot-{}-{}.csv", time::format_timestamp(now).replace(':', ""), rows)
+        };
+        let path = self.dir.join(name);
+        write_accounts(engine, &path)?;
+        self.last = Instant::now();
+        self.written += 1;
+        Ok(path)
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+fn write_accounts<S: StateStore>(engine: &Engine<S>, path: &Path) -> Result<(), Error> {
+    let mut accounts: Vec<_> = engine.reports()?.collect();
+    accounts.sort_by_key(|account| account.client);
+    let tmp = path.with_extension("tmp");
+    let mut report = ReportWriter::new(BufWriter::new(File::create(&tmp)?));
+    for account in &accounts {
+        report.write(account)?;
+    }
+    report.into_inner()?.into_inner().map_err(|err| Error::Io(err.into_error()))?.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::{ClientId, Transaction, TxId, TxType};
+
+    #[test]
+    fn test_parse_rows_or_duration() {
+        assert_eq!("1_000_000".parse(), Ok(SnapshotEvery::Rows(1_000_000)));
+        if cfg!(feature = "deterministic") {
+            assert!("5m".parse::<SnapshotEvery>().is_err());
+        } else {
+            assert_eq!("5m".parse(), Ok(SnapshotEvery::Interval(Duration::from_secs(300))));
+        }
+        assert!("0".parse::<SnapshotEvery>().is_err());
+        assert!("soon".parse::<SnapshotEvery>().is_err());
+    }
+
+    #[test]
+    fn test_write_snapshot() {
+        let dir = std::env::temp_dir().join(format!("txflow-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut engine = Engine::new();
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(1), Some(dec!(2.5)))).unwrap();
+
+        let mut snapshots = Snapshots::new(&dir, SnapshotEvery::Rows(2));
+        assert!(!snapshots.due(1) && snapshots.due(2));
+        let path = snapshots.write(&engine, 2).unwrap();
+        assert!(path.file_name().unwrap().to_string_lossy().ends_with("-2.csv"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "client,available,held,locked\n1,2.5,0,false\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
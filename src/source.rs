@@ -0,0 +1,181 @@
+//! Where transactions come from. The processor pulls rows from a
+//! [`TxSource`], so anything that can produce [`Transaction`]s (a file, stdin,
+//! a socket, a message queue) can feed the engine.
+
+use std::io;
+
+use crate::{
+    error::{Error, ParseError},
+    parse::{self, AmountFormat, Columns},
+    Transaction,
+};
+
+/// A transaction with the input line it came from, for diagnostics.
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub transaction: Transaction,
+    pub line: u64,
+}
+
+/// A stream of transactions.
+pub trait TxSource {
+    /// The next row, or `Ok(None)` once the input is exhausted. An
+    /// [`Error::Parse`] covers a single bad row and reading may continue past
+    /// it; any other error ends the input.
+    fn next_row(&mut self) -> Result<Option<Row>, Error>;
+
+    /// The fields of the row last returned or rejected, as they appeared in
+    /// the input, for sources that keep them.
+    fn raw(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Where the source stands just past the row last returned, for
+    /// sources that can [`seek`](TxSource::seek) back there.
+    fn position(&self) -> Option<u64> {
+        None
+    }
+
+    /// Moves to a position [`position`](TxSource::position) gave earlier;
+    /// `Ok(false)` if the source can't, and must be read past instead.
+    fn seek(&mut self, _position: u64) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// Told once state covering every row before `position` is on disk.
+    fn commit(&mut self, _position: u64) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T: TxSource + ?Sized> TxSource for &mut T {
+    fn next_row(&mut self) -> Result<Option<Row>, Error> {
+        (**self).next_row()
+    }
+
+    fn raw(&self) -> Option<Vec<String>> {
+        (**self).raw()
+    }
+
+    fn position(&self) -> Option<u64> {
+        (**self).position()
+    }
+
+    fn seek(&mut self, position: u64) -> Result<bool, Error> {
+        (**self).seek(position)
+    }
+
+    fn commit(&mut self, position: u64) -> Result<(), Error> {
+        (**self).commit(position)
+    }
+}
+
+impl<T: TxSource + ?Sized> TxSource for Box<T> {
+    fn next_row(&mut self) -> Result<Option<Row>, Error> {
+        (**self).next_row()
+    }
+
+    fn raw(&self) -> Option<Vec<String>> {
+        (**self).raw()
+    }
+
+    fn position(&self) -> Option<u64> {
+        (**self).position()
+    }
+
+    fn seek(&mut self, position: u64) -> Result<bool, Error> {
+        (**self).seek(position)
+    }
+
+    fn commit(&mut self, position: u64) -> Result<(), Error> {
+        (**self).commit(position)
+    }
+}
+
+/// Rows of a txflow CSV from any reader (a file, stdin, a socket).
+pub struct CsvSource<R: io::Read> {
+    reader: csv::Reader<R>,
+    columns: Columns,
+    amounts: AmountFormat,
+    raw: csv::StringRecord,
+}
+
+impl<R: io::Read> CsvSource<R> {
+    /// Reads the header row; fails if a required column is missing.
+    pub fn new(input: R, amounts: &AmountFormat) -> Result<Self, Error> {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+        let columns = Columns::from_headers(reader.headers()?)?;
+        Ok(CsvSource { reader, columns, amounts: *amounts, raw: csv::StringRecord::new() })
+    }
+
+    pub fn columns(&self) -> &Columns {
+        &self.columns
+    }
+}
+
+impl<R: io::Read> TxSource for CsvSource<R> {
+    fn next_row(&mut self) -> Result<Option<Row>, Error> {
+        match self.reader.read_record(&mut self.raw) {
+            Ok(false) => Ok(None),
+            Ok(true) => {
+                let transaction = parse::parse_record(&self.raw, &self.columns, &self.amounts)?;
+                let line = self.raw.position().map_or(0, |position| position.line());
+                Ok(Some(Row { transaction, line }))
+            }
+            Err(err) if matches!(err.kind(), csv::ErrorKind::Io(_)) => Err(err.into()),
+            Err(err) => Err(ParseError::from_read(&err).into()),
+        }
+    }
+
+    fn raw(&self) -> Option<Vec<String>> {
+        Some(self.raw.iter().map(str::to_string).collect())
+    }
+}
+
+/// Transactions built in code, numbered from line 1.
+pub struct IterSource<I> {
+    transactions: I,
+    line: u64,
+}
+
+impl<I: Iterator<Item = Transaction>> IterSource<I> {
+    pub fn new(transactions: impl IntoIterator<IntoIter = I>) -> Self {
+        IterSource { transactions: transactions.into_iter(), line: 0 }
+    }
+}
+
+impl<I: Iterator<Item = Transaction>> TxSource for IterSource<I> {
+    fn next_row(&mut self) -> Result<Option<Row>, Error> {
+        Ok(self.transactions.next().map(|transaction| {
+            self.line += 1;
+            Row { transaction, line: self.line }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientId, TxId, TxType};
+
+    #[test]
+    fn test_csv_source_reports_lines_and_bad_rows() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nbogus,1,2,1.0\ndeposit,1,3,2.0\n";
+        let mut source = CsvSource::new(input.as_bytes(), &AmountFormat::default()).unwrap();
+
+        let row = source.next_row().unwrap().unwrap();
+        assert_eq!((row.transaction.tx, row.line), (TxId(1), 2));
+        assert!(matches!(source.next_row(), Err(Error::Parse(ParseError::InvalidField { line: 3, .. }))));
+        assert_eq!(source.next_row().unwrap().unwrap().line, 4);
+        assert!(source.next_row().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_iter_source_numbers_rows() {
+        let deposit = Transaction::new(TxType::Deposit, ClientId(1), TxId(1), None);
+        let mut source = IterSource::new(vec![deposit.clone(), deposit]);
+        assert_eq!(source.next_row().unwrap().unwrap().line, 1);
+        assert_eq!(source.next_row().unwrap().unwrap().line, 2);
+        assert!(source.next_row().unwrap().is_none());
+    }
+}
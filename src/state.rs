@@ -0,0 +1,426 @@
+use std::{fs::{self, File}, io::{BufReader, BufWriter, Write}, path::Path};
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+
+use crate::{account::{Deposit, DisputeState, Extra}, engine::Undo, error::Error, quota::Usage, store::StateStore, Account, ClientId, Engine, TxId};
+
+const VERSION: u32 = 1;
+
+/// On-disk form of an engine: every account including its dispute-able
+/// history, so a later run can pick up exactly where this one stopped.
+#[derive(Debug, Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    accounts: Vec<AccountState>,
+    sealed: Vec<ClientId>,
+    /// Ids claimed so far, when global uniqueness is enforced.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tx_ids: Vec<TxId>,
+    /// SHA-256 of every input file applied, when resubmissions are detected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    files: Vec<String>,
+    /// The undo journal, oldest row first, when one is kept.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    journal: Vec<JournalEntry>,
+    /// Funds held by disputes, finalized accounts included; older files
+    /// lack it and fall back to what open accounts hold.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    escrowed: Option<Decimal>,
+    /// Chargeback losses booked so far, fees included.
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    losses: Decimal,
+    /// Each client's rows in its current quota windows.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    quotas: Vec<QuotaUsage>,
+    /// Each client's last sequence key, while ordering is enforced.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    last_keys: Vec<LastKey>,
+    /// Where the run stood, when this is a checkpoint of one still going.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resume: Option<ResumePoint>,
+}
+
+/// How far a checkpointed run had got: input rows consumed and report
+/// bytes written, both as of the state saved alongside.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumePoint {
+    pub rows: u64,
+    pub output_bytes: u64,
+    /// Where the source stood, for sources that can seek back to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    client: ClientId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wallet: Option<String>,
+    before: Option<AccountState>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    others: Vec<AccountState>,
+    #[serde(default)]
+    sealed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    claimed: Option<TxId>,
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    lost: Decimal,
+    /// Whether the row moved the client's sequence key, and from what.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    moved_key: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_key: Option<i64>,
+    /// The client a transfer credited, and its main account before the row.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    counterpart: Option<ClientId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    counterpart_before: Option<AccountState>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LastKey {
+    client: ClientId,
+    key: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct QuotaUsage {
+    client: ClientId,
+    #[serde(flatten)]
+    usage: Usage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountState {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wallet: Option<String>,
+    history: Vec<HistoryEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pruned: Vec<TxId>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HistoryEntry {
+    tx: TxId,
+    amount: Decimal,
+    disputed: bool,
+    /// The dispute stage, when `disputed` alone doesn't tell it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<DisputeState>,
+    #[serde(default)]
+    seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    case: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    opened: Option<i64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    withdrawal: bool,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    cycles: u32,
+}
+
+fn is_zero(cycles: &u32) -> bool {
+    *cycles == 0
+}
+
+impl From<&Account> for AccountState {
+    fn from(account: &Account) -> Self {
+        let mut history: Vec<_> = account.history.iter()
+            .map(|(tx, deposit)| HistoryEntry {
+                tx,
+                amount: deposit.amount,
+                disputed: deposit.state.is_open(),
+                state: (!matches!(deposit.state, DisputeState::Undisputed | DisputeState::Opened)).then_some(deposit.state),
+                seq: deposit.seq,
+                timestamp: deposit.timestamp,
+                case: account.case(tx).map(str::to_string),
+                opened: deposit.opened,
+                withdrawal: deposit.withdrawal,
+                cycles: deposit.cycles,
+            })
+            .collect();
+        history.sort_by_key(|entry| entry.tx);
+        let mut pruned: Vec<TxId> = account.pruned().collect();
+        pruned.sort();
+        AccountState {
+            client: account.client,
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+            wallet: account.wallet.clone(),
+            history,
+            pruned,
+        }
+    }
+}
+
+impl From<AccountState> for Account {
+    fn from(state: AccountState) -> Self {
+        let next_seq = state.history.iter().map(|entry| entry.seq + 1).max().unwrap_or(0);
+        let extra = Extra {
+            pruned: state.pruned.into_iter().collect(),
+            cases: state.history.iter()
+                .filter_map(|entry| entry.case.clone().map(|case| (entry.tx, case)))
+                .collect(),
+        };
+        Account {
+            client: state.client,
+            available: state.available,
+            held: state.held,
+            locked: state.locked,
+            wallet: state.wallet,
+            history: state.history.into_iter()
+                .map(|entry| (entry.tx, Deposit {
+                    amount: entry.amount,
+                    state: entry.state.unwrap_or(if entry.disputed { DisputeState::Opened } else { DisputeState::Undisputed }),
+                    seq: entry.seq,
+                    timestamp: entry.timestamp,
+                    opened: entry.opened,
+                    withdrawal: entry.withdrawal,
+                    cycles: entry.cycles,
+                }))
+                .collect(),
+            next_seq,
+            extra: (!extra.pruned.is_empty() || !extra.cases.is_empty()).then(|| Box::new(extra)),
+        }
+    }
+}
+
+/// Writes the engine's state to `path`, replacing it atomically so a crash
+/// mid-write never leaves a truncated state file behind.
+pub fn save<S: StateStore>(engine: &Engine<S>, path: &Path) -> Result<(), Error> {
+    write(engine, path, None)
+}
+
+/// Like [`save`], recording how far the run that owns `engine` has got.
+pub fn save_checkpoint<S: StateStore>(engine: &Engine<S>, path: &Path, point: ResumePoint) -> Result<(), Error> {
+    write(engine, path, Some(point))
+}
+
+fn write<S: StateStore>(engine: &Engine<S>, path: &Path, resume: Option<ResumePoint>) -> Result<(), Error> {
+    let mut accounts: Vec<AccountState> = engine.accounts().map(|account| account.map(|account| AccountState::from(&*account))).collect::<Result<_, _>>()?;
+    accounts.sort_by(|a, b| (a.client, &a.wallet).cmp(&(b.client, &b.wallet)));
+    let mut sealed: Vec<ClientId> = engine.sealed.iter().copied().collect();
+    sealed.sort();
+    let mut tx_ids: Vec<TxId> = engine.seen_tx.iter().copied().collect();
+    tx_ids.sort();
+    let journal = engine.journal.iter()
+        .map(|undo| JournalEntry {
+            client: undo.client,
+            wallet: undo.wallet.clone(),
+            before: undo.before.as_ref().map(AccountState::from),
+            others: undo.others.iter().map(AccountState::from).collect(),
+            sealed: undo.sealed,
+            claimed: undo.claimed,
+            lost: undo.lost,
+            moved_key: undo.last_key.is_some(),
+            last_key: undo.last_key.flatten(),
+            counterpart: undo.counterpart.as_ref().map(|(to, _)| *to),
+            counterpart_before: undo.counterpart.as_ref().and_then(|(_, before)| before.as_ref()).map(AccountState::from),
+        })
+        .collect();
+    let mut quotas: Vec<QuotaUsage> = engine.quota_usage.iter().map(|(client, usage)| QuotaUsage { client: *client, usage: *usage }).collect();
+    quotas.sort_by_key(|entry| entry.client);
+    let mut last_keys: Vec<LastKey> = engine.last_keys.iter().map(|(client, key)| LastKey { client: *client, key: *key }).collect();
+    last_keys.sort_by_key(|entry| entry.client);
+
+    let tmp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, &StateFile { version: VERSION, accounts, sealed, tx_ids, files: engine.applied_files.iter().cloned().collect(), journal, escrowed: Some(engine.escrowed), losses: engine.losses, quotas, last_keys, resume })?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Reads a state file written by [`save`].
+pub fn load(path: &Path) -> Result<Engine, Error> {
+    Ok(read(path)?.0)
+}
+
+/// Reads a checkpoint written by [`save_checkpoint`]; `None` for a plain state file.
+pub fn load_checkpoint(path: &Path) -> Result<(Engine, Option<ResumePoint>), Error> {
+    read(path)
+}
+
+fn read(path: &Path) -> Result<(Engine, Option<ResumePoint>), Error> {
+    let state: StateFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let mut engine = Engine::new();
+    for account in state.accounts {
+        engine.store.put(Account::from(account))?;
+    }
+    engine.sealed = state.sealed.into_iter().collect();
+    engine.seen_tx = state.tx_ids.into_iter().collect();
+    engine.applied_files = state.files.into_iter().collect();
+    engine.losses = state.losses;
+    engine.quota_usage = state.quotas.into_iter().map(|entry| (entry.client, entry.usage)).collect();
+    engine.last_keys = state.last_keys.into_iter().map(|entry| (entry.client, entry.key)).collect();
+    engine.escrowed = match state.escrowed {
+        Some(escrowed) => escrowed,
+        None => engine.accounts().map(|account| account.map(|account| account.held)).sum::<Result<_, Error>>()?,
+    };
+    let depth = state.journal.len();
+    engine.journal = state.journal.into_iter()
+        .map(|entry| Undo {
+            client: entry.client,
+            wallet: entry.wallet,
+            before: entry.before.map(Account::from),
+            others: entry.others.into_iter().map(Account::from).collect(),
+            sealed: entry.sealed,
+            claimed: entry.claimed,
+            lost: entry.lost,
+            last_key: entry.moved_key.then_some(entry.last_key),
+            counterpart: entry.counterpart.map(|to| (to, entry.counterpart_before.map(Account::from))),
+        })
+        .collect();
+    engine.set_undo_depth(depth);
+    engine.recount_history()?;
+    Ok((engine, state.resume))
+}
+
+/// Loads `path` if it exists, otherwise starts from an empty engine.
+pub fn load_or_default(path: &Path) -> Result<Engine, Error> {
+    if path.exists() { load(path) } else { Ok(Engine::new()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Reject, Transaction, TxType};
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_round_trip_keeps_history_disputable() {
+        let mut engine = Engine::new();
+        let deposit = Transaction::new(TxType::Deposit, ClientId(1), TxId(1), Some(dec!(5)));
+        engine.apply(&deposit).unwrap();
+
+        let path = std::env::temp_dir().join(format!("txflow-state-{}.json", std::process::id()));
+        save(&engine, &path).unwrap();
+        let mut restored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let dispute = Transaction { tx_type: TxType::Dispute, amount: None, ..deposit };
+        restored.apply(&dispute).unwrap();
+        assert_eq!(restored.account(ClientId(1)).unwrap().unwrap().held, dec!(5));
+        assert_eq!(restored.apply(&dispute), Err(Reject::AlreadyDisputed));
+    }
+
+    #[test]
+    fn test_round_trip_keeps_dispute_stage_and_case() {
+        let mut engine = Engine::new();
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(1), Some(dec!(5)))).unwrap();
+        let mut dispute = Transaction::new(TxType::Dispute, ClientId(1), TxId(1), None);
+        dispute.metadata.insert("case".to_string(), "CB-17".to_string());
+        engine.apply(&dispute).unwrap();
+        engine.apply(&Transaction::new(TxType::Review, ClientId(1), TxId(1), None)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("txflow-state-case-{}.json", std::process::id()));
+        save(&engine, &path).unwrap();
+        let restored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let account = restored.account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.case(TxId(1)), Some("CB-17"));
+        assert_eq!(account.history.get(TxId(1)).unwrap().state, DisputeState::UnderReview);
+        assert_eq!(restored.fingerprint().unwrap(), engine.fingerprint().unwrap());
+    }
+
+    #[test]
+    fn test_round_trip_keeps_pruned_ids() {
+        let mut engine = Engine::with_retention(crate::retention::Retention::KeepLast(1));
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(1), Some(dec!(5)))).unwrap();
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(2), Some(dec!(5)))).unwrap();
+
+        let path = std::env::temp_dir().join(format!("txflow-state-pruned-{}.json", std::process::id()));
+        save(&engine, &path).unwrap();
+        let mut restored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.fingerprint().unwrap(), engine.fingerprint().unwrap());
+        assert_eq!(restored.apply(&Transaction::new(TxType::Dispute, ClientId(1), TxId(1), None)), Err(Reject::TxPruned));
+    }
+
+    #[test]
+    fn test_round_trip_keeps_undo_journal() {
+        let mut engine = Engine::new();
+        engine.set_undo_depth(10);
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(1), Some(dec!(5)))).unwrap();
+        let fingerprint = engine.fingerprint().unwrap();
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(2), Some(dec!(7)))).unwrap();
+        engine.apply(&Transaction::new(TxType::Finalize, ClientId(1), TxId(0), None)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("txflow-state-journal-{}.json", std::process::id()));
+        save(&engine, &path).unwrap();
+        let mut restored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.revertible(), 3);
+        assert_eq!(restored.revert_last(2), 2);
+        assert_eq!(restored.fingerprint().unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_quota_usage() {
+        let policy: crate::policy::Policy = "[quotas]\nper_day = 1\n".parse().unwrap();
+        let mut engine = Engine::with_policy(&policy);
+        let at = |tx, seconds| Transaction { timestamp: Some(seconds), ..Transaction::new(TxType::Deposit, ClientId(1), TxId(tx), Some(dec!(5))) };
+        engine.apply(&at(1, 0)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("txflow-state-quotas-{}.json", std::process::id()));
+        save(&engine, &path).unwrap();
+        let mut restored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        restored.set_policy(&policy);
+        assert_eq!(restored.apply(&at(2, 3_600)), Err(Reject::QuotaExceeded));
+        restored.apply(&at(3, 86_400)).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_keeps_transfer_undo() {
+        let mut engine = Engine::new();
+        engine.set_undo_depth(3);
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(1), Some(dec!(5)))).unwrap();
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(2), TxId(2), Some(dec!(1)))).unwrap();
+        let fingerprint = engine.fingerprint().unwrap();
+        let mut transfer = Transaction::new(TxType::Transfer, ClientId(1), TxId(3), Some(dec!(2)));
+        transfer.metadata.insert("to".to_string(), "2".to_string());
+        engine.apply(&transfer).unwrap();
+
+        let path = std::env::temp_dir().join(format!("txflow-state-transfer-{}.json", std::process::id()));
+        save(&engine, &path).unwrap();
+        let mut restored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.revert_last(1), 1);
+        assert_eq!(restored.fingerprint().unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_sequence_keys() {
+        let mut policy = crate::policy::Policy::default();
+        policy.ordering.require = Some(crate::ordering::SequenceKey::TxId);
+        let mut engine = Engine::with_policy(&policy);
+        engine.set_undo_depth(2);
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(3), Some(dec!(5)))).unwrap();
+        engine.apply(&Transaction::new(TxType::Deposit, ClientId(1), TxId(5), Some(dec!(5)))).unwrap();
+
+        let path = std::env::temp_dir().join(format!("txflow-state-keys-{}.json", std::process::id()));
+        save(&engine, &path).unwrap();
+        let mut restored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        restored.set_policy(&policy);
+        let late = Transaction::new(TxType::Deposit, ClientId(1), TxId(4), Some(dec!(1)));
+        assert_eq!(restored.apply(&late), Err(Reject::OutOfOrder));
+        assert_eq!(restored.revert_last(2), 2, "the refused row and the deposit of 5");
+        restored.apply(&late).unwrap();
+    }
+}
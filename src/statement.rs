@@ -0,0 +1,164 @@
+//! Per-client statements: the client's applied rows over a period, each with
+//! the balance it left, between an opening and a closing balance. Like
+//! [`crate::query`], the input is replayed in full as the event log so every
+//! policy holds exactly as in the run that produced it.
+//!
+//! A row's time is its `timestamp`, or failing that the latest one seen
+//! before it; rows with no time at all count towards the opening balance,
+//! unless the period is open at the start, when they are listed too.
+//! Statements cover the client's main account.
+
+use std::{cell::RefCell, io, rc::Rc};
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::MAIN_WALLET,
+    error::Error,
+    logging::Logger,
+    observer::EngineObserver,
+    processor::{self, Options, Sinks},
+    time, Account, ClientId, Engine, Transaction, TxId, TxType,
+};
+
+/// An account's funds at a point in the statement.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Balance {
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+/// One applied row and the balance it left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementLine {
+    /// `None` while no row has carried a timestamp yet.
+    pub time: Option<i64>,
+    pub tx_type: TxType,
+    pub tx: TxId,
+    pub amount: Option<Decimal>,
+    pub balance: Balance,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub client: ClientId,
+    /// First and last second covered, inclusive; unbounded ends are `i64::MIN` / `i64::MAX`.
+    pub from: i64,
+    pub to: i64,
+    pub opening: Balance,
+    pub lines: Vec<StatementLine>,
+    pub closing: Balance,
+}
+
+/// Collects the client's applied rows with their times.
+struct Recorder {
+    client: ClientId,
+    clock: Option<i64>,
+    rows: Rc<RefCell<Vec<StatementLine>>>,
+}
+
+impl EngineObserver for Recorder {
+    fn on_applied(&mut self, transaction: &Transaction, account: &Account) {
+        self.clock = transaction.timestamp.or(self.clock);
+        if account.client != self.client || account.wallet.as_deref().is_some_and(|wallet| wallet != MAIN_WALLET) {
+            return;
+        }
+        self.rows.borrow_mut().push(StatementLine {
+            time: self.clock,
+            tx_type: transaction.tx_type,
+            tx: transaction.tx,
+            amount: transaction.amount,
+            balance: Balance { available: account.available, held: account.held },
+        });
+    }
+}
+
+/// Replays `input` under `options` and builds `client`'s statement for the
+/// seconds `from..=to`.
+pub fn build<R: io::Read>(input: R, client: ClientId, from: i64, to: i64, options: &Options, logger: &Logger) -> Result<Statement, Error> {
+    let rows = Rc::new(RefCell::new(Vec::new()));
+    let mut engine = Engine::with_policy(&options.policy);
+    engine.add_observer(Box::new(Recorder { client, clock: None, rows: rows.clone() }));
+    let source = processor::csv_source(input, options, logger)?;
+    processor::process(source, &mut engine, &mut Vec::new(), &mut Sinks::default(), options, logger)?;
+
+    let mut statement = Statement { client, from, to, opening: Balance::default(), lines: Vec::new(), closing: Balance::default() };
+    for line in rows.take() {
+        match line.time {
+            Some(time) if time > to => break,
+            Some(time) if time >= from => statement.lines.push(line),
+            None if from == i64::MIN => statement.lines.push(line),
+            _ => statement.opening = line.balance,
+        }
+    }
+    statement.closing = statement.lines.last().map_or(statement.opening, |line| line.balance);
+    Ok(statement)
+}
+
+impl Statement {
+    /// Writes the statement as a plain-text table.
+    pub fn write_text<W: io::Write>(&self, mut out: W) -> io::Result<()> {
+        let bound = |seconds: i64, open: &str| match seconds {
+            i64::MIN | i64::MAX => open.to_string(),
+            seconds => time::format_date(seconds),
+        };
+        writeln!(out, "Statement for client {}", self.client.0)?;
+        writeln!(out, "Period {} to {}", bound(self.from, "start"), bound(self.to, "end"))?;
+        writeln!(out)?;
+        writeln!(out, "{:<10}  {:<16}  {:>10}  {:>14}  {:>14}  {:>14}", "Date", "Type", "Tx", "Amount", "Available", "Held")?;
+        let balance = |out: &mut W, label: &str, balance: Balance| {
+            writeln!(out, "{:<10}  {:<16}  {:>10}  {:>14}  {:>14}  {:>14}", "", label, "", "", balance.available, balance.held)
+        };
+        balance(&mut out, "opening balance", self.opening)?;
+        for line in &self.lines {
+            writeln!(
+                out, "{:<10}  {:<16}  {:>10}  {:>14}  {:>14}  {:>14}",
+                line.time.map(time::format_date).unwrap_or_default(),
+                format!("{:?}", line.tx_type).to_lowercase(),
+                line.tx.0,
+                line.amount.map(|amount| amount.to_string()).unwrap_or_default(),
+                line.balance.available,
+                line.balance.held,
+            )?;
+        }
+        balance(&mut out, "closing balance", self.closing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_statement_for_a_period() {
+        let input = "type,client,tx,amount,timestamp\n\
+            deposit,42,1,100,2023-12-30\n\
+            deposit,7,2,5,2024-01-02\n\
+            deposit,42,3,50,2024-01-03\n\
+            dispute,42,1,,\n\
+            withdrawal,42,4,20,2024-01-31T18:00:00Z\n\
+            withdrawal,42,5,1,2024-02-01\n";
+        let day = |day: u32| crate::time::days_from_civil(2024, 1, day) * 86_400;
+        let statement = build(input.as_bytes(), ClientId(42), day(1), day(31) + 86_399, &Options::default(), &Logger::default()).unwrap();
+
+        assert_eq!(statement.opening, Balance { available: dec!(100), held: dec!(0) });
+        assert_eq!(statement.lines.iter().map(|line| line.tx.0).collect::<Vec<_>>(), [3, 1, 4]);
+        assert_eq!(statement.closing, Balance { available: dec!(30), held: dec!(100) });
+
+        let mut text = Vec::new();
+        statement.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("Statement for client 42\nPeriod 2024-01-01 to 2024-01-31\n"));
+        assert!(text.contains("2024-01-03  deposit                    3              50             150               0\n"));
+    }
+
+    #[test]
+    fn test_unbounded_statement_lists_untimed_rows() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\nwithdrawal,1,3,4\n";
+        let statement = build(input.as_bytes(), ClientId(1), i64::MIN, i64::MAX, &Options::default(), &Logger::default()).unwrap();
+
+        assert_eq!(statement.opening, Balance::default());
+        assert_eq!(statement.lines.iter().map(|line| line.tx.0).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(statement.closing, Balance { available: dec!(6), held: dec!(0) });
+    }
+}
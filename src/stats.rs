@@ -0,0 +1,187 @@
+use std::{fmt, mem, time::Duration};
+
+use crate::{history, Account, ClientId, TxId};
+
+const LINEAR: usize = 16;
+const SUB_BUCKETS: usize = 8;
+const BUCKETS: usize = LINEAR + (64 - 4) * SUB_BUCKETS;
+
+/// Fixed-size log-linear histogram of nanosecond latencies: exact below
+/// 16ns, then eight buckets per power of two (about 12% resolution), which
+/// is plenty for p50/p95/p99 while costing a few KiB regardless of volume.
+#[derive(Clone)]
+pub struct LatencyHistogram {
+    buckets: Box<[u64; BUCKETS]>,
+    count: u64,
+    max: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram { buckets: Box::new([0; BUCKETS]), count: 0, max: 0 }
+    }
+}
+
+impl fmt::Debug for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LatencyHistogram").field("count", &self.count).field("max", &self.max).finish()
+    }
+}
+
+fn bucket(nanos: u64) -> usize {
+    if nanos < LINEAR as u64 {
+        return nanos as usize;
+    }
+    let exp = 63 - nanos.leading_zeros() as usize;
+    let sub = (nanos >> (exp - 3)) as usize & (SUB_BUCKETS - 1);
+    LINEAR + (exp - 4) * SUB_BUCKETS + sub
+}
+
+/// Smallest value that lands in `index`.
+fn lower_bound(index: usize) -> u64 {
+    if index < LINEAR {
+        return index as u64;
+    }
+    let exp = (index - LINEAR) / SUB_BUCKETS + 4;
+    let sub = ((index - LINEAR) % SUB_BUCKETS) as u64;
+    (1u64 << exp) | (sub << (exp - 3))
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(nanos);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// Approximate latency below which `quantile` (0..=1) of samples fall.
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(lower_bound(index).min(self.max));
+            }
+        }
+        self.max()
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (mine, theirs) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *mine += theirs;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+            self.percentile(0.50), self.percentile(0.95), self.percentile(0.99), self.max()
+        )
+    }
+}
+
+/// Entry counts of the engine's in-memory state, from which a byte estimate
+/// is derived. The estimate models hash-table slots (entry plus one control
+/// byte at the 7/8 maximum load factor), slab slots and packed history
+/// entries, counts no spare vec capacity and ignores allocator overhead, so
+/// it tracks growth faithfully but reads somewhat below the process RSS.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub accounts: usize,
+    pub history_entries: usize,
+    pub sealed: usize,
+    /// Ids remembered for global uniqueness checks.
+    pub tx_ids: usize,
+    /// Bytes of the dispute lookup filter, if one is kept.
+    pub filter_bytes: usize,
+}
+
+fn table_bytes<T>(entries: usize) -> u64 {
+    (entries as u64 * (mem::size_of::<T>() as u64 + 1)) * 8 / 7
+}
+
+impl MemoryUsage {
+    pub fn bytes(&self) -> u64 {
+        table_bytes::<(ClientId, usize)>(self.accounts)
+            + (self.accounts * mem::size_of::<Account>()) as u64
+            + (self.history_entries * history::ENTRY_BYTES) as u64
+            + table_bytes::<ClientId>(self.sealed)
+            + table_bytes::<TxId>(self.tx_ids)
+            + self.filter_bytes as u64
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "~{:.1} MiB ({} accounts, {} history entries, {} finalized)",
+            self.bytes() as f64 / (1024.0 * 1024.0), self.accounts, self.history_entries, self.sealed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_bounds_are_monotonic() {
+        for nanos in [0, 1, 15, 16, 17, 100, 1_000, 123_456, u64::MAX / 2] {
+            let index = bucket(nanos);
+            assert!(lower_bound(index) <= nanos, "{} -> {}", nanos, index);
+            if index + 1 < BUCKETS {
+                assert!(lower_bound(index + 1) > nanos);
+            }
+        }
+    }
+
+    #[test]
+    fn test_percentiles_within_resolution() {
+        let mut histogram = LatencyHistogram::new();
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let p50 = histogram.percentile(0.5).as_nanos() as f64;
+        let p99 = histogram.percentile(0.99).as_nanos() as f64;
+        assert!((p50 - 50_000.0).abs() / 50_000.0 < 0.13, "p50 {}", p50);
+        assert!((p99 - 99_000.0).abs() / 99_000.0 < 0.13, "p99 {}", p99);
+        assert_eq!(histogram.max(), Duration::from_micros(100));
+    }
+
+    #[test]
+    fn test_merge_and_clear() {
+        let mut left = LatencyHistogram::new();
+        left.record(Duration::from_nanos(10));
+        let mut right = LatencyHistogram::new();
+        right.record(Duration::from_nanos(1_000));
+        left.merge(&right);
+        assert_eq!(left.count(), 2);
+        left.clear();
+        assert_eq!(left.percentile(0.5), Duration::ZERO);
+    }
+}
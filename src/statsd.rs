@@ -0,0 +1,180 @@
+//! StatsD metrics over UDP, for shops that run StatsD or a Datadog agent
+//! rather than Prometheus. The metrics are:
+//! - transactions by type and rejections by reason, as counters;
+//! - apply latency for a sample of rows, as timers;
+//! - the watch loop's backlog of waiting files, as a gauge.
+//!
+//! Counters build up in memory and go out about once a second, packed into
+//! as few datagrams as fit, so the row path only touches a map. Sends are
+//! fire and forget: a missing agent costs nothing and does not fail a run.
+//! When tags are configured the transaction type and reject reason are
+//! sent as DogStatsD tags as well. Without them, they become part of the
+//! metric name, which every StatsD server accepts.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    net::UdpSocket,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{error::Error, Reject, Transaction};
+
+/// Keeps datagrams within a typical path MTU.
+const DATAGRAM_BYTES: usize = 1_432;
+/// Rows between checks of whether counters are due to go out.
+const CHECK_EVERY: u64 = 1_024;
+const FLUSH_EVERY: Duration = Duration::from_secs(1);
+/// One row in this many has its latency sent.
+const SAMPLE_EVERY: u64 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsdConfig {
+    /// The agent, as `host:port`.
+    pub addr: String,
+    pub prefix: String,
+    /// `key:value` tags sent with every metric.
+    pub tags: Vec<String>,
+}
+
+impl FromStr for StatsdConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(StatsdConfig { addr: s.to_string(), prefix: "txflow".to_string(), tags: Vec::new() })
+            }
+            _ => Err(format!("'{}' is not a StatsD address (expected HOST:PORT)", s)),
+        }
+    }
+}
+
+/// Turns a reason such as "insufficient funds" into `insufficient_funds`.
+fn slug(text: &str) -> String {
+    text.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+pub struct Statsd {
+    socket: UdpSocket,
+    config: StatsdConfig,
+    /// Counts since the last flush, by metric and dimension.
+    counts: BTreeMap<(&'static str, String), u64>,
+    /// Lines for timers and gauges, waiting for the next flush.
+    lines: Vec<String>,
+    rows: u64,
+    last: Instant,
+}
+
+impl Statsd {
+    pub fn open(config: StatsdConfig) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(&config.addr)
+            .map_err(|err| io::Error::new(err.kind(), format!("StatsD agent {}: {}", config.addr, err)))?;
+        Ok(Statsd { socket, config, counts: BTreeMap::new(), lines: Vec::new(), rows: 0, last: Instant::now() })
+    }
+
+    /// `name` with `dimension` in the name or as a tag, then the value and type.
+    fn line(&self, name: &str, dimension: Option<(&str, &str)>, value: &str) -> String {
+        let mut line = format!("{}.{}", self.config.prefix, name);
+        let mut tags = self.config.tags.clone();
+        match dimension {
+            Some((key, dimension)) if !tags.is_empty() => tags.push(format!("{}:{}", key, dimension)),
+            Some((_, dimension)) => line = format!("{}.{}", line, dimension),
+            None => {}
+        }
+        line.push(':');
+        line.push_str(value);
+        if !tags.is_empty() {
+            line = format!("{}|#{}", line, tags.join(","));
+        }
+        line
+    }
+
+    /// Counts an applied or rejected row, and its latency when measured and sampled.
+    pub fn observe(&mut self, record: &Transaction, result: Result<(), Reject>, latency: Option<Duration>) {
+        *self.counts.entry(("transactions", record.tx_type.name().to_string())).or_default() += 1;
+        if let Err(reject) = result {
+            *self.counts.entry(("rejected", slug(&reject.to_string()))).or_default() += 1;
+        }
+        self.rows += 1;
+        if let Some(latency) = latency.filter(|_| self.rows.is_multiple_of(SAMPLE_EVERY)) {
+            let value = format!("{:.3}|ms|@{}", latency.as_secs_f64() * 1_000.0, 1.0 / SAMPLE_EVERY as f64);
+            let line = self.line("apply.latency", None, &value);
+            self.lines.push(line);
+        }
+        if self.rows.is_multiple_of(CHECK_EVERY) && self.last.elapsed() >= FLUSH_EVERY {
+            self.flush();
+        }
+    }
+
+    pub fn gauge(&mut self, name: &str, value: u64) {
+        let line = self.line(name, None, &format!("{}|g", value));
+        self.lines.push(line);
+    }
+
+    /// Sends everything waiting. Errors are dropped: StatsD is best effort.
+    pub fn flush(&mut self) {
+        let counts = std::mem::take(&mut self.counts);
+        let mut lines: Vec<String> = counts.into_iter().map(|((name, dimension), count)| {
+            let key = if name == "rejected" { "reason" } else { "type" };
+            self.line(name, Some((key, &dimension)), &format!("{}|c", count))
+        }).collect();
+        lines.append(&mut self.lines);
+        let mut datagram = String::new();
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > DATAGRAM_BYTES {
+                let _ = self.socket.send(datagram.as_bytes());
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            let _ = self.socket.send(datagram.as_bytes());
+        }
+        self.last = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logging::Logger,
+        processor::{self, Options, Sinks},
+        Engine,
+    };
+
+    fn receive(agent: &UdpSocket) -> Vec<String> {
+        let mut buffer = [0; DATAGRAM_BYTES];
+        let read = agent.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..read]).lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_counts_by_type_and_reason() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = agent.local_addr().unwrap().to_string();
+        let input = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,50\ndeposit,2,3,5\n";
+
+        let mut sinks = Sinks { statsd: Some(Statsd::open(addr.parse().unwrap()).unwrap()), ..Default::default() };
+        processor::process_csv(input.as_bytes(), &mut Engine::new(), &mut Vec::new(), &mut sinks, &Options::default(), &Logger::default()).unwrap();
+        assert_eq!(receive(&agent), [
+            "txflow.rejected.insufficient_funds:1|c",
+            "txflow.transactions.deposit:2|c",
+            "txflow.transactions.withdrawal:1|c",
+        ]);
+
+        let config = StatsdConfig { prefix: "pay".to_string(), tags: vec!["env:prod".to_string()], ..addr.parse().unwrap() };
+        let mut statsd = Statsd::open(config).unwrap();
+        statsd.gauge("queue_depth", 3);
+        statsd.flush();
+        assert_eq!(receive(&agent), ["pay.queue_depth:3|g|#env:prod"]);
+        assert!("localhost".parse::<StatsdConfig>().is_err());
+    }
+}
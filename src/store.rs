@@ -0,0 +1,325 @@
+//! Where the engine keeps open accounts, one per client and wallet. Each
+//! account carries its own dispute history, so a backend that persists
+//! accounts persists the transactions recorded against them too.
+//!
+//! Accounts cross the trait by value: a backend holding them in memory can
+//! lend them out as [`Cow::Borrowed`], one reading them from disk or over
+//! the network hands over copies. Every method can fail; the engine refuses
+//! the row it was applying with [`Reject::StoreUnavailable`] and keeps the
+//! error for [`Engine::take_store_error`](crate::Engine::take_store_error).
+//!
+//! [`Reject::StoreUnavailable`]: crate::Reject::StoreUnavailable
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use rust_decimal::Decimal;
+
+use crate::{collections::HashMap, engine::MAIN_WALLET, error::Error, money::MoneyOps, slab::Slab, Account, ClientId};
+
+/// The wallet an account is stored under; untagged accounts are in main.
+pub fn wallet<A>(account: &Account<A>) -> &str {
+    account.wallet.as_deref().unwrap_or(MAIN_WALLET)
+}
+
+/// Storage for open accounts, keyed by client and [`wallet`]. The engine
+/// only ever reaches accounts through this trait, so another backend can be
+/// dropped in with [`Engine::with_store`](crate::Engine::with_store). A
+/// store keeps accounts in one amount type, `Decimal` unless it says otherwise.
+pub trait StateStore<A: MoneyOps = Decimal> {
+    fn get(&self, client: ClientId, wallet: &str) -> Result<Option<Cow<'_, Account<A>>>, Error>;
+
+    /// The account for the engine to change and [`put`](StateStore::put)
+    /// back. The default reads a copy; a backend that owns its accounts in
+    /// memory can move the account out instead.
+    fn take(&mut self, client: ClientId, wallet: &str) -> Result<Option<Account<A>>, Error> {
+        Ok(self.get(client, wallet)?.map(Cow::into_owned))
+    }
+
+    /// Inserts or replaces the account for `account.client` in its wallet.
+    fn put(&mut self, account: Account<A>) -> Result<(), Error>;
+
+    fn remove(&mut self, client: ClientId, wallet: &str) -> Result<Option<Account<A>>, Error>;
+
+    /// Removes the client's account in every wallet, main first. The default
+    /// looks through every stored account for them.
+    fn remove_client(&mut self, client: ClientId) -> Result<Vec<Account<A>>, Error> {
+        let wallets: Vec<String> = self.iter()
+            .filter(|account| account.as_ref().map_or(true, |account| account.client == client))
+            .map(|account| account.map(|account| self::wallet(&account).to_string()))
+            .collect::<Result<_, _>>()?;
+        let mut removed = Vec::new();
+        for wallet in wallets {
+            removed.extend(self.remove(client, &wallet)?);
+        }
+        removed.sort_by_key(|account| account.wallet.as_deref().is_some_and(|wallet| wallet != MAIN_WALLET));
+        Ok(removed)
+    }
+
+    /// Every stored account, in no particular order.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Cow<'_, Account<A>>, Error>> + '_>;
+
+    /// Runs `change` on every stored account and keeps the result. The
+    /// default copies each one out and puts it back.
+    fn update_all(&mut self, change: &mut dyn FnMut(&mut Account<A>)) -> Result<(), Error> {
+        let accounts: Vec<Account<A>> = self.iter().map(|account| account.map(Cow::into_owned)).collect::<Result<_, _>>()?;
+        for mut account in accounts {
+            change(&mut account);
+            self.put(account)?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize, Error>;
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    fn contains(&self, client: ClientId, wallet: &str) -> Result<bool, Error> {
+        Ok(self.get(client, wallet)?.is_some())
+    }
+}
+
+/// The default backend: every account in a slab, found through maps of
+/// client (and wallet) to slot. The maps stay small however large accounts
+/// grow, and slots freed by finalized accounts are reused by new ones.
+#[derive(Debug, Default)]
+pub struct MemoryStore<A = Decimal> {
+    /// Each client's main account.
+    slots: HashMap<ClientId, usize>,
+    /// Each client's accounts in other wallets, by wallet.
+    wallets: HashMap<ClientId, BTreeMap<String, usize>>,
+    accounts: Slab<Account<A>>,
+}
+
+impl<A: MoneyOps> MemoryStore<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves every account out, leaving the store empty.
+    pub fn drain(&mut self) -> impl Iterator<Item = Account<A>> + '_ {
+        self.slots.clear();
+        self.wallets.clear();
+        self.accounts.drain()
+    }
+
+    fn slot(&self, client: ClientId, wallet: &str) -> Option<usize> {
+        match wallet {
+            MAIN_WALLET => self.slots.get(&client).copied(),
+            wallet => self.wallets.get(&client)?.get(wallet).copied(),
+        }
+    }
+
+    /// Like [`StateStore::put`], which this store never fails.
+    pub fn insert(&mut self, account: Account<A>) {
+        let client = account.client;
+        match self.slot(client, self::wallet(&account)) {
+            Some(slot) => *self.accounts.get_mut(slot).expect("mapped slots are occupied") = account,
+            None => {
+                let wallet = self::wallet(&account).to_string();
+                let slot = self.accounts.insert(account);
+                match wallet.as_str() {
+                    MAIN_WALLET => { self.slots.insert(client, slot); }
+                    _ => { self.wallets.entry(client).or_default().insert(wallet, slot); }
+                }
+            }
+        }
+    }
+
+    fn evict(&mut self, client: ClientId, wallet: &str) -> Option<Account<A>> {
+        let slot = match wallet {
+            MAIN_WALLET => self.slots.remove(&client)?,
+            wallet => {
+                let wallets = self.wallets.get_mut(&client)?;
+                let slot = wallets.remove(wallet)?;
+                if wallets.is_empty() {
+                    self.wallets.remove(&client);
+                }
+                slot
+            }
+        };
+        self.accounts.remove(slot)
+    }
+}
+
+impl<A: MoneyOps> FromIterator<Account<A>> for MemoryStore<A> {
+    fn from_iter<I: IntoIterator<Item = Account<A>>>(accounts: I) -> Self {
+        let mut store = MemoryStore::new();
+        for account in accounts {
+            store.insert(account);
+        }
+        store
+    }
+}
+
+impl<A: MoneyOps> StateStore<A> for MemoryStore<A> {
+    fn get(&self, client: ClientId, wallet: &str) -> Result<Option<Cow<'_, Account<A>>>, Error> {
+        Ok(self.slot(client, wallet).and_then(|slot| self.accounts.get(slot)).map(Cow::Borrowed))
+    }
+
+    /// Moves the account out of its slot, which the next `put` reuses.
+    fn take(&mut self, client: ClientId, wallet: &str) -> Result<Option<Account<A>>, Error> {
+        Ok(self.evict(client, wallet))
+    }
+
+    fn put(&mut self, account: Account<A>) -> Result<(), Error> {
+        self.insert(account);
+        Ok(())
+    }
+
+    fn remove(&mut self, client: ClientId, wallet: &str) -> Result<Option<Account<A>>, Error> {
+        Ok(self.evict(client, wallet))
+    }
+
+    fn remove_client(&mut self, client: ClientId) -> Result<Vec<Account<A>>, Error> {
+        let slots = self.slots.remove(&client).into_iter()
+            .chain(self.wallets.remove(&client).into_iter().flat_map(BTreeMap::into_values));
+        Ok(slots.filter_map(|slot| self.accounts.remove(slot)).collect())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Cow<'_, Account<A>>, Error>> + '_> {
+        Box::new(self.accounts.iter().map(|account| Ok(Cow::Borrowed(account))))
+    }
+
+    fn update_all(&mut self, change: &mut dyn FnMut(&mut Account<A>)) -> Result<(), Error> {
+        self.accounts.iter_mut().for_each(change);
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize, Error> {
+        Ok(self.accounts.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::BTreeMap, iter};
+    use rust_decimal::dec;
+    use crate::{
+        logging::Logger,
+        processor::{self, Options, Sinks},
+        policy::Policy,
+        report::ReportWriter,
+        Engine, Reject, Transaction, TxId, TxType,
+    };
+
+    /// A second backend handing out copies, as one off the process would,
+    /// to check the engine only goes through the trait.
+    #[derive(Default)]
+    struct OrderedStore {
+        accounts: BTreeMap<(ClientId, String), Account>,
+        down: bool,
+    }
+
+    impl OrderedStore {
+        fn reach(&self) -> Result<(), Error> {
+            if self.down { Err(Error::Store("connection refused".to_string())) } else { Ok(()) }
+        }
+    }
+
+    impl StateStore for OrderedStore {
+        fn get(&self, client: ClientId, wallet: &str) -> Result<Option<Cow<'_, Account>>, Error> {
+            self.reach()?;
+            Ok(self.accounts.get(&(client, wallet.to_string())).cloned().map(Cow::Owned))
+        }
+
+        fn put(&mut self, account: Account) -> Result<(), Error> {
+            self.reach()?;
+            self.accounts.insert((account.client, super::wallet(&account).to_string()), account);
+            Ok(())
+        }
+
+        fn remove(&mut self, client: ClientId, wallet: &str) -> Result<Option<Account>, Error> {
+            self.reach()?;
+            Ok(self.accounts.remove(&(client, wallet.to_string())))
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = Result<Cow<'_, Account>, Error>> + '_> {
+            match self.reach() {
+                Ok(()) => Box::new(self.accounts.values().cloned().map(|account| Ok(Cow::Owned(account)))),
+                Err(err) => Box::new(iter::once(Err(err))),
+            }
+        }
+
+        fn len(&self) -> Result<usize, Error> {
+            self.reach()?;
+            Ok(self.accounts.len())
+        }
+    }
+
+    #[test]
+    fn test_custom_store_matches_memory_store() {
+        let rows = [
+            Transaction::new(TxType::Deposit, ClientId(2), TxId(1), Some(dec!(5))),
+            Transaction::new(TxType::Deposit, ClientId(1), TxId(2), Some(dec!(3))),
+            Transaction::new(TxType::Dispute, ClientId(2), TxId(1), None),
+            Transaction::new(TxType::Finalize, ClientId(1), TxId(3), None),
+        ];
+        let mut memory = Engine::new();
+        let mut ordered = Engine::with_store(OrderedStore::default());
+        for row in &rows {
+            assert_eq!(memory.apply(row), ordered.apply(row));
+        }
+        assert_eq!(memory.fingerprint().unwrap(), ordered.fingerprint().unwrap());
+        assert_eq!(ordered.len().unwrap(), 1);
+        assert_eq!(ordered.account(ClientId(2)).unwrap().unwrap().held, dec!(5));
+    }
+
+    #[test]
+    fn test_custom_store_keeps_wallets_apart() {
+        let in_wallet = |tx_type, tx, amount, wallet: &str| {
+            let mut record = Transaction::new(tx_type, ClientId(1), TxId(tx), amount);
+            record.metadata.insert("wallet".to_string(), wallet.to_string());
+            record
+        };
+        let rows = [
+            in_wallet(TxType::Deposit, 1, Some(dec!(5)), "main"),
+            in_wallet(TxType::Deposit, 2, Some(dec!(3)), "bonus"),
+            in_wallet(TxType::Withdrawal, 3, Some(dec!(4)), "bonus"),
+            in_wallet(TxType::Finalize, 4, None, "main"),
+        ];
+        let policy = Policy { wallets: true, ..Default::default() };
+        let mut memory = Engine::with_policy(&policy);
+        let mut ordered = Engine::with_store(OrderedStore::default());
+        ordered.set_policy(&policy);
+        for row in &rows[..3] {
+            assert_eq!(memory.apply(row), ordered.apply(row));
+        }
+        assert_eq!(memory.fingerprint().unwrap(), ordered.fingerprint().unwrap());
+        assert_eq!(ordered.len().unwrap(), 2);
+
+        memory.apply(&rows[3]).unwrap();
+        ordered.apply(&rows[3]).unwrap();
+        let wallets = |accounts: Vec<Account>| accounts.into_iter().map(|account| account.wallet.unwrap()).collect::<Vec<_>>();
+        assert_eq!(wallets(ordered.take_finalized()), ["main", "bonus"]);
+        assert_eq!(wallets(memory.take_finalized()), ["main", "bonus"]);
+        assert!(ordered.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_store_failure_refuses_rows_until_handed_over() {
+        let deposit = |tx, amount| Transaction::new(TxType::Deposit, ClientId(1), TxId(tx), Some(amount));
+        let mut engine = Engine::with_store(OrderedStore::default());
+        engine.apply(&deposit(1, dec!(5))).unwrap();
+        engine.store.down = true;
+        assert_eq!(engine.apply(&deposit(2, dec!(1))), Err(Reject::StoreUnavailable));
+        assert!(engine.fingerprint().is_err());
+
+        engine.store.down = false;
+        assert_eq!(engine.apply(&deposit(2, dec!(1))), Err(Reject::StoreUnavailable));
+        assert!(matches!(engine.take_store_error(), Some(Error::Store(_))));
+        engine.apply(&deposit(2, dec!(1))).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(6));
+    }
+
+    #[test]
+    fn test_run_stops_on_the_store_error() {
+        let store = OrderedStore { down: true, ..Default::default() };
+        let mut report = ReportWriter::new(Vec::new());
+        let input = "type,client,tx,amount\ndeposit,1,1,5\n";
+        let result = processor::process_csv(input.as_bytes(), &mut Engine::with_store(store), &mut report, &mut Sinks::default(), &Options::default(), &Logger::default());
+        assert!(matches!(result, Err(Error::Store(reason)) if reason == "connection refused"));
+    }
+}
@@ -0,0 +1,204 @@
+//! Streams a local transactions file to a remote txflow endpoint over HTTP.
+//! Rows go out in batches, each a complete CSV with the header repeated, so
+//! the receiver can treat every POST as a small file of its own. A batch
+//! that fails is retried with exponential backoff; one that still fails
+//! stops the upload, and the error says how many rows were already taken.
+//! With an API key, every POST carries it as a bearer token.
+
+use std::{fmt, fs, io::{self, Read}, path::Path, thread, time::Duration};
+
+use csv::{ByteRecord, ReaderBuilder, Writer};
+
+use crate::{
+    error::Error,
+    http::{self, Url},
+    logging::{Level, LogEvent, Logger},
+    webhook,
+};
+
+/// A bearer token for the receiving endpoint, kept out of debug output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    /// Reads the key from the first line of `path`, so it stays out of
+    /// process listings and shell history.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path)?.lines().next().map(str::trim) {
+            Some(key) if !key.is_empty() => Ok(ApiKey(key.to_string())),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} holds no API key", path.display()))),
+        }
+    }
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ApiKey(..)")
+    }
+}
+
+/// Where to send and how to batch and retry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubmitConfig {
+    pub url: Url,
+    /// Rows per POST.
+    pub batch_rows: usize,
+    /// Attempts after the first before a batch is given up on.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after.
+    pub backoff: Duration,
+    pub timeout: Duration,
+    pub api_key: Option<ApiKey>,
+}
+
+impl SubmitConfig {
+    pub fn new(url: Url) -> Self {
+        SubmitConfig {
+            url,
+            batch_rows: 1_000,
+            retries: 5,
+            backoff: Duration::from_millis(500),
+            timeout: Duration::from_secs(30),
+            api_key: None,
+        }
+    }
+}
+
+/// What has been accepted so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Submitted {
+    pub batches: u64,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+fn batch(header: &ByteRecord) -> Result<Writer<Vec<u8>>, Error> {
+    let mut writer = Writer::from_writer(Vec::new());
+    writer.write_byte_record(header).map_err(Error::Csv)?;
+    Ok(writer)
+}
+
+/// Uploads every row of `input`, logging progress after each batch.
+pub fn submit<R: Read>(input: R, config: &SubmitConfig, logger: &Logger) -> Result<Submitted, Error> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_reader(input);
+    let header = reader.byte_headers().map_err(Error::Csv)?.clone();
+    let mut record = ByteRecord::new();
+    let mut submitted = Submitted::default();
+    let (mut pending, mut rows) = (batch(&header)?, 0);
+    loop {
+        let more = reader.read_byte_record(&mut record).map_err(Error::Csv)?;
+        if more {
+            pending.write_byte_record(&record).map_err(Error::Csv)?;
+            rows += 1;
+        }
+        if rows > 0 && (rows == config.batch_rows || !more) {
+            let body = std::mem::replace(&mut pending, batch(&header)?).into_inner().map_err(|err| Error::Io(err.into_error()))?;
+            send(&body, config).map_err(|reason| Error::Submit { batch: submitted.batches + 1, accepted: submitted.rows, reason })?;
+            submitted.batches += 1;
+            submitted.rows += rows as u64;
+            submitted.bytes += body.len() as u64;
+            rows = 0;
+            logger.log(LogEvent::new(Level::Info, "submitted").reason(format!("batch {}, {} rows so far", submitted.batches, submitted.rows)));
+        }
+        if !more {
+            return Ok(submitted);
+        }
+    }
+}
+
+fn send(body: &[u8], config: &SubmitConfig) -> Result<(), String> {
+    let authorization = config.api_key.as_ref().map(|key| format!("Bearer {}", key.0));
+    let headers: Vec<(&str, &str)> = authorization.iter().map(|value| ("Authorization", value.as_str())).collect();
+    let post = || match http::post_with(&config.url, "text/csv", &headers, body, config.timeout) {
+        Ok(status) if (200..300).contains(&status) => Ok(()),
+        Ok(status @ (401 | 403)) => Err(format!("HTTP {} (check the API key)", status)),
+        Ok(status) => Err(format!("HTTP {}", status)),
+        Err(err) => Err(err.to_string()),
+    };
+    webhook::deliver(post, config.retries, config.backoff, thread::sleep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    /// The bodies a test server was sent, and the last `Authorization` header.
+    type Received = (Vec<String>, Option<String>);
+
+    /// Answers `statuses` in turn and returns what it received.
+    fn server(statuses: &'static [u16]) -> (Url, thread::JoinHandle<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/transactions", listener.local_addr().unwrap().port()).parse().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut bodies, mut authorization) = (Vec::new(), None);
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if let Some(value) = line.strip_prefix("Authorization: ") {
+                        authorization = Some(value.trim().to_string());
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(stream, "HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+            (bodies, authorization)
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_submits_in_batches_and_retries() {
+        let (url, handle) = server(&[202, 503, 202]);
+        let config = SubmitConfig { batch_rows: 2, backoff: Duration::ZERO, ..SubmitConfig::new(url) };
+        let input = "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,6\ndispute,1,1,\n";
+        let submitted = submit(input.as_bytes(), &config, &Logger::default()).unwrap();
+        assert_eq!((submitted.batches, submitted.rows), (2, 3));
+        assert_eq!(handle.join().unwrap().0, [
+            "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,6\n",
+            "type,client,tx,amount\ndispute,1,1,\n",
+            "type,client,tx,amount\ndispute,1,1,\n",
+        ]);
+    }
+
+    #[test]
+    fn test_reports_rows_accepted_before_a_failure() {
+        let (url, handle) = server(&[200, 500, 500]);
+        let config = SubmitConfig { batch_rows: 1, retries: 1, backoff: Duration::ZERO, ..SubmitConfig::new(url) };
+        let input = "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,6\n";
+        let err = submit(input.as_bytes(), &config, &Logger::default()).unwrap_err();
+        assert!(matches!(err, Error::Submit { batch: 2, accepted: 1, ref reason } if reason == "HTTP 500"), "{}", err);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_sends_the_api_key_as_a_bearer_token() {
+        let path = std::env::temp_dir().join(format!("txflow-api-key-{}", std::process::id()));
+        fs::write(&path, "s3cret\n").unwrap();
+        let key = ApiKey::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(format!("{:?}", key), "ApiKey(..)");
+
+        let (url, handle) = server(&[200, 401]);
+        let config = SubmitConfig { batch_rows: 1, retries: 0, api_key: Some(key), ..SubmitConfig::new(url) };
+        let input = "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,6\n";
+        let err = submit(input.as_bytes(), &config, &Logger::default()).unwrap_err();
+        assert!(err.to_string().contains("HTTP 401 (check the API key)"), "{}", err);
+        assert_eq!(handle.join().unwrap().1.as_deref(), Some("Bearer s3cret"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Timestamps as Unix seconds (UTC), with just enough calendar arithmetic
+//! to read and print ISO 8601 dates without pulling in a date library.
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm).
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Inverse of [`days_from_civil`].
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Accepts Unix seconds (`1704067200`), a date (`2024-01-01`, midnight UTC)
+/// or a UTC date-time (`2024-01-01T12:30:00Z`, `2024-01-01 12:30:00`).
+pub fn parse_timestamp(raw: &str) -> Option<i64> {
+    if let Ok(seconds) = raw.parse::<i64>() {
+        return Some(seconds);
+    }
+
+    let (date, time) = match raw.find(['T', ' ']) {
+        Some(index) => (&raw[..index], Some(raw[index + 1..].trim_end_matches('Z'))),
+        None => (raw, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+
+    let mut seconds = days_from_civil(year, month, day) * 86_400;
+    if let Some(time) = time {
+        let mut parts = time.splitn(3, ':');
+        let hour: i64 = parts.next()?.parse().ok()?;
+        let minute: i64 = parts.next()?.parse().ok()?;
+        let second: i64 = parts.next().map_or(Some(0), |s| s.split('.').next()?.parse().ok())?;
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        seconds += hour * 3600 + minute * 60 + second;
+    }
+    Some(seconds)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// `YYYY-MM-DD` of the day containing `seconds`.
+pub fn format_date(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn format_timestamp(seconds: i64) -> String {
+    let within_day = seconds.rem_euclid(86_400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        format_date(seconds), within_day / 3600, within_day % 3600 / 60, within_day % 60
+    )
+}
+
+/// Parses spans like `30d`, `12h`, `15m`, `90s` or plain seconds into seconds.
+pub fn parse_duration(raw: &str) -> Option<i64> {
+    let (number, unit) = match raw.chars().last()? {
+        'd' => (&raw[..raw.len() - 1], 86_400),
+        'h' => (&raw[..raw.len() - 1], 3_600),
+        'm' => (&raw[..raw.len() - 1], 60),
+        's' => (&raw[..raw.len() - 1], 1),
+        _ => (raw, 1),
+    };
+    let number: i64 = number.parse().ok()?;
+    (number >= 0).then(|| number * unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_round_trip() {
+        for days in [-719_468, -1, 0, 1, 11_016, 19_723, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+    }
+
+    #[test]
+    fn test_parse_timestamp_forms() {
+        assert_eq!(parse_timestamp("1704067200"), Some(1_704_067_200));
+        assert_eq!(parse_timestamp("2024-01-01"), Some(1_704_067_200));
+        assert_eq!(parse_timestamp("2024-01-01T12:30:00Z"), Some(1_704_067_200 + 45_000));
+        assert_eq!(parse_timestamp("2024-02-29 00:00:01"), Some(1_709_164_801));
+        assert_eq!(parse_timestamp("2023-02-29"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_date(1_704_067_200 + 86_399), "2024-01-01");
+        assert_eq!(format_timestamp(1_704_067_200 + 45_000), "2024-01-01T12:30:00Z");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30d"), Some(30 * 86_400));
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("-1h"), None);
+        assert_eq!(parse_duration("soon"), None);
+    }
+}
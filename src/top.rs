@@ -0,0 +1,112 @@
+//! Top-N clients for risk reviews: after a batch, the clients with the
+//! most deposit volume, withdrawal volume, disputes and held funds, one
+//! ranked list per measure.
+
+use std::io;
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+use crate::{collections::HashMap, error::Error, Account, ClientId, Transaction, TxType};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct ClientTotals {
+    deposit_volume: Decimal,
+    withdrawal_volume: Decimal,
+    disputes: u64,
+    held: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct TopRow {
+    measure: &'static str,
+    rank: usize,
+    client: ClientId,
+    value: Decimal,
+}
+
+/// Totals each client's applied rows and writes the top `n` per measure
+/// when the run ends. Ties rank the lower client id first.
+pub struct TopClients {
+    n: usize,
+    writer: csv::Writer<Box<dyn io::Write>>,
+    clients: HashMap<ClientId, ClientTotals>,
+}
+
+impl TopClients {
+    pub fn new(output: Box<dyn io::Write>, n: usize) -> Self {
+        TopClients { n, writer: csv::Writer::from_writer(output), clients: HashMap::default() }
+    }
+
+    pub fn observe(&mut self, record: &Transaction) {
+        let totals = self.clients.entry(record.client).or_default();
+        match (record.tx_type, record.amount) {
+            (TxType::Deposit, Some(amount)) => totals.deposit_volume += amount,
+            (TxType::Withdrawal, Some(amount)) => totals.withdrawal_volume += amount,
+            (TxType::Dispute, _) => totals.disputes += 1,
+            _ => {}
+        }
+    }
+
+    /// Notes what an account holds when it leaves the engine or the run ends;
+    /// a client's wallets add up.
+    pub fn observe_account(&mut self, account: &Account) {
+        self.clients.entry(account.client).or_default().held += account.held;
+    }
+
+    /// The top clients by `measure` (`deposit_volume`, `withdrawal_volume`,
+    /// `disputes` or `held`), highest first, leaving out zeros.
+    pub fn top(&self, measure: &str) -> Vec<(ClientId, Decimal)> {
+        let value = |totals: &ClientTotals| match measure {
+            "deposit_volume" => totals.deposit_volume,
+            "withdrawal_volume" => totals.withdrawal_volume,
+            "disputes" => Decimal::from(totals.disputes),
+            _ => totals.held,
+        };
+        let mut ranked: Vec<(ClientId, Decimal)> = self.clients.iter()
+            .map(|(client, totals)| (*client, value(totals)))
+            .filter(|(_, value)| !value.is_zero())
+            .collect();
+        ranked.sort_by(|(a, x), (b, y)| y.cmp(x).then(a.cmp(b)));
+        ranked.truncate(self.n);
+        ranked
+    }
+
+    /// Writes `measure,rank,client,value` rows, measure by measure, and flushes.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        for measure in ["deposit_volume", "withdrawal_volume", "disputes", "held"] {
+            for (index, (client, value)) in self.top(measure).into_iter().enumerate() {
+                self.writer.serialize(TopRow { measure, rank: index + 1, client, value })?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::TxId;
+
+    #[test]
+    fn test_ranks_clients_per_measure() {
+        let mut top = TopClients::new(Box::new(io::sink()), 2);
+        for (tx_type, client, amount) in [
+            (TxType::Deposit, 1, Some(dec!(50))),
+            (TxType::Deposit, 2, Some(dec!(80))),
+            (TxType::Deposit, 3, Some(dec!(50))),
+            (TxType::Deposit, 1, Some(dec!(10))),
+            (TxType::Withdrawal, 3, Some(dec!(5))),
+            (TxType::Dispute, 2, None),
+        ] {
+            top.observe(&Transaction::new(tx_type, ClientId(client), TxId(1), amount));
+        }
+        top.observe_account(&Account { held: dec!(7), ..Account::new(ClientId(3)) });
+
+        assert_eq!(top.top("deposit_volume"), vec![(ClientId(2), dec!(80)), (ClientId(1), dec!(60))]);
+        assert_eq!(top.top("withdrawal_volume"), vec![(ClientId(3), dec!(5))]);
+        assert_eq!(top.top("disputes"), vec![(ClientId(2), dec!(1))]);
+        assert_eq!(top.top("held"), vec![(ClientId(3), dec!(7))]);
+    }
+}
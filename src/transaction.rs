@@ -0,0 +1,32 @@
+use std::collections::BTreeMap;
+use serde::Deserialize;
+use rust_decimal::Decimal;
+
+pub use txflow_core::{ClientId, TxId, TxType};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    pub tx_type: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Option<Decimal>,
+    /// Business time as Unix seconds, from the optional `timestamp` column.
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    /// Processing time as Unix seconds: when the row was recorded upstream,
+    /// from the optional `recorded_at` column. Differs from `timestamp` for
+    /// late entries and restatements.
+    #[serde(default)]
+    pub recorded: Option<i64>,
+    /// Values of input columns txflow doesn't interpret (e.g. `merchant`),
+    /// keyed by header, carried along for outputs that echo transactions.
+    #[serde(skip)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Transaction {
+    pub fn new(tx_type: TxType, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Self {
+        Transaction { tx_type, client, tx, amount, timestamp: None, recorded: None, metadata: BTreeMap::new() }
+    }
+}
@@ -0,0 +1,28 @@
+//! Transfers between clients. A `transfer` row debits its client and
+//! credits the client named in its `to` column. In one engine both legs are
+//! checked and booked together. When clients are sharded across engines, the
+//! debit and the credit fall to different engines, which agree through a
+//! [`Link`]: the debiting engine prepares by taking the funds, the crediting
+//! one prepares by checking it can book them, and each sends its vote to the
+//! other. Both commit only if both voted yes; otherwise the debit is put back,
+//! so a transfer never half-applies.
+
+use std::fmt;
+
+use crate::{ClientId, Reject, Transaction};
+
+/// The client a transfer row credits, from its `to` column.
+pub fn counterpart(record: &Transaction) -> Option<ClientId> {
+    record.metadata.get("to")?.trim().parse().ok().map(ClientId)
+}
+
+/// An engine's view of the other engines its clients are sharded with.
+pub trait Link: fmt::Debug {
+    /// Whether `client`'s rows are applied by this engine.
+    fn owns(&self, client: ClientId) -> bool;
+
+    /// Sends this engine's vote on a transfer to the engine owning `peer`
+    /// and waits for that engine's vote on the same transfer. Engines take
+    /// part in transfers in the order the rows were dealt, so votes pair up.
+    fn exchange(&mut self, peer: ClientId, vote: Result<(), Reject>) -> Result<(), Reject>;
+}
@@ -0,0 +1,182 @@
+//! Checks a transactions file without applying it: the header, every
+//! field's type and amount format, and that each dispute-family row points
+//! at a deposit or withdrawal listed earlier for the same client. The
+//! findings come out as a JSON document, so a pipeline can refuse a file
+//! before the engine ever sees it.
+
+use std::{collections::{BTreeMap, HashMap}, io};
+use serde::Serialize;
+
+use crate::{
+    error::{Error, ParseError},
+    parse::AmountFormat,
+    source::{CsvSource, TxSource},
+    ClientId, Transaction, TxId, TxType,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The engine would refuse the row or the file.
+    Error,
+    /// The row applies, but probably not as its author meant.
+    Warning,
+}
+
+/// Which check a finding comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Check {
+    Header,
+    Row,
+    Field,
+    Amount,
+    Duplicate,
+    Reference,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub line: u64,
+    pub severity: Severity,
+    pub check: Check,
+    pub message: String,
+}
+
+/// Everything validation found; `findings` keeps the first `sample_limit`
+/// per check, `counts` counts them all.
+#[derive(Debug, Default, Serialize)]
+pub struct Findings {
+    pub rows: u64,
+    pub errors: u64,
+    pub warnings: u64,
+    pub counts: BTreeMap<Check, u64>,
+    pub findings: Vec<Finding>,
+    #[serde(skip)]
+    sample_limit: usize,
+}
+
+impl Findings {
+    pub fn is_valid(&self) -> bool {
+        self.errors == 0
+    }
+
+    fn add(&mut self, line: u64, severity: Severity, check: Check, message: impl Into<String>) {
+        match severity {
+            Severity::Error => self.errors += 1,
+            Severity::Warning => self.warnings += 1,
+        }
+        let count = self.counts.entry(check).or_insert(0);
+        *count += 1;
+        if *count <= self.sample_limit as u64 {
+            self.findings.push(Finding { line, severity, check, message: message.into() });
+        }
+    }
+}
+
+/// Validates every row of `input`, reading amounts as `amounts` describes.
+pub fn validate(input: impl io::Read, amounts: &AmountFormat, sample_limit: usize) -> Result<Findings, Error> {
+    let mut findings = Findings { sample_limit, ..Default::default() };
+    let mut source = match CsvSource::new(input, amounts) {
+        Ok(source) => source,
+        Err(Error::Parse(err)) => {
+            findings.add(err.line(), Severity::Error, Check::Header, err.to_string());
+            return Ok(findings);
+        }
+        Err(err) => return Err(err),
+    };
+    // Owner of every deposit and withdrawal seen so far.
+    let mut funding: HashMap<TxId, ClientId> = HashMap::new();
+    loop {
+        let row = match source.next_row() {
+            Ok(Some(row)) => row,
+            Ok(None) => return Ok(findings),
+            Err(Error::Parse(err)) => {
+                findings.rows += 1;
+                let check = if matches!(err, ParseError::MalformedRow { .. }) { Check::Row } else { Check::Field };
+                findings.add(err.line(), Severity::Error, check, err.to_string());
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        findings.rows += 1;
+        check_row(&row.transaction, row.line, &mut funding, &mut findings);
+    }
+}
+
+fn check_row(transaction: &Transaction, line: u64, funding: &mut HashMap<TxId, ClientId>, findings: &mut Findings) {
+    let Transaction { tx_type, client, tx, amount, .. } = *transaction;
+    match tx_type {
+        TxType::Deposit | TxType::Withdrawal | TxType::Transfer => {
+            match amount {
+                None => findings.add(line, Severity::Error, Check::Amount, format!("{} has no amount", tx_type.name())),
+                Some(amount) if amount <= rust_decimal::Decimal::ZERO => {
+                    findings.add(line, Severity::Error, Check::Amount, format!("{} amount {} is not positive", tx_type.name(), amount))
+                }
+                Some(_) => {}
+            }
+            if funding.insert(tx, client).is_some() {
+                findings.add(line, Severity::Error, Check::Duplicate, format!("transaction {} is listed twice", tx.0));
+            }
+        }
+        TxType::Finalize => {}
+        _ => {
+            if amount.is_some() {
+                findings.add(line, Severity::Warning, Check::Amount, format!("{} amount is ignored", tx_type.name()));
+            }
+            match funding.get(&tx) {
+                None => findings.add(line, Severity::Error, Check::Reference, format!(
+                    "{} references transaction {}, which is not listed before it", tx_type.name(), tx.0
+                )),
+                Some(&owner) if owner != client => findings.add(line, Severity::Error, Check::Reference, format!(
+                    "{} by client {} references transaction {} of client {}", tx_type.name(), client.0, tx.0, owner.0
+                )),
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &str) -> Findings {
+        validate(input.as_bytes(), &AmountFormat::default(), 10).unwrap()
+    }
+
+    #[test]
+    fn test_reports_each_check() {
+        let findings = run(concat!(
+            "type,client,tx,amount\n",
+            "deposit,1,1,10\n",
+            "deposit,1,1,5\n",
+            "withdrawal,2,2,\n",
+            "deposit,x,3,1\n",
+            "dispute,2,1,\n",
+            "resolve,1,9,\n",
+            "dispute,1,1,4\n",
+            "deposit,1,4\n",
+        ));
+        let checks: Vec<(u64, Check, Severity)> = findings.findings.iter().map(|f| (f.line, f.check, f.severity)).collect();
+        assert_eq!(checks, [
+            (3, Check::Duplicate, Severity::Error),
+            (4, Check::Amount, Severity::Error),
+            (5, Check::Field, Severity::Error),
+            (6, Check::Reference, Severity::Error),
+            (7, Check::Reference, Severity::Error),
+            (8, Check::Amount, Severity::Warning),
+            (9, Check::Row, Severity::Error),
+        ]);
+        assert_eq!((findings.rows, findings.errors, findings.warnings, findings.is_valid()), (8, 6, 1, false));
+    }
+
+    #[test]
+    fn test_missing_header_column() {
+        let findings = run("type,client,amount\ndeposit,1,10\n");
+        assert_eq!(findings.findings[0].check, Check::Header);
+        assert!(findings.findings[0].message.contains("'tx'"));
+        let json = serde_json::to_string(&findings).unwrap();
+        assert!(json.starts_with(r#"{"rows":0,"errors":1,"warnings":0,"counts":{"header":1},"findings":[{"line":1,"severity":"error","check":"header""#), "{}", json);
+    }
+}
@@ -0,0 +1,454 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, Read}, path::{Path, PathBuf}, thread, time::{Duration, SystemTime}};
+use serde::Serialize;
+
+use crate::{
+    anchor::{AnchorConfig, Anchors},
+    crash,
+    deadletter::DeadLetterWriter,
+    error::Error,
+    health::{self, Health},
+    logging::{Level, LogEvent, Logger},
+    processor::{self, Options, Sinks, Summary},
+    report::ReportWriter,
+    observer::Notifiers,
+    sha256::{self, Sha256},
+    signals, state,
+    statsd::{Statsd, StatsdConfig},
+    Engine,
+};
+
+/// Where a watch loop reads from, moves processed files to, and keeps its state.
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    pub dir: PathBuf,
+    pub done: PathBuf,
+    pub state: PathBuf,
+    pub poll: Duration,
+    /// Process whatever is waiting and return instead of polling forever.
+    pub once: bool,
+    /// Collects skipped and rejected rows from every file, appended across restarts.
+    pub dead_letter: Option<PathBuf>,
+    /// Rows journaled in the state file so `revert` can take them back.
+    pub undo_depth: usize,
+    /// Roll a file that fails part-way back out of the state, so the batch
+    /// can be restarted from that file.
+    pub atomic: bool,
+    /// Where to serve `/healthz` and `/readyz`, if anywhere.
+    pub health: Option<String>,
+    /// Waiting files beyond which `/readyz` reports not ready.
+    pub max_backlog: usize,
+    /// Policy file whose changes trigger a reload.
+    pub policy: Option<PathBuf>,
+    /// Record each file's SHA-256 in the state and skip a file whose content
+    /// was applied before, e.g. one resubmitted or left behind by a crash
+    /// between saving state and moving it to the done directory.
+    pub idempotent: bool,
+    /// Where to publish state fingerprints after each file and optionally during it.
+    pub anchor: Option<AnchorConfig>,
+    /// Where to send StatsD metrics, the backlog of waiting files included.
+    pub statsd: Option<StatsdConfig>,
+}
+
+/// Rebuilds the run options, policy file and flags alike, for a reload.
+pub type Reload<'a> = &'a dyn Fn() -> Result<Options, String>;
+
+/// Result of one input file, written next to it in the done directory.
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    pub file: String,
+    pub rows: u64,
+    pub applied: u64,
+    pub skipped: u64,
+    pub rejected: u64,
+    pub finalized: u64,
+    pub accounts: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Rows undone after the error, when files are applied atomically.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rolled_back: Option<usize>,
+    /// The same content was applied before, so nothing was applied this time.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub already_applied: bool,
+}
+
+/// Processes `*.csv` files dropped into `config.dir` in name order against
+/// state persisted at `config.state`. Producers should write under another
+/// extension and rename to `.csv` once complete so partial files are never read.
+///
+/// With `options.interruptible`, a termination signal lets the file in
+/// flight finish, checkpoints state and returns. Given `reload`, a change to
+/// `config.policy` or a SIGHUP swaps in fresh options between files; limits,
+/// dispute windows and rules apply from the next file on, while notifiers
+/// keep the settings they started with.
+pub fn run(config: &WatchConfig, options: &Options, reload: Option<Reload>, logger: &Logger) -> Result<(), Error> {
+    let health = match &config.health {
+        Some(address) => {
+            let storage = config.state.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            let health = Health::new(config.max_backlog, storage);
+            let bound = health::serve(address, health.clone(), *logger)?;
+            logger.log(LogEvent::new(Level::Info, "probes").reason(format!("http://{}/healthz and /readyz", bound)));
+            Some(health)
+        }
+        None => None,
+    };
+    let mut engine = state::load_or_default(&config.state)?;
+    engine.set_policy(&options.policy);
+    engine.set_undo_depth(config.undo_depth);
+    let notifiers = Notifiers::start(&options.policy, &mut engine, logger);
+    fs::create_dir_all(&config.done)?;
+    let mut sinks = Sinks::default();
+    if let Some(path) = &config.dead_letter {
+        let started = fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0);
+        let file = Box::new(OpenOptions::new().create(true).append(true).open(path)?);
+        sinks.dead_letter = Some(if started { DeadLetterWriter::continuing(file) } else { DeadLetterWriter::new(file) });
+    }
+    if let Some(anchor) = &config.anchor {
+        sinks.anchors = Some(Anchors::open(anchor)?);
+    }
+    if let Some(statsd) = &config.statsd {
+        sinks.statsd = Some(Statsd::open(statsd.clone())?);
+    }
+    logger.log(LogEvent::new(Level::Info, "watching").reason(config.dir.display()));
+    if let Some(health) = &health {
+        health.set_loaded();
+    }
+
+    // Files are drained whole; signals are only acted on between them.
+    let mut per_file = Options { interruptible: false, ..options.clone() };
+    let stopping = || options.interruptible && signals::shutdown_requested();
+    let mut policy = PolicyFile::new(config.policy.clone());
+    let mut reload_due = || policy.changed() | signals::take_reload();
+    loop {
+        let files = pending_files(&config.dir)?;
+        for (index, path) in files.iter().enumerate() {
+            if stopping() {
+                break;
+            }
+            if let Some(reload) = reload.filter(|_| reload_due()) {
+                reload_options(reload, &mut engine, &mut per_file, logger);
+            }
+            if let Some(health) = &health {
+                health.set_backlog(files.len() - index);
+            }
+            if let Some(statsd) = sinks.statsd.as_mut() {
+                statsd.gauge("queue_depth", (files.len() - index) as u64);
+            }
+            process_file(&mut engine, path, &mut sinks, config, &per_file, logger)?;
+        }
+        if let Some(health) = &health {
+            health.set_backlog(0);
+        }
+        if let Some(statsd) = sinks.statsd.as_mut().filter(|_| !files.is_empty()) {
+            statsd.gauge("queue_depth", 0);
+            statsd.flush();
+        }
+        if let Some(reload) = reload.filter(|_| reload_due()) {
+            reload_options(reload, &mut engine, &mut per_file, logger);
+        }
+        if stopping() {
+            state::save(&engine, &config.state)?;
+            if let Some(dead_letter) = sinks.dead_letter.as_mut() {
+                dead_letter.flush()?;
+            }
+            notifiers.finish(logger);
+            logger.log(LogEvent::new(Level::Info, "shutdown").reason(config.state.display()));
+            return Ok(());
+        }
+        if config.once {
+            notifiers.finish(logger);
+            return Ok(());
+        }
+        sleep_unless_stopping(config.poll, &stopping);
+    }
+}
+
+/// Tracks a policy file's modification time.
+struct PolicyFile {
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+}
+
+impl PolicyFile {
+    fn new(path: Option<PathBuf>) -> Self {
+        let modified = path.as_deref().and_then(modified);
+        PolicyFile { path, modified }
+    }
+
+    /// Whether the file changed since the last call.
+    fn changed(&mut self) -> bool {
+        let Some(path) = &self.path else { return false };
+        let modified = modified(path);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Swaps in freshly built options, or keeps the current ones if they don't
+/// build.
+fn reload_options(reload: Reload, engine: &mut Engine, options: &mut Options, logger: &Logger) {
+    match reload() {
+        Ok(reloaded) => {
+            engine.set_policy(&reloaded.policy);
+            *options = Options { interruptible: false, ..reloaded };
+            logger.log(LogEvent::new(Level::Info, "policy-reloaded"));
+        }
+        Err(err) => logger.log(LogEvent::new(Level::Error, "reload-failed").reason(format!("{}; keeping the current policy", err))),
+    }
+}
+
+/// Sleeps for `poll` in short slices so a signal is noticed promptly.
+fn sleep_unless_stopping(poll: Duration, stopping: &dyn Fn() -> bool) {
+    let slice = Duration::from_millis(100);
+    let mut slept = Duration::ZERO;
+    while slept < poll && !stopping() {
+        thread::sleep(slice.min(poll - slept));
+        slept += slice;
+    }
+}
+
+fn pending_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "csv") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// SHA-256 of a file's content, as hex.
+fn file_digest(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(sha256::to_hex(&hasher.finalize())),
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+}
+
+/// Applies one file, saves state, then moves the file and its summary to the
+/// done directory. A file that fails to parse is still moved, with the
+/// failure and the rows applied before it recorded in its summary, so it is
+/// not retried forever.
+fn process_file(
+    engine: &mut Engine,
+    path: &Path,
+    sinks: &mut Sinks,
+    config: &WatchConfig,
+    options: &Options,
+    logger: &Logger,
+) -> Result<FileSummary, Error> {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    logger.log(LogEvent::new(Level::Info, "picked-up").reason(&name));
+    crash::set_input(&path.to_string_lossy());
+    let digest = config.idempotent.then(|| file_digest(path)).transpose()?;
+    if digest.as_deref().is_some_and(|digest| engine.applied_file(digest)) {
+        logger.log(LogEvent::new(Level::Warn, "already-applied").reason(&name));
+        let summary = FileSummary {
+            file: name.clone(),
+            rows: 0,
+            applied: 0,
+            skipped: 0,
+            rejected: 0,
+            finalized: 0,
+            accounts: engine.len()?,
+            error: None,
+            rolled_back: None,
+            already_applied: true,
+        };
+        return move_to_done(path, &name, summary, config, logger);
+    }
+    if let Some(dead_letter) = sinks.dead_letter.as_mut() {
+        dead_letter.set_input(&name);
+    }
+
+    if config.atomic {
+        engine.savepoint();
+    }
+    let report_path = config.done.join(format!("{}.finalized.csv", name));
+    let mut report = ReportWriter::new(File::create(&report_path)?);
+    let (counts, result) = match processor::csv_source(File::open(path)?, options, logger) {
+        Ok(source) => processor::process_partial(source, engine, &mut report, sinks, options, logger),
+        Err(err) => (Summary::default(), Err(err)),
+    };
+    report.flush()?;
+
+    let (counts, error, rolled_back) = match result {
+        Ok(()) => (counts, None, None),
+        // Neither the disk nor the account store failing is the file's fault.
+        Err(err @ (Error::Io(_) | Error::Store(_))) => return Err(err),
+        Err(err) => {
+            logger.log(LogEvent::new(Level::Error, "file-failed").reason(format!("{}: {}", name, err)));
+            let rolled_back = config.atomic.then(|| engine.rollback_to_savepoint());
+            if let Some(rows) = rolled_back {
+                // Accounts it finalized are open again, so their report rows no longer hold.
+                fs::remove_file(&report_path)?;
+                logger.log(LogEvent::new(Level::Warn, "rolled-back").reason(format!("{}: {} rows", name, rows)));
+            }
+            // Without a rollback, the rows before the failure stay applied and are counted.
+            let counts = if rolled_back.is_some() { Summary::default() } else { counts };
+            (counts, Some(err.to_string()), rolled_back)
+        }
+    };
+    let summary = FileSummary {
+        file: name.clone(),
+        rows: counts.rows,
+        applied: counts.applied,
+        skipped: counts.errors.parse_errors(),
+        rejected: counts.errors.rejected(),
+        finalized: counts.finalized,
+        accounts: engine.len()?,
+        error,
+        rolled_back,
+        already_applied: false,
+    };
+    engine.release_savepoint();
+    // Saved with the state, so a file applied but not yet moved is skipped after a restart.
+    if let Some(digest) = digest.filter(|_| summary.rolled_back.is_none()) {
+        engine.record_file(digest);
+    }
+
+    state::save(engine, &config.state)?;
+    move_to_done(path, &name, summary, config, logger)
+}
+
+/// Moves an input file to the done directory with its summary.
+fn move_to_done(path: &Path, name: &str, summary: FileSummary, config: &WatchConfig, logger: &Logger) -> Result<FileSummary, Error> {
+    fs::rename(path, config.done.join(name))?;
+    let summary_file = File::create(config.done.join(format!("{}.summary.json", name)))?;
+    serde_json::to_writer_pretty(io::BufWriter::new(summary_file), &summary)?;
+    logger.log(LogEvent::new(Level::Info, "done").reason(name));
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientId;
+    use rust_decimal::dec;
+
+    /// A single pass over `root/incoming` with every option off.
+    fn config(root: &Path) -> WatchConfig {
+        WatchConfig {
+            dir: root.join("incoming"),
+            done: root.join("processed"),
+            state: root.join("state.json"),
+            poll: Duration::from_millis(10),
+            once: true,
+            dead_letter: None,
+            undo_depth: 0,
+            atomic: false,
+            health: None,
+            max_backlog: 0,
+            policy: None,
+            idempotent: false,
+            anchor: None,
+            statsd: None,
+        }
+    }
+
+    #[test]
+    fn test_once_processes_files_in_order_against_persisted_state() {
+        let root = std::env::temp_dir().join(format!("txflow-watch-{}", std::process::id()));
+        let config = config(&root);
+        fs::create_dir_all(&config.dir).unwrap();
+        fs::write(config.dir.join("a.csv"), "type,client,tx,amount\ndeposit,1,1,10\n").unwrap();
+        fs::write(config.dir.join("b.csv"), "type,client,tx,amount\nwithdrawal,1,2,4\n").unwrap();
+        fs::write(config.dir.join("c.partial"), "type,client,tx,amount\n").unwrap();
+
+        run(&config, &Options::default(), None, &Logger::default()).unwrap();
+
+        let engine = state::load(&config.state).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(6));
+        assert!(config.done.join("a.csv").exists() && config.done.join("b.csv.summary.json").exists());
+        assert_eq!(pending_files(&config.dir).unwrap(), Vec::<PathBuf>::new());
+        assert!(config.dir.join("c.partial").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_failed_file_counts_rows_applied_before_the_failure() {
+        let root = std::env::temp_dir().join(format!("txflow-watch-failed-{}", std::process::id()));
+        let config = config(&root);
+        fs::create_dir_all(&config.dir).unwrap();
+        fs::write(config.dir.join("a.csv"), "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,20\ndeposit,1,3,x\n").unwrap();
+
+        run(&config, &Options::default(), None, &Logger::default()).unwrap();
+
+        let engine = state::load(&config.state).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(10));
+        let summary = fs::read_to_string(config.done.join("a.csv.summary.json")).unwrap();
+        assert!(summary.contains("\"rows\": 3") && summary.contains("\"applied\": 1") && summary.contains("\"rejected\": 1"));
+        assert!(summary.contains("line 4: amount 'x' is not a valid decimal"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_atomic_file_is_rolled_back_on_failure() {
+        let root = std::env::temp_dir().join(format!("txflow-watch-atomic-{}", std::process::id()));
+        let config = WatchConfig { atomic: true, ..config(&root) };
+        fs::create_dir_all(&config.dir).unwrap();
+        fs::write(config.dir.join("a.csv"), "type,client,tx,amount\ndeposit,1,1,10\n").unwrap();
+        fs::write(config.dir.join("b.csv"), "type,client,tx,amount\ndeposit,1,2,4\nfinalize,1,0,\ndeposit,1,3,x\n").unwrap();
+
+        run(&config, &Options::default(), None, &Logger::default()).unwrap();
+
+        let engine = state::load(&config.state).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(10));
+        let summary = fs::read_to_string(config.done.join("b.csv.summary.json")).unwrap();
+        assert!(summary.contains("\"rolled_back\": 2"));
+        assert!(!config.done.join("b.csv.finalized.csv").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_idempotent_skips_resubmitted_and_overlapping_rows() {
+        let root = std::env::temp_dir().join(format!("txflow-watch-idempotent-{}", std::process::id()));
+        let config = WatchConfig { idempotent: true, ..config(&root) };
+        let options = Options { lenient: true, policy: crate::policy::Policy { unique_tx_ids: true, ..Default::default() }, ..Default::default() };
+        fs::create_dir_all(&config.dir).unwrap();
+        let first = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,3\n";
+        fs::write(config.dir.join("a.csv"), first).unwrap();
+        run(&config, &options, None, &Logger::default()).unwrap();
+
+        fs::write(config.dir.join("a.csv"), first).unwrap();
+        fs::write(config.dir.join("b.csv"), "type,client,tx,amount\nwithdrawal,1,2,3\ndeposit,1,3,1\n").unwrap();
+        run(&config, &options, None, &Logger::default()).unwrap();
+
+        let engine = state::load(&config.state).unwrap();
+        assert_eq!(engine.account(ClientId(1)).unwrap().unwrap().available, dec!(8));
+        assert!(fs::read_to_string(config.done.join("a.csv.summary.json")).unwrap().contains("\"already_applied\": true"));
+        assert!(fs::read_to_string(config.done.join("b.csv.summary.json")).unwrap().contains("\"rejected\": 1"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_policy_reload() {
+        let path = std::env::temp_dir().join(format!("txflow-watch-policy-{}.toml", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let mut policy = PolicyFile::new(Some(path.clone()));
+        assert!(!policy.changed());
+        File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert!(policy.changed() && !policy.changed());
+        fs::remove_file(&path).unwrap();
+
+        let mut engine = Engine::new();
+        let mut options = Options::default();
+        let reloaded = || Ok(Options { policy: crate::policy::Policy { max_cycles: 2, ..Default::default() }, ..Default::default() });
+        reload_options(&reloaded, &mut engine, &mut options, &Logger::default());
+        assert_eq!(options.policy.max_cycles, 2);
+        reload_options(&|| Err("bad policy".to_string()), &mut engine, &mut options, &Logger::default());
+        assert_eq!(options.policy.max_cycles, 2);
+    }
+}
@@ -0,0 +1,242 @@
+//! Posts account events (chargebacks, locks, large withdrawals) to an HTTP
+//! endpoint as they happen. Deliveries run on a background thread so a slow
+//! endpoint never stalls processing; failed posts are retried with
+//! exponential backoff and then dropped.
+
+use std::{
+    str::FromStr,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    http::{self, Url},
+    logging::{Level, LogEvent, Logger},
+    observer::EngineObserver,
+    Account, ClientId, Transaction, TxId, TxType,
+};
+
+/// Where and when to post, from the policy file's `[webhooks]` section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookConfig {
+    pub url: Url,
+    /// Withdrawals of at least this much are reported; `None` reports none.
+    pub large_withdrawal: Option<Decimal>,
+    /// Attempts after the first before an event is dropped.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after.
+    pub backoff: Duration,
+    pub timeout: Duration,
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}' for webhook {}", value, key))
+}
+
+impl WebhookConfig {
+    pub fn new(url: Url) -> Self {
+        WebhookConfig {
+            url,
+            large_withdrawal: None,
+            retries: 5,
+            backoff: Duration::from_millis(500),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets one option by name; `url` is given to [`WebhookConfig::new`].
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "url" => self.url = value.parse()?,
+            "large_withdrawal" => self.large_withdrawal = Some(parse(key, value)?),
+            "retries" => self.retries = parse(key, value)?,
+            "backoff_ms" => self.backoff = Duration::from_millis(parse(key, value)?),
+            "timeout_ms" => self.timeout = Duration::from_millis(parse(key, value)?),
+            other => return Err(format!("unknown webhook option '{}'", other)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Event {
+    event: &'static str,
+    client: ClientId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx: Option<TxId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<Decimal>,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+impl Event {
+    fn new(event: &'static str, account: &Account, tx: Option<TxId>, amount: Option<Decimal>) -> Self {
+        Event { event, client: account.client, tx, amount, available: account.available, held: account.held, locked: account.locked }
+    }
+}
+
+/// Calls `send` until it succeeds or `retries` retries have failed, sleeping
+/// `backoff`, then twice that, and so on between attempts. Returns the last
+/// error if every attempt failed.
+pub(crate) fn deliver(
+    mut send: impl FnMut() -> Result<(), String>,
+    retries: u32,
+    backoff: Duration,
+    mut sleep: impl FnMut(Duration),
+) -> Result<(), String> {
+    let mut delay = backoff;
+    let mut attempt = 0;
+    loop {
+        match send() {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt >= retries => return Err(err),
+            Err(_) => {
+                sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// How a webhook's deliveries went.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Deliveries {
+    pub sent: u64,
+    pub dropped: u64,
+}
+
+/// The background sender; hand [`Webhook::observer`] to the engine and call
+/// [`Webhook::finish`] at the end of the run to flush what is queued.
+pub struct Webhook {
+    queue: mpsc::Sender<Option<String>>,
+    worker: JoinHandle<Deliveries>,
+    large_withdrawal: Option<Decimal>,
+}
+
+impl Webhook {
+    pub fn start(config: WebhookConfig, logger: Logger) -> Self {
+        let (queue, events) = mpsc::channel::<Option<String>>();
+        let large_withdrawal = config.large_withdrawal;
+        let worker = thread::spawn(move || {
+            let mut deliveries = Deliveries::default();
+            while let Ok(Some(body)) = events.recv() {
+                let send = || match http::post(&config.url, "application/json", body.as_bytes(), config.timeout) {
+                    Ok(status) if (200..300).contains(&status) => Ok(()),
+                    Ok(status) => Err(format!("HTTP {}", status)),
+                    Err(err) => Err(err.to_string()),
+                };
+                match deliver(send, config.retries, config.backoff, thread::sleep) {
+                    Ok(()) => deliveries.sent += 1,
+                    Err(err) => {
+                        deliveries.dropped += 1;
+                        logger.log(LogEvent::new(Level::Warn, "webhook").reason(format!("{}: dropped event: {}", config.url, err)));
+                    }
+                }
+            }
+            deliveries
+        });
+        Webhook { queue, worker, large_withdrawal }
+    }
+
+    pub fn observer(&self) -> WebhookObserver {
+        WebhookObserver { queue: self.queue.clone(), large_withdrawal: self.large_withdrawal }
+    }
+
+    /// Waits for queued events to be delivered or dropped.
+    pub fn finish(self) -> Deliveries {
+        let _ = self.queue.send(None);
+        self.worker.join().unwrap_or_default()
+    }
+}
+
+/// Queues webhook events from engine callbacks.
+pub struct WebhookObserver {
+    queue: mpsc::Sender<Option<String>>,
+    large_withdrawal: Option<Decimal>,
+}
+
+impl WebhookObserver {
+    fn send(&self, event: Event) {
+        if let Ok(body) = serde_json::to_string(&event) {
+            let _ = self.queue.send(Some(body));
+        }
+    }
+}
+
+impl EngineObserver for WebhookObserver {
+    fn on_applied(&mut self, transaction: &Transaction, account: &Account) {
+        if let (TxType::Withdrawal, Some(amount), Some(limit)) = (transaction.tx_type, transaction.amount, self.large_withdrawal) {
+            if amount >= limit {
+                self.send(Event::new("large-withdrawal", account, Some(transaction.tx), Some(amount)));
+            }
+        }
+    }
+
+    fn on_lock(&mut self, account: &Account) {
+        self.send(Event::new("locked", account, None, None));
+    }
+
+    fn on_chargeback(&mut self, transaction: &Transaction, amount: Decimal, account: &Account) {
+        self.send(Event::new("chargeback", account, Some(transaction.tx), Some(amount)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::Engine;
+
+    #[test]
+    fn test_deliver_backs_off_exponentially() {
+        let mut slept = Vec::new();
+        let mut calls = 0;
+        let result = deliver(|| { calls += 1; if calls < 3 { Err("down".into()) } else { Ok(()) } }, 5, Duration::from_millis(100), |d| slept.push(d));
+        assert_eq!(result, Ok(()));
+        assert_eq!(slept, [Duration::from_millis(100), Duration::from_millis(200)]);
+
+        let mut attempts = 0;
+        let result = deliver(|| { attempts += 1; Err("down".into()) }, 2, Duration::ZERO, |_| {});
+        assert_eq!((result, attempts), (Err("down".to_string()), 3));
+    }
+
+    #[test]
+    fn test_observer_queues_account_events() {
+        let (queue, events) = mpsc::channel();
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(WebhookObserver { queue, large_withdrawal: Some(dec!(10)) }));
+        for (tx_type, tx, amount) in [
+            (TxType::Deposit, 1, Some(dec!(50))),
+            (TxType::Withdrawal, 2, Some(dec!(5))),
+            (TxType::Withdrawal, 3, Some(dec!(10))),
+            (TxType::Deposit, 4, Some(dec!(7))),
+            (TxType::Dispute, 4, None),
+            (TxType::Chargeback, 4, None),
+        ] {
+            engine.apply(&Transaction::new(tx_type, ClientId(1), TxId(tx), amount)).unwrap();
+        }
+        drop(engine);
+
+        let bodies: Vec<String> = events.iter().flatten().collect();
+        assert_eq!(bodies, [
+            r#"{"event":"large-withdrawal","client":1,"tx":3,"amount":"10","available":"35","held":"0","locked":false}"#,
+            r#"{"event":"chargeback","client":1,"tx":4,"amount":"7","available":"35","held":"0","locked":true}"#,
+            r#"{"event":"locked","client":1,"available":"35","held":"0","locked":true}"#,
+        ]);
+    }
+
+    #[test]
+    fn test_config_options() {
+        let mut config = WebhookConfig::new("http://localhost/hook".parse().unwrap());
+        config.set("retries", "2").unwrap();
+        config.set("backoff_ms", "50").unwrap();
+        assert_eq!((config.retries, config.backoff), (2, Duration::from_millis(50)));
+        assert!(config.set("colour", "red").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Writes a run's results as an Excel workbook: an accounts sheet, a
+//! rejects sheet and a summary sheet, with bold frozen headers and amounts
+//! formatted to four decimal places. Strings are stored inline, so the
+//! package needs no shared-strings part.
+
+use std::{fmt::Write as _, io};
+use rust_decimal::Decimal;
+
+use crate::{
+    diagnostics::RejectLog,
+    error::Error,
+    processor::Summary,
+    report::AccountReport,
+    zip::ZipWriter,
+};
+
+enum Cell<'a> {
+    Header(&'a str),
+    Text(String),
+    Number(u64),
+    Amount(Decimal),
+    Bool(bool),
+    Empty,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn column(mut index: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("ASCII column name")
+}
+
+/// Style indices into `cellXfs` in [`STYLES`].
+const BOLD: u8 = 1;
+const AMOUNT: u8 = 2;
+
+fn sheet(rows: &[Vec<Cell>], widths: &[u32], filter: bool) -> String {
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+        r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+        r#"<sheetViews><sheetView workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews>"#,
+        "<cols>",
+    ));
+    for (index, width) in widths.iter().enumerate() {
+        let _ = write!(xml, r#"<col min="{0}" max="{0}" width="{1}" customWidth="1"/>"#, index + 1, width);
+    }
+    xml.push_str("</cols><sheetData>");
+
+    for (row_index, row) in rows.iter().enumerate() {
+        let _ = write!(xml, r#"<row r="{}">"#, row_index + 1);
+        for (column_index, cell) in row.iter().enumerate() {
+            let reference = format!("{}{}", column(column_index), row_index + 1);
+            let _ = match cell {
+                Cell::Header(text) => write!(xml, r#"<c r="{}" s="{}" t="inlineStr"><is><t>{}</t></is></c>"#, reference, BOLD, escape(text)),
+                Cell::Text(text) => write!(xml, r#"<c r="{}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#, reference, escape(text)),
+                Cell::Number(value) => write!(xml, r#"<c r="{}"><v>{}</v></c>"#, reference, value),
+                Cell::Amount(value) => write!(xml, r#"<c r="{}" s="{}"><v>{}</v></c>"#, reference, AMOUNT, value),
+                Cell::Bool(value) => write!(xml, r#"<c r="{}" t="b"><v>{}</v></c>"#, reference, u8::from(*value)),
+                Cell::Empty => Ok(()),
+            };
+        }
+        xml.push_str("</row>");
+    }
+    xml.push_str("</sheetData>");
+    if filter && !rows.is_empty() {
+        let _ = write!(xml, r#"<autoFilter ref="A1:{}{}"/>"#, column(widths.len() - 1), rows.len());
+    }
+    xml.push_str("</worksheet>");
+    xml
+}
+
+const CONTENT_TYPES: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+    r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+    r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+    r#"<Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#,
+    r#"<Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>"#,
+    r#"<Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#,
+    r#"<Override PartName="/xl/worksheets/sheet2.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#,
+    r#"<Override PartName="/xl/worksheets/sheet3.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#,
+    "</Types>",
+);
+
+const ROOT_RELS: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+    r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>"#,
+    "</Relationships>",
+);
+
+const WORKBOOK: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" "#,
+    r#"xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>"#,
+    r#"<sheet name="Accounts" sheetId="1" r:id="rId1"/>"#,
+    r#"<sheet name="Rejects" sheetId="2" r:id="rId2"/>"#,
+    r#"<sheet name="Summary" sheetId="3" r:id="rId3"/>"#,
+    "</sheets></workbook>",
+);
+
+const WORKBOOK_RELS: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+    r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/>"#,
+    r#"<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet2.xml"/>"#,
+    r#"<Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet3.xml"/>"#,
+    r#"<Relationship Id="rId4" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>"#,
+    "</Relationships>",
+);
+
+const STYLES: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+    r##"<numFmts count="1"><numFmt numFmtId="164" formatCode="#,##0.0000"/></numFmts>"##,
+    r#"<fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts>"#,
+    r#"<fills count="3"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill>"#,
+    r#"<fill><patternFill patternType="solid"><fgColor rgb="FFD9E1F2"/></patternFill></fill></fills>"#,
+    r#"<borders count="1"><border/></borders>"#,
+    r#"<cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs>"#,
+    r#"<cellXfs count="3"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/>"#,
+    r#"<xf numFmtId="0" fontId="1" fillId="2" borderId="0" xfId="0" applyFont="1" applyFill="1"/>"#,
+    r#"<xf numFmtId="164" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/></cellXfs>"#,
+    "</styleSheet>",
+);
+
+/// Writes the workbook for a finished run; `accounts` should include finalized accounts.
+pub fn write<W: io::Write>(output: W, accounts: &[AccountReport], rejects: &RejectLog, summary: &Summary) -> Result<(), Error> {
+    let mut account_rows = vec![vec![
+        Cell::Header("client"), Cell::Header("available"), Cell::Header("held"), Cell::Header("total"), Cell::Header("locked"),
+    ]];
+    account_rows.extend(accounts.iter().map(|row| vec![
+        Cell::Number(row.client.0 as u64),
+        Cell::Amount(row.available),
+        Cell::Amount(row.held),
+        Cell::Amount(row.available + row.held),
+        Cell::Bool(row.locked),
+    ]));
+
+    let mut reject_rows = vec![vec![
+        Cell::Header("line"), Cell::Header("client"), Cell::Header("tx"), Cell::Header("category"), Cell::Header("reason"),
+    ]];
+    reject_rows.extend(rejects.records().iter().map(|record| vec![
+        Cell::Number(record.line),
+        record.client.map_or(Cell::Empty, |client| Cell::Number(client.0 as u64)),
+        record.tx.map_or(Cell::Empty, |tx| Cell::Number(tx.0 as u64)),
+        Cell::Text(record.category.to_string()),
+        Cell::Text(record.reason.clone()),
+    ]));
+
+    let total = |field: fn(&AccountReport) -> Decimal| accounts.iter().map(field).sum::<Decimal>();
+    let mut summary_rows = vec![
+        vec![Cell::Header("metric"), Cell::Header("value")],
+        vec![Cell::Text("rows".into()), Cell::Number(summary.rows)],
+        vec![Cell::Text("applied".into()), Cell::Number(summary.applied)],
+        vec![Cell::Text("skipped".into()), Cell::Number(summary.errors.parse_errors())],
+        vec![Cell::Text("rejected".into()), Cell::Number(summary.errors.rejected())],
+        vec![Cell::Text("accounts".into()), Cell::Number(accounts.len() as u64)],
+        vec![Cell::Text("locked accounts".into()), Cell::Number(accounts.iter().filter(|row| row.locked).count() as u64)],
+        vec![Cell::Text("finalized".into()), Cell::Number(summary.finalized)],
+        vec![Cell::Text("total available".into()), Cell::Amount(total(|row| row.available))],
+        vec![Cell::Text("total held".into()), Cell::Amount(total(|row| row.held))],
+    ];
+    summary_rows.extend(summary.errors.iter().map(|(category, count)| vec![Cell::Text(category.to_string()), Cell::Number(count)]));
+    if rejects.dropped() > 0 {
+        summary_rows.push(vec![Cell::Text("rejects not listed".into()), Cell::Number(rejects.dropped())]);
+    }
+
+    let mut zip = ZipWriter::new(output);
+    zip.add("[Content_Types].xml", CONTENT_TYPES.as_bytes())?;
+    zip.add("_rels/.rels", ROOT_RELS.as_bytes())?;
+    zip.add("xl/workbook.xml", WORKBOOK.as_bytes())?;
+    zip.add("xl/_rels/workbook.xml.rels", WORKBOOK_RELS.as_bytes())?;
+    zip.add("xl/styles.xml", STYLES.as_bytes())?;
+    zip.add("xl/worksheets/sheet1.xml", sheet(&account_rows, &[10, 18, 18, 18, 8], true).as_bytes())?;
+    zip.add("xl/worksheets/sheet2.xml", sheet(&reject_rows, &[8, 10, 12, 28, 48], true).as_bytes())?;
+    zip.add("xl/worksheets/sheet3.xml", sheet(&summary_rows, &[36, 18], false).as_bytes())?;
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientId;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_column_names() {
+        assert_eq!(column(0), "A");
+        assert_eq!(column(25), "Z");
+        assert_eq!(column(26), "AA");
+    }
+
+    #[test]
+    fn test_sheet_cells() {
+        let rows = vec![
+            vec![Cell::Header("a<b")],
+            vec![Cell::Amount(dec!(1.5)), Cell::Empty, Cell::Bool(true)],
+        ];
+        let xml = sheet(&rows, &[10, 10, 10], true);
+        assert!(xml.contains(r#"<c r="A1" s="1" t="inlineStr"><is><t>a&lt;b</t></is></c>"#));
+        assert!(xml.contains(r#"<row r="2"><c r="A2" s="2"><v>1.5</v></c><c r="C2" t="b"><v>1</v></c></row>"#));
+        assert!(xml.contains(r#"<autoFilter ref="A1:C2"/>"#));
+    }
+
+    #[test]
+    fn test_workbook_is_a_zip_of_its_parts() {
+        let accounts = [AccountReport { client: ClientId(1), available: dec!(2), held: dec!(1), locked: false, wallet: None, owners: None }];
+        let mut out = Vec::new();
+        write(&mut out, &accounts, &RejectLog::default(), &Summary::default()).unwrap();
+        assert_eq!(&out[..4], b"PK\x03\x04");
+        let text = String::from_utf8_lossy(&out);
+        for part in ["[Content_Types].xml", "xl/workbook.xml", "xl/worksheets/sheet3.xml", "<v>3</v>"] {
+            assert!(text.contains(part), "missing {}", part);
+        }
+    }
+}
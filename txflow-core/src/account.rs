@@ -0,0 +1,598 @@
+use alloc::{boxed::Box, collections::{BTreeMap, BTreeSet}, string::String, vec::Vec};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+
+use crate::{history::History, money::{self, MoneyOps}, ClientId, TxId, TxType};
+
+/// Why a transaction was not applied to an account.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Reject {
+    AccountLocked,
+    InsufficientFunds,
+    MissingAmount,
+    UnknownTx,
+    AlreadyDisputed,
+    NotDisputed,
+    UnknownClient,
+    AccountFinalized,
+    TxPruned,
+    LimitExceeded,
+    RuleRejected,
+    OutOfOrder,
+    DuplicateTx,
+    EscrowAccount,
+    TypeNotAllowed,
+    LossAccount,
+    DisputeStage,
+    CycleLimit,
+    QuotaExceeded,
+    MissingTimestamp,
+    InvalidTransfer,
+    TransferAborted,
+    StoreUnavailable,
+    AmountOutOfRange,
+}
+
+impl fmt::Display for Reject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Reject::AccountLocked => "account locked",
+            Reject::InsufficientFunds => "insufficient funds",
+            Reject::MissingAmount => "missing amount",
+            Reject::UnknownTx => "unknown transaction",
+            Reject::AlreadyDisputed => "transaction already disputed",
+            Reject::NotDisputed => "transaction not disputed",
+            Reject::UnknownClient => "unknown client",
+            Reject::AccountFinalized => "account already finalized",
+            Reject::TxPruned => "transaction pruned by retention policy",
+            Reject::LimitExceeded => "amount exceeds configured limit",
+            Reject::RuleRejected => "rejected by policy rule",
+            Reject::OutOfOrder => "transaction out of order",
+            Reject::DuplicateTx => "duplicate transaction id",
+            Reject::EscrowAccount => "account reserved for escrow",
+            Reject::TypeNotAllowed => "transaction type not allowed for account class",
+            Reject::LossAccount => "account reserved for chargeback losses",
+            Reject::DisputeStage => "dispute not at a stage allowing this",
+            Reject::CycleLimit => "no dispute cycles left for this transaction",
+            Reject::QuotaExceeded => "client quota exceeded",
+            Reject::MissingTimestamp => "missing timestamp",
+            Reject::InvalidTransfer => "transfer needs another client to credit",
+            Reject::TransferAborted => "transfer aborted by the other side",
+            Reject::StoreUnavailable => "account store unavailable",
+            Reject::AmountOutOfRange => "amount out of range for the account's amount type",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Where a deposit is in the dispute lifecycle. Funds are held from
+/// `Opened` until a terminal state: `Won` releases them, `Lost` charges
+/// them back. A representment contests a chargeback, holding the funds
+/// again as `Represented` until the next cycle's outcome.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisputeState {
+    #[default]
+    Undisputed,
+    Opened,
+    EvidenceRequested,
+    UnderReview,
+    Represented,
+    Won,
+    Lost,
+}
+
+impl DisputeState {
+    /// Whether funds are held for the dispute.
+    pub fn is_open(self) -> bool {
+        matches!(self, DisputeState::Opened | DisputeState::EvidenceRequested | DisputeState::UnderReview | DisputeState::Represented)
+    }
+
+    /// The state a dispute-flow row moves a deposit to.
+    pub fn after(tx_type: TxType) -> Option<DisputeState> {
+        match tx_type {
+            TxType::Dispute => Some(DisputeState::Opened),
+            TxType::RequestEvidence => Some(DisputeState::EvidenceRequested),
+            TxType::Review => Some(DisputeState::UnderReview),
+            TxType::Representment => Some(DisputeState::Represented),
+            TxType::Resolve => Some(DisputeState::Won),
+            TxType::Chargeback => Some(DisputeState::Lost),
+            TxType::Deposit | TxType::Withdrawal | TxType::Finalize | TxType::Transfer => None,
+        }
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DisputeState::Undisputed => "undisputed",
+            DisputeState::Opened => "opened",
+            DisputeState::EvidenceRequested => "evidence-requested",
+            DisputeState::UnderReview => "under-review",
+            DisputeState::Represented => "represented",
+            DisputeState::Won => "won",
+            DisputeState::Lost => "lost",
+        })
+    }
+}
+
+/// A deposit, or under provisional credit a withdrawal, kept so it can
+/// later be disputed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deposit<A = Decimal> {
+    pub amount: A,
+    pub state: DisputeState,
+    /// Arrival order within the account, for keep-last-N eviction.
+    pub seq: u64,
+    pub timestamp: Option<i64>,
+    /// When the open dispute on it was raised, by the stream clock.
+    pub opened: Option<i64>,
+    /// A withdrawal: disputing it credits the client provisionally instead
+    /// of holding funds, a resolve takes the credit back and a chargeback
+    /// makes it final.
+    pub withdrawal: bool,
+    /// Chargebacks it has been through, for the cycle limit.
+    pub cycles: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct Account<A = Decimal> {
+    pub client: ClientId,
+    pub available: A,
+    pub held: A,
+    pub locked: bool,
+    /// The wallet this account holds, while the engine keeps wallets apart.
+    #[serde(skip)]
+    pub wallet: Option<String>,
+
+    #[serde(skip)]
+    pub history: History<A>,
+    #[serde(skip)]
+    pub next_seq: u64,
+    /// Allocated once the account first needs any of it, so the common
+    /// account pays one pointer for bookkeeping most never use.
+    #[serde(skip)]
+    pub extra: Option<Box<Extra>>,
+}
+
+/// The parts of an account only some accounts need.
+#[derive(Debug, Clone, Default)]
+pub struct Extra {
+    /// Deposits dropped by the retention policy, so disputes against them
+    /// can be told apart from disputes against ids that never existed.
+    pub pruned: BTreeSet<TxId>,
+    /// Case references of disputed deposits, from the `case` column, so
+    /// txflow's state can be tied to the case management system.
+    pub cases: BTreeMap<TxId, String>,
+}
+
+impl<A: MoneyOps> Account<A> {
+    pub fn new(client: ClientId) -> Self {
+        Account { client, ..Default::default() }
+    }
+
+    pub fn deposit(&mut self, tx: TxId, amount: A) -> Result<(), Reject> {
+        self.deposit_at(tx, amount, None)
+    }
+
+    /// Deposits with the business time of the row, used by time-based retention.
+    pub fn deposit_at(&mut self, tx: TxId, amount: A, timestamp: Option<i64>) -> Result<(), Reject> {
+        if self.locked { return Err(Reject::AccountLocked); }
+        self.credit(tx, amount, timestamp)
+    }
+
+    /// Books a deposit without the lock check, for policies that accept deposits on locked accounts.
+    pub fn credit(&mut self, tx: TxId, amount: A, timestamp: Option<i64>) -> Result<(), Reject> {
+        self.available = money::add(self.available, amount)?;
+        self.history.insert(tx, Deposit { amount, state: DisputeState::Undisputed, seq: self.next_seq, timestamp, opened: None, withdrawal: false, cycles: 0 });
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// Keeps a booked withdrawal in history so the client can dispute it.
+    pub fn keep_withdrawal(&mut self, tx: TxId, amount: A, timestamp: Option<i64>) {
+        self.history.insert(tx, Deposit { amount, state: DisputeState::Undisputed, seq: self.next_seq, timestamp, opened: None, withdrawal: true, cycles: 0 });
+        self.next_seq += 1;
+    }
+
+    pub fn withdrawal(&mut self, amount: A) -> Result<(), Reject> {
+        if self.locked { return Err(Reject::AccountLocked); }
+        if self.available < amount { return Err(Reject::InsufficientFunds); }
+        self.available = money::sub(self.available, amount)?;
+        Ok(())
+    }
+
+    /// The case reference recorded for a disputed deposit, if any.
+    pub fn case(&self, tx: TxId) -> Option<&str> {
+        self.extra.as_ref()?.cases.get(&tx).map(String::as_str)
+    }
+
+    pub fn set_case(&mut self, tx: TxId, case: String) {
+        self.extra.get_or_insert_default().cases.insert(tx, case);
+    }
+
+    /// Ids the retention policy dropped from history, in no particular order.
+    pub fn pruned(&self) -> impl Iterator<Item = TxId> + '_ {
+        self.extra.iter().flat_map(|extra| extra.pruned.iter().copied())
+    }
+
+    /// Finds a deposit for dispute handling, distinguishing pruned from unknown ids.
+    fn lookup(&self, tx: TxId) -> Result<Deposit<A>, Reject> {
+        match self.history.get(tx) {
+            Some(deposit) => Ok(deposit),
+            None if self.extra.as_ref().is_some_and(|extra| extra.pruned.contains(&tx)) => Err(Reject::TxPruned),
+            None => Err(Reject::UnknownTx),
+        }
+    }
+
+    /// Opens a dispute on a deposit or kept withdrawal that has never been
+    /// disputed. One that was resolved or charged back is settled for good; a
+    /// charged-back deposit is contested with a representment instead.
+    pub fn dispute(&mut self, tx: TxId) -> Result<(), Reject> {
+        if self.locked { return Err(Reject::AccountLocked); }
+        let mut deposit = self.lookup(tx)?;
+        if deposit.state.is_open() { return Err(Reject::AlreadyDisputed); }
+        if deposit.state != DisputeState::Undisputed { return Err(Reject::DisputeStage); }
+        if deposit.withdrawal {
+            self.available = money::add(self.available, deposit.amount)?;
+        } else {
+            if self.available < deposit.amount { return Err(Reject::InsufficientFunds); }
+            let held = money::add(self.held, deposit.amount)?;
+            self.available = money::sub(self.available, deposit.amount)?;
+            self.held = held;
+        }
+        deposit.state = DisputeState::Opened;
+        self.history.insert(tx, deposit);
+        Ok(())
+    }
+
+    /// Moves an open dispute to `EvidenceRequested`, from `Opened` only.
+    pub fn request_evidence(&mut self, tx: TxId) -> Result<(), Reject> {
+        self.advance(tx, &[DisputeState::Opened], DisputeState::EvidenceRequested)
+    }
+
+    /// Moves an open dispute to `UnderReview`, from any earlier open state.
+    pub fn review(&mut self, tx: TxId) -> Result<(), Reject> {
+        self.advance(tx, &[DisputeState::Opened, DisputeState::EvidenceRequested], DisputeState::UnderReview)
+    }
+
+    /// A non-terminal step: held funds stay held.
+    fn advance(&mut self, tx: TxId, from: &[DisputeState], to: DisputeState) -> Result<(), Reject> {
+        if self.locked { return Err(Reject::AccountLocked); }
+        let mut deposit = self.lookup(tx)?;
+        if !deposit.state.is_open() { return Err(Reject::NotDisputed); }
+        if !from.contains(&deposit.state) { return Err(Reject::DisputeStage); }
+        deposit.state = to;
+        self.history.insert(tx, deposit);
+        Ok(())
+    }
+
+    /// Contests a chargeback, holding the deposit's funds again for another
+    /// cycle. The lock the chargeback left doesn't stop the representment or
+    /// its outcome; `max_cycles` counts chargebacks a deposit may go through.
+    pub fn represent(&mut self, tx: TxId, max_cycles: u32) -> Result<(), Reject> {
+        let mut deposit = self.lookup(tx)?;
+        if deposit.state != DisputeState::Lost || deposit.withdrawal { return Err(Reject::DisputeStage); }
+        if deposit.cycles >= max_cycles { return Err(Reject::CycleLimit); }
+        self.held = money::add(self.held, deposit.amount)?;
+        deposit.state = DisputeState::Represented;
+        self.history.insert(tx, deposit);
+        Ok(())
+    }
+
+    /// Whether the lock refuses a dispute outcome for `tx`; a represented
+    /// deposit's next outcome gets through.
+    fn locked_for(&self, tx: TxId) -> bool {
+        self.locked && self.history.get(tx).is_none_or(|deposit| deposit.state != DisputeState::Represented)
+    }
+
+    pub fn resolve(&mut self, tx: TxId) -> Result<(), Reject> {
+        if self.locked_for(tx) { return Err(Reject::AccountLocked); }
+        self.force_resolve(tx)
+    }
+
+    /// Resolves an open dispute whether or not the account is locked; an
+    /// operator's override. A withdrawal's provisional credit is taken back
+    /// only as far as the available funds go, since the client may have
+    /// spent it; what is left uncovered is the caller's to book.
+    pub fn force_resolve(&mut self, tx: TxId) -> Result<(), Reject> {
+        let mut deposit = self.lookup(tx)?;
+        if !deposit.state.is_open() { return Err(Reject::NotDisputed); }
+        if deposit.withdrawal {
+            self.available = money::sub(self.available, deposit.amount.min(self.available.max(A::default())))?;
+        } else {
+            let available = money::add(self.available, deposit.amount)?;
+            self.held = money::sub(self.held, deposit.amount)?;
+            self.available = available;
+        }
+        deposit.state = DisputeState::Won;
+        self.history.insert(tx, deposit);
+        Ok(())
+    }
+
+    /// Adds `amount`, which may be negative, to the available funds, lock or
+    /// no lock; an operator's manual correction. Never leaves them negative.
+    pub fn adjust(&mut self, amount: A) -> Result<(), Reject> {
+        let available = money::add(self.available, amount)?;
+        if available < A::default() { return Err(Reject::InsufficientFunds); }
+        self.available = available;
+        Ok(())
+    }
+
+    pub fn chargeback(&mut self, tx: TxId) -> Result<(), Reject> {
+        if self.locked_for(tx) { return Err(Reject::AccountLocked); }
+        let mut deposit = self.lookup(tx)?;
+        if !deposit.state.is_open() { return Err(Reject::NotDisputed); }
+        if !deposit.withdrawal {
+            self.held = money::sub(self.held, deposit.amount)?;
+            self.locked = true;
+        }
+        deposit.state = DisputeState::Lost;
+        deposit.cycles += 1;
+        self.history.insert(tx, deposit);
+        Ok(())
+    }
+
+    /// Drops all but the `keep` most recent undisputed deposits. Returns how
+    /// many entries were removed.
+    pub fn keep_last(&mut self, keep: usize) -> usize {
+        let mut undisputed: Vec<(u64, TxId)> = self.history.iter()
+            .filter(|(_, deposit)| !deposit.state.is_open())
+            .map(|(tx, deposit)| (deposit.seq, tx))
+            .collect();
+        if undisputed.len() <= keep {
+            return 0;
+        }
+        undisputed.sort_unstable();
+        undisputed.truncate(undisputed.len() - keep);
+        self.forget_all(undisputed.into_iter().map(|(_, tx)| tx).collect())
+    }
+
+    /// Drops undisputed deposits timestamped before `cutoff`; deposits
+    /// without a timestamp never expire. Returns how many were removed.
+    pub fn expire_before(&mut self, cutoff: i64) -> usize {
+        let expired = self.history.iter()
+            .filter(|(_, deposit)| !deposit.state.is_open() && deposit.timestamp.is_some_and(|at| at < cutoff))
+            .map(|(tx, _)| tx)
+            .collect();
+        self.forget_all(expired)
+    }
+
+    fn forget_all(&mut self, expired: Vec<TxId>) -> usize {
+        for tx in &expired {
+            self.forget(*tx);
+        }
+        expired.len()
+    }
+
+    /// Removes a deposit from history, remembering that it existed.
+    pub fn forget(&mut self, tx: TxId) -> bool {
+        let removed = self.history.remove(tx).is_some();
+        if removed {
+            let extra = self.extra.get_or_insert_default();
+            extra.pruned.insert(tx);
+            extra.cases.remove(&tx);
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn test_account(client: ClientId) -> Account {
+        Account { client, ..Default::default() }
+    }
+
+    #[test]
+    fn test_deposit() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        assert_eq!(account.available, dec!(10.0));
+        assert_eq!(account.held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_withdrawal() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.withdrawal(dec!(4.0)).unwrap();
+        assert_eq!(account.available, dec!(6.0));
+    }
+
+    #[test]
+    fn test_withdrawal_insufficient() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(2.0)).unwrap();
+        assert_eq!(account.withdrawal(dec!(3.0)), Err(Reject::InsufficientFunds));
+        assert_eq!(account.available, dec!(2.0));
+    }
+
+    #[test]
+    fn test_dispute_valid() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.dispute(TxId(1)).unwrap();
+        assert_eq!(account.available, dec!(0.0));
+        assert_eq!(account.held, dec!(10.0));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.dispute(TxId(1)).unwrap();
+        account.resolve(TxId(1)).unwrap();
+        assert_eq!(account.available, dec!(10.0));
+        assert_eq!(account.held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_chargeback() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.dispute(TxId(1)).unwrap();
+        account.chargeback(TxId(1)).unwrap();
+        assert_eq!(account.held, dec!(0.0));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_dispute_stages_hold_funds_until_terminal() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10)).unwrap();
+        assert_eq!(account.review(TxId(1)), Err(Reject::NotDisputed));
+        account.dispute(TxId(1)).unwrap();
+        account.request_evidence(TxId(1)).unwrap();
+        account.review(TxId(1)).unwrap();
+        assert_eq!(account.request_evidence(TxId(1)), Err(Reject::DisputeStage));
+        assert_eq!((account.available, account.held), (dec!(0), dec!(10)));
+        assert_eq!(account.history.get(TxId(1)).unwrap().state, DisputeState::UnderReview);
+
+        account.resolve(TxId(1)).unwrap();
+        assert_eq!((account.available, account.held), (dec!(10), dec!(0)));
+        assert_eq!(account.history.get(TxId(1)).unwrap().state, DisputeState::Won);
+    }
+
+    #[test]
+    fn test_locked_account_blocks_deposit() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.dispute(TxId(1)).unwrap();
+        account.chargeback(TxId(1)).unwrap();
+        assert_eq!(account.deposit(TxId(2), dec!(10.0)), Err(Reject::AccountLocked));
+        assert_eq!(account.available, dec!(0.0));
+    }
+
+    #[test]
+    fn test_dispute_nonexistent_tx() {
+        let mut account = test_account(ClientId(1));
+        assert_eq!(account.dispute(TxId(99)), Err(Reject::UnknownTx)); // No tx inserted
+        assert_eq!(account.available, dec!(0.0));
+        assert_eq!(account.held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_dispute_on_withdrawal_should_be_ignored() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.withdrawal(dec!(5.0)).unwrap(); // No tx id stored for withdrawal
+        assert_eq!(account.dispute(TxId(2)), Err(Reject::UnknownTx)); // Attempt to dispute non-existent withdrawal
+        assert_eq!(account.available, dec!(5.0));
+        assert_eq!(account.held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_dispute_tx_not_owned_by_client_is_ignored() {
+        let mut acc1 = test_account(ClientId(1));
+        let mut acc2 = test_account(ClientId(2));
+
+        // Only acc1 has tx 100
+        acc1.deposit(TxId(100), dec!(15.0)).unwrap();
+
+        // acc2 tries to dispute tx 100 (which it doesn't own)
+        assert_eq!(acc2.dispute(TxId(100)), Err(Reject::UnknownTx));
+
+        // Assert acc1 remains unchanged
+        assert_eq!(acc1.available, dec!(15.0));
+        assert_eq!(acc1.held, dec!(0.0));
+
+        // Assert acc2 remains unchanged
+        assert_eq!(acc2.available, dec!(0.0));
+        assert_eq!(acc2.held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_keep_last_prunes_oldest_undisputed() {
+        let mut account = test_account(ClientId(1));
+        for tx in 1..=4 {
+            account.deposit(TxId(tx), dec!(1.0)).unwrap();
+        }
+        account.dispute(TxId(1)).unwrap();
+        assert_eq!(account.keep_last(2), 1);
+        assert_eq!(account.dispute(TxId(2)), Err(Reject::TxPruned));
+        account.dispute(TxId(3)).unwrap();
+        account.resolve(TxId(1)).unwrap();
+    }
+
+    #[test]
+    fn test_plain_accounts_stay_compact() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(1.0)).unwrap();
+        account.deposit(TxId(2), dec!(2.0)).unwrap();
+        account.withdrawal(dec!(0.5)).unwrap();
+        assert!(account.extra.is_none());
+        assert!(core::mem::size_of::<Account>() <= 144);
+
+        account.forget(TxId(1));
+        assert_eq!(account.dispute(TxId(1)), Err(Reject::TxPruned));
+        assert_eq!(account.pruned().collect::<Vec<_>>(), [TxId(1)]);
+    }
+
+    #[test]
+    fn test_keep_within_uses_timestamps() {
+        let mut account = test_account(ClientId(1));
+        account.deposit_at(TxId(1), dec!(1.0), Some(100)).unwrap();
+        account.deposit_at(TxId(2), dec!(1.0), Some(200)).unwrap();
+        account.deposit(TxId(3), dec!(1.0)).unwrap();
+        assert_eq!(account.expire_before(220 - 50), 1);
+        assert_eq!(account.dispute(TxId(1)), Err(Reject::TxPruned));
+        assert_eq!(account.dispute(TxId(9)), Err(Reject::UnknownTx));
+        account.dispute(TxId(3)).unwrap();
+    }
+
+    #[test]
+    fn test_representment_starts_another_cycle() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.dispute(TxId(1)).unwrap();
+        account.chargeback(TxId(1)).unwrap();
+        assert_eq!(account.represent(TxId(1), 1), Err(Reject::CycleLimit));
+        account.represent(TxId(1), 2).unwrap();
+        assert_eq!(account.held, dec!(10.0));
+        assert_eq!(account.review(TxId(1)), Err(Reject::AccountLocked));
+        account.chargeback(TxId(1)).unwrap();
+        assert_eq!(account.held, dec!(0.0));
+        assert_eq!(account.represent(TxId(1), 2), Err(Reject::CycleLimit));
+        assert_eq!(account.history.get(TxId(1)).unwrap().cycles, 2);
+    }
+
+    #[test]
+    fn test_dispute_after_funds_already_withdrawn_should_fail() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.withdrawal(dec!(10.0)).unwrap();
+        assert_eq!(account.dispute(TxId(1)), Err(Reject::InsufficientFunds)); // Should be ignored
+        assert_eq!(account.available, dec!(0.0));
+        assert_eq!(account.held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_resolve_takes_back_only_unspent_provisional_credit() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.withdrawal(dec!(8.0)).unwrap();
+        account.keep_withdrawal(TxId(2), dec!(8.0), None);
+        account.dispute(TxId(2)).unwrap();
+        account.withdrawal(dec!(7.0)).unwrap();
+        account.resolve(TxId(2)).unwrap();
+        assert_eq!(account.available, dec!(0.0));
+    }
+
+    #[test]
+    fn test_charged_back_withdrawal_cannot_be_disputed_again() {
+        let mut account = test_account(ClientId(1));
+        account.deposit(TxId(1), dec!(10.0)).unwrap();
+        account.withdrawal(dec!(10.0)).unwrap();
+        account.keep_withdrawal(TxId(2), dec!(10.0), None);
+        account.dispute(TxId(2)).unwrap();
+        account.chargeback(TxId(2)).unwrap();
+        assert_eq!(account.dispute(TxId(2)), Err(Reject::DisputeStage));
+        assert_eq!(account.chargeback(TxId(2)), Err(Reject::NotDisputed));
+        assert_eq!(account.available, dec!(10.0));
+    }
+
+}
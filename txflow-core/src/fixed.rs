@@ -0,0 +1,110 @@
+//! Fixed-point amounts: an `i64` count of ten-thousandths. Parsing one is a
+//! single pass over the digits with no intermediate `Decimal`, and every
+//! amount read this way shares one scale, so the arithmetic downstream never
+//! rescales and history entries never spill. The price is generality: more
+//! than four decimal places, or more than about 922 trillion, is an error.
+
+use alloc::{format, string::String};
+use core::{fmt, str::FromStr};
+use rust_decimal::Decimal;
+
+/// Implied decimal places.
+pub const SCALE: u32 = 4;
+const ONE: i64 = 10_i64.pow(SCALE);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct Fixed(pub i64);
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+
+    /// The amount in minor units.
+    pub fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Fixed) -> Option<Fixed> {
+        self.0.checked_add(other.0).map(Fixed)
+    }
+
+    pub fn checked_sub(self, other: Fixed) -> Option<Fixed> {
+        self.0.checked_sub(other.0).map(Fixed)
+    }
+}
+
+impl FromStr for Fixed {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, String> {
+        let invalid = || format!("'{}' is not an amount with at most {} decimal places", raw, SCALE);
+        let text = raw.trim();
+        let (negative, digits) = match text.as_bytes().first() {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if (whole.is_empty() && fraction.is_empty()) || fraction.len() > SCALE as usize {
+            return Err(invalid());
+        }
+        let mut units: i64 = 0;
+        for byte in whole.bytes().chain(fraction.bytes()) {
+            if !byte.is_ascii_digit() {
+                return Err(invalid());
+            }
+            units = units.checked_mul(10).and_then(|units| units.checked_add(i64::from(byte - b'0'))).ok_or_else(invalid)?;
+        }
+        units = units.checked_mul(10_i64.pow(SCALE - fraction.len() as u32)).ok_or_else(invalid)?;
+        Ok(Fixed(if negative { -units } else { units }))
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let magnitude = self.0.unsigned_abs();
+        write!(f, "{}{}.{:04}", sign, magnitude / ONE as u64, magnitude % ONE as u64)
+    }
+}
+
+impl From<Fixed> for Decimal {
+    fn from(amount: Fixed) -> Self {
+        Decimal::new(amount.0, SCALE)
+    }
+}
+
+impl TryFrom<Decimal> for Fixed {
+    type Error = String;
+
+    /// Exact conversions only: no rounding of extra places.
+    fn try_from(amount: Decimal) -> Result<Self, String> {
+        let mut scaled = amount;
+        scaled.rescale(SCALE);
+        match i64::try_from(scaled.mantissa()) {
+            Ok(units) if scaled == amount && scaled.scale() == SCALE => Ok(Fixed(units)),
+            _ => Err(format!("{} does not fit {} decimal places in 64 bits", amount, SCALE)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_parses_straight_to_minor_units() {
+        assert_eq!("1.5".parse(), Ok(Fixed(15_000)));
+        assert_eq!("-0.0001".parse(), Ok(Fixed(-1)));
+        assert_eq!(" +12 ".parse(), Ok(Fixed(120_000)));
+        assert_eq!(".25".parse(), Ok(Fixed(2_500)));
+        for bad in ["", ".", "1.23456", "1e3", "12,5", "99999999999999999"] {
+            assert!(bad.parse::<Fixed>().is_err(), "{}", bad);
+        }
+        assert_eq!(Fixed(-15_000).to_string(), "-1.5000");
+        assert_eq!(Decimal::from(Fixed(15_000)), dec!(1.5));
+        assert_eq!(Fixed::try_from(dec!(2.25)), Ok(Fixed(22_500)));
+        assert!(Fixed::try_from(dec!(0.00001)).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//! An account's disputable history, packed. Entries sit in a vec sorted by
+//! transaction id, each a fixed 24-byte record of the amount as an integer
+//! mantissa with its scale, the arrival order, and the dispute state and
+//! kind as flag bits. Business times live in a parallel column that only
+//! exists once some entry has one, and what doesn't fit a record (a
+//! mantissa beyond 64 bits, an open dispute's start, a very long chargeback
+//! count) spills into a side table. That is several times smaller than a
+//! hash map of full deposits, and lookups are binary searches. The first
+//! couple of entries sit inside the history itself and the side table is
+//! only allocated once something spills, so the typical account, with a
+//! deposit or two and no dispute, allocates nothing for its history.
+
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+use core::mem;
+use rust_decimal::Decimal;
+
+use crate::{account::{Deposit, DisputeState}, money::MoneyOps, TxId};
+
+const STATE: u8 = 0b0000_0111;
+const WITHDRAWAL: u8 = 0b0000_1000;
+/// Part of the entry is kept in `Sparse::spilled`.
+const SPILLED: u8 = 0b0001_0000;
+
+/// Marks an entry without a timestamp in the timestamp column.
+const NO_TIME: i64 = i64::MIN;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Entry {
+    seq: u64,
+    /// The amount's mantissa, when it fits.
+    units: i64,
+    tx: u32,
+    scale: u8,
+    flags: u8,
+    cycles: u8,
+}
+
+/// The parts of a deposit its entry has no room for.
+#[derive(Debug, Clone, Copy, Default)]
+struct Spilled<A> {
+    amount: Option<A>,
+    opened: Option<i64>,
+    cycles: u32,
+}
+
+/// Bytes one kept deposit takes in the common case, for memory estimates.
+pub const ENTRY_BYTES: usize = mem::size_of::<Entry>();
+
+/// Entries kept in place before a history moves to the heap.
+const INLINE: usize = 2;
+
+/// A small vector: up to [`INLINE`] entries in place, then a vec. A history
+/// that has grown past that stays on the heap.
+#[derive(Debug, Clone)]
+enum Entries {
+    Inline { len: u8, entries: [Entry; INLINE] },
+    Heap(Vec<Entry>),
+}
+
+impl Default for Entries {
+    fn default() -> Self {
+        Entries::Inline { len: 0, entries: [Entry::default(); INLINE] }
+    }
+}
+
+impl Entries {
+    fn as_slice(&self) -> &[Entry] {
+        match self {
+            Entries::Inline { len, entries } => &entries[..usize::from(*len)],
+            Entries::Heap(entries) => entries,
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [Entry] {
+        match self {
+            Entries::Inline { len, entries } => &mut entries[..usize::from(*len)],
+            Entries::Heap(entries) => entries,
+        }
+    }
+
+    fn insert(&mut self, at: usize, entry: Entry) {
+        match self {
+            Entries::Inline { len, entries } if usize::from(*len) < INLINE => {
+                entries.copy_within(at..usize::from(*len), at + 1);
+                entries[at] = entry;
+                *len += 1;
+            }
+            Entries::Inline { entries, .. } => {
+                let mut heap = Vec::with_capacity(INLINE * 2);
+                heap.extend_from_slice(entries);
+                heap.insert(at, entry);
+                *self = Entries::Heap(heap);
+            }
+            Entries::Heap(entries) => entries.insert(at, entry),
+        }
+    }
+
+    fn remove(&mut self, at: usize) {
+        match self {
+            Entries::Inline { len, entries } => {
+                entries.copy_within(at + 1..usize::from(*len), at);
+                *len -= 1;
+            }
+            Entries::Heap(entries) => {
+                entries.remove(at);
+            }
+        }
+    }
+}
+
+/// The columns only some histories need.
+#[derive(Debug, Clone)]
+struct Sparse<A> {
+    /// Business times parallel to the entries, once some entry has one.
+    timestamps: Option<Vec<i64>>,
+    spilled: BTreeMap<TxId, Spilled<A>>,
+}
+
+impl<A> Default for Sparse<A> {
+    fn default() -> Self {
+        Sparse { timestamps: None, spilled: BTreeMap::new() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct History<A = Decimal> {
+    entries: Entries,
+    /// Allocated on the first timestamp or spill.
+    sparse: Option<Box<Sparse<A>>>,
+}
+
+impl<A> Default for History<A> {
+    fn default() -> Self {
+        History { entries: Entries::default(), sparse: None }
+    }
+}
+
+fn state_bits(state: DisputeState) -> u8 {
+    match state {
+        DisputeState::Undisputed => 0,
+        DisputeState::Opened => 1,
+        DisputeState::EvidenceRequested => 2,
+        DisputeState::UnderReview => 3,
+        DisputeState::Represented => 4,
+        DisputeState::Won => 5,
+        DisputeState::Lost => 6,
+    }
+}
+
+fn state_of(flags: u8) -> DisputeState {
+    match flags & STATE {
+        0 => DisputeState::Undisputed,
+        1 => DisputeState::Opened,
+        2 => DisputeState::EvidenceRequested,
+        3 => DisputeState::UnderReview,
+        4 => DisputeState::Represented,
+        5 => DisputeState::Won,
+        _ => DisputeState::Lost,
+    }
+}
+
+impl<A: MoneyOps> History<A> {
+    pub fn len(&self) -> usize {
+        self.entries.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn position(&self, tx: TxId) -> Result<usize, usize> {
+        self.entries.as_slice().binary_search_by_key(&tx.0, |entry| entry.tx)
+    }
+
+    pub fn contains(&self, tx: TxId) -> bool {
+        self.position(tx).is_ok()
+    }
+
+    pub fn get(&self, tx: TxId) -> Option<Deposit<A>> {
+        self.position(tx).ok().map(|at| self.unpack(at))
+    }
+
+    /// Adds a deposit, or replaces the one kept under the same id.
+    pub fn insert(&mut self, tx: TxId, deposit: Deposit<A>) {
+        let parts = deposit.amount.to_parts().filter(|&(_, scale)| scale <= u32::from(u8::MAX));
+        let cycles = u8::try_from(deposit.cycles).ok();
+        let spills = parts.is_none() || cycles.is_none() || deposit.opened.is_some();
+        let entry = Entry {
+            seq: deposit.seq,
+            units: parts.map_or(0, |(units, _)| units),
+            tx: tx.0,
+            scale: parts.map_or(0, |(_, scale)| scale as u8),
+            flags: state_bits(deposit.state) | if deposit.withdrawal { WITHDRAWAL } else { 0 } | if spills { SPILLED } else { 0 },
+            cycles: cycles.unwrap_or_default(),
+        };
+        let len = self.len();
+        if spills || deposit.timestamp.is_some() {
+            let sparse = self.sparse.get_or_insert_default();
+            if spills {
+                let spilled = Spilled { amount: parts.is_none().then_some(deposit.amount), opened: deposit.opened, cycles: deposit.cycles };
+                sparse.spilled.insert(tx, spilled);
+            }
+            if deposit.timestamp.is_some() && sparse.timestamps.is_none() {
+                sparse.timestamps = Some(vec![NO_TIME; len]);
+            }
+        }
+        if let Some(sparse) = self.sparse.as_deref_mut().filter(|_| !spills) {
+            sparse.spilled.remove(&tx);
+        }
+        let position = self.position(tx);
+        let timestamps = self.sparse.as_deref_mut().and_then(|sparse| sparse.timestamps.as_mut());
+        let timestamp = deposit.timestamp.unwrap_or(NO_TIME);
+        match position {
+            Ok(at) => {
+                self.entries.as_mut_slice()[at] = entry;
+                if let Some(timestamps) = timestamps {
+                    timestamps[at] = timestamp;
+                }
+            }
+            Err(at) => {
+                self.entries.insert(at, entry);
+                if let Some(timestamps) = timestamps {
+                    timestamps.insert(at, timestamp);
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, tx: TxId) -> Option<Deposit<A>> {
+        let at = self.position(tx).ok()?;
+        let deposit = self.unpack(at);
+        self.entries.remove(at);
+        if let Some(sparse) = &mut self.sparse {
+            if let Some(timestamps) = &mut sparse.timestamps {
+                timestamps.remove(at);
+            }
+            sparse.spilled.remove(&tx);
+        }
+        Some(deposit)
+    }
+
+    /// Every kept deposit, by ascending id.
+    pub fn iter(&self) -> impl Iterator<Item = (TxId, Deposit<A>)> + '_ {
+        (0..self.len()).map(|at| (TxId(self.entries.as_slice()[at].tx), self.unpack(at)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = TxId> + '_ {
+        self.entries.as_slice().iter().map(|entry| TxId(entry.tx))
+    }
+
+    fn unpack(&self, at: usize) -> Deposit<A> {
+        let entry = &self.entries.as_slice()[at];
+        let sparse = self.sparse.as_deref();
+        let spilled = sparse.filter(|_| entry.flags & SPILLED != 0).and_then(|sparse| sparse.spilled.get(&TxId(entry.tx)).copied());
+        Deposit {
+            amount: spilled.and_then(|spilled| spilled.amount)
+                .unwrap_or_else(|| A::from_parts(entry.units, u32::from(entry.scale))),
+            state: state_of(entry.flags),
+            seq: entry.seq,
+            timestamp: sparse.and_then(|sparse| sparse.timestamps.as_ref()).map(|timestamps| timestamps[at]).filter(|&at| at != NO_TIME),
+            opened: spilled.and_then(|spilled| spilled.opened),
+            withdrawal: entry.flags & WITHDRAWAL != 0,
+            cycles: spilled.map_or(u32::from(entry.cycles), |spilled| spilled.cycles),
+        }
+    }
+}
+
+impl<A: MoneyOps> FromIterator<(TxId, Deposit<A>)> for History<A> {
+    fn from_iter<I: IntoIterator<Item = (TxId, Deposit<A>)>>(iter: I) -> Self {
+        let mut history = History::default();
+        for (tx, deposit) in iter {
+            history.insert(tx, deposit);
+        }
+        history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use rust_decimal::dec;
+
+    fn deposit(amount: Decimal, seq: u64) -> Deposit {
+        Deposit { amount, state: DisputeState::Undisputed, seq, timestamp: None, opened: None, withdrawal: false, cycles: 0 }
+    }
+
+    #[test]
+    fn test_round_trips_every_field() {
+        let mut history = History::default();
+        let huge = Decimal::from_i128_with_scale(i128::from(i64::MAX) * 10, 2);
+        let kept = [
+            (TxId(7), deposit(dec!(1.50), 0)),
+            (TxId(3), Deposit { timestamp: Some(-5), withdrawal: true, state: DisputeState::Lost, cycles: 300, ..deposit(dec!(2), 1) }),
+            (TxId(9), Deposit { opened: Some(40), state: DisputeState::UnderReview, ..deposit(huge, 2) }),
+        ];
+        for (tx, deposit) in kept {
+            history.insert(tx, deposit);
+        }
+        assert_eq!(history.keys().map(|tx| tx.0).collect::<Vec<_>>(), [3, 7, 9]);
+        for (tx, deposit) in kept {
+            assert_eq!(history.get(tx), Some(deposit));
+        }
+        assert_eq!(history.get(TxId(7)).unwrap().amount.to_string(), "1.50");
+
+        history.insert(TxId(9), deposit(dec!(4), 2));
+        assert_eq!(history.remove(TxId(3)).map(|deposit| deposit.cycles), Some(300));
+        assert_eq!(history.sparse.as_ref().map(|sparse| sparse.spilled.len()), Some(0));
+        assert!(matches!(history.entries, Entries::Heap(_)));
+        assert_eq!(history.iter().map(|(_, deposit)| deposit).collect::<Vec<_>>(), [kept[0].1, deposit(dec!(4), 2)]);
+        assert_eq!(ENTRY_BYTES, 24);
+    }
+}
@@ -0,0 +1,17 @@
+//! txflow's settlement core: client accounts and the deposit, withdrawal
+//! and dispute rules that move their funds. It needs only `core` and
+//! `alloc`, so the exact rules the `txflow` crate applies can run without
+//! an operating system and be fuzzed in isolation.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod account;
+pub mod fixed;
+pub mod history;
+pub mod money;
+pub mod types;
+
+pub use account::{Account, Reject};
+pub use types::{ClientId, TxId, TxType};
@@ -0,0 +1,137 @@
+//! The amount type behind an account's balances and history. The settlement
+//! rules in [`Account`](crate::Account) are written against [`MoneyOps`], so
+//! a library user can keep accounts in `Decimal` (the default), in [`Fixed`]
+//! minor units for plain 64-bit integer arithmetic, or in a money type of
+//! their own. The CSV front end reads `Decimal` and converts through
+//! [`MoneyOps::from_decimal`].
+//!
+//! Arithmetic is checked: a sum or difference the type can't hold refuses
+//! the transaction with [`Reject::AmountOutOfRange`] instead of panicking
+//! or wrapping, which matters for `Fixed` near its 922 trillion ceiling.
+
+use core::fmt;
+use rust_decimal::Decimal;
+
+use crate::{fixed::{self, Fixed}, Reject};
+
+pub trait MoneyOps: Copy + Ord + Default + fmt::Debug + fmt::Display {
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    fn checked_sub(self, other: Self) -> Option<Self>;
+
+    /// Converts an amount read from input, if it is exactly representable.
+    fn from_decimal(amount: Decimal) -> Option<Self>;
+
+    fn to_decimal(self) -> Decimal;
+
+    /// The amount as a 64-bit mantissa and decimal scale, when it has that
+    /// form, so history can store it packed; other amounts are kept whole.
+    fn to_parts(self) -> Option<(i64, u32)>;
+
+    /// The inverse of [`MoneyOps::to_parts`].
+    fn from_parts(units: i64, scale: u32) -> Self;
+}
+
+/// `a + b`, refusing a sum out of the type's range.
+pub fn add<A: MoneyOps>(a: A, b: A) -> Result<A, Reject> {
+    a.checked_add(b).ok_or(Reject::AmountOutOfRange)
+}
+
+/// `a - b`, refusing a difference out of the type's range.
+pub fn sub<A: MoneyOps>(a: A, b: A) -> Result<A, Reject> {
+    a.checked_sub(b).ok_or(Reject::AmountOutOfRange)
+}
+
+impl MoneyOps for Decimal {
+    fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        Decimal::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+        Decimal::checked_sub(self, other)
+    }
+
+    fn from_decimal(amount: Decimal) -> Option<Self> {
+        Some(amount)
+    }
+
+    fn to_decimal(self) -> Decimal {
+        self
+    }
+
+    fn to_parts(self) -> Option<(i64, u32)> {
+        i64::try_from(self.mantissa()).ok().map(|units| (units, self.scale()))
+    }
+
+    fn from_parts(units: i64, scale: u32) -> Self {
+        Decimal::new(units, scale)
+    }
+}
+
+impl MoneyOps for Fixed {
+    fn checked_add(self, other: Fixed) -> Option<Fixed> {
+        Fixed::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Fixed) -> Option<Fixed> {
+        Fixed::checked_sub(self, other)
+    }
+
+    fn from_decimal(amount: Decimal) -> Option<Self> {
+        Fixed::try_from(amount).ok()
+    }
+
+    fn to_decimal(self) -> Decimal {
+        Decimal::from(self)
+    }
+
+    fn to_parts(self) -> Option<(i64, u32)> {
+        Some((self.0, fixed::SCALE))
+    }
+
+    fn from_parts(units: i64, _scale: u32) -> Self {
+        Fixed(units)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use crate::{account::DisputeState, Account, ClientId, Reject, TxId};
+
+    /// The same rows settle alike whichever amount type the account keeps.
+    fn settle<A: MoneyOps>() -> (Decimal, Decimal, bool) {
+        let amount = |raw: Decimal| A::from_decimal(raw).unwrap();
+        let mut account: Account<A> = Account::new(ClientId(1));
+        account.deposit(TxId(1), amount(dec!(10.25))).unwrap();
+        account.deposit(TxId(2), amount(dec!(3))).unwrap();
+        account.withdrawal(amount(dec!(1.5))).unwrap();
+        assert_eq!(account.withdrawal(amount(dec!(100))), Err(Reject::InsufficientFunds));
+        account.dispute(TxId(2)).unwrap();
+        account.resolve(TxId(2)).unwrap();
+        account.dispute(TxId(1)).unwrap();
+        account.chargeback(TxId(1)).unwrap();
+        (account.available.to_decimal(), account.held.to_decimal(), account.locked)
+    }
+
+    #[test]
+    fn test_fixed_and_decimal_accounts_agree() {
+        assert_eq!(settle::<Fixed>(), settle::<Decimal>());
+        assert_eq!(settle::<Fixed>(), (dec!(1.5), dec!(0), true));
+        assert_eq!(Fixed::from_decimal(dec!(0.00001)), None);
+    }
+
+    #[test]
+    fn test_overflow_refuses_the_row() {
+        let mut account: Account<Fixed> = Account::new(ClientId(1));
+        account.deposit(TxId(1), Fixed(i64::MAX - 1)).unwrap();
+        assert_eq!(account.deposit(TxId(2), Fixed(2)), Err(Reject::AmountOutOfRange));
+        assert_eq!((account.available, account.history.len()), (Fixed(i64::MAX - 1), 1));
+        account.dispute(TxId(1)).unwrap();
+        assert_eq!(account.deposit(TxId(3), Fixed(i64::MAX)), Ok(()));
+        assert_eq!(account.adjust(Fixed(1)), Err(Reject::AmountOutOfRange));
+        assert_eq!(account.dispute(TxId(3)), Err(Reject::AmountOutOfRange), "held can't take both");
+        assert_eq!(account.history.get(TxId(3)).map(|deposit| deposit.state), Some(DisputeState::Undisputed));
+    }
+}
@@ -0,0 +1,71 @@
+//! Identifiers and transaction types shared by every layer.
+
+use alloc::{format, string::String};
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Asks for evidence on an open dispute; funds stay held.
+    #[serde(rename = "request-evidence")]
+    RequestEvidence,
+    /// Puts an open dispute under review; funds stay held.
+    Review,
+    /// Contests a chargeback, holding the funds again for a further cycle.
+    Representment,
+    /// Seals the client's account: its report row is written at once and no
+    /// further transactions are accepted for it.
+    Finalize,
+    /// Moves the amount from the client to the one named in the row's `to`
+    /// column; both sides change or neither does.
+    Transfer,
+}
+
+impl TxType {
+    /// The name rows spell the type with.
+    pub fn name(self) -> &'static str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::RequestEvidence => "request-evidence",
+            TxType::Review => "review",
+            TxType::Representment => "representment",
+            TxType::Finalize => "finalize",
+            TxType::Transfer => "transfer",
+        }
+    }
+}
+
+impl FromStr for TxType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TxType::Deposit),
+            "withdrawal" => Ok(TxType::Withdrawal),
+            "dispute" => Ok(TxType::Dispute),
+            "resolve" => Ok(TxType::Resolve),
+            "chargeback" => Ok(TxType::Chargeback),
+            "request-evidence" => Ok(TxType::RequestEvidence),
+            "review" => Ok(TxType::Review),
+            "representment" => Ok(TxType::Representment),
+            "finalize" => Ok(TxType::Finalize),
+            "transfer" => Ok(TxType::Transfer),
+            other => Err(format!("unknown transaction type '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Default)]
+pub struct ClientId(pub u32);
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct TxId(pub u32);